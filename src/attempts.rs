@@ -0,0 +1,116 @@
+//! Budget of boot attempts.
+//!
+//! With `boot.attempts=<n>`, a boot failing before completion reboots
+//! to try again instead of dropping to a rescue shell, until `n` attempts
+//! failed in a row. The attempts are counted in a file on the root
+//! filesystem, which is removed once a boot completed.
+use std::{fs, io, path::Path, sync::Mutex};
+
+/// File counting the failed boot attempts in a row.
+pub const BOOT_ATTEMPTS: &str = "/var/lib/linos/boot-attempts";
+
+/// The current attempt and the budget, while counted.
+static ATTEMPT: Mutex<Option<(u32, u32)>> = Mutex::new(None);
+
+/// Reads the number of the previous attempts, a missing
+/// or unreadable counter counts as no previous attempt.
+fn read(path: &Path) -> u32 {
+    fs::read_to_string(path)
+        .ok()
+        .and_then(|count| count.trim().parse::<u32>().ok())
+        .unwrap_or(0)
+}
+
+/// Counts the current boot attempt.
+///
+/// The root filesystem has to be mounted writable.
+///
+/// # Arguments
+///
+/// * `path` - The path of the counter (see `BOOT_ATTEMPTS`).
+/// * `budget` - The number of attempts before giving up.
+///
+/// # Returns
+///
+/// * `io::Result<u32>` - The current attempt, starting at 1.
+pub fn begin(path: &Path, budget: u32) -> io::Result<u32> {
+    let attempt = read(path).saturating_add(1);
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, format!("{}\n", attempt))?;
+    nix::unistd::sync();
+    // only a persisted attempt may reboot, otherwise the boot never gives up
+    *ATTEMPT.lock().unwrap() = Some((attempt, budget));
+
+    Ok(attempt)
+}
+
+/// Resets the counter after the boot completed.
+///
+/// Later failures (e.g. of the handoff) drop to a rescue shell,
+/// as they would fail again after a reboot.
+///
+/// # Arguments
+///
+/// * `path` - The path of the counter (see `BOOT_ATTEMPTS`).
+pub fn complete(path: &Path) -> io::Result<()> {
+    // not counted anymore, even if the counter can not be removed
+    *ATTEMPT.lock().unwrap() = None;
+    match fs::remove_file(path) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+        _ => Ok(()),
+    }
+}
+
+/// Checks if a failed boot should reboot to try again.
+///
+/// # Returns
+///
+/// * `Option<(u32, u32)>` - The current attempt and the budget if
+///   attempts are left, `None` if the boot should give up.
+pub fn retry() -> Option<(u32, u32)> {
+    ATTEMPT
+        .lock()
+        .unwrap()
+        .filter(|(attempt, budget)| attempt < budget)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn retries_until_the_budget_is_exhausted() {
+        let dir = std::env::temp_dir().join(format!("linos-test-attempts-{}", std::process::id()));
+        let path = dir.join("var/lib/linos/boot-attempts");
+        let _ = fs::remove_dir_all(&dir);
+
+        // each begin counts one more failed boot in a row
+        assert_eq!(begin(&path, 3).unwrap(), 1);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "1\n");
+        assert_eq!(retry(), Some((1, 3)));
+        assert_eq!(begin(&path, 3).unwrap(), 2);
+        assert_eq!(retry(), Some((2, 3)));
+        assert_eq!(begin(&path, 3).unwrap(), 3);
+        assert_eq!(retry(), None);
+
+        // a completed boot neither retries nor counts the next boot
+        complete(&path).unwrap();
+        assert!(!path.exists());
+        assert_eq!(retry(), None);
+        assert_eq!(begin(&path, 3).unwrap(), 1);
+        complete(&path).unwrap();
+        assert_eq!(retry(), None);
+        // completing twice is fine
+        complete(&path).unwrap();
+
+        // an unreadable counter counts as no previous attempt
+        fs::write(&path, "garbage").unwrap();
+        assert_eq!(begin(&path, 3).unwrap(), 1);
+        complete(&path).unwrap();
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
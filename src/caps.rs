@@ -0,0 +1,232 @@
+//! Linux capabilities of the handed-off process.
+//!
+//! linµos itself runs fully privileged, but the process it
+//! hands off to can be restricted to a whitelist of capabilities
+//! (see: https://man7.org/linux/man-pages/man7/capabilities.7.html).
+use core::fmt;
+use std::{io, os::unix::process::CommandExt, process::Command};
+
+/// The names of the known capabilities, indexed by their number.
+const CAPABILITIES: [&str; 41] = [
+    "chown",
+    "dac_override",
+    "dac_read_search",
+    "fowner",
+    "fsetid",
+    "kill",
+    "setgid",
+    "setuid",
+    "setpcap",
+    "linux_immutable",
+    "net_bind_service",
+    "net_broadcast",
+    "net_admin",
+    "net_raw",
+    "ipc_lock",
+    "ipc_owner",
+    "sys_module",
+    "sys_rawio",
+    "sys_chroot",
+    "sys_ptrace",
+    "sys_pacct",
+    "sys_admin",
+    "sys_boot",
+    "sys_nice",
+    "sys_resource",
+    "sys_time",
+    "sys_tty_config",
+    "mknod",
+    "lease",
+    "audit_write",
+    "audit_control",
+    "setfcap",
+    "mac_override",
+    "mac_admin",
+    "syslog",
+    "wake_alarm",
+    "block_suspend",
+    "audit_read",
+    "perfmon",
+    "bpf",
+    "checkpoint_restore",
+];
+
+/// `_LINUX_CAPABILITY_VERSION_3`, using two 32 bit words per set.
+const CAPABILITY_VERSION_3: u32 = 0x20080522;
+
+#[repr(C)]
+struct CapUserHeader {
+    version: u32,
+    pid: libc::c_int,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct CapUserData {
+    effective: u32,
+    permitted: u32,
+    inheritable: u32,
+}
+
+#[derive(Debug, Clone)]
+pub struct CapabilityError {
+    pub name: String,
+}
+
+impl fmt::Display for CapabilityError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "unknown capability: {}", self.name)
+    }
+}
+
+/// A single linux capability, e.g. `CAP_NET_BIND_SERVICE`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Capability(u8);
+
+impl Capability {
+    /// Looks up a capability by name.
+    ///
+    /// The name is case insensitive and the `cap_` prefix is optional,
+    /// so `CAP_NET_RAW`, `cap_net_raw` and `net_raw` are equivalent.
+    pub fn from_name(name: &str) -> Result<Capability, CapabilityError> {
+        let lower = name.trim().to_lowercase();
+        let short = lower.strip_prefix("cap_").unwrap_or(&lower);
+
+        match CAPABILITIES.iter().position(|cap| *cap == short) {
+            Some(number) => Ok(Capability(number as u8)),
+            None => Err(CapabilityError {
+                name: name.to_string(),
+            }),
+        }
+    }
+}
+
+impl fmt::Display for Capability {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "cap_{}", CAPABILITIES[self.0 as usize])
+    }
+}
+
+/// Parses a comma separated capability whitelist (e.g. `net_raw,sys_time`).
+///
+/// An empty list is valid and drops all capabilities.
+pub fn parse_capabilities(list: &str) -> Result<Vec<Capability>, CapabilityError> {
+    list.split(',')
+        .filter(|name| !name.trim().is_empty())
+        .map(Capability::from_name)
+        .collect()
+}
+
+/// Restricts the process spawned by `command` to the `keep` capabilities.
+///
+/// Right before the exec, all other capabilities are dropped from the
+/// bounding set and the effective, permitted and inheritable sets of the
+/// child. linµos itself keeps running with all capabilities.
+///
+/// # Arguments
+///
+/// * `command` - The command to restrict.
+/// * `keep` - The capabilities the child is allowed to keep.
+pub fn restrict(command: &mut Command, keep: &[Capability]) {
+    // the mask is computed up front, as the pre exec hook
+    // runs in the forked child and should not allocate
+    let mut mask = [0u32; 2];
+    keep.iter().for_each(|cap| {
+        mask[cap.0 as usize / 32] |= 1 << (cap.0 % 32);
+    });
+
+    unsafe {
+        command.pre_exec(move || drop_capabilities(mask));
+    }
+}
+
+/// Drops all capabilities not contained in `mask` from the calling process.
+fn drop_capabilities(mask: [u32; 2]) -> io::Result<()> {
+    for cap in 0..64u32 {
+        if mask[cap as usize / 32] & (1 << (cap % 32)) != 0 {
+            continue;
+        }
+        let res = unsafe { libc::prctl(libc::PR_CAPBSET_DROP, cap as libc::c_ulong, 0, 0, 0) };
+        if res != 0 {
+            let err = io::Error::last_os_error();
+            // the running kernel does not know about this capability
+            if err.raw_os_error() == Some(libc::EINVAL) {
+                continue;
+            }
+            return Err(err);
+        }
+    }
+
+    let mut header = CapUserHeader {
+        version: CAPABILITY_VERSION_3,
+        pid: 0,
+    };
+    let mut data = [CapUserData::default(); 2];
+    for (i, word) in mask.iter().enumerate() {
+        data[i] = CapUserData {
+            effective: *word,
+            permitted: *word,
+            inheritable: *word,
+        };
+    }
+
+    let res = unsafe {
+        libc::syscall(
+            libc::SYS_capset,
+            &mut header as *mut CapUserHeader,
+            data.as_mut_ptr(),
+        )
+    };
+    if res != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn looks_up_known_names() {
+        for name in ["CAP_NET_RAW", "cap_net_raw", "net_raw", " Net_Raw "] {
+            assert_eq!(
+                Capability::from_name(name).unwrap(),
+                Capability(13),
+                "{}",
+                name
+            );
+        }
+        assert_eq!(Capability::from_name("chown").unwrap(), Capability(0));
+        assert_eq!(
+            Capability::from_name("checkpoint_restore").unwrap(),
+            Capability(40)
+        );
+        assert_eq!(Capability(10).to_string(), "cap_net_bind_service");
+    }
+
+    #[test]
+    fn rejects_unknown_names() {
+        for name in ["", "cap_", "net-raw", "cap_cap_net_raw", "superuser"] {
+            assert_eq!(
+                Capability::from_name(name).unwrap_err().name,
+                name,
+                "{}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn parses_capability_lists() {
+        assert_eq!(
+            parse_capabilities("net_raw, CAP_SYS_TIME").unwrap(),
+            [Capability(13), Capability(25)]
+        );
+        // an empty list drops all capabilities
+        assert!(parse_capabilities("").unwrap().is_empty());
+        assert!(parse_capabilities(",,").unwrap().is_empty());
+        assert_eq!(parse_capabilities("net_raw,nope").unwrap_err().name, "nope");
+    }
+}
@@ -0,0 +1,928 @@
+//! Parsing of the kernel command line.
+use core::fmt;
+use std::{
+    fs, io,
+    net::{Ipv4Addr, SocketAddr},
+    time::Duration,
+};
+
+use pnet::util::MacAddr;
+
+use crate::{
+    caps::{self, Capability, CapabilityError},
+    debugshell,
+    fs::{
+        cgroup::CgroupMode,
+        fsck::CorrectedPolicy,
+        mount::{self, EXTRA_FILESYSTEMS},
+        nfs::{self, NfsRoot},
+    },
+    hostname::{self, Hostname},
+    logbuf,
+    lsm::{self, Policy},
+    net::{dhcp, networkd},
+    phase::{self, Phase},
+    power::ExitAction,
+    priority,
+};
+
+/// Root device used when the kernel command line does not specify one.
+///
+/// Set at build time via the `LINOS_DEFAULT_ROOT` environment variable
+/// (e.g. `LINOS_DEFAULT_ROOT=/dev/vda cargo build`), unset by default.
+pub const DEFAULT_ROOT: Option<&str> = option_env!("LINOS_DEFAULT_ROOT");
+
+#[derive(Debug)]
+pub enum CmdlineError {
+    /// `/proc/cmdline` could not be read.
+    Read(io::Error),
+    /// The command line is empty or contains only whitespace.
+    Empty,
+    /// No `root=` argument was given and there is no `DEFAULT_ROOT`.
+    NoRoot,
+    /// The `caps=` argument contains an unknown capability.
+    InvalidCaps(CapabilityError),
+    /// The `dhcp.vendor=` argument is empty or too long.
+    InvalidVendorClass(io::Error),
+    /// A `linos.pause=` argument is not of the form `phase:secs`.
+    InvalidPause(String),
+    /// The `break=` argument is no phase.
+    InvalidBreak(String),
+    /// The `ntp=` argument contains an invalid IPv4 address.
+    InvalidNtp(String),
+    /// The `mount.extra=` argument contains an unknown filesystem.
+    InvalidMountExtra(String),
+    /// The `dnssearch=` argument contains an invalid domain.
+    InvalidDnsSearch(String),
+    /// The `dhcp.parallel=` argument is not a positive number.
+    InvalidDhcpParallel(String),
+    /// An `onexit=` or `oncrash=` argument is no known action.
+    InvalidExitAction(&'static str, String),
+    /// The `skip=` argument contains an unknown or unskippable phase.
+    InvalidSkip(String),
+    /// The `boot.attempts=` argument is not a positive number.
+    InvalidBootAttempts(String),
+    /// The `dhcp.server=` argument is no IPv4 address.
+    InvalidDhcpServer(String),
+    /// The `dhcp.maxsize=` argument is not a number within 576 and 65535.
+    InvalidDhcpMaxSize(String),
+    /// The `dhcp.leasetime=` argument is not a positive number.
+    InvalidDhcpLeaseTime(String),
+    /// The `dhcp.csum=` argument is neither `none` nor `full`.
+    InvalidDhcpCsum(String),
+    /// The `dhcp.transport=` argument is no known transport.
+    InvalidDhcpTransport(String),
+    /// The `hostname=` argument is no valid hostname.
+    InvalidHostname(String),
+    /// The `hostname.persist=` argument is no absolute path.
+    InvalidHostnamePersist(String),
+    /// The `nice=` argument is not a number within -20 and 19.
+    InvalidNice(String),
+    /// The `cgroup=` argument is no known cgroup layout.
+    InvalidCgroup(String),
+    /// The `fsck.corrected=` argument is no known policy.
+    InvalidFsckCorrected(String),
+    /// The `log.tmpfs=` argument is no valid tmpfs size.
+    InvalidLogTmpfs(String),
+    /// The `log.lines=` argument is not a number.
+    InvalidLogLines(String),
+    /// The `BOOTIF=` argument is not of the form `<type>-<mac address>`.
+    InvalidBootIf(String),
+    /// `root=` selects an NFS root, but it is missing or invalid.
+    InvalidNfsRoot(String),
+    /// The `debugshell=` argument is no terminal name.
+    InvalidDebugShell(String),
+    /// The `net.check=` argument is not of the form `<ip>:<port>`.
+    InvalidNetCheck(String),
+    /// The `dnscheck=` argument is no valid domain name.
+    InvalidDnsCheck(String),
+}
+
+impl fmt::Display for CmdlineError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CmdlineError::Read(err) => write!(f, "could not read /proc/cmdline: {}", err),
+            CmdlineError::Empty => write!(
+                f,
+                "the kernel command line is empty, at least 'root=' is required"
+            ),
+            CmdlineError::NoRoot => write!(f, "no root device specified ('root=' is missing)"),
+            CmdlineError::InvalidCaps(err) => write!(f, "invalid 'caps=' argument: {}", err),
+            CmdlineError::InvalidVendorClass(err) => {
+                write!(f, "invalid 'dhcp.vendor=' argument: {}", err)
+            }
+            CmdlineError::InvalidNtp(server) => {
+                write!(
+                    f,
+                    "invalid 'ntp=' argument, '{}' is no IPv4 address",
+                    server
+                )
+            }
+            CmdlineError::InvalidMountExtra(name) => write!(
+                f,
+                "invalid 'mount.extra=' argument, unknown filesystem '{}', expected one of {}",
+                name,
+                EXTRA_FILESYSTEMS.map(|fs| fs.0).join(", ")
+            ),
+            CmdlineError::InvalidDnsSearch(domain) => {
+                write!(
+                    f,
+                    "invalid 'dnssearch=' argument, '{}' is no domain",
+                    domain
+                )
+            }
+            CmdlineError::InvalidDhcpParallel(value) => write!(
+                f,
+                "invalid 'dhcp.parallel={}' argument, expected a positive number",
+                value
+            ),
+            CmdlineError::InvalidExitAction(key, value) => write!(
+                f,
+                "invalid '{}={}' argument, expected one of {}",
+                key,
+                value,
+                ExitAction::ALL.map(|action| action.name()).join(", ")
+            ),
+            CmdlineError::InvalidSkip(name) => write!(
+                f,
+                "invalid 'skip=' argument, '{}' is none of the skippable phases {}",
+                name,
+                Phase::ALL
+                    .iter()
+                    .filter(|phase| phase.is_skippable())
+                    .map(|phase| phase.name())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            CmdlineError::InvalidBootAttempts(value) => write!(
+                f,
+                "invalid 'boot.attempts={}' argument, expected a positive number",
+                value
+            ),
+            CmdlineError::InvalidDhcpServer(server) => write!(
+                f,
+                "invalid 'dhcp.server=' argument, '{}' is no IPv4 address",
+                server
+            ),
+            CmdlineError::InvalidDhcpMaxSize(value) => write!(
+                f,
+                "invalid 'dhcp.maxsize={}' argument, expected a number from {} to {}",
+                value,
+                dhcp::MIN_MAX_MESSAGE_SIZE,
+                u16::MAX
+            ),
+            CmdlineError::InvalidDhcpLeaseTime(value) => write!(
+                f,
+                "invalid 'dhcp.leasetime={}' argument, expected a positive number of seconds",
+                value
+            ),
+            CmdlineError::InvalidDhcpCsum(value) => write!(
+                f,
+                "invalid 'dhcp.csum={}' argument, expected 'none' or 'full'",
+                value
+            ),
+            CmdlineError::InvalidDhcpTransport(value) => write!(
+                f,
+                "invalid 'dhcp.transport={}' argument, expected one of {}",
+                value,
+                dhcp::DhcpTransport::ALL
+                    .map(|transport| transport.name())
+                    .join(", ")
+            ),
+            CmdlineError::InvalidHostnamePersist(path) => write!(
+                f,
+                "invalid 'hostname.persist={}' argument, expected an absolute path or 'none'",
+                path
+            ),
+            CmdlineError::InvalidHostname(hostname) => write!(
+                f,
+                "invalid 'hostname=' argument, '{}' is no valid hostname",
+                hostname
+            ),
+            CmdlineError::InvalidNice(value) => write!(
+                f,
+                "invalid 'nice={}' argument, expected a number from {} to {}",
+                value,
+                priority::NICE_RANGE.start(),
+                priority::NICE_RANGE.end()
+            ),
+            CmdlineError::InvalidCgroup(value) => write!(
+                f,
+                "invalid 'cgroup={}' argument, expected one of {}",
+                value,
+                CgroupMode::ALL.map(|mode| mode.name()).join(", ")
+            ),
+            CmdlineError::InvalidFsckCorrected(value) => write!(
+                f,
+                "invalid 'fsck.corrected={}' argument, expected one of {}",
+                value,
+                CorrectedPolicy::ALL.map(|policy| policy.name()).join(", ")
+            ),
+            CmdlineError::InvalidLogTmpfs(size) => write!(
+                f,
+                "invalid 'log.tmpfs={}' argument, expected a size like '64m' or '10%'",
+                size
+            ),
+            CmdlineError::InvalidLogLines(value) => write!(
+                f,
+                "invalid 'log.lines={}' argument, expected a number",
+                value
+            ),
+            CmdlineError::InvalidBootIf(value) => write!(
+                f,
+                "invalid 'BOOTIF={}' argument, expected e.g. '01-52-54-00-12-34-56'",
+                value
+            ),
+            CmdlineError::InvalidNfsRoot(root) => write!(
+                f,
+                "invalid NFS root '{}', expected 'root=/dev/nfs nfsroot=<ip>:<path>[,<options>]' \
+                 or 'root=nfs:<ip>:<path>[:<options>]'",
+                root
+            ),
+            CmdlineError::InvalidDebugShell(tty) => write!(
+                f,
+                "invalid 'debugshell={}' argument, expected a terminal like 'tty2'",
+                tty
+            ),
+            CmdlineError::InvalidNetCheck(endpoint) => write!(
+                f,
+                "invalid 'net.check={}' argument, expected '<ip>:<port>'",
+                endpoint
+            ),
+            CmdlineError::InvalidDnsCheck(host) => write!(
+                f,
+                "invalid 'dnscheck={}' argument, expected a domain name",
+                host
+            ),
+            CmdlineError::InvalidPause(spec) => write!(
+                f,
+                "invalid 'linos.pause={}' argument, expected 'phase:secs' with phase one of {}",
+                spec,
+                Phase::ALL.map(|phase| phase.name()).join(", ")
+            ),
+            CmdlineError::InvalidBreak(name) => write!(
+                f,
+                "invalid 'break={}' argument, expected one of {}",
+                name,
+                Phase::ALL.map(|phase| phase.name()).join(", ")
+            ),
+        }
+    }
+}
+
+/// Represents arguments parsed from
+/// the kernel command line.
+#[derive(Debug)]
+pub struct Cmdline {
+    /// The console the kernel writes to, the last `console=` argument.
+    pub console: Option<String>,
+    /// The hostname of the system or `dhcp` (`hostname=`).
+    pub hostname: Option<Hostname>,
+    /// The file the hostname is persisted to, unless `hostname.persist=none`.
+    pub hostname_persist: Option<String>,
+    /// The terminal a debug shell runs on during the boot (`debugshell=`).
+    pub debug_shell: Option<String>,
+    pub quiet: bool,
+    pub root: String,
+    /// The NFS export mounted as root (`root=/dev/nfs`, `root=nfs:`).
+    pub nfs_root: Option<NfsRoot>,
+    pub dhcp_rapid: bool,
+    pub net_required: bool,
+    /// The endpoint connected to, to check the network works (`net.check=`).
+    pub net_check: Option<SocketAddr>,
+    /// The name resolved, to check that DNS works (`dnscheck=`).
+    pub dns_check: Option<String>,
+    pub nsswitch: bool,
+    pub net_monitor: bool,
+    pub net_force: bool,
+    pub container: bool,
+    pub etc_overlay: bool,
+    /// Only starts the services marked for maintenance (`maintenance`, `single`).
+    pub maintenance: bool,
+    pub mount_extra: Vec<String>,
+    /// The size of the tmpfs mounted at `/var/log`, if any.
+    pub log_tmpfs: Option<String>,
+    /// The number of log lines kept for the `log` control query, `0` keeps none.
+    pub log_lines: usize,
+    pub dhcp_vendor: Option<String>,
+    pub dhcp_iface: Option<String>,
+    /// The mac address of the interface PXE booted from (`BOOTIF=`).
+    pub boot_if: Option<MacAddr>,
+    pub dhcp_parallel: Option<usize>,
+    pub dhcp_max_size: Option<u16>,
+    /// The lease time in seconds requested from the DHCP server (`dhcp.leasetime=`).
+    pub dhcp_lease_time: Option<u32>,
+    /// Sends DHCP messages with a zero UDP checksum (`dhcp.csum=none`).
+    pub dhcp_csum_none: bool,
+    /// How the DHCP messages are sent and received (`dhcp.transport=`).
+    pub dhcp_transport: dhcp::DhcpTransport,
+    pub dhcp_server: Option<Ipv4Addr>,
+    pub ntp: Vec<Ipv4Addr>,
+    pub dns_search: Vec<String>,
+    pub pauses: Vec<(Phase, Duration)>,
+    /// The phases to drop to a shell before (`break=`, `rd.break=`).
+    pub breaks: Vec<Phase>,
+    pub skip: Vec<Phase>,
+    /// The action once the handed-off process exited cleanly.
+    pub on_exit: ExitAction,
+    /// The action once the handed-off process crashed.
+    pub on_crash: ExitAction,
+    /// The number of boot attempts before giving up to a rescue shell.
+    pub boot_attempts: Option<u32>,
+    /// The MAC policies to load (`selinux=1`, `apparmor=1`).
+    pub mac_policies: Vec<Policy>,
+    /// Drops to a rescue shell instead of halting if a MAC policy fails to load.
+    pub mac_rescue: bool,
+    /// The cgroup layout mounted at `/sys/fs/cgroup` (`cgroup=`).
+    pub cgroup: CgroupMode,
+    /// What to do if fsck corrected errors and advises a reboot.
+    pub fsck_corrected: CorrectedPolicy,
+    /// Excludes linµos from the OOM killer, unless `oom.protect=0` is given.
+    pub oom_protect: bool,
+    /// The nice value of linµos and the processes it starts.
+    pub nice: Option<i32>,
+    pub caps: Option<Vec<Capability>>,
+    /// All arguments, for the boot phases to look up their own arguments
+    /// (e.g. `modules=` and `<module>.<param>=`).
+    pub args: CmdlineArgs,
+}
+
+/// Parses the UDP checksum of the DHCP messages (`dhcp.csum=`).
+///
+/// # Returns
+///
+/// * `Option<bool>` - Whether the checksum is zero (`none`) instead of
+///   computed (`full`), or `None` if the value is neither.
+pub(crate) fn parse_dhcp_csum(value: &str) -> Option<bool> {
+    match value {
+        "none" => Some(true),
+        "full" => Some(false),
+        _ => None,
+    }
+}
+
+/// Checks if `domain` is a valid domain name.
+///
+/// Each label has to be 1 to 63 letters, digits or hyphens,
+/// not starting or ending with a hyphen.
+pub(crate) fn is_valid_domain(domain: &str) -> bool {
+    let domain = domain.strip_suffix('.').unwrap_or(domain);
+    domain.len() <= 253
+        && domain.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        })
+}
+
+/// The arguments of the kernel command line, in order.
+///
+/// Kept in the parsed `Cmdline`, so later boot phases can look up
+/// their own arguments without reading `/proc/cmdline` again.
+#[derive(Debug, Clone, Default)]
+pub struct CmdlineArgs {
+    args: Vec<String>,
+}
+
+impl CmdlineArgs {
+    /// Splits the kernel command line into its arguments.
+    pub fn new(contents: &str) -> CmdlineArgs {
+        CmdlineArgs {
+            args: contents
+                .split_whitespace()
+                .map(|arg| arg.to_string())
+                .collect(),
+        }
+    }
+
+    /// Iterates over the arguments in order.
+    pub fn iter(&self) -> impl Iterator<Item = &str> {
+        self.args.iter().map(|arg| arg.as_str())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.args.is_empty()
+    }
+
+    /// Checks if the argument `arg` (e.g. `net.force=1`) is given.
+    pub fn contains(&self, arg: &str) -> bool {
+        self.iter().any(|a| a == arg)
+    }
+
+    /// Checks if the argument `key` is given, with or without a value.
+    pub fn has(&self, key: &str) -> bool {
+        self.iter().any(|arg| {
+            arg.strip_prefix(key)
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with('='))
+        })
+    }
+
+    /// Returns the values of all `key=<value>` arguments in order.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key without the `=` (e.g. `linos.pause`).
+    pub fn all(&self, key: &str) -> Vec<&str> {
+        self.iter()
+            .filter_map(|arg| arg.strip_prefix(key)?.strip_prefix('='))
+            .collect()
+    }
+
+    /// Returns the value of the last `key=<value>` argument, as
+    /// later arguments override earlier ones (e.g. `root=`).
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.all(key).pop()
+    }
+
+    /// Returns the comma separated values of all `key=<value>` arguments, as
+    /// list arguments accumulate (e.g. `ntp=a ntp=b` is the same as `ntp=a,b`).
+    ///
+    /// Empty entries (e.g. `dnssearch=a.com,,b.com,`) are ignored.
+    pub fn list(&self, key: &str) -> Vec<&str> {
+        self.all(key)
+            .into_iter()
+            .flat_map(|value| value.split(','))
+            .filter(|value| !value.is_empty())
+            .collect()
+    }
+}
+
+/// Parses the given kernel command line.
+///
+/// Arguments given more than once are resolved per key: single valued
+/// arguments (e.g. `root=`, `console=`, `caps=`) use the last value, list
+/// arguments (e.g. `modules=`, `ntp=`, `linos.pause=`) accumulate all values.
+///
+/// If `root=` is missing and linµos was built with a `DEFAULT_ROOT`,
+/// the default root is used instead of failing.
+///
+/// # Arguments
+///
+/// * `contents` - The kernel command line (e.g. `root=/dev/vda quiet`).
+pub fn parse_cmdline(contents: &str) -> Result<Cmdline, CmdlineError> {
+    let args = CmdlineArgs::new(contents);
+
+    let root = match args.get("root") {
+        Some(root) => root.to_string(),
+        None => match DEFAULT_ROOT {
+            Some(root) => root.to_string(),
+            None if args.is_empty() => return Err(CmdlineError::Empty),
+            None => return Err(CmdlineError::NoRoot),
+        },
+    };
+
+    let nfs_root = nfs::parse(&root, args.get("nfsroot")).ok_or_else(|| {
+        CmdlineError::InvalidNfsRoot(args.get("nfsroot").unwrap_or(&root).to_string())
+    })?;
+
+    let console = args.get("console").map(|console| console.to_string());
+
+    let hostname = match args.get("hostname") {
+        Some(value) => Some(
+            Hostname::parse(value)
+                .ok_or_else(|| CmdlineError::InvalidHostname(value.to_string()))?,
+        ),
+        None => None,
+    };
+
+    let hostname_persist = match args.get("hostname.persist") {
+        Some(value) => hostname::parse_persist(value)
+            .ok_or_else(|| CmdlineError::InvalidHostnamePersist(value.to_string()))?,
+        None => Some(hostname::PERSIST_FILE.to_string()),
+    };
+
+    let debug_shell = match args.get("debugshell") {
+        Some(tty) if debugshell::is_valid_tty(tty) => Some(tty.to_string()),
+        Some(tty) => return Err(CmdlineError::InvalidDebugShell(tty.to_string())),
+        None => None,
+    };
+
+    let quiet = args.iter().any(|arg| arg.starts_with("quiet"));
+
+    let dhcp_rapid = args.contains("dhcp.rapid=1");
+    let net_required = args.contains("net.required=1");
+    let nsswitch = args.contains("nsswitch=1");
+    let net_monitor = args.contains("net.monitor=1");
+    let net_force = args.contains("net.force=1");
+    let container = args.contains("container=1");
+    let etc_overlay = args.contains("etc.overlay=1");
+    let maintenance = args.contains("maintenance") || args.contains("single");
+
+    let net_check = match args.get("net.check") {
+        Some(endpoint) => Some(
+            endpoint
+                .parse::<SocketAddr>()
+                .map_err(|_| CmdlineError::InvalidNetCheck(endpoint.to_string()))?,
+        ),
+        None => None,
+    };
+
+    let dns_check = match args.get("dnscheck") {
+        Some(host) if is_valid_domain(host) => Some(host.to_string()),
+        Some(host) => return Err(CmdlineError::InvalidDnsCheck(host.to_string())),
+        None => None,
+    };
+
+    let dhcp_vendor = match args.get("dhcp.vendor") {
+        Some(vendor_class) => {
+            dhcp::validate_vendor_class(vendor_class).map_err(CmdlineError::InvalidVendorClass)?;
+            Some(vendor_class.to_string())
+        }
+        None => None,
+    };
+
+    let mount_extra = args
+        .list("mount.extra")
+        .into_iter()
+        .map(|name| {
+            if EXTRA_FILESYSTEMS.iter().any(|fs| fs.0 == name) {
+                Ok(name.to_string())
+            } else {
+                Err(CmdlineError::InvalidMountExtra(name.to_string()))
+            }
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let log_tmpfs = match args.get("log.tmpfs") {
+        Some(size) if mount::is_valid_tmpfs_size(size) => Some(size.to_string()),
+        Some(size) => return Err(CmdlineError::InvalidLogTmpfs(size.to_string())),
+        None => None,
+    };
+
+    let log_lines = match args.get("log.lines") {
+        Some(value) => value
+            .parse::<usize>()
+            .map_err(|_| CmdlineError::InvalidLogLines(value.to_string()))?,
+        None => logbuf::DEFAULT_LOG_LINES,
+    };
+
+    let dhcp_iface = args
+        .get("dhcp.iface")
+        .filter(|iface| !iface.is_empty())
+        .map(|iface| iface.to_string());
+
+    let boot_if = match args.get("BOOTIF") {
+        Some(value) => Some(
+            networkd::parse_bootif(value)
+                .ok_or_else(|| CmdlineError::InvalidBootIf(value.to_string()))?,
+        ),
+        None => None,
+    };
+
+    let dhcp_parallel = match args.get("dhcp.parallel") {
+        Some(value) => match value.parse::<usize>() {
+            Ok(parallel) if parallel > 0 => Some(parallel),
+            _ => return Err(CmdlineError::InvalidDhcpParallel(value.to_string())),
+        },
+        None => None,
+    };
+
+    let dhcp_max_size = match args.get("dhcp.maxsize") {
+        Some(value) => match value.parse::<u16>() {
+            Ok(size) if size >= dhcp::MIN_MAX_MESSAGE_SIZE => Some(size),
+            _ => return Err(CmdlineError::InvalidDhcpMaxSize(value.to_string())),
+        },
+        None => None,
+    };
+
+    let dhcp_lease_time = match args.get("dhcp.leasetime") {
+        Some(value) => Some(
+            dhcp::parse_lease_time(value)
+                .ok_or_else(|| CmdlineError::InvalidDhcpLeaseTime(value.to_string()))?,
+        ),
+        None => None,
+    };
+
+    let dhcp_csum_none = match args.get("dhcp.csum") {
+        Some(value) => parse_dhcp_csum(value)
+            .ok_or_else(|| CmdlineError::InvalidDhcpCsum(value.to_string()))?,
+        None => false,
+    };
+
+    let dhcp_transport = match args.get("dhcp.transport") {
+        Some(name) => dhcp::DhcpTransport::parse(name)
+            .ok_or_else(|| CmdlineError::InvalidDhcpTransport(name.to_string()))?,
+        None => dhcp::DhcpTransport::Raw,
+    };
+
+    let dhcp_server = match args.get("dhcp.server") {
+        Some(server) => Some(
+            server
+                .parse::<Ipv4Addr>()
+                .map_err(|_| CmdlineError::InvalidDhcpServer(server.to_string()))?,
+        ),
+        None => None,
+    };
+
+    let ntp = args
+        .list("ntp")
+        .into_iter()
+        .map(|server| {
+            server
+                .parse::<Ipv4Addr>()
+                .map_err(|_| CmdlineError::InvalidNtp(server.to_string()))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let dns_search = args
+        .list("dnssearch")
+        .into_iter()
+        .map(|domain| {
+            if is_valid_domain(domain) {
+                Ok(domain.to_string())
+            } else {
+                Err(CmdlineError::InvalidDnsSearch(domain.to_string()))
+            }
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let pauses = args
+        .all("linos.pause")
+        .into_iter()
+        .map(|spec| {
+            phase::parse_pause(spec).ok_or_else(|| CmdlineError::InvalidPause(spec.to_string()))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // the dracut spelling is accepted for muscle memory
+    let mut breaks = args.list("rd.break");
+    breaks.extend(args.list("break"));
+    let breaks = phase::parse_breaks(&breaks)
+        .map_err(|name| CmdlineError::InvalidBreak(name.to_string()))?;
+
+    let skip = args
+        .list("skip")
+        .into_iter()
+        .map(|name| {
+            Phase::from_name(name)
+                .filter(|phase| phase.is_skippable())
+                .ok_or_else(|| CmdlineError::InvalidSkip(name.to_string()))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let exit_action = |key: &'static str, default: ExitAction| match args.get(key) {
+        Some(name) => ExitAction::parse(name)
+            .ok_or_else(|| CmdlineError::InvalidExitAction(key, name.to_string())),
+        None => Ok(default),
+    };
+    let on_exit = exit_action("onexit", ExitAction::DEFAULT_ON_EXIT)?;
+    let on_crash = exit_action("oncrash", ExitAction::DEFAULT_ON_CRASH)?;
+
+    let boot_attempts = match args.get("boot.attempts") {
+        Some(value) => match value.parse::<u32>() {
+            Ok(attempts) if attempts > 0 => Some(attempts),
+            _ => return Err(CmdlineError::InvalidBootAttempts(value.to_string())),
+        },
+        None => None,
+    };
+
+    let mut mac_policies = Vec::new();
+    if args.contains("selinux=1") {
+        let path = args
+            .get("selinux.policy")
+            .unwrap_or(lsm::DEFAULT_SELINUX_POLICY);
+        mac_policies.push(Policy::SELinux(path.to_string()));
+    }
+    if args.contains("apparmor=1") {
+        let path = args
+            .get("apparmor.profiles")
+            .unwrap_or(lsm::DEFAULT_APPARMOR_PROFILES);
+        mac_policies.push(Policy::AppArmor(path.to_string()));
+    }
+    // anything but an explicit rescue halts, a confined system is not optional
+    let mac_rescue = args.get("mac.onfail") == Some("rescue");
+
+    let cgroup = match args.get("cgroup") {
+        Some(name) => {
+            CgroupMode::parse(name).ok_or_else(|| CmdlineError::InvalidCgroup(name.to_string()))?
+        }
+        None => CgroupMode::V2,
+    };
+
+    let fsck_corrected = match args.get("fsck.corrected") {
+        Some(name) => CorrectedPolicy::parse(name)
+            .ok_or_else(|| CmdlineError::InvalidFsckCorrected(name.to_string()))?,
+        None => CorrectedPolicy::Reboot,
+    };
+
+    // an OOM killed init panics the kernel, so only an explicit opt-out disables it
+    let oom_protect = args.get("oom.protect") != Some("0");
+
+    let nice = match args.get("nice") {
+        Some(value) => match value.parse::<i32>() {
+            Ok(nice) if priority::NICE_RANGE.contains(&nice) => Some(nice),
+            _ => return Err(CmdlineError::InvalidNice(value.to_string())),
+        },
+        None => None,
+    };
+
+    let caps = match args.get("caps") {
+        Some(caps) => Some(caps::parse_capabilities(caps).map_err(CmdlineError::InvalidCaps)?),
+        None => None,
+    };
+
+    Ok(Cmdline {
+        console,
+        hostname,
+        hostname_persist,
+        debug_shell,
+        quiet,
+        root,
+        nfs_root,
+        dhcp_rapid,
+        net_required,
+        net_check,
+        dns_check,
+        nsswitch,
+        net_monitor,
+        net_force,
+        container,
+        etc_overlay,
+        maintenance,
+        mount_extra,
+        log_tmpfs,
+        log_lines,
+        dhcp_vendor,
+        dhcp_iface,
+        boot_if,
+        dhcp_parallel,
+        dhcp_max_size,
+        dhcp_lease_time,
+        dhcp_csum_none,
+        dhcp_transport,
+        dhcp_server,
+        ntp,
+        dns_search,
+        pauses,
+        breaks,
+        skip,
+        on_exit,
+        on_crash,
+        boot_attempts,
+        mac_policies,
+        mac_rescue,
+        cgroup,
+        fsck_corrected,
+        oom_protect,
+        nice,
+        caps,
+        args,
+    })
+}
+
+/// Parses the kernel command line by reading `/proc/cmdline`.
+///
+/// The proc filesystem must be mounted before running
+/// this function.
+pub fn read_cmdline() -> Result<Cmdline, CmdlineError> {
+    let contents = fs::read_to_string("/proc/cmdline").map_err(CmdlineError::Read)?;
+    parse_cmdline(&contents)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_cmdline_is_an_error() {
+        for contents in ["", "  \n\t "] {
+            match (parse_cmdline(contents), DEFAULT_ROOT) {
+                (Err(CmdlineError::Empty), None) => {}
+                (Ok(cmdline), Some(root)) => assert_eq!(cmdline.root, root),
+                (result, _) => panic!("unexpected result for {:?}: {:?}", contents, result),
+            }
+        }
+        if DEFAULT_ROOT.is_none() {
+            assert!(matches!(parse_cmdline("quiet"), Err(CmdlineError::NoRoot)));
+        }
+    }
+
+    #[test]
+    fn duplicate_arguments_are_resolved_per_key() {
+        let cmdline = parse_cmdline(
+            "root=/dev/vda console=tty0 ntp=10.0.0.1 root=/dev/vdb \
+             console=ttyS0,115200 ntp=10.0.0.2,10.0.0.3 dnssearch=a.com,,b.com,",
+        )
+        .unwrap();
+
+        assert_eq!(cmdline.root, "/dev/vdb");
+        assert_eq!(cmdline.console.as_deref(), Some("ttyS0,115200"));
+        assert_eq!(
+            cmdline.ntp,
+            [
+                Ipv4Addr::new(10, 0, 0, 1),
+                Ipv4Addr::new(10, 0, 0, 2),
+                Ipv4Addr::new(10, 0, 0, 3)
+            ]
+        );
+        assert_eq!(cmdline.dns_search, ["a.com", "b.com"]);
+    }
+
+    #[test]
+    fn parses_net_check_endpoint() {
+        let cmdline = parse_cmdline("root=/dev/vda net.check=10.0.0.1:443").unwrap();
+        assert_eq!(
+            cmdline.net_check,
+            Some(SocketAddr::from((Ipv4Addr::new(10, 0, 0, 1), 443)))
+        );
+
+        for invalid in ["10.0.0.1", "example.com:443", "10.0.0.1:http"] {
+            assert!(matches!(
+                parse_cmdline(&format!("root=/dev/vda net.check={}", invalid)),
+                Err(CmdlineError::InvalidNetCheck(endpoint)) if endpoint == invalid
+            ));
+        }
+    }
+
+    #[test]
+    fn parses_dhcp_lease_time() {
+        assert_eq!(
+            parse_cmdline("root=/dev/vda").unwrap().dhcp_lease_time,
+            None
+        );
+        assert_eq!(
+            parse_cmdline("root=/dev/vda dhcp.leasetime=3600")
+                .unwrap()
+                .dhcp_lease_time,
+            Some(3600)
+        );
+
+        for invalid in ["", "0", "-1", "1h", "4294967296"] {
+            assert!(matches!(
+                parse_cmdline(&format!("root=/dev/vda dhcp.leasetime={}", invalid)),
+                Err(CmdlineError::InvalidDhcpLeaseTime(value)) if value == invalid
+            ));
+        }
+    }
+
+    #[test]
+    fn parses_dhcp_checksum() {
+        assert!(!parse_cmdline("root=/dev/vda").unwrap().dhcp_csum_none);
+        assert!(
+            parse_cmdline("root=/dev/vda dhcp.csum=none")
+                .unwrap()
+                .dhcp_csum_none
+        );
+        // the last argument wins
+        assert!(
+            !parse_cmdline("root=/dev/vda dhcp.csum=none dhcp.csum=full")
+                .unwrap()
+                .dhcp_csum_none
+        );
+
+        for invalid in ["", "zero", "NONE", "0"] {
+            assert!(matches!(
+                parse_cmdline(&format!("root=/dev/vda dhcp.csum={}", invalid)),
+                Err(CmdlineError::InvalidDhcpCsum(value)) if value == invalid
+            ));
+        }
+    }
+
+    #[test]
+    fn parses_dhcp_transport() {
+        assert_eq!(
+            parse_cmdline("root=/dev/vda").unwrap().dhcp_transport,
+            dhcp::DhcpTransport::Raw
+        );
+        assert_eq!(
+            parse_cmdline("root=/dev/vda dhcp.transport=udp")
+                .unwrap()
+                .dhcp_transport,
+            dhcp::DhcpTransport::Udp
+        );
+
+        for invalid in ["", "tcp", "UDP"] {
+            assert!(matches!(
+                parse_cmdline(&format!("root=/dev/vda dhcp.transport={}", invalid)),
+                Err(CmdlineError::InvalidDhcpTransport(value)) if value == invalid
+            ));
+        }
+    }
+
+    #[test]
+    fn breaks_accumulate_both_spellings() {
+        let cmdline = parse_cmdline("root=/dev/vda break=network rd.break=mount,network").unwrap();
+        assert_eq!(cmdline.breaks, [Phase::Mount, Phase::Network]);
+
+        assert!(matches!(
+            parse_cmdline("root=/dev/vda break=pre-mount"),
+            Err(CmdlineError::InvalidBreak(name)) if name == "pre-mount"
+        ));
+    }
+
+    #[test]
+    fn args_match_whole_keys() {
+        let args = CmdlineArgs::new("quiet net.force=1 root=/dev/vda rootfstype=ext4");
+
+        assert!(args.has("quiet"));
+        assert!(args.has("root"));
+        assert!(!args.has("net"));
+        assert!(args.contains("net.force=1"));
+        assert!(!args.contains("net.force"));
+        assert_eq!(args.all("root"), ["/dev/vda"]);
+        assert_eq!(args.get("rootfstype"), Some("ext4"));
+        assert_eq!(args.get("fstype"), None);
+    }
+}
@@ -0,0 +1,524 @@
+//! The init configuration file.
+//!
+//! `/etc/linos/init.toml` describes the system in one place, e.g.:
+//!
+//! ```toml
+//! hostname = "node-1"
+//! hostname_persist = "/data/hostname"
+//! nice = -5
+//!
+//! [log]
+//! quiet = true
+//! tmpfs = "64m"
+//! lines = 5000
+//!
+//! [mount]
+//! extra = ["bpf", "tracefs"]
+//! etc_overlay = true
+//!
+//! [network]
+//! dhcp_iface = "eth0"
+//! dns_search = ["example.com"]
+//! ntp = ["192.168.1.1"]
+//! required = true
+//!
+//! [shutdown]
+//! on_exit = "reboot"
+//! on_crash = "rescue"
+//! boot_attempts = 3
+//!
+//! [[mounts]]
+//! source = "/dev/vdb1"
+//! target = "/data"
+//! fstype = "ext4"
+//! options = ["noatime"]
+//! pass = 2
+//!
+//! [[services]]
+//! name = "sshd"
+//! exec = ["/usr/sbin/sshd", "-D"]
+//! maintenance = true
+//! ```
+//!
+//! Every field is optional. A value is taken from the kernel command line
+//! if the matching argument is given, from this file otherwise, and falls
+//! back to the built-in default if neither sets it. The `mounts` are
+//! mounted after the entries of `/etc/fstab`, the `services` are started
+//! with the ones in `/etc/linos/services.d`, where a definition of the
+//! same name replaces the one in this file.
+//!
+//! The file is read from the root filesystem, so it is only loaded once
+//! the root is mounted, for an NFS root after the network was configured
+//! to reach it. Settings used before can only be given on the kernel
+//! command line: `root=`, `console=`, `debugshell=`, `cgroup=` and
+//! `oom.protect=`, the log level of the early messages and, for an NFS
+//! root, `modules=` and the network settings.
+use std::{fs, io, net::Ipv4Addr, path::Path};
+
+use log::{debug, warn};
+use serde::Deserialize;
+
+use crate::{
+    cmdline::{self, Cmdline, CmdlineArgs},
+    fs::{
+        fstab::FstabEntry,
+        mount::{self, EXTRA_FILESYSTEMS},
+    },
+    hostname::{self, Hostname},
+    net::dhcp,
+    power::ExitAction,
+    priority,
+    supervisor::Service,
+};
+
+/// Path of the init configuration.
+pub const INIT_CONFIG: &str = "/etc/linos/init.toml";
+
+/// The `[log]` section.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogConfig {
+    /// Logs warnings and errors only (`quiet`).
+    pub quiet: Option<bool>,
+    /// The size of the tmpfs mounted at `/var/log` (`log.tmpfs=`).
+    pub tmpfs: Option<String>,
+    /// The number of lines kept for the `log` control query (`log.lines=`).
+    pub lines: Option<usize>,
+}
+
+/// The `[mount]` section.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MountConfig {
+    /// Additional kernel filesystems to mount (`mount.extra=`).
+    pub extra: Option<Vec<String>>,
+    /// Mounts a writable overlay over `/etc` (`etc.overlay=1`).
+    pub etc_overlay: Option<bool>,
+    /// Prepares the mounts for container runtimes (`container=1`).
+    pub container: Option<bool>,
+}
+
+/// The `[network]` section.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NetworkConfig {
+    pub dhcp_iface: Option<String>,
+    pub dhcp_parallel: Option<usize>,
+    pub dhcp_rapid: Option<bool>,
+    pub dhcp_vendor: Option<String>,
+    pub dhcp_server: Option<Ipv4Addr>,
+    pub dhcp_max_size: Option<u16>,
+    /// The lease time in seconds requested from the server (`dhcp.leasetime=`).
+    pub dhcp_lease_time: Option<u32>,
+    /// The UDP checksum of the DHCP messages, `none` or `full` (`dhcp.csum=`).
+    pub dhcp_csum: Option<String>,
+    /// How the DHCP messages are sent, `raw` or `udp` (`dhcp.transport=`).
+    pub dhcp_transport: Option<String>,
+    pub dns_search: Option<Vec<String>>,
+    pub ntp: Option<Vec<Ipv4Addr>>,
+    pub required: Option<bool>,
+    pub force: Option<bool>,
+    pub monitor: Option<bool>,
+    pub nsswitch: Option<bool>,
+}
+
+/// The `[shutdown]` section.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ShutdownConfig {
+    /// The action once the handed-off process exited cleanly (`onexit=`).
+    pub on_exit: Option<String>,
+    /// The action once the handed-off process crashed (`oncrash=`).
+    pub on_crash: Option<String>,
+    /// The number of boot attempts before giving up (`boot.attempts=`).
+    pub boot_attempts: Option<u32>,
+}
+
+/// The contents of the init configuration.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct InitConfig {
+    /// The hostname of the system or `dhcp` (`hostname=`).
+    pub hostname: Option<String>,
+    /// The file the hostname is persisted to or `none` (`hostname.persist=`).
+    pub hostname_persist: Option<String>,
+    /// The nice value of linµos and the processes it starts (`nice=`).
+    pub nice: Option<i32>,
+    pub log: LogConfig,
+    pub mount: MountConfig,
+    pub network: NetworkConfig,
+    pub shutdown: ShutdownConfig,
+    /// Filesystems mounted after the entries of `/etc/fstab`.
+    pub mounts: Vec<FstabEntry>,
+    /// Services started next to the handed-off process.
+    pub services: Vec<Service>,
+}
+
+/// Loads the init configuration, a missing file is an empty configuration.
+///
+/// # Arguments
+///
+/// * `path` - The path of the configuration (see `INIT_CONFIG`).
+pub fn load(path: &Path) -> io::Result<InitConfig> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(InitConfig::default()),
+        Err(err) => return Err(err),
+    };
+    toml::from_str::<InitConfig>(&contents)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err.to_string()))
+}
+
+/// Sets `field` to the configured `value`, unless the
+/// kernel command line argument `key` is given.
+fn merge<T>(args: &CmdlineArgs, key: &str, value: Option<T>, field: &mut T) {
+    if let Some(value) = value {
+        if args.has(key) {
+            debug!("'{}' overrides the init configuration", key);
+        } else {
+            *field = value;
+        }
+    }
+}
+
+/// Keeps a configured value only if it is valid, an invalid
+/// value is ignored with a warning as if it was not configured.
+fn valid<T>(name: &str, value: Option<T>, is_valid: impl Fn(&T) -> bool) -> Option<T> {
+    match value {
+        Some(value) if !is_valid(&value) => {
+            warn!("Ignoring invalid '{}' in {}", name, INIT_CONFIG);
+            None
+        }
+        value => value,
+    }
+}
+
+/// Parses a configured exit action, see `valid`.
+fn exit_action(name: &str, value: Option<String>) -> Option<ExitAction> {
+    let value = valid(name, value, |action| ExitAction::parse(action).is_some());
+    value.and_then(|action| ExitAction::parse(&action))
+}
+
+impl InitConfig {
+    /// Merges the configuration into the parsed kernel command line.
+    ///
+    /// Arguments given on the command line take precedence over
+    /// the configuration, which takes precedence over the defaults.
+    pub fn merge_into(&self, cmdline: &mut Cmdline) {
+        let args = cmdline.args.clone();
+        let config = self.clone();
+        let (mount, network, shutdown) = (config.mount, config.network, config.shutdown);
+
+        let hostname = valid("hostname", config.hostname, |name| {
+            Hostname::parse(name).is_some()
+        });
+        let hostname = hostname.and_then(|name| Hostname::parse(&name));
+        merge(&args, "hostname", hostname.map(Some), &mut cmdline.hostname);
+        let persist = valid("hostname_persist", config.hostname_persist, |value| {
+            hostname::parse_persist(value).is_some()
+        });
+        merge(
+            &args,
+            "hostname.persist",
+            persist.and_then(|value| hostname::parse_persist(&value)),
+            &mut cmdline.hostname_persist,
+        );
+        merge(&args, "quiet", config.log.quiet, &mut cmdline.quiet);
+        let log_tmpfs = valid("log.tmpfs", config.log.tmpfs, |size| {
+            mount::is_valid_tmpfs_size(size)
+        });
+        merge(
+            &args,
+            "log.tmpfs",
+            log_tmpfs.map(Some),
+            &mut cmdline.log_tmpfs,
+        );
+        merge(&args, "log.lines", config.log.lines, &mut cmdline.log_lines);
+        let nice = valid("nice", config.nice, |nice| {
+            priority::NICE_RANGE.contains(nice)
+        });
+        merge(&args, "nice", nice.map(Some), &mut cmdline.nice);
+
+        let extra = valid("mount.extra", mount.extra, |names| {
+            names
+                .iter()
+                .all(|name| EXTRA_FILESYSTEMS.iter().any(|fs| fs.0 == name))
+        });
+        merge(&args, "mount.extra", extra, &mut cmdline.mount_extra);
+        merge(
+            &args,
+            "etc.overlay",
+            mount.etc_overlay,
+            &mut cmdline.etc_overlay,
+        );
+        merge(&args, "container", mount.container, &mut cmdline.container);
+
+        let iface = valid("network.dhcp_iface", network.dhcp_iface, |iface| {
+            !iface.is_empty()
+        });
+        merge(
+            &args,
+            "dhcp.iface",
+            iface.map(Some),
+            &mut cmdline.dhcp_iface,
+        );
+        let parallel = valid("network.dhcp_parallel", network.dhcp_parallel, |parallel| {
+            *parallel > 0
+        });
+        merge(
+            &args,
+            "dhcp.parallel",
+            parallel.map(Some),
+            &mut cmdline.dhcp_parallel,
+        );
+        merge(
+            &args,
+            "dhcp.rapid",
+            network.dhcp_rapid,
+            &mut cmdline.dhcp_rapid,
+        );
+        let vendor = valid("network.dhcp_vendor", network.dhcp_vendor, |vendor| {
+            dhcp::validate_vendor_class(vendor).is_ok()
+        });
+        merge(
+            &args,
+            "dhcp.vendor",
+            vendor.map(Some),
+            &mut cmdline.dhcp_vendor,
+        );
+        merge(
+            &args,
+            "dhcp.server",
+            network.dhcp_server.map(Some),
+            &mut cmdline.dhcp_server,
+        );
+        let max_size = valid("network.dhcp_max_size", network.dhcp_max_size, |size| {
+            *size >= dhcp::MIN_MAX_MESSAGE_SIZE
+        });
+        merge(
+            &args,
+            "dhcp.maxsize",
+            max_size.map(Some),
+            &mut cmdline.dhcp_max_size,
+        );
+        let lease_time = valid("network.dhcp_lease_time", network.dhcp_lease_time, |secs| {
+            *secs > 0
+        });
+        merge(
+            &args,
+            "dhcp.leasetime",
+            lease_time.map(Some),
+            &mut cmdline.dhcp_lease_time,
+        );
+        let csum = valid("network.dhcp_csum", network.dhcp_csum, |csum| {
+            cmdline::parse_dhcp_csum(csum).is_some()
+        });
+        merge(
+            &args,
+            "dhcp.csum",
+            csum.and_then(|csum| cmdline::parse_dhcp_csum(&csum)),
+            &mut cmdline.dhcp_csum_none,
+        );
+        let transport = valid("network.dhcp_transport", network.dhcp_transport, |name| {
+            dhcp::DhcpTransport::parse(name).is_some()
+        });
+        merge(
+            &args,
+            "dhcp.transport",
+            transport.and_then(|name| dhcp::DhcpTransport::parse(&name)),
+            &mut cmdline.dhcp_transport,
+        );
+        let dns_search = valid("network.dns_search", network.dns_search, |domains| {
+            domains
+                .iter()
+                .all(|domain| cmdline::is_valid_domain(domain))
+        });
+        merge(&args, "dnssearch", dns_search, &mut cmdline.dns_search);
+        merge(&args, "ntp", network.ntp, &mut cmdline.ntp);
+        merge(
+            &args,
+            "net.required",
+            network.required,
+            &mut cmdline.net_required,
+        );
+        merge(&args, "net.force", network.force, &mut cmdline.net_force);
+        merge(
+            &args,
+            "net.monitor",
+            network.monitor,
+            &mut cmdline.net_monitor,
+        );
+        merge(&args, "nsswitch", network.nsswitch, &mut cmdline.nsswitch);
+
+        let on_exit = exit_action("shutdown.on_exit", shutdown.on_exit);
+        merge(&args, "onexit", on_exit, &mut cmdline.on_exit);
+        let on_crash = exit_action("shutdown.on_crash", shutdown.on_crash);
+        merge(&args, "oncrash", on_crash, &mut cmdline.on_crash);
+        let attempts = valid("shutdown.boot_attempts", shutdown.boot_attempts, |n| *n > 0);
+        merge(
+            &args,
+            "boot.attempts",
+            attempts.map(Some),
+            &mut cmdline.boot_attempts,
+        );
+    }
+
+    /// The services to start, the configured ones followed by the
+    /// `loaded` ones, which replace configured services of the same name.
+    ///
+    /// # Arguments
+    ///
+    /// * `loaded` - The services loaded from `SERVICES_DIR` (see `supervisor::load`).
+    pub fn services(&self, loaded: Vec<Service>) -> Vec<Service> {
+        let mut services = self
+            .services
+            .iter()
+            .filter(|service| {
+                if service.exec.is_empty() {
+                    warn!(
+                        "Skipping service '{}' in {}: 'exec' is empty",
+                        service.name, INIT_CONFIG
+                    );
+                }
+                !service.exec.is_empty()
+            })
+            .cloned()
+            .collect::<Vec<Service>>();
+        for service in loaded {
+            match services.iter().position(|s| s.name == service.name) {
+                Some(i) => services[i] = service,
+                None => services.push(service),
+            }
+        }
+        services
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = r#"
+        hostname = "node-1"
+        nice = 5
+
+        [log]
+        lines = 100
+
+        [network]
+        dhcp_iface = "eth0"
+        dhcp_parallel = 0
+        ntp = ["192.168.1.1"]
+
+        [shutdown]
+        on_exit = "reboot"
+
+        [[mounts]]
+        source = "/dev/vdb1"
+        target = "/data"
+        fstype = "ext4"
+
+        [[services]]
+        name = "sshd"
+        exec = ["/usr/sbin/sshd", "-D"]
+    "#;
+
+    #[test]
+    fn cmdline_overrides_config_overrides_defaults() {
+        let config = toml::from_str::<InitConfig>(CONFIG).unwrap();
+        let mut cmdline =
+            cmdline::parse_cmdline("root=/dev/vda hostname=node-2 ntp=10.0.0.1").unwrap();
+        config.merge_into(&mut cmdline);
+
+        // given on the command line
+        assert_eq!(
+            cmdline.hostname,
+            Some(Hostname::Static("node-2".to_string()))
+        );
+        assert_eq!(cmdline.ntp, vec![Ipv4Addr::new(10, 0, 0, 1)]);
+        // only configured
+        assert_eq!(cmdline.nice, Some(5));
+        assert_eq!(cmdline.log_lines, 100);
+        assert_eq!(cmdline.dhcp_iface.as_deref(), Some("eth0"));
+        assert_eq!(cmdline.on_exit, ExitAction::Reboot);
+        // neither, or invalid in the configuration
+        assert_eq!(cmdline.on_crash, ExitAction::DEFAULT_ON_CRASH);
+        assert_eq!(cmdline.dhcp_parallel, None);
+    }
+
+    #[test]
+    fn flags_given_on_cmdline_are_kept() {
+        let config = toml::from_str::<InitConfig>("[network]\nrequired = false\n").unwrap();
+        let mut cmdline = cmdline::parse_cmdline("root=/dev/vda net.required=1").unwrap();
+        config.merge_into(&mut cmdline);
+
+        assert!(cmdline.net_required);
+    }
+
+    #[test]
+    fn configures_dhcp_checksum() {
+        let config = toml::from_str::<InitConfig>("[network]\ndhcp_csum = \"none\"\n").unwrap();
+        let mut cmdline = cmdline::parse_cmdline("root=/dev/vda").unwrap();
+        config.merge_into(&mut cmdline);
+        assert!(cmdline.dhcp_csum_none);
+
+        let mut cmdline = cmdline::parse_cmdline("root=/dev/vda dhcp.csum=full").unwrap();
+        config.merge_into(&mut cmdline);
+        assert!(!cmdline.dhcp_csum_none);
+
+        // an invalid value is ignored
+        let config = toml::from_str::<InitConfig>("[network]\ndhcp_csum = \"zero\"\n").unwrap();
+        let mut cmdline = cmdline::parse_cmdline("root=/dev/vda").unwrap();
+        config.merge_into(&mut cmdline);
+        assert!(!cmdline.dhcp_csum_none);
+    }
+
+    #[test]
+    fn configures_dhcp_lease_time() {
+        let config = toml::from_str::<InitConfig>("[network]\ndhcp_lease_time = 3600\n").unwrap();
+        let mut cmdline = cmdline::parse_cmdline("root=/dev/vda").unwrap();
+        config.merge_into(&mut cmdline);
+        assert_eq!(cmdline.dhcp_lease_time, Some(3600));
+
+        let mut cmdline = cmdline::parse_cmdline("root=/dev/vda dhcp.leasetime=60").unwrap();
+        config.merge_into(&mut cmdline);
+        assert_eq!(cmdline.dhcp_lease_time, Some(60));
+
+        // an invalid value is ignored
+        let config = toml::from_str::<InitConfig>("[network]\ndhcp_lease_time = 0\n").unwrap();
+        let mut cmdline = cmdline::parse_cmdline("root=/dev/vda").unwrap();
+        config.merge_into(&mut cmdline);
+        assert_eq!(cmdline.dhcp_lease_time, None);
+    }
+
+    #[test]
+    fn configures_dhcp_transport() {
+        let config = toml::from_str::<InitConfig>("[network]\ndhcp_transport = \"udp\"\n").unwrap();
+        let mut cmdline = cmdline::parse_cmdline("root=/dev/vda").unwrap();
+        config.merge_into(&mut cmdline);
+        assert_eq!(cmdline.dhcp_transport, dhcp::DhcpTransport::Udp);
+
+        let mut cmdline = cmdline::parse_cmdline("root=/dev/vda dhcp.transport=raw").unwrap();
+        config.merge_into(&mut cmdline);
+        assert_eq!(cmdline.dhcp_transport, dhcp::DhcpTransport::Raw);
+
+        // an invalid value is ignored
+        let config = toml::from_str::<InitConfig>("[network]\ndhcp_transport = \"tcp\"\n").unwrap();
+        let mut cmdline = cmdline::parse_cmdline("root=/dev/vda").unwrap();
+        config.merge_into(&mut cmdline);
+        assert_eq!(cmdline.dhcp_transport, dhcp::DhcpTransport::Raw);
+    }
+
+    #[test]
+    fn loaded_services_replace_configured_ones() {
+        let config = toml::from_str::<InitConfig>(CONFIG).unwrap();
+        assert_eq!(config.mounts[0].target, "/data");
+        assert!(config.mounts[0].options.is_empty());
+
+        let loaded = toml::from_str::<Service>("name = \"sshd\"\nexec = [\"/bin/dropbear\"]\n");
+        let services = config.services(vec![loaded.unwrap()]);
+
+        assert_eq!(services.len(), 1);
+        assert_eq!(services[0].exec, vec!["/bin/dropbear".to_string()]);
+    }
+}
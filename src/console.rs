@@ -0,0 +1,262 @@
+//! Setup of serial consoles.
+//!
+//! The kernel command line names the console and, for serial consoles,
+//! its line settings (e.g. `console=ttyS0,115200n8`). Before handing off,
+//! the terminal settings of the console are set accordingly, so the shell
+//! gets ungarbled output and working line editing.
+use std::{fs::File, io, os::unix::io::AsRawFd};
+
+use log::debug;
+use nix::sys::termios::{
+    self, BaudRate, ControlFlags, InputFlags, LocalFlags, OutputFlags, SetArg,
+};
+
+/// Parity of a serial line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Parity {
+    None,
+    Odd,
+    Even,
+}
+
+/// Line settings of a serial console (e.g. `115200n8`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SerialSettings {
+    pub baud: u32,
+    pub parity: Parity,
+    pub bits: u8,
+    /// Hardware (RTS/CTS) flow control, the `r` suffix.
+    pub flow_control: bool,
+}
+
+/// Parses the line settings of a serial console.
+///
+/// The settings are `<baud><parity><bits><flow>` as documented for the
+/// kernel, all but the baud rate are optional and default to `n8`
+/// without flow control (e.g. `115200`, `9600n8`, `115200n8r`).
+///
+/// # Returns
+///
+/// * `Option<SerialSettings>` - The settings, or `None` if they are invalid.
+pub fn parse_settings(settings: &str) -> Option<SerialSettings> {
+    let digits = settings
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(settings.len());
+    let (baud, rest) = settings.split_at(digits);
+    let baud = baud.parse::<u32>().ok()?;
+    baud_rate(baud)?;
+
+    let mut rest = rest.chars();
+    let parity = match rest.next() {
+        None | Some('n') => Parity::None,
+        Some('o') => Parity::Odd,
+        Some('e') => Parity::Even,
+        Some(_) => return None,
+    };
+    let bits = match rest.next() {
+        None => 8,
+        Some(bits @ '5'..='8') => bits.to_digit(10)? as u8,
+        Some(_) => return None,
+    };
+    let flow_control = match rest.next() {
+        None => false,
+        Some('r') => true,
+        Some(_) => return None,
+    };
+    if rest.next().is_some() {
+        return None;
+    }
+
+    Some(SerialSettings {
+        baud,
+        parity,
+        bits,
+        flow_control,
+    })
+}
+
+/// Splits a `console=` argument (e.g. `ttyS0,115200n8`)
+/// into the device name and its line settings.
+///
+/// # Returns
+///
+/// * `(&str, Option<&str>)` - The device and the settings, if any.
+pub fn split_console(console: &str) -> (&str, Option<&str>) {
+    match console.split_once(',') {
+        Some((device, settings)) => (device, Some(settings)),
+        None => (console, None),
+    }
+}
+
+/// Checks if the console is a virtual terminal (`tty`, `tty0`, `tty1`, ...).
+fn is_virtual_terminal(device: &str) -> bool {
+    device
+        .strip_prefix("tty")
+        .is_some_and(|n| n.chars().all(|c| c.is_ascii_digit()))
+}
+
+/// Maps a baud rate to its termios constant.
+fn baud_rate(baud: u32) -> Option<BaudRate> {
+    Some(match baud {
+        1200 => BaudRate::B1200,
+        2400 => BaudRate::B2400,
+        4800 => BaudRate::B4800,
+        9600 => BaudRate::B9600,
+        19200 => BaudRate::B19200,
+        38400 => BaudRate::B38400,
+        57600 => BaudRate::B57600,
+        115200 => BaudRate::B115200,
+        230400 => BaudRate::B230400,
+        460800 => BaudRate::B460800,
+        921600 => BaudRate::B921600,
+        _ => return None,
+    })
+}
+
+/// Applies the line settings to a serial console.
+///
+/// The console is set to cooked mode with echo, so
+/// line editing in the shell works as expected.
+///
+/// # Arguments
+///
+/// * `device` - The console device (e.g. `/dev/ttyS0`).
+/// * `settings` - The line settings.
+pub fn apply(device: &str, settings: &SerialSettings) -> io::Result<()> {
+    let console = File::options().read(true).write(true).open(device)?;
+    let mut attrs = termios::tcgetattr(console.as_raw_fd())?;
+
+    // parse_settings only accepts known baud rates
+    let baud = baud_rate(settings.baud).unwrap_or(BaudRate::B115200);
+    termios::cfsetspeed(&mut attrs, baud)?;
+
+    attrs.control_flags &= !(ControlFlags::CSIZE
+        | ControlFlags::PARENB
+        | ControlFlags::PARODD
+        | ControlFlags::CRTSCTS);
+    attrs.control_flags |= ControlFlags::CREAD
+        | ControlFlags::CLOCAL
+        | match settings.bits {
+            5 => ControlFlags::CS5,
+            6 => ControlFlags::CS6,
+            7 => ControlFlags::CS7,
+            _ => ControlFlags::CS8,
+        };
+    match settings.parity {
+        Parity::None => {}
+        Parity::Odd => attrs.control_flags |= ControlFlags::PARENB | ControlFlags::PARODD,
+        Parity::Even => attrs.control_flags |= ControlFlags::PARENB,
+    }
+    if settings.flow_control {
+        attrs.control_flags |= ControlFlags::CRTSCTS;
+    }
+
+    // software flow control swallows ^S and ^Q in the shell
+    attrs.input_flags &= !(InputFlags::IXON | InputFlags::IXOFF | InputFlags::INLCR);
+    attrs.input_flags |= InputFlags::ICRNL;
+    attrs.output_flags |= OutputFlags::OPOST | OutputFlags::ONLCR;
+    attrs.local_flags |= LocalFlags::ICANON
+        | LocalFlags::ECHO
+        | LocalFlags::ECHOE
+        | LocalFlags::ECHOK
+        | LocalFlags::ISIG
+        | LocalFlags::IEXTEN;
+
+    termios::tcsetattr(console.as_raw_fd(), SetArg::TCSADRAIN, &attrs)?;
+
+    Ok(())
+}
+
+/// Sets up the console given on the kernel command line.
+///
+/// Virtual terminals (e.g. `tty0`) and consoles without
+/// line settings are left as they are.
+///
+/// # Arguments
+///
+/// * `console` - The `console=` argument (e.g. `ttyS0,115200n8`).
+pub fn setup(console: &str) -> io::Result<()> {
+    let (device, settings) = match split_console(console) {
+        (device, _) if is_virtual_terminal(device) => {
+            debug!("Console '{}' is no serial console, skipping", device);
+            return Ok(());
+        }
+        (device, Some(settings)) => (device, settings),
+        (device, None) => {
+            debug!("Console '{}' has no line settings, skipping", device);
+            return Ok(());
+        }
+    };
+    let settings = parse_settings(settings).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("invalid line settings '{}'", settings),
+        )
+    })?;
+
+    debug!("Setting up console '{}': {:?}", device, settings);
+    apply(&format!("/dev/{}", device), &settings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings(baud: u32, parity: Parity, bits: u8, flow_control: bool) -> SerialSettings {
+        SerialSettings {
+            baud,
+            parity,
+            bits,
+            flow_control,
+        }
+    }
+
+    #[test]
+    fn parses_serial_settings() {
+        assert_eq!(
+            parse_settings("115200"),
+            Some(settings(115200, Parity::None, 8, false))
+        );
+        assert_eq!(
+            parse_settings("9600n8"),
+            Some(settings(9600, Parity::None, 8, false))
+        );
+        assert_eq!(
+            parse_settings("38400e7"),
+            Some(settings(38400, Parity::Even, 7, false))
+        );
+        assert_eq!(
+            parse_settings("115200o"),
+            Some(settings(115200, Parity::Odd, 8, false))
+        );
+        assert_eq!(
+            parse_settings("115200n8r"),
+            Some(settings(115200, Parity::None, 8, true))
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_serial_settings() {
+        for invalid in [
+            "",
+            "n8",
+            "12345",
+            "115200x8",
+            "115200n9",
+            "115200n8x",
+            "115200n8rr",
+        ] {
+            assert_eq!(parse_settings(invalid), None, "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn splits_console_argument() {
+        assert_eq!(split_console("ttyS0,115200n8"), ("ttyS0", Some("115200n8")));
+        assert_eq!(split_console("tty0"), ("tty0", None));
+        assert!(is_virtual_terminal("tty"));
+        assert!(is_virtual_terminal("tty1"));
+        assert!(!is_virtual_terminal("ttyS0"));
+        assert!(!is_virtual_terminal("hvc0"));
+    }
+}
@@ -0,0 +1,212 @@
+//! Control socket.
+//!
+//! linµos listens on the unix socket `/run/linos/control.sock` and answers
+//! a single line based query per connection, e.g.:
+//!
+//! ```sh
+//! echo status | nc -U /run/linos/control.sock
+//! ```
+//!
+//! Supported queries are `status`, `state` (the boot state, see `state`),
+//! `interfaces`, `uptime` and `log` (the latest log lines, see `logbuf`),
+//! `reconfigure network` configures the network again and `hostname <name>`
+//! changes and persists the hostname (see `hostname`).
+use std::{
+    fs,
+    io::{self, BufRead, BufReader, Write},
+    os::unix::net::{UnixListener, UnixStream},
+    path::Path,
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use log::{debug, warn};
+use pnet::datalink;
+
+use crate::{
+    cmdline, hostname, logbuf,
+    marker::BOOT_MARKER,
+    net::{self, NetworkOptions},
+    state,
+};
+
+/// Path of the control socket.
+pub const CONTROL_SOCKET: &str = "/run/linos/control.sock";
+
+/// Time a client is given to send its query and read the answer,
+/// a stalled client must not block the following connections.
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Answers a single query.
+///
+/// # Arguments
+///
+/// * `query` - The query (e.g. `status`).
+/// * `network` - The settings used to reconfigure the network.
+/// * `persist` - The file a changed hostname is persisted to.
+///
+/// # Returns
+///
+/// * `String` - The answer, terminated by a newline.
+fn handle(query: &str, network: &NetworkOptions, persist: Option<&str>) -> String {
+    if let Some(name) = query.strip_prefix("hostname ") {
+        let name = name.trim();
+        if !cmdline::is_valid_domain(name) {
+            return format!("error: '{}' is no valid hostname\n", name);
+        }
+        return match hostname::change(name, persist) {
+            Ok(()) => format!("ok: hostname set to '{}'\n", name),
+            Err(err) => format!("error: {}\n", err),
+        };
+    }
+
+    match query {
+        "status" => match fs::read_to_string(BOOT_MARKER) {
+            Ok(marker) => format!("booted\n{}", marker),
+            Err(_) => "booting\n".to_string(),
+        },
+        "state" => format!("{}\n", state::current()),
+        "interfaces" => datalink::interfaces()
+            .iter()
+            .map(|iface| {
+                let ips = iface
+                    .ips
+                    .iter()
+                    .map(|ip| ip.to_string())
+                    .collect::<Vec<String>>()
+                    .join(" ");
+                format!(
+                    "{} {} {}\n",
+                    iface.name,
+                    if iface.is_up() { "up" } else { "down" },
+                    ips
+                )
+            })
+            .collect(),
+        "uptime" => match fs::read_to_string("/proc/uptime") {
+            Ok(uptime) => format!("{}\n", uptime.split_whitespace().next().unwrap_or("0")),
+            Err(err) => format!("error: {}\n", err),
+        },
+        "log" => logbuf::dump(),
+        "reconfigure network" => match net::configure_network(network) {
+            Ok(leases) => format!("ok: network reconfigured, {} leases\n", leases.len()),
+            Err(err) => format!("error: {}\n", err),
+        },
+        _ => format!(
+            "error: unknown query '{}', expected one of: \
+             status, state, interfaces, uptime, log, reconfigure network, hostname <name>\n",
+            query
+        ),
+    }
+}
+
+/// Reads the query of a connection and writes the answer.
+///
+/// # Arguments
+///
+/// * `stream` - The connection.
+/// * `timeout` - The time the client is given to send and receive (see `CLIENT_TIMEOUT`).
+/// * `network` - The settings used to reconfigure the network.
+/// * `persist` - The file a changed hostname is persisted to.
+fn serve(
+    stream: UnixStream,
+    timeout: Duration,
+    network: &NetworkOptions,
+    persist: Option<&str>,
+) -> io::Result<()> {
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+    let mut query = String::new();
+    BufReader::new(&stream).read_line(&mut query)?;
+    let query = query.trim();
+    debug!("Control query '{}'", query);
+
+    (&stream).write_all(handle(query, network, persist).as_bytes())
+}
+
+/// Starts listening on the control socket.
+///
+/// Connections are accepted in a dedicated thread, so the
+/// boot is never blocked by a client.
+///
+/// # Arguments
+///
+/// * `path` - The path of the socket (see `CONTROL_SOCKET`).
+/// * `network` - The settings used to reconfigure the network.
+/// * `persist` - The file a changed hostname is persisted to (`hostname.persist=`).
+pub fn spawn(
+    path: &str,
+    network: NetworkOptions,
+    persist: Option<String>,
+) -> io::Result<JoinHandle<()>> {
+    // the carrier monitor started by the initial configuration keeps
+    // running, the addresses configured during boot are replaced
+    let network = NetworkOptions {
+        force: true,
+        monitor: false,
+        ..network
+    };
+
+    let path = Path::new(path);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    // a stale socket of a previous run prevents binding
+    if path.exists() {
+        fs::remove_file(path)?;
+    }
+    let listener = UnixListener::bind(path)?;
+
+    Ok(thread::spawn(move || {
+        for stream in listener.incoming() {
+            let result = stream
+                .and_then(|stream| serve(stream, CLIENT_TIMEOUT, &network, persist.as_deref()));
+            if let Err(err) = result {
+                warn!("Failed serving control connection: {}", err);
+            }
+        }
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Read, time::Instant};
+
+    use super::*;
+
+    #[test]
+    fn stalled_client_times_out() {
+        let (server, _client) = UnixStream::pair().unwrap();
+        let started = Instant::now();
+
+        let err = serve(
+            server,
+            Duration::from_millis(50),
+            &NetworkOptions::default(),
+            None,
+        )
+        .unwrap_err();
+        assert!(matches!(
+            err.kind(),
+            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+        ));
+        assert!(started.elapsed() < CLIENT_TIMEOUT);
+    }
+
+    #[test]
+    fn answers_query() {
+        let (server, mut client) = UnixStream::pair().unwrap();
+        client.write_all(b"bogus\n").unwrap();
+
+        serve(
+            server,
+            Duration::from_millis(50),
+            &NetworkOptions::default(),
+            None,
+        )
+        .unwrap();
+        let mut answer = String::new();
+        client.read_to_string(&mut answer).unwrap();
+        assert!(answer.starts_with("error: unknown query 'bogus'"));
+    }
+}
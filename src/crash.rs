@@ -0,0 +1,111 @@
+//! Last resort handling of crashes of linµos itself.
+//!
+//! If PID 1 exits, the kernel panics with a message saying nothing about
+//! the cause. Instead, a panic of the main thread (or a fatal signal like
+//! `SIGSEGV`) is reported on `/dev/console` and in the kernel log, and
+//! linµos replaces itself with a rescue shell. If the shell can not be
+//! executed, linµos keeps reaping orphans forever (see `exec::halt`).
+//!
+//! What the handlers do is limited by the state the process is in:
+//!
+//! * The panic hook runs on the panicking thread, which might hold locks
+//!   (e.g. of the logger), so it does not log but writes to the devices
+//!   directly. A panic of any other thread is reported, but only ends
+//!   that thread, as with the default hook.
+//! * The signal handler can only use async-signal-safe functions, so it
+//!   writes a fixed message using `open` and `write` and calls `execv`,
+//!   without allocating or formatting. It runs on the alternate signal
+//!   stack set up by the Rust runtime, so a stack overflow is handled as
+//!   well.
+//! * The memory of the crashed process is gone once the shell is executed,
+//!   only the mounts and the processes started by linµos survive.
+use std::{
+    backtrace::Backtrace, ffi::CStr, fs::File, io::Write, os::unix::process::CommandExt, panic,
+    process::Command, ptr,
+};
+
+use nix::unistd::{getpid, gettid};
+
+use crate::{debugshell::DEBUG_SHELL, exec};
+
+/// The kernel log, messages written to it show up in `dmesg`.
+pub const KMSG: &str = "/dev/kmsg";
+
+/// The rescue shell (`DEBUG_SHELL`), nul terminated for the signal handler.
+const RESCUE_SHELL: &CStr = c"/busybox";
+
+/// The signals linµos does not survive.
+const FATAL_SIGNALS: [libc::c_int; 4] = [libc::SIGSEGV, libc::SIGBUS, libc::SIGILL, libc::SIGFPE];
+
+/// Writes `message` to the console and the kernel log, failures are ignored.
+fn report(message: &str) {
+    if let Ok(mut console) = File::options().write(true).open(exec::CONSOLE) {
+        let _ = console.write_all(format!("\n[fatal] {}\n", message).as_bytes());
+    }
+    if let Ok(mut kmsg) = File::options().write(true).open(KMSG) {
+        // each write is a single record, `<2>` is the critical level
+        for line in message.lines() {
+            let _ = kmsg.write_all(format!("<2>linos: {}\n", line).as_bytes());
+        }
+    }
+}
+
+/// Replaces linµos with the rescue shell, halting if that fails.
+fn rescue_shell() -> ! {
+    report("starting a rescue shell, exiting it panics the kernel");
+    let err = Command::new(DEBUG_SHELL).arg("sh").exec();
+    exec::halt(&format!("failed executing rescue shell: {}", err))
+}
+
+/// Handles the fatal signals, see the module documentation for its limits.
+extern "C" fn on_fatal_signal(_signal: libc::c_int) {
+    const MESSAGE: &[u8] =
+        b"\n[fatal] linos crashed with a fatal signal, starting a rescue shell\n";
+    const KMSG_MESSAGE: &[u8] = b"<2>linos: crashed with a fatal signal\n";
+    unsafe {
+        for (path, message) in [(c"/dev/console", MESSAGE), (c"/dev/kmsg", KMSG_MESSAGE)] {
+            let fd = libc::open(path.as_ptr(), libc::O_WRONLY | libc::O_NOCTTY);
+            if fd >= 0 {
+                libc::write(fd, message.as_ptr() as *const libc::c_void, message.len());
+                libc::close(fd);
+            }
+        }
+
+        let argv = [c"sh".as_ptr(), ptr::null()];
+        libc::execv(RESCUE_SHELL.as_ptr(), argv.as_ptr());
+        // PID 1 must not exit, even if there is nothing left to do
+        loop {
+            libc::pause();
+        }
+    }
+}
+
+/// Installs the panic hook and the handlers of the fatal signals.
+///
+/// Only meant for PID 1, the handlers replace the process with a shell.
+pub fn install() {
+    panic::set_hook(Box::new(|info| {
+        let thread = std::thread::current();
+        let name = thread.name().unwrap_or("unnamed");
+        report(&format!("linos panicked in thread '{}': {}", name, info));
+        if gettid() != getpid() {
+            return;
+        }
+
+        if let Ok(mut console) = File::options().write(true).open(exec::CONSOLE) {
+            let _ = console.write_all(format!("{}\n", Backtrace::force_capture()).as_bytes());
+        }
+        rescue_shell();
+    }));
+
+    for signal in FATAL_SIGNALS {
+        unsafe {
+            let mut action: libc::sigaction = std::mem::zeroed();
+            action.sa_sigaction =
+                on_fatal_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+            action.sa_flags = libc::SA_ONSTACK | libc::SA_RESETHAND;
+            libc::sigemptyset(&mut action.sa_mask);
+            libc::sigaction(signal, &action, ptr::null_mut());
+        }
+    }
+}
@@ -0,0 +1,88 @@
+//! A debug shell running next to the boot.
+//!
+//! With `debugshell=tty2` a shell is started on the given terminal as soon
+//! as `/dev` is mounted, so a hanging boot can be inspected from another
+//! virtual terminal (e.g. `Alt+F2`). The shell is respawned whenever it
+//! exits, independently of the boot and the handoff.
+use std::{
+    fs::File,
+    io,
+    os::unix::process::CommandExt,
+    path::Path,
+    process::Command,
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use log::{info, warn};
+
+use crate::{exec, reaper};
+
+/// The shell started on the terminal, as `busybox sh`.
+pub const DEBUG_SHELL: &str = "/busybox";
+
+/// Time waited before respawning an exited shell.
+const RESPAWN_DELAY: Duration = Duration::from_secs(1);
+
+/// Checks if `tty` is a terminal name (e.g. `tty2` or `ttyS1`).
+pub fn is_valid_tty(tty: &str) -> bool {
+    tty.starts_with("tty") && tty.chars().all(|c| c.is_ascii_alphanumeric())
+}
+
+/// Creates the command running the shell on `device`, as the
+/// session leader with `device` as its controlling terminal.
+fn command(device: &str) -> io::Result<Command> {
+    let tty = File::options().read(true).write(true).open(device)?;
+    let mut command = exec::command(DEBUG_SHELL, &["sh"], exec::Output::Inherit)?;
+    command
+        .stdin(tty.try_clone()?)
+        .stdout(tty.try_clone()?)
+        .stderr(tty);
+    unsafe {
+        command.pre_exec(|| {
+            nix::unistd::setsid()?;
+            if libc::ioctl(libc::STDIN_FILENO, libc::TIOCSCTTY, 1) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+
+    Ok(command)
+}
+
+/// Starts the debug shell on `tty` and keeps respawning it.
+///
+/// # Arguments
+///
+/// * `tty` - The terminal to run the shell on (e.g. `tty2`).
+pub fn spawn(tty: &str) -> io::Result<JoinHandle<()>> {
+    let device = format!("/dev/{}", tty);
+    if !Path::new(&device).exists() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("'{}' does not exist", device),
+        ));
+    }
+
+    thread::Builder::new()
+        .name("debugshell".to_string())
+        .spawn(move || loop {
+            let child = match command(&device)
+                .and_then(|mut command| reaper::spawn_command(&mut command, "debugshell"))
+            {
+                Ok(child) => child,
+                Err(err) => {
+                    // respawning would fail alike
+                    warn!("Failed starting debug shell on '{}': {}", device, err);
+                    return;
+                }
+            };
+            info!("Started debug shell on '{}' ({})", device, child.id());
+            match reaper::wait(&child) {
+                Ok(status) => info!("Debug shell {}, respawning", reaper::describe(&status)),
+                Err(err) => warn!("Failed waiting for debug shell: {}", err),
+            }
+            thread::sleep(RESPAWN_DELAY);
+        })
+}
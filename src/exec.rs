@@ -0,0 +1,341 @@
+//! Spawning of programs.
+use std::{
+    fs::{self, File},
+    io::{self, BufRead, BufReader, Read, Write},
+    os::unix::process::CommandExt,
+    path::{Path, PathBuf},
+    process::{Child, Command, Stdio},
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use log::warn;
+use nix::{
+    errno::Errno,
+    sys::wait::{waitpid, WaitStatus},
+    time::{clock_gettime, ClockId},
+    unistd::Pid,
+};
+
+use crate::{priority, reaper};
+
+/// Directory the output of logged programs is written to.
+pub const LOG_DIR: &str = "/var/log";
+
+/// Shells handed off to after booting, tried in order.
+pub const SHELLS: [&str; 3] = ["/busybox", "/bin/sh", "/bin/busybox"];
+
+/// The console unrecoverable errors are reported to.
+pub const CONSOLE: &str = "/dev/console";
+
+/// Where the output of a program goes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Output {
+    /// Same as the output of linµos, usually the console.
+    Inherit,
+    /// Redirects stdout and stderr to `/var/log/<program>.log`,
+    /// with the least overhead for programs with a lot of output.
+    File,
+    /// Pipes stdout and stderr through linµos, which appends every line
+    /// with a timestamp to `/var/log/<program>.log` as soon as it is
+    /// complete, and with `tee` writes it to the console as well.
+    Capture { tee: bool },
+}
+
+/// Returns the log file of the program at `path`.
+fn log_file(path: &str) -> PathBuf {
+    let name = match Path::new(path).file_name() {
+        Some(name) => name.to_string_lossy().to_string(),
+        None => path.to_string(),
+    };
+    Path::new(LOG_DIR).join(format!("{}.log", name))
+}
+
+/// Opens the log file of the program at `path` for appending.
+fn open_log(path: &str) -> io::Result<File> {
+    fs::create_dir_all(LOG_DIR)?;
+    File::options()
+        .create(true)
+        .append(true)
+        .open(log_file(path))
+}
+
+/// Creates the command running the program at `path`.
+///
+/// # Arguments
+///
+/// * `path` - The path of the program (e.g. `/busybox`).
+/// * `args` - The arguments passed to the program.
+/// * `output` - Where the output of the program goes, a captured
+///   output has to be forwarded once spawned (see `spawn`).
+pub fn command(path: &str, args: &[&str], output: Output) -> io::Result<Command> {
+    let mut command = Command::new(path);
+    command.args(args);
+    unsafe {
+        command.pre_exec(reset_oom_score_adj);
+    }
+
+    match output {
+        Output::Inherit => {}
+        Output::File => {
+            let file = open_log(path)?;
+            command.stdout(Stdio::from(file.try_clone()?));
+            command.stderr(Stdio::from(file));
+        }
+        Output::Capture { .. } => {
+            command.stdout(Stdio::piped());
+            command.stderr(Stdio::piped());
+        }
+    }
+
+    Ok(command)
+}
+
+/// Resets the OOM score adjustment inherited from init in a spawned
+/// child (see `priority`), a child failing to reset it still runs.
+pub(crate) fn reset_oom_score_adj() -> io::Result<()> {
+    let _ = priority::reset_oom_score_adj();
+    Ok(())
+}
+
+/// Forwards the lines of a captured stream in a dedicated thread.
+///
+/// # Arguments
+///
+/// * `stream` - The stdout or stderr of the program.
+/// * `file` - The log file, each line is written with a single `write`,
+///   so the lines of stdout and stderr do not interleave.
+/// * `tee` - Writes the lines, prefixed by `name`, to the console as well.
+/// * `name` - The name of the program in the logs.
+///
+/// # Returns
+///
+/// * `JoinHandle<()>` - The thread, which ends with the stream.
+fn forward(
+    stream: impl Read + Send + 'static,
+    mut file: File,
+    tee: bool,
+    name: String,
+) -> JoinHandle<()> {
+    thread::spawn(move || {
+        for line in BufReader::new(stream).split(b'\n') {
+            let line = match line {
+                Ok(line) => String::from_utf8_lossy(&line).to_string(),
+                Err(_) => break,
+            };
+            let uptime = clock_gettime(ClockId::CLOCK_MONOTONIC)
+                .map(Duration::from)
+                .unwrap_or_default();
+            let entry = format!(
+                "[{:>5}.{:06}] {}\n",
+                uptime.as_secs(),
+                uptime.subsec_micros(),
+                line
+            );
+            if let Err(err) = file.write_all(entry.as_bytes()) {
+                warn!("Failed logging output of '{}': {}", name, err);
+            }
+            if tee {
+                eprintln!("{}: {}", name, line);
+            }
+        }
+    })
+}
+
+/// Spawns the given command and forwards its captured output.
+///
+/// The exit of the program is logged by the reaper (see `reaper`).
+///
+/// # Arguments
+///
+/// * `command` - The command to spawn, see `command`.
+/// * `name` - The name of the child in the logs (e.g. the service name).
+/// * `output` - The output the command was created with.
+pub fn spawn(command: &mut Command, name: &str, output: Output) -> io::Result<Child> {
+    let mut child = reaper::spawn_command(command, name)?;
+
+    if let Output::Capture { tee } = output {
+        let program = command.get_program().to_string_lossy().to_string();
+        let file = open_log(&program)?;
+        if let Some(stdout) = child.stdout.take() {
+            forward(stdout, file.try_clone()?, tee, name.to_string());
+        }
+        if let Some(stderr) = child.stderr.take() {
+            forward(stderr, file, tee, name.to_string());
+        }
+    }
+
+    Ok(child)
+}
+
+/// Spawns the given command.
+///
+/// The exit of the program is logged by the reaper (see `reaper`).
+///
+/// # Arguments
+///
+/// * `command` - The command to spawn, see `command`.
+/// * `wait` - Waits for the program to exit.
+/// * `output` - The output the command was created with.
+pub fn run(mut command: Command, wait: bool, output: Output) -> io::Result<()> {
+    let name = match Path::new(command.get_program()).file_name() {
+        Some(name) => name.to_string_lossy().to_string(),
+        None => command.get_program().to_string_lossy().to_string(),
+    };
+    let child = spawn(&mut command, &name, output)?;
+
+    if wait {
+        reaper::wait(&child)?;
+    }
+
+    Ok(())
+}
+
+/// Runs the program at `path`.
+///
+/// # Arguments
+///
+/// * `path` - The path of the program (e.g. `/busybox`).
+/// * `args` - The arguments passed to the program.
+/// * `wait` - Waits for the program to exit.
+/// * `output` - Where the output of the program goes.
+///
+/// # Example
+///
+/// ```rust
+/// exec("/busybox", &["sh"], true, Output::Inherit).unwrap();
+/// ```
+pub fn exec(path: &str, args: &[&str], wait: bool, output: Output) -> io::Result<()> {
+    run(command(path, args, output)?, wait, output)
+}
+
+/// Hands off to the first of the `shells` which can be spawned
+/// and waits for it to exit.
+///
+/// Busybox binaries are started as `busybox sh`.
+///
+/// # Arguments
+///
+/// * `shells` - The paths of the shells to try (see `SHELLS`).
+/// * `prepare` - Called on the command of each shell before spawning it.
+///
+/// # Returns
+///
+/// * `io::Result<WaitStatus>` - How the shell exited, or the error
+///   of the last shell tried if none could be spawned.
+pub fn handoff(shells: &[&str], prepare: impl Fn(&mut Command)) -> io::Result<WaitStatus> {
+    let mut last_err = io::Error::new(io::ErrorKind::NotFound, "no shell given");
+    for shell in shells {
+        let args: &[&str] = if shell.ends_with("busybox") {
+            &["sh"]
+        } else {
+            &[]
+        };
+        let mut command = command(shell, args, Output::Inherit)?;
+        prepare(&mut command);
+        match reaper::spawn_command(&mut command, shell).and_then(|child| reaper::wait(&child)) {
+            Ok(status) => return Ok(status),
+            Err(err) => {
+                eprintln!("failed spawning {}: {}", shell, err);
+                last_err = err;
+            }
+        }
+    }
+
+    Err(last_err)
+}
+
+/// Reports an unrecoverable error on the console and keeps reaping
+/// orphaned processes forever, as PID 1 must never exit.
+///
+/// # Arguments
+///
+/// * `reason` - The error to report.
+pub fn halt(reason: &str) -> ! {
+    let message = format!(
+        "\n[fatal] {}\n[fatal] the system is halted, nothing is left to run\n",
+        reason
+    );
+    match File::options().write(true).open(CONSOLE) {
+        Ok(mut console) => {
+            let _ = console.write_all(message.as_bytes());
+        }
+        Err(_) => eprint!("{}", message),
+    }
+
+    loop {
+        match waitpid(Pid::from_raw(-1), None) {
+            Ok(_) | Err(Errno::EINTR) => {}
+            // no children to reap (yet)
+            Err(_) => thread::sleep(Duration::from_secs(1)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn forwards_timestamped_lines() {
+        let dir = std::env::temp_dir().join(format!("linos-test-forward-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let log = dir.join("sh.log");
+        let file = File::options()
+            .create(true)
+            .append(true)
+            .open(&log)
+            .unwrap();
+
+        let mut child = Command::new("sh")
+            .args(["-c", "echo a; echo b"])
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let stdout = child.stdout.take().unwrap();
+        forward(stdout, file, false, "sh".to_string())
+            .join()
+            .unwrap();
+        child.wait().unwrap();
+
+        let contents = fs::read_to_string(&log).unwrap();
+        let lines = contents.lines().collect::<Vec<&str>>();
+        assert_eq!(lines.len(), 2, "{:?}", contents);
+        for (line, expected) in lines.iter().zip(["a", "b"]) {
+            // [<secs>.<micros>] <line>
+            let (stamp, text) = line.split_once("] ").unwrap();
+            let (secs, micros) = stamp.trim_start_matches('[').split_once('.').unwrap();
+            assert!(secs.trim().parse::<u64>().is_ok(), "{}", line);
+            assert!(
+                micros.len() == 6 && micros.parse::<u32>().is_ok(),
+                "{}",
+                line
+            );
+            assert_eq!(text, expected);
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn commands_do_not_inherit_the_oom_score_adj() {
+        // stands in for init protected from the OOM killer, in a forked
+        // process to leave the adjustment of the test process alone
+        match unsafe { nix::unistd::fork() }.unwrap() {
+            nix::unistd::ForkResult::Child => {
+                let adjusted = fs::write(priority::OOM_SCORE_ADJ, "500").is_ok()
+                    && command(
+                        "sh",
+                        &["-c", "cat /proc/self/oom_score_adj"],
+                        Output::Inherit,
+                    )
+                    .and_then(|mut command| command.stdout(Stdio::piped()).output())
+                    .is_ok_and(|output| output.stdout == b"0\n");
+                unsafe { libc::_exit(if adjusted { 0 } else { 1 }) };
+            }
+            nix::unistd::ForkResult::Parent { child } => {
+                assert_eq!(waitpid(child, None).unwrap(), WaitStatus::Exited(child, 0));
+            }
+        }
+    }
+}
@@ -0,0 +1,330 @@
+//! Identification of block devices by `UUID=`, `LABEL=` and `PARTUUID=`.
+//!
+//! The `/dev/disk/by-*` symlinks are created by udev, which linµos does
+//! not run. Instead, the block devices listed in `/sys/class/block` are
+//! probed directly: the UUID and label are read from the superblock of
+//! the filesystem (ext2/3/4, xfs, btrfs and vfat) and the partition UUID
+//! from the GPT or MBR partition table of the disk.
+use std::{
+    fs::{self, File},
+    io::{self, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+};
+
+use log::debug;
+
+/// The block devices known to the kernel.
+pub const SYS_BLOCK: &str = "/sys/class/block";
+
+/// The bytes read from the start of a device to probe its filesystem,
+/// up to the end of the btrfs superblock.
+const PROBE_LENGTH: usize = 0x11000;
+
+/// The size of a sector of the partition table.
+const SECTOR_SIZE: u64 = 512;
+
+/// What a device spec identifies a device by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceId {
+    /// The UUID of the filesystem (`UUID=`).
+    Uuid,
+    /// The label of the filesystem (`LABEL=`).
+    Label,
+    /// The UUID of the partition (`PARTUUID=`).
+    PartUuid,
+}
+
+/// The identity of a filesystem read from its superblock.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Superblock {
+    pub fstype: &'static str,
+    pub uuid: Option<String>,
+    pub label: Option<String>,
+}
+
+/// Formats bytes as lowercase hex.
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Formats a UUID stored in big endian (e.g. by ext4 and xfs).
+fn uuid(bytes: &[u8]) -> Option<String> {
+    if bytes.len() != 16 || bytes.iter().all(|byte| *byte == 0) {
+        return None;
+    }
+    Some(format!(
+        "{}-{}-{}-{}-{}",
+        hex(&bytes[0..4]),
+        hex(&bytes[4..6]),
+        hex(&bytes[6..8]),
+        hex(&bytes[8..10]),
+        hex(&bytes[10..16])
+    ))
+}
+
+/// Formats a GUID, whose first three fields are little endian (e.g. GPT).
+fn guid(bytes: &[u8]) -> Option<String> {
+    if bytes.len() != 16 {
+        return None;
+    }
+    let mut swapped = bytes.to_vec();
+    swapped[0..4].reverse();
+    swapped[4..6].reverse();
+    swapped[6..8].reverse();
+    uuid(&swapped)
+}
+
+/// Reads a nul padded label.
+fn label(bytes: &[u8]) -> Option<String> {
+    let end = bytes
+        .iter()
+        .position(|byte| *byte == 0)
+        .unwrap_or(bytes.len());
+    let label = String::from_utf8_lossy(&bytes[..end])
+        .trim_end()
+        .to_string();
+    (!label.is_empty()).then_some(label)
+}
+
+/// Reads the filesystem identity from the start of a device.
+///
+/// # Arguments
+///
+/// * `data` - The first bytes of the device (up to `PROBE_LENGTH`).
+///
+/// # Returns
+///
+/// * `Option<Superblock>` - The identity, or `None` if no supported
+///   filesystem was found.
+pub fn probe(data: &[u8]) -> Option<Superblock> {
+    let at = |offset: usize, len: usize| data.get(offset..offset + len);
+
+    // ext2/3/4: superblock at 1024, magic 0xef53
+    if at(1024 + 0x38, 2) == Some(&[0x53, 0xef]) {
+        return Some(Superblock {
+            fstype: "ext4",
+            uuid: uuid(at(1024 + 0x68, 16)?),
+            label: label(at(1024 + 0x78, 16)?),
+        });
+    }
+    if at(0, 4) == Some(b"XFSB") {
+        return Some(Superblock {
+            fstype: "xfs",
+            uuid: uuid(at(32, 16)?),
+            label: label(at(108, 12)?),
+        });
+    }
+    // btrfs: superblock at 64KiB
+    if at(0x10040, 8) == Some(b"_BHRfS_M") {
+        return Some(Superblock {
+            fstype: "btrfs",
+            uuid: uuid(at(0x10020, 16)?),
+            label: label(at(0x1012b, 256)?),
+        });
+    }
+    // vfat: the volume id is shown as `XXXX-XXXX`
+    if at(510, 2) == Some(&[0x55, 0xaa]) {
+        let (id, name) = if at(0x52, 5) == Some(b"FAT32") {
+            (0x43, 0x47)
+        } else if at(0x36, 3) == Some(b"FAT") {
+            (0x27, 0x2b)
+        } else {
+            return None;
+        };
+        let id = at(id, 4)?;
+        return Some(Superblock {
+            fstype: "vfat",
+            uuid: Some(format!(
+                "{:02X}{:02X}-{:02X}{:02X}",
+                id[3], id[2], id[1], id[0]
+            )),
+            label: label(at(name, 11)?).filter(|label| label != "NO NAME"),
+        });
+    }
+
+    None
+}
+
+/// Reads exactly `len` bytes at `offset`.
+fn read_at(disk: &mut (impl Read + Seek), offset: u64, len: usize) -> io::Result<Vec<u8>> {
+    let mut buf = vec![0; len];
+    disk.seek(SeekFrom::Start(offset))?;
+    disk.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+/// Reads the UUID of a partition from the partition table of its disk.
+///
+/// GPT partitions have a GUID, MBR partitions are identified by the disk
+/// signature and the partition number (`<signature>-<number>`).
+///
+/// # Arguments
+///
+/// * `disk` - The disk holding the partition table.
+/// * `number` - The number of the partition, starting at 1.
+pub fn partuuid(disk: &mut (impl Read + Seek), number: u32) -> Option<String> {
+    if number == 0 {
+        return None;
+    }
+
+    let header = read_at(disk, SECTOR_SIZE, 92).ok()?;
+    if &header[0..8] == b"EFI PART" {
+        let entries = u64::from_le_bytes(header[72..80].try_into().ok()?);
+        let count = u32::from_le_bytes(header[80..84].try_into().ok()?);
+        let size = u32::from_le_bytes(header[84..88].try_into().ok()?) as u64;
+        if number > count || size < 32 {
+            return None;
+        }
+        let offset = entries * SECTOR_SIZE + (number as u64 - 1) * size;
+        return guid(&read_at(disk, offset + 16, 16).ok()?);
+    }
+
+    let mbr = read_at(disk, 0, 512).ok()?;
+    if mbr[510..512] != [0x55, 0xaa] {
+        return None;
+    }
+    let signature = u32::from_le_bytes(mbr[440..444].try_into().ok()?);
+    Some(format!("{:08x}-{:02x}", signature, number))
+}
+
+/// Reads the identity of the device `name` (e.g. `sda2`) by `id`.
+fn read_id(name: &str, id: DeviceId) -> Option<String> {
+    let device = Path::new("/dev").join(name);
+    match id {
+        DeviceId::Uuid | DeviceId::Label => {
+            let mut data = Vec::with_capacity(PROBE_LENGTH);
+            File::open(&device)
+                .ok()?
+                .take(PROBE_LENGTH as u64)
+                .read_to_end(&mut data)
+                .ok()?;
+            let superblock = probe(&data)?;
+            match id {
+                DeviceId::Uuid => superblock.uuid,
+                _ => superblock.label,
+            }
+        }
+        DeviceId::PartUuid => {
+            let sys = Path::new(SYS_BLOCK).join(name);
+            let number = fs::read_to_string(sys.join("partition"))
+                .ok()?
+                .trim()
+                .parse::<u32>()
+                .ok()?;
+            // the partition is a subdirectory of its disk in sysfs
+            let disk = fs::canonicalize(&sys).ok()?;
+            let disk = disk.parent()?.file_name()?;
+            partuuid(&mut File::open(Path::new("/dev").join(disk)).ok()?, number)
+        }
+    }
+}
+
+/// Finds the block device identified by `value`.
+///
+/// # Arguments
+///
+/// * `id` - What `value` identifies the device by.
+/// * `value` - The UUID or label, UUIDs are compared ignoring case.
+///
+/// # Returns
+///
+/// * `Option<PathBuf>` - The device node (e.g. `/dev/sda2`).
+pub fn find(id: DeviceId, value: &str) -> Option<PathBuf> {
+    let mut names = fs::read_dir(SYS_BLOCK)
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.file_name().to_string_lossy().to_string())
+        .collect::<Vec<String>>();
+    names.sort();
+
+    names
+        .into_iter()
+        .find(|name| {
+            let found = read_id(name, id);
+            debug!("Probed '{}': {:?} {:?}", name, id, found);
+            match (id, found) {
+                (DeviceId::Label, Some(found)) => found == value,
+                (_, Some(found)) => found.eq_ignore_ascii_case(value),
+                (_, None) => false,
+            }
+        })
+        .map(|name| Path::new("/dev").join(name))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    #[test]
+    fn probes_ext4() {
+        let mut data = vec![0; 4096];
+        data[1024 + 0x38..1024 + 0x3a].copy_from_slice(&[0x53, 0xef]);
+        data[1024 + 0x68..1024 + 0x78].copy_from_slice(&[
+            0x3f, 0x2a, 0x9b, 0x10, 0x6c, 0x1e, 0x4d, 0x5a, 0x9e, 0x11, 0x22, 0x33, 0x44, 0x55,
+            0x66, 0x77,
+        ]);
+        data[1024 + 0x78..1024 + 0x7c].copy_from_slice(b"root");
+
+        assert_eq!(
+            probe(&data),
+            Some(Superblock {
+                fstype: "ext4",
+                uuid: Some("3f2a9b10-6c1e-4d5a-9e11-223344556677".to_string()),
+                label: Some("root".to_string()),
+            })
+        );
+    }
+
+    #[test]
+    fn probes_vfat() {
+        let mut data = vec![0; 512];
+        data[510..512].copy_from_slice(&[0x55, 0xaa]);
+        data[0x52..0x57].copy_from_slice(b"FAT32");
+        data[0x43..0x47].copy_from_slice(&[0x78, 0x56, 0x34, 0x12]);
+        data[0x47..0x52].copy_from_slice(b"EFI        ");
+
+        let superblock = probe(&data).unwrap();
+        assert_eq!(superblock.uuid.as_deref(), Some("1234-5678"));
+        assert_eq!(superblock.label.as_deref(), Some("EFI"));
+    }
+
+    #[test]
+    fn unknown_filesystem() {
+        assert_eq!(probe(&[0; 4096]), None);
+    }
+
+    #[test]
+    fn partuuid_of_gpt() {
+        let mut disk = vec![0; 4 * 512];
+        disk[512..520].copy_from_slice(b"EFI PART");
+        disk[512 + 72..512 + 80].copy_from_slice(&2u64.to_le_bytes());
+        disk[512 + 80..512 + 84].copy_from_slice(&4u32.to_le_bytes());
+        disk[512 + 84..512 + 88].copy_from_slice(&128u32.to_le_bytes());
+        // the unique GUID of the second entry
+        let entry = 2 * 512 + 128;
+        disk[entry + 16..entry + 32].copy_from_slice(&[
+            0x10, 0x9b, 0x2a, 0x3f, 0x1e, 0x6c, 0x5a, 0x4d, 0x9e, 0x11, 0x22, 0x33, 0x44, 0x55,
+            0x66, 0x77,
+        ]);
+
+        assert_eq!(
+            partuuid(&mut Cursor::new(&disk), 2).as_deref(),
+            Some("3f2a9b10-6c1e-4d5a-9e11-223344556677")
+        );
+        assert_eq!(partuuid(&mut Cursor::new(&disk), 5), None);
+    }
+
+    #[test]
+    fn partuuid_of_mbr() {
+        let mut disk = vec![0; 1024];
+        disk[440..444].copy_from_slice(&0xdeadbeefu32.to_le_bytes());
+        disk[510..512].copy_from_slice(&[0x55, 0xaa]);
+
+        assert_eq!(
+            partuuid(&mut Cursor::new(&disk), 3).as_deref(),
+            Some("deadbeef-03")
+        );
+    }
+}
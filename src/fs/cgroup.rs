@@ -0,0 +1,286 @@
+//! Mounting of the cgroup hierarchies (`cgroup=`).
+//!
+//! By default the unified cgroup2 hierarchy is mounted at `/sys/fs/cgroup`.
+//! Some workloads (e.g. older container tooling) still need the v1
+//! hierarchies, one per controller at `/sys/fs/cgroup/<controller>` on a
+//! tmpfs. The hybrid layout mounts both, cgroup2 at `/sys/fs/cgroup/unified`.
+use std::{fs, path::Path};
+
+use log::{debug, warn};
+use nix::mount::MsFlags;
+
+use super::mount::{MountError, Mounter, SystemMounter};
+
+/// Where the cgroup hierarchies are mounted.
+pub const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+
+/// Where cgroup2 is mounted below `CGROUP_ROOT` in the hybrid layout.
+pub const UNIFIED_DIR_NAME: &str = "unified";
+
+/// Lists the cgroup v1 controllers known to the kernel.
+pub const PROC_CGROUPS: &str = "/proc/cgroups";
+
+/// The cgroup layout (`cgroup=`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CgroupMode {
+    /// The v1 hierarchies only.
+    V1,
+    /// The unified cgroup2 hierarchy only.
+    V2,
+    /// The v1 hierarchies and cgroup2 below them.
+    Hybrid,
+}
+
+impl CgroupMode {
+    pub const ALL: [CgroupMode; 3] = [CgroupMode::V1, CgroupMode::V2, CgroupMode::Hybrid];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            CgroupMode::V1 => "v1",
+            CgroupMode::V2 => "v2",
+            CgroupMode::Hybrid => "hybrid",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<CgroupMode> {
+        CgroupMode::ALL.into_iter().find(|mode| mode.name() == name)
+    }
+}
+
+/// Parses the enabled controllers from the contents of `/proc/cgroups`.
+///
+/// Each line is `<name> <hierarchy> <cgroups> <enabled>`,
+/// following a header line starting with `#`.
+pub fn parse_controllers(contents: &str) -> Vec<String> {
+    contents
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .filter_map(|line| {
+            let fields = line.split_whitespace().collect::<Vec<&str>>();
+            match fields.as_slice() {
+                [name, _, _, "1"] => Some(name.to_string()),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+/// Mounts a filesystem, creating the mountpoint first.
+fn mount_at(
+    mounter: &impl Mounter,
+    fstype: &str,
+    target: &str,
+    flags: MsFlags,
+    data: Option<&str>,
+) -> Result<(), MountError> {
+    if let Err(err) = fs::create_dir_all(target) {
+        return Err(MountError {
+            mountpoint: target.to_string(),
+            err: nix::errno::Errno::from_i32(err.raw_os_error().unwrap_or(0)),
+        });
+    }
+
+    if let Err(err) = mounter.mount(Some(fstype), target, Some(fstype), flags, data) {
+        return Err(MountError {
+            mountpoint: target.to_string(),
+            err,
+        });
+    }
+
+    Ok(())
+}
+
+/// Mounts a v1 hierarchy for each controller enabled in `proc_cgroups`
+/// on a tmpfs at `root`.
+///
+/// A controller failing to mount (e.g. as it is bound to another
+/// hierarchy already) is skipped with a warning.
+fn mount_v1(mounter: &impl Mounter, root: &str, proc_cgroups: &Path) -> Result<(), MountError> {
+    let flags = MsFlags::MS_NOSUID | MsFlags::MS_NODEV | MsFlags::MS_NOEXEC;
+    mount_at(mounter, "tmpfs", root, flags, Some("mode=755"))?;
+
+    let controllers = match fs::read_to_string(proc_cgroups) {
+        Ok(contents) => parse_controllers(&contents),
+        Err(err) => {
+            warn!("Failed reading '{}': {}", proc_cgroups.display(), err);
+            Vec::new()
+        }
+    };
+    for controller in controllers {
+        let target = format!("{}/{}", root, controller);
+        match mount_at(mounter, "cgroup", &target, flags, Some(&controller)) {
+            Ok(()) => debug!("Mounted cgroup controller '{}'", controller),
+            Err(err) => warn!("Skipping cgroup controller '{}': {}", controller, err),
+        }
+    }
+
+    Ok(())
+}
+
+/// Mounts the cgroup hierarchies, `/sys` has to be mounted.
+///
+/// # Arguments
+///
+/// * `mode` - The cgroup layout (see `CgroupMode`).
+pub fn mount_cgroups(mode: CgroupMode) -> Result<(), MountError> {
+    mount_cgroups_with(&SystemMounter, mode, CGROUP_ROOT, Path::new(PROC_CGROUPS))
+}
+
+/// Mounts the cgroup hierarchies (see `mount_cgroups`).
+///
+/// # Arguments
+///
+/// * `mounter` - Mounts the filesystems (see `SystemMounter`).
+/// * `mode` - The cgroup layout (see `CgroupMode`).
+/// * `root` - Where the hierarchies are mounted (see `CGROUP_ROOT`).
+/// * `proc_cgroups` - Lists the v1 controllers (see `PROC_CGROUPS`).
+pub(crate) fn mount_cgroups_with(
+    mounter: &impl Mounter,
+    mode: CgroupMode,
+    root: &str,
+    proc_cgroups: &Path,
+) -> Result<(), MountError> {
+    match mode {
+        CgroupMode::V2 => mount_at(mounter, "cgroup2", root, MsFlags::empty(), None),
+        CgroupMode::V1 => mount_v1(mounter, root, proc_cgroups),
+        CgroupMode::Hybrid => {
+            mount_v1(mounter, root, proc_cgroups)?;
+            let unified = format!("{}/{}", root, UNIFIED_DIR_NAME);
+            mount_at(mounter, "cgroup2", &unified, MsFlags::empty(), None)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use super::*;
+
+    /// The contents of `/proc/cgroups` of a kernel with
+    /// the `memory` controller disabled (`cgroup_disable=memory`).
+    const PROC_CGROUPS_CONTENTS: &str = "\
+#subsys_name\thierarchy\tnum_cgroups\tenabled
+cpuset\t0\t1\t1
+cpu\t0\t1\t1
+memory\t0\t1\t0
+pids\t0\t1\t1
+";
+
+    /// A mount as passed to the mount system call.
+    type Mount = (String, String, Option<String>);
+
+    /// Records the mounts instead of mounting.
+    #[derive(Default)]
+    struct RecordingMounter {
+        mounts: RefCell<Vec<Mount>>,
+        /// The target failing to mount.
+        fail: Option<String>,
+    }
+
+    impl Mounter for RecordingMounter {
+        fn mount(
+            &self,
+            _src: Option<&str>,
+            target: &str,
+            fstype: Option<&str>,
+            _flags: MsFlags,
+            data: Option<&str>,
+        ) -> nix::Result<()> {
+            self.mounts.borrow_mut().push((
+                fstype.unwrap_or_default().to_string(),
+                target.to_string(),
+                data.map(|data| data.to_string()),
+            ));
+            match &self.fail {
+                Some(fail) if fail == target => Err(nix::errno::Errno::EBUSY),
+                _ => Ok(()),
+            }
+        }
+    }
+
+    /// Mounts the hierarchies of `mode` below a temporary directory.
+    ///
+    /// # Returns
+    ///
+    /// * `(Vec<Mount>, String)` - The mounts and the root they were mounted at.
+    fn mount_mode(mode: CgroupMode, fail: Option<&str>) -> (Vec<Mount>, String) {
+        let dir = std::env::temp_dir().join(format!(
+            "linos-test-cgroup-{}-{}",
+            mode.name(),
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let proc_cgroups = dir.join("cgroups");
+        fs::write(&proc_cgroups, PROC_CGROUPS_CONTENTS).unwrap();
+        let root = dir.join("cgroup").to_string_lossy().to_string();
+
+        let mounter = RecordingMounter {
+            fail: fail.map(|fail| format!("{}/{}", root, fail)),
+            ..RecordingMounter::default()
+        };
+        mount_cgroups_with(&mounter, mode, &root, &proc_cgroups).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        (mounter.mounts.into_inner(), root)
+    }
+
+    fn mount(fstype: &str, target: String, data: Option<&str>) -> Mount {
+        (
+            fstype.to_string(),
+            target,
+            data.map(|data| data.to_string()),
+        )
+    }
+
+    #[test]
+    fn parses_enabled_controllers() {
+        assert_eq!(
+            parse_controllers(PROC_CGROUPS_CONTENTS),
+            ["cpuset", "cpu", "pids"]
+        );
+        // malformed lines are skipped
+        assert_eq!(
+            parse_controllers("#subsys_name\nio 0 1\n\nnet_cls 0 1 1 extra\nrdma 0 1 1\n"),
+            ["rdma"]
+        );
+        assert!(parse_controllers("").is_empty());
+    }
+
+    #[test]
+    fn v2_mounts_the_unified_hierarchy_only() {
+        let (mounts, root) = mount_mode(CgroupMode::V2, None);
+        assert_eq!(mounts, [mount("cgroup2", root, None)]);
+    }
+
+    #[test]
+    fn v1_mounts_a_hierarchy_per_enabled_controller() {
+        let (mounts, root) = mount_mode(CgroupMode::V1, None);
+        assert_eq!(
+            mounts,
+            [
+                mount("tmpfs", root.clone(), Some("mode=755")),
+                mount("cgroup", format!("{}/cpuset", root), Some("cpuset")),
+                mount("cgroup", format!("{}/cpu", root), Some("cpu")),
+                mount("cgroup", format!("{}/pids", root), Some("pids")),
+            ]
+        );
+    }
+
+    #[test]
+    fn hybrid_mounts_the_unified_hierarchy_below_v1() {
+        // a controller failing to mount is skipped
+        let (mounts, root) = mount_mode(CgroupMode::Hybrid, Some("cpu"));
+        assert_eq!(
+            mounts,
+            [
+                mount("tmpfs", root.clone(), Some("mode=755")),
+                mount("cgroup", format!("{}/cpuset", root), Some("cpuset")),
+                mount("cgroup", format!("{}/cpu", root), Some("cpu")),
+                mount("cgroup", format!("{}/pids", root), Some("pids")),
+                mount("cgroup2", format!("{}/unified", root), None),
+            ]
+        );
+    }
+}
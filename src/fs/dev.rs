@@ -0,0 +1,177 @@
+//! Creation of device nodes.
+//!
+//! devtmpfs provides the nodes of all devices known to the kernel, but
+//! images might rely on nodes it does not create, and a plain tmpfs
+//! mounted at `/dev` has none at all.
+use core::fmt;
+use std::{fs, os::unix::fs::PermissionsExt, path::Path};
+
+use log::debug;
+use nix::{
+    errno::Errno,
+    sys::stat::{makedev, mknod, Mode, SFlag},
+};
+
+/// The type of a device node.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceKind {
+    Char,
+    Block,
+}
+
+/// A device node (see `mknod(2)`).
+#[derive(Debug, Clone, Copy)]
+pub struct DeviceNode {
+    pub path: &'static str,
+    pub kind: DeviceKind,
+    pub major: u64,
+    pub minor: u64,
+    /// The permissions of the node (e.g. `0o666`).
+    pub mode: u32,
+}
+
+impl DeviceNode {
+    const fn char(path: &'static str, major: u64, minor: u64, mode: u32) -> DeviceNode {
+        DeviceNode {
+            path,
+            kind: DeviceKind::Char,
+            major,
+            minor,
+            mode,
+        }
+    }
+}
+
+/// The nodes essential for a shell and linµos itself.
+pub const ESSENTIAL_DEVICE_NODES: [DeviceNode; 9] = [
+    DeviceNode::char("/dev/console", 5, 1, 0o600),
+    DeviceNode::char("/dev/null", 1, 3, 0o666),
+    DeviceNode::char("/dev/zero", 1, 5, 0o666),
+    DeviceNode::char("/dev/full", 1, 7, 0o666),
+    DeviceNode::char("/dev/random", 1, 8, 0o666),
+    DeviceNode::char("/dev/urandom", 1, 9, 0o666),
+    DeviceNode::char("/dev/kmsg", 1, 11, 0o644),
+    DeviceNode::char("/dev/tty", 5, 0, 0o666),
+    DeviceNode::char("/dev/ptmx", 5, 2, 0o666),
+];
+
+#[derive(Debug, Clone)]
+pub struct DeviceNodeError {
+    pub path: String,
+    pub err: Errno,
+}
+
+impl fmt::Display for DeviceNodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "failed creating device node {}: {}", self.path, self.err)
+    }
+}
+
+/// Creates a single device node, an existing node is kept.
+///
+/// # Returns
+///
+/// * `Result<bool, DeviceNodeError>` - Whether the node was created.
+fn create(node: &DeviceNode) -> Result<bool, DeviceNodeError> {
+    let error = |err| DeviceNodeError {
+        path: node.path.to_string(),
+        err,
+    };
+    let kind = match node.kind {
+        DeviceKind::Char => SFlag::S_IFCHR,
+        DeviceKind::Block => SFlag::S_IFBLK,
+    };
+
+    if let Some(parent) = Path::new(node.path).parent() {
+        fs::create_dir_all(parent)
+            .map_err(|err| error(Errno::from_i32(err.raw_os_error().unwrap_or(0))))?;
+    }
+    match mknod(
+        node.path,
+        kind,
+        Mode::from_bits_truncate(node.mode),
+        makedev(node.major, node.minor),
+    ) {
+        Ok(()) => {}
+        Err(Errno::EEXIST) => return Ok(false),
+        Err(err) => return Err(error(err)),
+    }
+
+    // mknod is subject to the umask
+    fs::set_permissions(node.path, fs::Permissions::from_mode(node.mode))
+        .map_err(|err| error(Errno::from_i32(err.raw_os_error().unwrap_or(0))))?;
+
+    Ok(true)
+}
+
+/// Creates the device nodes missing in `/dev`.
+///
+/// # Arguments
+///
+/// * `nodes` - The nodes to create (e.g. `ESSENTIAL_DEVICE_NODES`).
+pub fn create_device_nodes(nodes: &[DeviceNode]) -> Result<(), DeviceNodeError> {
+    for node in nodes {
+        if create(node)? {
+            debug!("Created device node {}", node.path);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn essential_nodes_match_the_kernel_numbers() {
+        let spec = |path: &str| {
+            ESSENTIAL_DEVICE_NODES
+                .iter()
+                .find(|node| node.path == path)
+                .map(|node| (node.kind, node.major, node.minor, node.mode))
+        };
+        assert_eq!(spec("/dev/console"), Some((DeviceKind::Char, 5, 1, 0o600)));
+        assert_eq!(spec("/dev/null"), Some((DeviceKind::Char, 1, 3, 0o666)));
+        assert_eq!(spec("/dev/urandom"), Some((DeviceKind::Char, 1, 9, 0o666)));
+        assert_eq!(spec("/dev/kmsg"), Some((DeviceKind::Char, 1, 11, 0o644)));
+        assert_eq!(spec("/dev/ptmx"), Some((DeviceKind::Char, 5, 2, 0o666)));
+
+        for (i, node) in ESSENTIAL_DEVICE_NODES.iter().enumerate() {
+            assert!(node.path.starts_with("/dev/"), "{}", node.path);
+            assert_eq!(node.mode & !0o777, 0, "{}", node.path);
+            // neither two paths nor two paths for the same device
+            assert!(ESSENTIAL_DEVICE_NODES[i + 1..].iter().all(|other| {
+                other.path != node.path
+                    && (other.kind, other.major, other.minor) != (node.kind, node.major, node.minor)
+            }));
+        }
+    }
+
+    #[test]
+    fn existing_node_is_kept() {
+        let dir = std::env::temp_dir().join(format!("linos-test-dev-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("null");
+        fs::write(&path, "keep").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o600)).unwrap();
+
+        let node = DeviceNode::char(
+            Box::leak(path.to_str().unwrap().to_string().into_boxed_str()),
+            1,
+            3,
+            0o666,
+        );
+        assert!(!create(&node).unwrap());
+        assert!(create_device_nodes(&[node]).is_ok());
+        // neither replaced nor its permissions changed
+        assert_eq!(fs::read_to_string(&path).unwrap(), "keep");
+        assert_eq!(
+            fs::metadata(&path).unwrap().permissions().mode() & 0o777,
+            0o600
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,259 @@
+//! Checking of filesystems before they are mounted.
+//!
+//! Entries of `/etc/fstab` with a nonzero pass number (the sixth field)
+//! are checked with `fsck -a` before mounting them. The exit code of
+//! fsck decides whether the boot continues:
+//!
+//! | Exit code | Meaning | Action |
+//! | --- | --- | --- |
+//! | `0` | No errors | Mount |
+//! | `1` | Errors corrected | Mount |
+//! | `2` | Errors corrected, reboot advised | Reboot, or check once more with `fsck.corrected=rerun` |
+//! | `4` | Errors left uncorrected | Rescue shell |
+//! | `8` | Operational error | Rescue shell |
+//!
+//! The codes are combined bitwise, the most severe bit wins. A failing
+//! check of an entry with the `nofail` option only skips the entry.
+use core::fmt;
+use std::{io, path::Path};
+
+use log::{debug, info, warn};
+use nix::sys::wait::WaitStatus;
+
+use super::fstab::FstabEntry;
+use crate::{exec, reaper};
+
+/// The filesystem checker, running the type specific `fsck.<type>`.
+pub const FSCK: &str = "/sbin/fsck";
+
+/// No errors.
+pub const EXIT_CLEAN: i32 = 0;
+/// Filesystem errors corrected.
+pub const EXIT_CORRECTED: i32 = 1;
+/// Filesystem errors corrected, the system should be rebooted.
+pub const EXIT_REBOOT: i32 = 2;
+/// Filesystem errors left uncorrected.
+pub const EXIT_UNCORRECTED: i32 = 4;
+/// Operational error.
+pub const EXIT_OPERATIONAL: i32 = 8;
+
+/// What to do if fsck corrected errors and advises a reboot (`fsck.corrected=`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CorrectedPolicy {
+    /// Reboot, so nothing runs with stale filesystem state.
+    Reboot,
+    /// Check the filesystem once more and mount it if it is clean then.
+    Rerun,
+}
+
+impl CorrectedPolicy {
+    pub const ALL: [CorrectedPolicy; 2] = [CorrectedPolicy::Reboot, CorrectedPolicy::Rerun];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            CorrectedPolicy::Reboot => "reboot",
+            CorrectedPolicy::Rerun => "rerun",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<CorrectedPolicy> {
+        CorrectedPolicy::ALL
+            .into_iter()
+            .find(|policy| policy.name() == name)
+    }
+}
+
+/// The action following a check.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum FsckAction {
+    /// The filesystem can be mounted.
+    Mount,
+    /// The filesystem has to be checked once more.
+    Rerun,
+    /// The system has to be rebooted.
+    Reboot,
+    /// The errors have to be repaired manually.
+    Rescue,
+}
+
+impl fmt::Display for FsckAction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FsckAction::Mount => write!(f, "mount"),
+            FsckAction::Rerun => write!(f, "check again"),
+            FsckAction::Reboot => write!(f, "reboot"),
+            FsckAction::Rescue => write!(f, "drop to a rescue shell"),
+        }
+    }
+}
+
+/// Decides the action following a check.
+///
+/// # Arguments
+///
+/// * `code` - The exit code of fsck.
+/// * `policy` - What to do if fsck advises a reboot.
+/// * `rerun` - Whether this was the second check already.
+pub fn decide(code: i32, policy: CorrectedPolicy, rerun: bool) -> FsckAction {
+    if code & !(EXIT_CORRECTED | EXIT_REBOOT) != 0 {
+        // uncorrected, operational errors and everything more severe
+        return FsckAction::Rescue;
+    }
+    if code & EXIT_REBOOT != 0 {
+        return match policy {
+            CorrectedPolicy::Rerun if !rerun => FsckAction::Rerun,
+            _ => FsckAction::Reboot,
+        };
+    }
+
+    FsckAction::Mount
+}
+
+/// Runs fsck on the source of an entry.
+///
+/// # Returns
+///
+/// * `io::Result<i32>` - The exit code, a check killed by a
+///   signal counts as an operational error.
+fn run(entry: &FstabEntry) -> io::Result<i32> {
+    let mut command = exec::command(FSCK, &["-a", &entry.source], exec::Output::Inherit)?;
+    let child = reaper::spawn_command(&mut command, "fsck")?;
+    match reaper::wait(&child)? {
+        WaitStatus::Exited(_, code) => Ok(code),
+        _ => Ok(EXIT_OPERATIONAL),
+    }
+}
+
+/// Checks a single entry, at most twice.
+fn check(entry: &FstabEntry, policy: CorrectedPolicy) -> FsckAction {
+    let mut rerun = false;
+    loop {
+        debug!("Checking '{}'", entry.source);
+        let code = match run(entry) {
+            Ok(code) => code,
+            Err(err) => {
+                warn!("Failed checking '{}': {}", entry.source, err);
+                EXIT_OPERATIONAL
+            }
+        };
+        let action = decide(code, policy, rerun);
+        if code != EXIT_CLEAN {
+            info!(
+                "fsck of '{}' exited with {}, {}",
+                entry.source, code, action
+            );
+        }
+        if action != FsckAction::Rerun {
+            return action;
+        }
+        rerun = true;
+    }
+}
+
+/// Checks the entries with a nonzero pass number, in the order of their pass.
+///
+/// The root filesystem is mounted already and not checked. Checks are
+/// skipped if fsck is not installed. Entries with the `nofail` option
+/// failing their check are removed, so they are not mounted.
+///
+/// # Arguments
+///
+/// * `entries` - The entries of the filesystem table (see `fstab::read`).
+/// * `policy` - What to do if fsck advises a reboot.
+///
+/// # Returns
+///
+/// * `FsckAction` - The most severe action of all checks.
+pub fn check_all(entries: &mut Vec<FstabEntry>, policy: CorrectedPolicy) -> FsckAction {
+    let mut checked = entries
+        .iter()
+        .filter(|entry| entry.pass > 0 && entry.target != "/" && !entry.has_option("noauto"))
+        .cloned()
+        .collect::<Vec<_>>();
+    if checked.is_empty() {
+        return FsckAction::Mount;
+    }
+    if !Path::new(FSCK).exists() {
+        warn!("Skipping filesystem checks, '{}' is missing", FSCK);
+        return FsckAction::Mount;
+    }
+    checked.sort_by_key(|entry| entry.pass);
+
+    let mut result = FsckAction::Mount;
+    for entry in checked {
+        let action = check(&entry, policy);
+        if action == FsckAction::Rescue && entry.has_option("nofail") {
+            warn!(
+                "Skipping '{}' (nofail): filesystem check failed",
+                entry.target
+            );
+            entries.retain(|e| e.target != entry.target);
+            continue;
+        }
+        result = result.max(action);
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exit_code_decides_action() {
+        use CorrectedPolicy::{Reboot, Rerun};
+        use FsckAction::{Mount, Reboot as Restart, Rerun as Again, Rescue};
+
+        // code, policy, second check, action
+        let table = [
+            (EXIT_CLEAN, Reboot, false, Mount),
+            (EXIT_CLEAN, Rerun, false, Mount),
+            (EXIT_CLEAN, Rerun, true, Mount),
+            (EXIT_CORRECTED, Reboot, false, Mount),
+            (EXIT_CORRECTED, Rerun, false, Mount),
+            (EXIT_CORRECTED, Rerun, true, Mount),
+            (EXIT_REBOOT, Reboot, false, Restart),
+            (EXIT_REBOOT, Reboot, true, Restart),
+            (EXIT_REBOOT, Rerun, false, Again),
+            (EXIT_REBOOT, Rerun, true, Restart),
+            (EXIT_CORRECTED | EXIT_REBOOT, Reboot, false, Restart),
+            (EXIT_CORRECTED | EXIT_REBOOT, Rerun, false, Again),
+            (EXIT_UNCORRECTED, Reboot, false, Rescue),
+            (EXIT_UNCORRECTED, Rerun, false, Rescue),
+            (EXIT_UNCORRECTED, Rerun, true, Rescue),
+            (EXIT_OPERATIONAL, Reboot, false, Rescue),
+            (EXIT_OPERATIONAL, Rerun, false, Rescue),
+            (EXIT_OPERATIONAL, Rerun, true, Rescue),
+            // the most severe bit wins
+            (EXIT_REBOOT | EXIT_UNCORRECTED, Rerun, false, Rescue),
+            // usage error, cancelled by the user, shared library error
+            (16, Reboot, false, Rescue),
+            (32, Rerun, false, Rescue),
+            (128, Reboot, false, Rescue),
+        ];
+        for (code, policy, rerun, action) in table {
+            assert_eq!(
+                decide(code, policy, rerun),
+                action,
+                "{} {:?} rerun={}",
+                code,
+                policy,
+                rerun
+            );
+        }
+    }
+
+    #[test]
+    fn parses_corrected_policy() {
+        assert_eq!(
+            CorrectedPolicy::parse("reboot"),
+            Some(CorrectedPolicy::Reboot)
+        );
+        assert_eq!(
+            CorrectedPolicy::parse("rerun"),
+            Some(CorrectedPolicy::Rerun)
+        );
+        assert_eq!(CorrectedPolicy::parse("ignore"), None);
+    }
+}
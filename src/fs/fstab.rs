@@ -0,0 +1,256 @@
+//! Mounting of the filesystems listed in `/etc/fstab`.
+//!
+//! Each line is `<source> <target> <type> <options> [<dump> <pass>]`,
+//! e.g.:
+//!
+//! ```text
+//! /dev/vdb1     /data          ext4  noatime   0 2
+//! /data/foo     /var/lib/foo   none  bind      0 0
+//! ```
+//!
+//! Entries for `/`, `swap` and entries with the `noauto` option are
+//! skipped, entries with the `bind` (or `rbind`) option are bind mounts,
+//! remounted read-only with the `ro` option.
+//! Entries with the `nofail` option (e.g. a removable data disk) are
+//! skipped with a warning if they fail to mount.
+use std::{fs, path::Path};
+
+use log::{debug, warn};
+use nix::mount::{mount, MsFlags};
+use serde::Deserialize;
+
+use super::mount::{bind_mount_with, MountError, SystemMounter};
+
+/// Path of the filesystem table.
+pub const FSTAB: &str = "/etc/fstab";
+
+/// An entry of the filesystem table.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct FstabEntry {
+    pub source: String,
+    pub target: String,
+    pub fstype: String,
+    #[serde(default)]
+    pub options: Vec<String>,
+    /// The order in which the filesystem is checked, `0` skips the check (see `fsck`).
+    #[serde(default)]
+    pub pass: u32,
+}
+
+impl FstabEntry {
+    /// Checks if the entry has the option `name`.
+    pub fn has_option(&self, name: &str) -> bool {
+        self.options.iter().any(|option| option == name)
+    }
+
+    /// Splits the options into mount flags and the
+    /// filesystem specific data passed to the kernel.
+    fn flags(&self) -> (MsFlags, String) {
+        let mut flags = MsFlags::empty();
+        let mut data = Vec::new();
+        for option in &self.options {
+            match option.as_str() {
+                "ro" => flags |= MsFlags::MS_RDONLY,
+                "nosuid" => flags |= MsFlags::MS_NOSUID,
+                "nodev" => flags |= MsFlags::MS_NODEV,
+                "noexec" => flags |= MsFlags::MS_NOEXEC,
+                "noatime" => flags |= MsFlags::MS_NOATIME,
+                "relatime" => flags |= MsFlags::MS_RELATIME,
+                "sync" => flags |= MsFlags::MS_SYNCHRONOUS,
+                // options handled by linµos itself or the defaults
+                "defaults" | "rw" | "auto" | "suid" | "dev" | "exec" | "async" | "nofail" => {}
+                _ => data.push(option.as_str()),
+            }
+        }
+
+        (flags, data.join(","))
+    }
+}
+
+/// Parses the filesystem table.
+///
+/// Comments, empty and malformed lines are skipped, the latter with a warning.
+///
+/// # Arguments
+///
+/// * `contents` - The contents of the filesystem table.
+pub fn parse(contents: &str) -> Vec<FstabEntry> {
+    contents
+        .lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| {
+            let fields = line.split_whitespace().collect::<Vec<&str>>();
+            if fields.len() < 4 {
+                warn!("Skipping malformed fstab entry '{}'", line);
+                return None;
+            }
+            Some(FstabEntry {
+                source: fields[0].to_string(),
+                target: fields[1].to_string(),
+                fstype: fields[2].to_string(),
+                options: fields[3].split(',').map(|o| o.to_string()).collect(),
+                pass: fields
+                    .get(5)
+                    .and_then(|pass| pass.parse::<u32>().ok())
+                    .unwrap_or(0),
+            })
+        })
+        .collect()
+}
+
+/// Reads the filesystem table, a missing table has no entries.
+///
+/// # Arguments
+///
+/// * `path` - The path of the filesystem table (see `FSTAB`).
+pub fn read(path: &Path) -> Vec<FstabEntry> {
+    match fs::read_to_string(path) {
+        Ok(contents) => parse(&contents),
+        Err(_) => Vec::new(),
+    }
+}
+
+/// Mounts a single entry.
+pub fn mount_entry(entry: &FstabEntry) -> Result<(), MountError> {
+    if entry.has_option("bind") || entry.has_option("rbind") {
+        return bind_mount_with(
+            &SystemMounter,
+            &entry.source,
+            &entry.target,
+            entry.has_option("rbind"),
+            entry.has_option("ro"),
+        );
+    }
+
+    if let Err(err) = fs::create_dir_all(&entry.target) {
+        return Err(MountError {
+            mountpoint: entry.target.clone(),
+            err: nix::errno::Errno::from_i32(err.raw_os_error().unwrap_or(0)),
+        });
+    }
+
+    let (flags, data) = entry.flags();
+    if let Err(err) = mount(
+        Some(entry.source.as_str()),
+        Path::new(&entry.target),
+        Some(entry.fstype.as_str()),
+        flags,
+        Some(data.as_str()).filter(|data| !data.is_empty()),
+    ) {
+        return Err(MountError {
+            mountpoint: format!("{} -> {}", entry.source, entry.target),
+            err,
+        });
+    }
+
+    Ok(())
+}
+
+/// Mounts the entries in order.
+///
+/// A failing entry aborts the mounting, unless it has the `nofail` option.
+///
+/// # Arguments
+///
+/// * `entries` - The entries of the filesystem table (see `read`).
+pub fn mount_all(entries: &[FstabEntry]) -> Result<(), MountError> {
+    mount_entries(entries, mount_entry)
+}
+
+/// Mounts the entries in order using `mount` (see `mount_all`).
+fn mount_entries(
+    entries: &[FstabEntry],
+    mut mount: impl FnMut(&FstabEntry) -> Result<(), MountError>,
+) -> Result<(), MountError> {
+    for entry in entries {
+        if entry.target == "/" || entry.fstype == "swap" || entry.has_option("noauto") {
+            debug!("Skipping fstab entry for '{}'", entry.target);
+            continue;
+        }
+        debug!("Mounting '{}' at '{}'", entry.source, entry.target);
+        match mount(entry) {
+            Ok(()) => {}
+            Err(err) if entry.has_option("nofail") => {
+                warn!("Skipping '{}' (nofail): {}", entry.target, err)
+            }
+            Err(err) => return Err(err),
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_entries() {
+        let entries = parse(
+            "# <source> <target> <type> <options> <dump> <pass>\n\
+             \n\
+             /dev/vdb1\t/data  ext4  noatime,ro,commit=60  0 2\n\
+             /data/foo /var/lib/foo none bind\n\
+             /dev/vdc1 /broken ext4\n",
+        );
+
+        assert_eq!(
+            entries,
+            [
+                FstabEntry {
+                    source: "/dev/vdb1".to_string(),
+                    target: "/data".to_string(),
+                    fstype: "ext4".to_string(),
+                    options: vec![
+                        "noatime".to_string(),
+                        "ro".to_string(),
+                        "commit=60".to_string()
+                    ],
+                    pass: 2,
+                },
+                FstabEntry {
+                    source: "/data/foo".to_string(),
+                    target: "/var/lib/foo".to_string(),
+                    fstype: "none".to_string(),
+                    options: vec!["bind".to_string()],
+                    pass: 0,
+                },
+            ]
+        );
+        assert_eq!(
+            entries[0].flags(),
+            (
+                MsFlags::MS_NOATIME | MsFlags::MS_RDONLY,
+                "commit=60".to_string()
+            )
+        );
+        assert!(entries[1].has_option("bind"));
+    }
+
+    #[test]
+    fn only_nofail_entries_may_fail() {
+        let entries = parse(
+            "/dev/vda / ext4 defaults 0 1\n\
+             /dev/sdb1 /media/usb vfat nofail 0 0\n\
+             /dev/vdb1 /data ext4 noauto 0 2\n\
+             /dev/vdc1 /srv ext4 defaults 0 2\n\
+             /dev/vdd1 /var ext4 defaults 0 2\n",
+        );
+        let mut mounted = Vec::new();
+        let result = mount_entries(&entries, |entry| {
+            mounted.push(entry.target.clone());
+            match entry.target.as_str() {
+                "/media/usb" | "/srv" => Err(MountError {
+                    mountpoint: entry.target.clone(),
+                    err: nix::errno::Errno::ENODEV,
+                }),
+                _ => Ok(()),
+            }
+        });
+
+        // the failing `/srv` stops the mounting before `/var`
+        assert_eq!(result.unwrap_err().mountpoint, "/srv");
+        assert_eq!(mounted, ["/media/usb", "/srv"]);
+    }
+}
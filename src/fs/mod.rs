@@ -1,3 +1,13 @@
+pub mod blkid;
+pub mod cgroup;
+pub mod dev;
+pub mod fsck;
+pub mod fstab;
 pub mod mount;
+pub mod nfs;
+pub mod root;
+pub mod sysfile;
 
-pub use mount::mountfs;
+pub use dev::{create_device_nodes, DeviceKind, DeviceNode};
+pub use mount::{bind_mount, mountfs, move_mount, set_propagation, switch_root, Propagation};
+pub use sysfile::{atomic_write, write_config_file};
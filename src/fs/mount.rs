@@ -1,7 +1,57 @@
 use core::fmt;
-use std::path::Path;
+use std::{env, fs, path::Path};
 
-use nix::mount::{mount, MsFlags};
+use log::{debug, warn};
+
+use nix::{
+    mount::{mount, umount, MsFlags},
+    unistd::chroot,
+};
+
+use super::{
+    cgroup::{self, CgroupMode},
+    dev, nfs, root,
+};
+use crate::exec;
+
+/// Filesystems the kernel has to support to boot linµos.
+pub const REQUIRED_FILESYSTEMS: [&str; 5] = ["proc", "tmpfs", "devtmpfs", "sysfs", "cgroup2"];
+
+/// Optional kernel filesystems (`mount.extra=`): name, type and mountpoint.
+pub const EXTRA_FILESYSTEMS: [(&str, &str, &str); 3] = [
+    ("bpf", "bpf", "/sys/fs/bpf"),
+    ("tracefs", "tracefs", "/sys/kernel/tracing"),
+    ("debugfs", "debugfs", "/sys/kernel/debug"),
+];
+
+/// Where a root mounted by linµos itself (e.g. an NFS root) is
+/// mounted before it is switched to (see `switch_root`).
+pub const SYSROOT: &str = "/sysroot";
+
+/// The kernel filesystems moved along when switching the root.
+const MOVED_MOUNTS: [&str; 5] = ["/dev", "/proc", "/sys", "/run", "/tmp"];
+
+/// Directory on the `/run` tmpfs holding the writable layer of `/etc` (`etc.overlay=1`).
+pub const ETC_OVERLAY_DIR: &str = "/run/linos/etc";
+
+/// Filesystems without data to flush, left mounted on shutdown.
+const VIRTUAL_FILESYSTEMS: [&str; 15] = [
+    "proc",
+    "sysfs",
+    "devtmpfs",
+    "devpts",
+    "tmpfs",
+    "cgroup",
+    "cgroup2",
+    "bpf",
+    "tracefs",
+    "debugfs",
+    "securityfs",
+    "pstore",
+    "mqueue",
+    "configfs",
+    "hugetlbfs",
+];
 
 #[derive(Debug, Clone)]
 pub struct MountError {
@@ -15,6 +65,33 @@ impl fmt::Display for MountError {
     }
 }
 
+/// Mount propagation types (see: https://docs.kernel.org/filesystems/sharedsubtree.html).
+///
+/// The `R` variants apply the propagation to all mounts below the path as well.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Propagation {
+    Shared,
+    RShared,
+    Slave,
+    RSlave,
+    Private,
+    RPrivate,
+}
+
+impl Propagation {
+    /// Returns the mount flags setting this propagation.
+    pub fn flags(&self) -> MsFlags {
+        match self {
+            Propagation::Shared => MsFlags::MS_SHARED,
+            Propagation::RShared => MsFlags::MS_SHARED | MsFlags::MS_REC,
+            Propagation::Slave => MsFlags::MS_SLAVE,
+            Propagation::RSlave => MsFlags::MS_SLAVE | MsFlags::MS_REC,
+            Propagation::Private => MsFlags::MS_PRIVATE,
+            Propagation::RPrivate => MsFlags::MS_PRIVATE | MsFlags::MS_REC,
+        }
+    }
+}
+
 /// Mounts the proc filesystem at `/proc`.
 pub fn proc() -> Result<(), MountError> {
     if let Err(err) = mount(
@@ -26,7 +103,192 @@ pub fn proc() -> Result<(), MountError> {
     ) {
         return Err(MountError {
             mountpoint: "/proc".to_string(),
-            err: err,
+            err,
+        });
+    }
+
+    Ok(())
+}
+
+/// Moves an existing mount to a new location.
+///
+/// # Arguments
+///
+/// * `src` - The mountpoint of the existing mount (e.g. `/newroot/dev`).
+/// * `dst` - The new mountpoint (e.g. `/dev`).
+pub fn move_mount(src: &str, dst: &str) -> Result<(), MountError> {
+    if let Err(err) = mount(
+        Some(Path::new(src)),
+        Path::new(dst),
+        None::<&str>,
+        MsFlags::MS_MOVE,
+        None::<&str>,
+    ) {
+        return Err(MountError {
+            mountpoint: format!("{} -> {}", src, dst),
+            err,
+        });
+    }
+
+    Ok(())
+}
+
+/// Switches the root to the filesystem mounted at `new_root`.
+///
+/// The kernel filesystems are moved to the new root, the old root (e.g.
+/// an initramfs) stays in memory but is no longer reachable.
+///
+/// # Arguments
+///
+/// * `new_root` - The mountpoint of the new root (e.g. `SYSROOT`).
+pub fn switch_root(new_root: &str) -> Result<(), MountError> {
+    let error = |err| MountError {
+        mountpoint: format!("{} -> /", new_root),
+        err,
+    };
+
+    for mountpoint in MOVED_MOUNTS {
+        let target = format!("{}{}", new_root, mountpoint);
+        fs::create_dir_all(&target)
+            .map_err(|err| error(nix::errno::Errno::from_i32(err.raw_os_error().unwrap_or(0))))?;
+        move_mount(mountpoint, &target)?;
+    }
+
+    env::set_current_dir(new_root)
+        .map_err(|err| error(nix::errno::Errno::from_i32(err.raw_os_error().unwrap_or(0))))?;
+    move_mount(".", "/")?;
+    chroot(".").map_err(error)?;
+    env::set_current_dir("/")
+        .map_err(|err| error(nix::errno::Errno::from_i32(err.raw_os_error().unwrap_or(0))))
+}
+
+/// The mount system call (see `SystemMounter`), so the sequences
+/// of mounts can be compared against the mounts of a fake system.
+pub(crate) trait Mounter {
+    fn mount(
+        &self,
+        src: Option<&str>,
+        target: &str,
+        fstype: Option<&str>,
+        flags: MsFlags,
+        data: Option<&str>,
+    ) -> nix::Result<()>;
+}
+
+/// Mounts on the running system.
+pub(crate) struct SystemMounter;
+
+impl Mounter for SystemMounter {
+    fn mount(
+        &self,
+        src: Option<&str>,
+        target: &str,
+        fstype: Option<&str>,
+        flags: MsFlags,
+        data: Option<&str>,
+    ) -> nix::Result<()> {
+        mount(src, Path::new(target), fstype, flags, data)
+    }
+}
+
+/// Bind mounts `src` to `dst`.
+///
+/// A missing destination is created, as a directory or
+/// an empty file depending on the type of `src`.
+///
+/// # Arguments
+///
+/// * `src` - The directory to bind (e.g. `/data/foo`).
+/// * `dst` - The mountpoint (e.g. `/var/lib/foo`).
+/// * `recursive` - Binds the mounts below `src` as well.
+pub fn bind_mount(src: &str, dst: &str, recursive: bool) -> Result<(), MountError> {
+    bind_mount_with(&SystemMounter, src, dst, recursive, false)
+}
+
+/// Bind mounts `src` to `dst` (see `bind_mount`), read-only if requested.
+///
+/// The kernel ignores `MS_RDONLY` when binding, so a read-only bind mount
+/// is remounted read-only once bound. Only `dst` itself is remounted,
+/// the mounts bound below it keep their flags.
+///
+/// # Arguments
+///
+/// * `mounter` - Mounts the filesystems (see `SystemMounter`).
+/// * `src` - The directory to bind (e.g. `/data/foo`).
+/// * `dst` - The mountpoint (e.g. `/var/lib/foo`).
+/// * `recursive` - Binds the mounts below `src` as well.
+/// * `read_only` - Remounts the bind mount read-only.
+pub(crate) fn bind_mount_with(
+    mounter: &impl Mounter,
+    src: &str,
+    dst: &str,
+    recursive: bool,
+    read_only: bool,
+) -> Result<(), MountError> {
+    if !Path::new(dst).exists() {
+        let created = if Path::new(src).is_dir() {
+            fs::create_dir_all(dst)
+        } else {
+            fs::File::create(dst).map(|_| ())
+        };
+        if let Err(err) = created {
+            return Err(MountError {
+                mountpoint: dst.to_string(),
+                err: nix::errno::Errno::from_i32(err.raw_os_error().unwrap_or(0)),
+            });
+        }
+    }
+
+    let mut flags = MsFlags::MS_BIND;
+    if recursive {
+        flags |= MsFlags::MS_REC;
+    }
+    if let Err(err) = mounter.mount(Some(src), dst, None, flags, None) {
+        return Err(MountError {
+            mountpoint: format!("{} -> {} (bind)", src, dst),
+            err,
+        });
+    }
+
+    if read_only {
+        let flags = MsFlags::MS_BIND | MsFlags::MS_REMOUNT | MsFlags::MS_RDONLY;
+        if let Err(err) = mounter.mount(None, dst, None, flags, None) {
+            return Err(MountError {
+                mountpoint: format!("{} (read-only)", dst),
+                err,
+            });
+        }
+    }
+
+    Ok(())
+}
+
+/// Changes the propagation of an existing mount.
+///
+/// # Arguments
+///
+/// * `path` - The mountpoint (e.g. `/`).
+/// * `flag` - The propagation to set.
+pub fn set_propagation(path: &str, flag: Propagation) -> Result<(), MountError> {
+    set_propagation_with(&SystemMounter, path, flag)
+}
+
+/// Changes the propagation of an existing mount (see `set_propagation`).
+///
+/// # Arguments
+///
+/// * `mounter` - Mounts the filesystems (see `SystemMounter`).
+/// * `path` - The mountpoint (e.g. `/`).
+/// * `flag` - The propagation to set.
+pub(crate) fn set_propagation_with(
+    mounter: &impl Mounter,
+    path: &str,
+    flag: Propagation,
+) -> Result<(), MountError> {
+    if let Err(err) = mounter.mount(None, path, None, flag.flags(), None) {
+        return Err(MountError {
+            mountpoint: format!("{} ({:?})", path, flag),
+            err,
         });
     }
 
@@ -34,17 +296,18 @@ pub fn proc() -> Result<(), MountError> {
 }
 
 /// Sets up the required filesystems for the system to boot.
-/// This includes mounting /tmp, /proc, /dev, / and /sys.
+/// This includes mounting /tmp, /proc, /dev, /, /run and /sys.
 ///
 /// # Arguments
 ///
 /// * `root_disk` - The path to the root disk
-///                (e.g. '/dev/sda' or '/dev/vda')
+///   (e.g. '/dev/sda' or '/dev/vda')
+/// * `cgroup` - The cgroup layout mounted at `/sys/fs/cgroup`.
 ///
 /// # Panics
 ///
 /// Panics if mounting any of the filesystems fails.
-pub fn mountfs(root_disk: &str) -> Result<(), MountError> {
+pub fn mountfs(root_disk: &str, cgroup: CgroupMode) -> Result<(), MountError> {
     if let Err(err) = mount(
         Some("tmpfs"),
         Path::new("/tmp"),
@@ -54,7 +317,7 @@ pub fn mountfs(root_disk: &str) -> Result<(), MountError> {
     ) {
         return Err(MountError {
             mountpoint: "/tmp".to_string(),
-            err: err,
+            err,
         });
     }
 
@@ -67,7 +330,7 @@ pub fn mountfs(root_disk: &str) -> Result<(), MountError> {
     ) {
         return Err(MountError {
             mountpoint: "/proc".to_string(),
-            err: err,
+            err,
         });
     }
 
@@ -81,21 +344,42 @@ pub fn mountfs(root_disk: &str) -> Result<(), MountError> {
         if err != nix::errno::Errno::EBUSY {
             return Err(MountError {
                 mountpoint: "/dev".to_string(),
-                err: err,
+                err,
             });
         } // otherwise /dev is already mounted
     }
+    // before anything opens them, a failure leaves a usable /dev behind
+    if let Err(err) = dev::create_device_nodes(&dev::ESSENTIAL_DEVICE_NODES) {
+        warn!("{}", err);
+    }
+
+    // an NFS root is mounted once the network is up (see `nfs`),
+    // root candidates are mounted and switched to afterwards (see `root`)
+    if !nfs::is_nfs_root(root_disk) && !root::is_mounted_by_init(root_disk) {
+        if let Err(err) = mount(
+            Some(root_disk),
+            Path::new("/"),
+            Some(""),
+            MsFlags::MS_REMOUNT,
+            None::<&str>,
+        ) {
+            return Err(MountError {
+                mountpoint: format!("/ -> {}", root_disk),
+                err,
+            });
+        }
+    }
 
     if let Err(err) = mount(
-        Some(root_disk),
-        Path::new("/"),
-        Some(""),
-        MsFlags::MS_REMOUNT,
-        None::<&str>,
+        Some("tmpfs"),
+        Path::new("/run"),
+        Some("tmpfs"),
+        MsFlags::MS_NOSUID | MsFlags::MS_NODEV,
+        Some("mode=755"),
     ) {
         return Err(MountError {
-            mountpoint: format!("/ -> {}", root_disk),
-            err: err,
+            mountpoint: "/run".to_string(),
+            err,
         });
     }
 
@@ -108,22 +392,448 @@ pub fn mountfs(root_disk: &str) -> Result<(), MountError> {
     ) {
         return Err(MountError {
             mountpoint: "/sys".to_string(),
-            err: err,
+            err,
         });
     }
 
+    cgroup::mount_cgroups(cgroup)
+}
+
+/// Mounts an overlay filesystem.
+///
+/// # Arguments
+///
+/// * `lower` - The read-only lower directory (e.g. `/etc`).
+/// * `upper` - The writable upper directory, changes end up here.
+/// * `work` - The work directory, on the same filesystem as `upper`.
+/// * `target` - The mountpoint, may be the same as `lower`.
+pub fn overlay(lower: &str, upper: &str, work: &str, target: &str) -> Result<(), MountError> {
+    for dir in [upper, work] {
+        if let Err(err) = fs::create_dir_all(dir) {
+            return Err(MountError {
+                mountpoint: dir.to_string(),
+                err: nix::errno::Errno::from_i32(err.raw_os_error().unwrap_or(0)),
+            });
+        }
+    }
+
     if let Err(err) = mount(
-        Some("cgroup2"),
-        Path::new("/sys/fs/cgroup"),
-        Some("cgroup2"),
+        Some("overlay"),
+        Path::new(target),
+        Some("overlay"),
         MsFlags::empty(),
-        None::<&str>,
+        Some(format!("lowerdir={},upperdir={},workdir={}", lower, upper, work).as_str()),
     ) {
         return Err(MountError {
-            mountpoint: "/sys/fs/cgroup".to_string(),
-            err: err,
+            mountpoint: format!("{} (overlay)", target),
+            err,
         });
     }
 
     Ok(())
 }
+
+/// Mounts a writable overlay over a read-only `/etc`.
+///
+/// The upper layer lives on the `/run` tmpfs, so runtime files
+/// (e.g. `resolv.conf` or `hosts`) are written there and the
+/// base image is left untouched. `/run` has to be mounted.
+pub fn etc_overlay() -> Result<(), MountError> {
+    overlay(
+        "/etc",
+        &format!("{}/upper", ETC_OVERLAY_DIR),
+        &format!("{}/work", ETC_OVERLAY_DIR),
+        "/etc",
+    )
+}
+
+/// Checks if `size` is a valid tmpfs size, a number of bytes with an
+/// optional `k`, `m` or `g` suffix or a percentage of the RAM (e.g. `64m`, `10%`).
+pub fn is_valid_tmpfs_size(size: &str) -> bool {
+    let number = size
+        .strip_suffix(['k', 'K', 'm', 'M', 'g', 'G', '%'])
+        .unwrap_or(size);
+    number.parse::<u64>().is_ok_and(|number| number > 0)
+}
+
+/// Mounts a size capped tmpfs at `/var/log` (`log.tmpfs=`), so the logs of
+/// the started programs can not fill the root filesystem. Once the tmpfs is
+/// full, writing logs fails for the logging programs only.
+///
+/// # Arguments
+///
+/// * `size` - The size of the tmpfs (see `is_valid_tmpfs_size`).
+pub fn log_tmpfs(size: &str) -> Result<(), MountError> {
+    if let Err(err) = fs::create_dir_all(exec::LOG_DIR) {
+        return Err(MountError {
+            mountpoint: exec::LOG_DIR.to_string(),
+            err: nix::errno::Errno::from_i32(err.raw_os_error().unwrap_or(0)),
+        });
+    }
+
+    if let Err(err) = mount(
+        Some("tmpfs"),
+        Path::new(exec::LOG_DIR),
+        Some("tmpfs"),
+        MsFlags::MS_NOSUID | MsFlags::MS_NODEV | MsFlags::MS_NOEXEC,
+        Some(format!("size={},mode=755", size).as_str()),
+    ) {
+        return Err(MountError {
+            mountpoint: exec::LOG_DIR.to_string(),
+            err,
+        });
+    }
+
+    Ok(())
+}
+
+/// Mounts optional kernel filesystems (see `EXTRA_FILESYSTEMS`).
+///
+/// Filesystems not supported by the kernel are skipped with a warning.
+/// The mountpoints are created if missing, `/sys` provides them for
+/// all filesystems the kernel supports.
+///
+/// # Arguments
+///
+/// * `names` - The names of the filesystems to mount (e.g. `bpf`).
+pub fn mount_extra(names: &[String]) -> Result<(), MountError> {
+    for name in names {
+        let (_, fstype, mountpoint) = match EXTRA_FILESYSTEMS.iter().find(|fs| fs.0 == name) {
+            Some(fs) => fs,
+            None => {
+                warn!("Skipping unknown filesystem '{}'", name);
+                continue;
+            }
+        };
+
+        if let Err(err) = fs::create_dir_all(mountpoint) {
+            warn!(
+                "Skipping '{}', missing mountpoint {}: {}",
+                name, mountpoint, err
+            );
+            continue;
+        }
+
+        if let Err(err) = mount(
+            Some(*fstype),
+            Path::new(mountpoint),
+            Some(*fstype),
+            MsFlags::MS_NOSUID | MsFlags::MS_NODEV | MsFlags::MS_NOEXEC,
+            None::<&str>,
+        ) {
+            match err {
+                nix::errno::Errno::ENODEV => {
+                    warn!("Skipping '{}', not supported by the kernel", name)
+                }
+                nix::errno::Errno::EBUSY => debug!("'{}' is already mounted", name),
+                err => {
+                    return Err(MountError {
+                        mountpoint: mountpoint.to_string(),
+                        err,
+                    })
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Decodes the octal escapes (e.g. `\040` for a space) of `/proc/mounts`.
+fn unescape(field: &str) -> String {
+    let mut out = String::new();
+    let mut rest = field;
+    while let Some(i) = rest.find('\\') {
+        out.push_str(&rest[..i]);
+        match rest
+            .get(i + 1..i + 4)
+            .and_then(|code| u8::from_str_radix(code, 8).ok())
+        {
+            Some(byte) => {
+                out.push(byte as char);
+                rest = &rest[i + 4..];
+            }
+            None => {
+                out.push('\\');
+                rest = &rest[i + 1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Lists the mountpoints to unmount on shutdown, the last mounted first.
+///
+/// The root and the virtual filesystems (see `VIRTUAL_FILESYSTEMS`)
+/// are left mounted.
+///
+/// # Arguments
+///
+/// * `mounts` - The contents of `/proc/self/mounts`.
+pub fn unmount_order(mounts: &str) -> Vec<String> {
+    mounts
+        .lines()
+        .rev()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let target = unescape(fields.nth(1)?);
+            let fstype = fields.next()?;
+            (target != "/" && !VIRTUAL_FILESYSTEMS.contains(&fstype)).then_some(target)
+        })
+        .collect()
+}
+
+/// Remounts a mounted filesystem read-only.
+fn remount_readonly(target: &str) -> nix::Result<()> {
+    mount(
+        None::<&str>,
+        Path::new(target),
+        None::<&str>,
+        MsFlags::MS_REMOUNT | MsFlags::MS_RDONLY,
+        None::<&str>,
+    )
+}
+
+/// Unmounts the filesystems and remounts the root read-only before the
+/// system is powered off, so no data is lost. Filesystems still in use
+/// are remounted read-only instead, failures are logged.
+pub fn unmount_all() {
+    let mounts = match fs::read_to_string("/proc/self/mounts") {
+        Ok(mounts) => mounts,
+        Err(err) => {
+            warn!("Failed reading the mounts: {}", err);
+            return;
+        }
+    };
+
+    for target in unmount_order(&mounts) {
+        match umount(Path::new(&target)) {
+            Ok(()) => debug!("Unmounted '{}'", target),
+            Err(err) => match remount_readonly(&target) {
+                Ok(()) => debug!("Remounted busy '{}' read-only: {}", target, err),
+                Err(err) => warn!("Failed unmounting '{}': {}", target, err),
+            },
+        }
+    }
+    if let Err(err) = remount_readonly("/") {
+        warn!("Failed remounting '/' read-only: {}", err);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use super::*;
+
+    /// A mount as passed to the mount system call.
+    type Mount = (Option<String>, String, MsFlags);
+
+    /// Records the mounts instead of mounting.
+    #[derive(Default)]
+    struct RecordingMounter {
+        mounts: RefCell<Vec<Mount>>,
+        fail: Option<nix::Error>,
+    }
+
+    impl Mounter for RecordingMounter {
+        fn mount(
+            &self,
+            src: Option<&str>,
+            target: &str,
+            _fstype: Option<&str>,
+            flags: MsFlags,
+            _data: Option<&str>,
+        ) -> nix::Result<()> {
+            self.mounts.borrow_mut().push((
+                src.map(|src| src.to_string()),
+                target.to_string(),
+                flags,
+            ));
+            match self.fail {
+                Some(err) => Err(err),
+                None => Ok(()),
+            }
+        }
+    }
+
+    #[test]
+    fn read_only_bind_mounts_are_remounted() {
+        let dir = std::env::temp_dir().join(format!("linos-test-bind-{}", std::process::id()));
+        let src = dir.join("src");
+        fs::create_dir_all(&src).unwrap();
+        fs::write(dir.join("file"), "").unwrap();
+        let path = |name: &str| dir.join(name).to_string_lossy().to_string();
+
+        let mounter = RecordingMounter::default();
+        bind_mount_with(&mounter, &path("src"), &path("dst"), false, false).unwrap();
+        bind_mount_with(&mounter, &path("src"), &path("ro"), true, true).unwrap();
+        bind_mount_with(&mounter, &path("file"), &path("file-dst"), false, false).unwrap();
+        // the missing destinations are created like the source
+        assert!(dir.join("dst").is_dir());
+        assert!(dir.join("ro").is_dir());
+        assert!(dir.join("file-dst").is_file());
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(
+            *mounter.mounts.borrow(),
+            [
+                (Some(path("src")), path("dst"), MsFlags::MS_BIND),
+                (
+                    Some(path("src")),
+                    path("ro"),
+                    MsFlags::MS_BIND | MsFlags::MS_REC
+                ),
+                (
+                    None,
+                    path("ro"),
+                    MsFlags::MS_BIND | MsFlags::MS_REMOUNT | MsFlags::MS_RDONLY
+                ),
+                (Some(path("file")), path("file-dst"), MsFlags::MS_BIND),
+            ]
+        );
+    }
+
+    #[test]
+    fn failed_bind_mounts_are_not_remounted() {
+        let mounter = RecordingMounter {
+            fail: Some(nix::errno::Errno::ENOENT),
+            ..RecordingMounter::default()
+        };
+        let dir = std::env::temp_dir().to_string_lossy().to_string();
+
+        let err =
+            bind_mount_with(&mounter, "/nonexistent/linos-src", &dir, false, true).unwrap_err();
+        assert_eq!(
+            err.mountpoint,
+            format!("/nonexistent/linos-src -> {} (bind)", dir)
+        );
+        assert_eq!(mounter.mounts.borrow().len(), 1);
+    }
+
+    #[test]
+    fn sets_propagation_flags() {
+        let mounter = RecordingMounter::default();
+        for flag in [
+            Propagation::Shared,
+            Propagation::RShared,
+            Propagation::Slave,
+            Propagation::RSlave,
+            Propagation::Private,
+            Propagation::RPrivate,
+        ] {
+            set_propagation_with(&mounter, "/", flag).unwrap();
+        }
+
+        let flags = mounter
+            .mounts
+            .borrow()
+            .iter()
+            .map(|(src, target, flags)| {
+                assert_eq!((src.as_deref(), target.as_str()), (None, "/"));
+                *flags
+            })
+            .collect::<Vec<MsFlags>>();
+        assert_eq!(
+            flags,
+            [
+                MsFlags::MS_SHARED,
+                MsFlags::MS_SHARED | MsFlags::MS_REC,
+                MsFlags::MS_SLAVE,
+                MsFlags::MS_SLAVE | MsFlags::MS_REC,
+                MsFlags::MS_PRIVATE,
+                MsFlags::MS_PRIVATE | MsFlags::MS_REC,
+            ]
+        );
+
+        let mounter = RecordingMounter {
+            fail: Some(nix::errno::Errno::EINVAL),
+            ..RecordingMounter::default()
+        };
+        let err = set_propagation_with(&mounter, "/data", Propagation::RShared).unwrap_err();
+        assert_eq!(err.mountpoint, "/data (RShared)");
+    }
+
+    #[test]
+    fn unmounts_real_filesystems_last_mounted_first() {
+        let mounts = "/dev/vda / ext4 rw,relatime 0 0\n\
+                      proc /proc proc rw,nosuid,nodev,noexec 0 0\n\
+                      tmpfs /run tmpfs rw,nosuid,nodev 0 0\n\
+                      /dev/vdb1 /data ext4 rw,noatime 0 0\n\
+                      /dev/vdb1 /var/lib/my\\040app ext4 rw,noatime 0 0\n\
+                      cgroup2 /sys/fs/cgroup cgroup2 rw 0 0\n";
+
+        assert_eq!(unmount_order(mounts), ["/var/lib/my app", "/data"]);
+    }
+
+    #[test]
+    fn validates_tmpfs_sizes() {
+        for valid in ["1048576", "64k", "64m", "1G", "10%"] {
+            assert!(is_valid_tmpfs_size(valid), "{}", valid);
+        }
+        for invalid in ["", "0", "0m", "m", "-1m", "64mb", "1.5g", "10%%"] {
+            assert!(!is_valid_tmpfs_size(invalid), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn moving_a_missing_mount_names_both_paths() {
+        let err = move_mount("/nonexistent/linos-src", "/nonexistent/linos-dst").unwrap_err();
+
+        assert_eq!(
+            err.mountpoint,
+            "/nonexistent/linos-src -> /nonexistent/linos-dst"
+        );
+    }
+
+    #[test]
+    fn moves_mounts() {
+        let dir = std::env::temp_dir().join(format!("linos-test-move-{}", std::process::id()));
+        let (src, dst) = (dir.join("src"), dir.join("dst"));
+        fs::create_dir_all(&src).unwrap();
+        fs::create_dir_all(&dst).unwrap();
+
+        // the mount namespace is per thread, so the mounts stay private to it
+        let moved = std::thread::spawn({
+            let (src, dst) = (src.clone(), dst.clone());
+            move || {
+                if nix::sched::unshare(nix::sched::CloneFlags::CLONE_NEWNS).is_err() {
+                    // needs root
+                    return None;
+                }
+                mount(
+                    None::<&str>,
+                    "/",
+                    None::<&str>,
+                    MsFlags::MS_REC | MsFlags::MS_PRIVATE,
+                    None::<&str>,
+                )
+                .unwrap();
+                mount(
+                    Some("tmpfs"),
+                    &src,
+                    Some("tmpfs"),
+                    MsFlags::empty(),
+                    None::<&str>,
+                )
+                .ok()?;
+                fs::write(src.join("file"), "moved").unwrap();
+
+                move_mount(src.to_str().unwrap(), dst.to_str().unwrap()).unwrap();
+                let moved = fs::read_to_string(dst.join("file")).ok();
+                let left = src.join("file").exists();
+                umount(&dst).unwrap();
+                Some((moved, left))
+            }
+        })
+        .join()
+        .unwrap();
+
+        if let Some((moved, left)) = moved {
+            assert_eq!(moved.as_deref(), Some("moved"));
+            assert!(!left);
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
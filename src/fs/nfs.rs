@@ -0,0 +1,184 @@
+//! Mounting of the root filesystem over NFS, for diskless netboots.
+//!
+//! With `root=/dev/nfs nfsroot=<server>:<path>[,<options>]` (or
+//! `root=nfs:<server>:<path>[:<options>]`) linµos is started from an
+//! initramfs and the root is mounted over NFS. As the root is only
+//! reachable over the network, the boot is reordered:
+//!
+//! 1. the kernel filesystems are mounted (see `mountfs`),
+//! 2. the kernel modules are loaded and the network is configured, using
+//!    the configuration of the initramfs and the kernel command line,
+//! 3. the NFS root is mounted at `/sysroot` and switched to (see `switch_root`), moving the
+//!    kernel filesystems along,
+//! 4. the boot continues on the NFS root, with `init.toml`, fstab etc.
+//!    read from it.
+use core::fmt;
+use std::{net::Ipv4Addr, path::Path};
+
+use nix::mount::{mount, MsFlags};
+
+use super::mount::MountError;
+
+/// The `root=` device selecting an NFS root given with `nfsroot=`.
+pub const NFS_ROOT_DEVICE: &str = "/dev/nfs";
+
+/// Mount options used if `nfsroot=` does not give any. Locking needs
+/// `rpc.statd`, which is not running this early.
+pub const DEFAULT_NFS_OPTIONS: &str = "vers=3,nolock";
+
+/// An NFS export to mount as root.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NfsRoot {
+    pub server: Ipv4Addr,
+    /// The exported path (e.g. `/srv/nfs/node-1`).
+    pub path: String,
+    /// The NFS mount options (e.g. `vers=4.2`).
+    pub options: String,
+}
+
+impl fmt::Display for NfsRoot {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.server, self.path)
+    }
+}
+
+/// Parses an NFS root of the form `<server>:<path>`, followed by
+/// the mount options separated by `separator`.
+fn parse_spec(spec: &str, separator: char) -> Option<NfsRoot> {
+    let (server, rest) = spec.split_once(':')?;
+    let (path, options) = rest.split_once(separator).unwrap_or((rest, ""));
+    if !path.starts_with('/') {
+        return None;
+    }
+
+    Some(NfsRoot {
+        server: server.parse::<Ipv4Addr>().ok()?,
+        path: path.to_string(),
+        options: match options {
+            "" => DEFAULT_NFS_OPTIONS.to_string(),
+            options => options.replace(separator, ","),
+        },
+    })
+}
+
+/// Checks if `root=` selects an NFS root.
+pub fn is_nfs_root(root: &str) -> bool {
+    root == NFS_ROOT_DEVICE || root.starts_with("nfs:")
+}
+
+/// Parses the NFS root selected by the `root=` and `nfsroot=` arguments.
+///
+/// # Returns
+///
+/// * `Option<Option<NfsRoot>>` - `Some(None)` if the root is not on NFS,
+///   `None` if the NFS root is missing or invalid.
+pub fn parse(root: &str, nfsroot: Option<&str>) -> Option<Option<NfsRoot>> {
+    if root == NFS_ROOT_DEVICE {
+        return parse_spec(nfsroot?, ',').map(Some);
+    }
+    match root.strip_prefix("nfs:") {
+        Some(spec) => parse_spec(spec, ':').map(Some),
+        None => Some(None),
+    }
+}
+
+impl NfsRoot {
+    /// Mounts the export at `target`, the network has to be configured.
+    pub fn mount(&self, target: &str) -> Result<(), MountError> {
+        let error = |err| MountError {
+            mountpoint: format!("{} -> {}", self, target),
+            err,
+        };
+        std::fs::create_dir_all(target)
+            .map_err(|err| error(nix::errno::Errno::from_i32(err.raw_os_error().unwrap_or(0))))?;
+
+        // without the mount helper, the kernel needs the server address
+        let data = format!("addr={},{}", self.server, self.options);
+        mount(
+            Some(self.to_string().as_str()),
+            Path::new(target),
+            Some("nfs"),
+            MsFlags::empty(),
+            Some(data.as_str()),
+        )
+        .map_err(error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn nfs_root(path: &str, options: &str) -> NfsRoot {
+        NfsRoot {
+            server: Ipv4Addr::new(10, 0, 0, 1),
+            path: path.to_string(),
+            options: options.to_string(),
+        }
+    }
+
+    #[test]
+    fn parses_nfsroot_argument() {
+        assert_eq!(
+            parse(NFS_ROOT_DEVICE, Some("10.0.0.1:/srv/nfs/node-1")),
+            Some(Some(nfs_root("/srv/nfs/node-1", DEFAULT_NFS_OPTIONS)))
+        );
+        assert_eq!(
+            parse(NFS_ROOT_DEVICE, Some("10.0.0.1:/srv/nfs,vers=4.2,ro")),
+            Some(Some(nfs_root("/srv/nfs", "vers=4.2,ro")))
+        );
+    }
+
+    #[test]
+    fn parses_nfs_root_device() {
+        assert_eq!(
+            parse("nfs:10.0.0.1:/srv/nfs", None),
+            Some(Some(nfs_root("/srv/nfs", DEFAULT_NFS_OPTIONS)))
+        );
+        // the options are separated by colons, like the spec itself
+        assert_eq!(
+            parse("nfs:10.0.0.1:/srv/nfs:vers=4.2:ro", None),
+            Some(Some(nfs_root("/srv/nfs", "vers=4.2,ro")))
+        );
+        // nfsroot= only applies to root=/dev/nfs
+        assert_eq!(
+            parse("nfs:10.0.0.1:/srv/nfs", Some("10.0.0.2:/other")),
+            Some(Some(nfs_root("/srv/nfs", DEFAULT_NFS_OPTIONS)))
+        );
+    }
+
+    #[test]
+    fn other_roots_are_not_on_nfs() {
+        assert_eq!(parse("/dev/vda", None), Some(None));
+        assert_eq!(parse("/dev/vda", Some("10.0.0.1:/srv/nfs")), Some(None));
+        assert!(!is_nfs_root("/dev/vda"));
+        assert!(is_nfs_root(NFS_ROOT_DEVICE));
+        assert!(is_nfs_root("nfs:10.0.0.1:/srv/nfs"));
+    }
+
+    #[test]
+    fn rejects_malformed_specs() {
+        // root=/dev/nfs without nfsroot=
+        assert_eq!(parse(NFS_ROOT_DEVICE, None), None);
+        for spec in [
+            "",
+            "10.0.0.1",
+            "10.0.0.1:",
+            "10.0.0.1:srv/nfs",
+            "server:/srv/nfs",
+            "10.0.0.256:/srv/nfs",
+            ":/srv/nfs",
+        ] {
+            assert_eq!(parse(NFS_ROOT_DEVICE, Some(spec)), None, "{}", spec);
+            assert_eq!(parse(&format!("nfs:{}", spec), None), None, "{}", spec);
+        }
+    }
+
+    #[test]
+    fn displays_server_and_path() {
+        assert_eq!(
+            nfs_root("/srv/nfs", DEFAULT_NFS_OPTIONS).to_string(),
+            "10.0.0.1:/srv/nfs"
+        );
+    }
+}
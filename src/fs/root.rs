@@ -0,0 +1,225 @@
+//! Mounting of the root filesystem from a list of candidates.
+//!
+//! `root=` may list several devices separated by `:` (e.g.
+//! `root=/dev/sda2:/dev/sdb2`) and devices may be given as `UUID=`,
+//! `LABEL=` or `PARTUUID=`. The kernel can mount neither, so linµos,
+//! started from an initramfs, mounts the first candidate which mounts
+//! successfully at `/sysroot` and switches to it.
+use core::fmt;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use log::{info, warn};
+use nix::{
+    errno::Errno,
+    mount::{mount, MsFlags},
+};
+
+use super::{
+    blkid::{self, DeviceId},
+    nfs,
+};
+
+/// Prefixes of the device specs, what they identify the device
+/// by and the directories of the udev symlinks resolving them.
+const DEVICE_SPECS: [(&str, DeviceId, &str); 3] = [
+    ("UUID=", DeviceId::Uuid, "/dev/disk/by-uuid"),
+    ("LABEL=", DeviceId::Label, "/dev/disk/by-label"),
+    ("PARTUUID=", DeviceId::PartUuid, "/dev/disk/by-partuuid"),
+];
+
+/// No candidate of the root could be mounted.
+#[derive(Debug, Clone)]
+pub struct RootError {
+    /// The candidates tried and why they failed, in order.
+    pub attempted: Vec<(String, String)>,
+}
+
+impl fmt::Display for RootError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "no root could be mounted, tried {}",
+            self.attempted
+                .iter()
+                .map(|(candidate, err)| format!("'{}' ({})", candidate, err))
+                .collect::<Vec<_>>()
+                .join(", ")
+        )
+    }
+}
+
+/// Splits `root=` into its candidates.
+pub fn candidates(root: &str) -> Vec<&str> {
+    root.split(':').filter(|root| !root.is_empty()).collect()
+}
+
+/// Checks if the root is mounted by linµos instead of the kernel,
+/// as it has more than one candidate or is given as a device spec.
+pub fn is_mounted_by_init(root: &str) -> bool {
+    if nfs::is_nfs_root(root) {
+        return false;
+    }
+    let candidates = candidates(root);
+    candidates.len() > 1
+        || candidates.iter().any(|candidate| {
+            DEVICE_SPECS
+                .iter()
+                .any(|(prefix, _, _)| candidate.starts_with(prefix))
+        })
+}
+
+/// Resolves a candidate to the path of its device.
+///
+/// `UUID=`, `LABEL=` and `PARTUUID=` specs are resolved through the
+/// symlinks in `/dev/disk` if udev created them, otherwise by probing
+/// the block devices (see `blkid`). An unresolved spec resolves to the
+/// missing symlink.
+pub fn resolve(candidate: &str) -> PathBuf {
+    for (prefix, kind, dir) in DEVICE_SPECS {
+        if let Some(id) = candidate.strip_prefix(prefix) {
+            let link = PathBuf::from(dir).join(id);
+            if link.exists() {
+                return link;
+            }
+            return blkid::find(kind, id).unwrap_or(link);
+        }
+    }
+
+    PathBuf::from(candidate)
+}
+
+/// Returns the filesystem types the kernel supports on block devices,
+/// from `/proc/filesystems` (e.g. `ext4`, `xfs`).
+pub fn block_filesystems() -> Vec<String> {
+    fs::read_to_string("/proc/filesystems")
+        .unwrap_or_default()
+        .lines()
+        .filter(|line| !line.starts_with("nodev"))
+        .map(|line| line.trim().to_string())
+        .filter(|fstype| !fstype.is_empty())
+        .collect()
+}
+
+/// Mounts the first candidate which mounts successfully.
+///
+/// # Arguments
+///
+/// * `candidates` - The candidates in order (see `candidates`).
+/// * `mounter` - Mounts a candidate, given the candidate and its device.
+///
+/// # Returns
+///
+/// * `Result<String, RootError>` - The mounted candidate.
+pub fn mount_first(
+    candidates: &[&str],
+    mut mounter: impl FnMut(&str, &Path) -> Result<(), String>,
+) -> Result<String, RootError> {
+    let mut attempted = Vec::new();
+    for candidate in candidates {
+        let device = resolve(candidate);
+        match mounter(candidate, &device) {
+            Ok(()) => return Ok(candidate.to_string()),
+            Err(err) => {
+                warn!("Failed mounting root '{}': {}", candidate, err);
+                attempted.push((candidate.to_string(), err));
+            }
+        }
+    }
+
+    Err(RootError { attempted })
+}
+
+/// Mounts `device` at `target`, trying each of the `fstypes`.
+fn mount_device(device: &Path, target: &str, fstypes: &[String]) -> Result<(), String> {
+    if !device.exists() {
+        return Err(format!("'{}' does not exist", device.display()));
+    }
+
+    let mut last_err = Errno::ENODEV;
+    for fstype in fstypes {
+        match mount(
+            Some(device),
+            target,
+            Some(fstype.as_str()),
+            MsFlags::empty(),
+            None::<&str>,
+        ) {
+            Ok(()) => return Ok(()),
+            Err(err) => last_err = err,
+        }
+    }
+
+    Err(last_err.to_string())
+}
+
+/// Mounts the first mountable candidate of `root=` at `target`.
+///
+/// # Arguments
+///
+/// * `root` - The `root=` argument (e.g. `/dev/sda2:LABEL=root`).
+/// * `target` - The mountpoint (e.g. `SYSROOT`).
+pub fn mount_root(root: &str, target: &str) -> Result<String, RootError> {
+    if let Err(err) = fs::create_dir_all(target) {
+        return Err(RootError {
+            attempted: vec![(target.to_string(), err.to_string())],
+        });
+    }
+
+    let fstypes = block_filesystems();
+    let mounted = mount_first(&candidates(root), |_, device| {
+        mount_device(device, target, &fstypes)
+    })?;
+    info!("Mounted root '{}'", mounted);
+
+    Ok(mounted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mounts_first_mountable_candidate() {
+        let mut tried = Vec::new();
+        let mounted = mount_first(&candidates("/dev/sda2:/dev/sdb2:/dev/sdc2"), |_, device| {
+            tried.push(device.to_path_buf());
+            match device.to_str() {
+                Some("/dev/sdb2") => Ok(()),
+                _ => Err("no such device".to_string()),
+            }
+        });
+
+        assert_eq!(mounted.unwrap(), "/dev/sdb2");
+        assert_eq!(
+            tried,
+            [PathBuf::from("/dev/sda2"), PathBuf::from("/dev/sdb2")]
+        );
+    }
+
+    #[test]
+    fn lists_every_failed_candidate() {
+        let err = mount_first(&candidates("/dev/sda2::/dev/sdb2"), |candidate, _| {
+            Err(format!("{} is broken", candidate))
+        })
+        .unwrap_err();
+
+        assert_eq!(
+            err.attempted,
+            [
+                ("/dev/sda2".to_string(), "/dev/sda2 is broken".to_string()),
+                ("/dev/sdb2".to_string(), "/dev/sdb2 is broken".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn detects_roots_mounted_by_init() {
+        assert!(!is_mounted_by_init("/dev/vda"));
+        assert!(is_mounted_by_init("/dev/sda2:/dev/sdb2"));
+        assert!(is_mounted_by_init("LABEL=root"));
+        assert!(!is_mounted_by_init("/dev/nfs"));
+    }
+}
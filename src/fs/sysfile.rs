@@ -0,0 +1,302 @@
+//! Writing of system files.
+//!
+//! linµos writes a few files on the root filesystem during the boot
+//! (e.g. `/etc/resolv.conf` or `/etc/hosts`), all of them are written
+//! through `write_config_file`, so read-only roots are handled alike.
+//!
+//! The files might be rewritten at runtime (e.g. by the carrier monitor or
+//! the control socket), so they are replaced atomically (see `atomic_write`)
+//! and concurrent writes are serialized. Files which can not be replaced
+//! (on a read-only root or bind mounted over) are replaced by bind mounting
+//! an atomically written file over them (see `redirect`).
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+};
+
+use log::warn;
+use nix::mount::{umount2, MntFlags};
+
+use super::mount::bind_mount;
+
+/// Directory on the `/run` tmpfs holding the system files
+/// written while the root filesystem is read-only.
+pub const REDIRECT_DIR: &str = "/run/linos/rootfs";
+
+/// Serializes the writes of system files.
+static WRITE_LOCK: Mutex<()> = Mutex::new(());
+
+/// Counter making the names of temporary files unique.
+static TMP_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Maximum number of symlinks followed when resolving a path.
+const MAX_SYMLINKS: usize = 40;
+
+/// Resolves `path` to the file it (transitively) points to.
+///
+/// Unlike `fs::canonicalize` this also resolves dangling symlinks,
+/// e.g. `/etc/resolv.conf -> /run/resolv.conf` before the target
+/// has been created.
+///
+/// # Arguments
+///
+/// * `path`: The path to resolve
+pub fn resolve_symlink(path: &Path) -> io::Result<PathBuf> {
+    let mut path = path.to_path_buf();
+
+    for _ in 0..MAX_SYMLINKS {
+        match fs::symlink_metadata(&path) {
+            Ok(meta) if meta.file_type().is_symlink() => {
+                // relative targets are relative to the symlink's directory
+                let target = fs::read_link(&path)?;
+                path = match path.parent() {
+                    Some(parent) => parent.join(target),
+                    None => target,
+                };
+            }
+            Ok(_) => return Ok(path),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(path),
+            Err(err) => return Err(err),
+        }
+    }
+
+    Err(io::Error::other(format!(
+        "Too many levels of symbolic links: {}",
+        path.display()
+    )))
+}
+
+/// Writes a file atomically, creating missing parent directories.
+///
+/// The contents are written to a temporary file next to `path`, which
+/// then replaces `path`. Readers either see the old or the new contents,
+/// never a partially written file.
+///
+/// # Arguments
+///
+/// * `path`: The path of the file to write
+/// * `contents`: The new contents of the file
+pub fn atomic_write(path: &Path, contents: &str) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let tmp = path.with_file_name(format!(
+        ".{}.{}.{}.tmp",
+        name,
+        std::process::id(),
+        TMP_COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+    let result = fs::write(&tmp, contents).and_then(|_| fs::rename(&tmp, path));
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+
+    result
+}
+
+/// Writes the file to `REDIRECT_DIR` and bind mounts it over `path`,
+/// so the file which can not be replaced shows the new contents.
+///
+/// The file is written atomically, a rewritten file is mounted again,
+/// as the earlier mount still holds the replaced file. In between,
+/// readers see the previous contents of `path`, never a partial file.
+///
+/// # Arguments
+///
+/// * `path`: The path of the file to write
+/// * `contents`: The new contents of the file
+/// * `err`: Why the file can not be replaced
+fn redirect(path: &Path, contents: &str, err: io::Error) -> io::Result<()> {
+    if !path.exists() {
+        return Err(io::Error::new(
+            io::ErrorKind::ReadOnlyFilesystem,
+            format!(
+                "'{}' can not be created on the read-only root, add it to the \
+                 image or mount a writable overlay over /etc (etc.overlay=1)",
+                path.display()
+            ),
+        ));
+    }
+
+    let redirected = Path::new(REDIRECT_DIR).join(path.strip_prefix("/").unwrap_or(path));
+    let mounted = redirected.exists();
+    atomic_write(&redirected, contents)?;
+    if mounted {
+        umount2(path, MntFlags::MNT_DETACH).map_err(io::Error::from)?;
+    }
+    bind_mount(
+        &redirected.to_string_lossy(),
+        &path.to_string_lossy(),
+        false,
+    )
+    .map_err(|err| io::Error::other(err.to_string()))?;
+    if !mounted {
+        warn!(
+            "'{}' can not be replaced ({}), redirected to '{}'",
+            path.display(),
+            err,
+            redirected.display()
+        );
+    }
+
+    Ok(())
+}
+
+/// Writes a system file like `/etc/resolv.conf` atomically.
+///
+/// If the file is a symlink, the symlink's target is written
+/// instead of replacing the symlink itself. Missing parent
+/// directories of the target are created.
+///
+/// If the root is read-only or the file is bind mounted over (e.g. by a
+/// container runtime), existing files are redirected to `REDIRECT_DIR`
+/// (see `redirect`), writing them again updates the redirected file.
+///
+/// # Arguments
+///
+/// * `path`: The path of the file to write
+/// * `contents`: The new contents of the file
+pub fn write_config_file(path: &str, contents: &str) -> io::Result<()> {
+    let _lock = WRITE_LOCK.lock().unwrap_or_else(|err| err.into_inner());
+    let path = resolve_symlink(Path::new(path))?;
+    write_or_redirect(&path, contents, atomic_write, redirect)
+}
+
+/// Writes a file with `write`, falling back to `redirect`
+/// if the file can not be replaced.
+fn write_or_redirect(
+    path: &Path,
+    contents: &str,
+    write: impl Fn(&Path, &str) -> io::Result<()>,
+    redirect: impl Fn(&Path, &str, io::Error) -> io::Result<()>,
+) -> io::Result<()> {
+    match write(path, contents) {
+        Err(err) if err.kind() == io::ErrorKind::ReadOnlyFilesystem => {
+            redirect(path, contents, err)
+        }
+        // a file bind mounted over (e.g. by a redirect) can not be replaced
+        Err(err) if err.raw_os_error() == Some(libc::EBUSY) => redirect(path, contents, err),
+        result => result,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, thread};
+
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("linos-test-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn writes_through_symlinks() {
+        let dir = temp_dir("symlink");
+        let link = dir.join("resolv.conf");
+        // dangling and relative, the target directory does not exist yet
+        std::os::unix::fs::symlink("run/resolv.conf", &link).unwrap();
+
+        write_config_file(&link.to_string_lossy(), "nameserver 10.0.0.1\n").unwrap();
+        assert!(fs::symlink_metadata(&link)
+            .unwrap()
+            .file_type()
+            .is_symlink());
+        assert_eq!(
+            fs::read_to_string(dir.join("run/resolv.conf")).unwrap(),
+            "nameserver 10.0.0.1\n"
+        );
+
+        let chained = dir.join("chained.conf");
+        std::os::unix::fs::symlink(&link, &chained).unwrap();
+        assert_eq!(
+            resolve_symlink(&chained).unwrap(),
+            dir.join("run/resolv.conf")
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn unreplaceable_files_are_redirected() {
+        let write_failing = |errno| {
+            move |_: &Path, _: &str| -> io::Result<()> { Err(io::Error::from_raw_os_error(errno)) }
+        };
+        let redirected = std::cell::Cell::new(false);
+        let redirect = |_: &Path, _: &str, _: io::Error| {
+            redirected.set(true);
+            Ok(())
+        };
+        let path = Path::new("/etc/resolv.conf");
+
+        for errno in [libc::EROFS, libc::EBUSY] {
+            redirected.set(false);
+            assert!(write_or_redirect(path, "", write_failing(errno), redirect).is_ok());
+            assert!(redirected.get(), "errno {} is not redirected", errno);
+        }
+
+        redirected.set(false);
+        let result = write_or_redirect(path, "", write_failing(libc::EACCES), redirect);
+        assert_eq!(result.unwrap_err().raw_os_error(), Some(libc::EACCES));
+        assert!(!redirected.get());
+
+        let result = write_or_redirect(path, "", |_, _| Ok(()), redirect);
+        assert!(result.is_ok());
+        assert!(!redirected.get());
+    }
+
+    #[test]
+    fn missing_files_are_not_redirected() {
+        let path = Path::new("/nonexistent/linos-test/hosts");
+        let err = io::Error::from_raw_os_error(libc::EROFS);
+
+        let result = redirect(path, "", err);
+        assert_eq!(
+            result.unwrap_err().kind(),
+            io::ErrorKind::ReadOnlyFilesystem
+        );
+        assert!(!Path::new(REDIRECT_DIR).join("nonexistent").exists());
+    }
+
+    #[test]
+    fn concurrent_writes_are_never_partial() {
+        let dir = temp_dir("atomic-write");
+        let path = Arc::new(dir.join("resolv.conf"));
+        let contents = (0..4)
+            .map(|writer| format!("nameserver 10.0.0.{}\n", writer).repeat(4096))
+            .collect::<Vec<String>>();
+        atomic_write(&path, &contents[0]).unwrap();
+
+        let writers = contents
+            .iter()
+            .cloned()
+            .map(|contents| {
+                let path = path.clone();
+                thread::spawn(move || {
+                    for _ in 0..50 {
+                        atomic_write(&path, &contents).unwrap();
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for _ in 0..200 {
+            let read = fs::read_to_string(path.as_path()).unwrap();
+            assert!(contents.contains(&read), "partial contents read");
+        }
+        writers
+            .into_iter()
+            .for_each(|writer| writer.join().unwrap());
+
+        // no temporary files are left behind
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
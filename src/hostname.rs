@@ -0,0 +1,199 @@
+//! The hostname of the system.
+//!
+//! The hostname is taken from, in order of precedence:
+//!
+//! 1. `hostname=<name>` on the kernel command line (or in `init.toml`)
+//! 2. the DHCP lease, with `hostname=dhcp`
+//! 3. the persisted hostname (`hostname.persist=`)
+//! 4. `/etc/hostname`
+//! 5. the kernel default
+//!
+//! A hostname handed out by DHCP or changed at runtime (using the
+//! control socket) is persisted, as `/etc` might be a read-only image
+//! or an overlay on a tmpfs. The next boot restores it once the
+//! filesystems are mounted, before the network is configured.
+use std::{fs, io, path::Path};
+
+use crate::{
+    cmdline,
+    fs::{atomic_write, write_config_file},
+    net::DhcpLease,
+};
+
+/// File holding the static hostname.
+pub const HOSTNAME_FILE: &str = "/etc/hostname";
+
+/// File the hostname is persisted to by default (`hostname.persist=`).
+pub const PERSIST_FILE: &str = "/var/lib/linos/hostname";
+
+/// Where the hostname is taken from (`hostname=`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Hostname {
+    /// The given hostname.
+    Static(String),
+    /// The hostname handed out by the DHCP server (`hostname=dhcp`).
+    Dhcp,
+}
+
+impl Hostname {
+    /// Parses the value of `hostname=`.
+    ///
+    /// # Returns
+    ///
+    /// * `Option<Hostname>` - `None` if the value is no valid hostname.
+    pub fn parse(value: &str) -> Option<Hostname> {
+        match value {
+            "dhcp" => Some(Hostname::Dhcp),
+            name if cmdline::is_valid_domain(name) => Some(Hostname::Static(name.to_string())),
+            _ => None,
+        }
+    }
+}
+
+/// Sets the hostname of the system.
+pub fn set(hostname: &str) -> nix::Result<()> {
+    nix::unistd::sethostname(hostname)
+}
+
+/// Reads the static hostname, a missing or invalid file has none.
+///
+/// # Arguments
+///
+/// * `path` - The path of the file (see `HOSTNAME_FILE`).
+pub fn read(path: &Path) -> Option<String> {
+    let contents = fs::read_to_string(path).ok()?;
+    let hostname = contents
+        .lines()
+        .map(|line| line.trim())
+        .find(|line| !line.is_empty() && !line.starts_with('#'))?;
+    cmdline::is_valid_domain(hostname).then(|| hostname.to_string())
+}
+
+/// Returns the hostname set early during boot: the configured
+/// hostname, otherwise the static hostname of `HOSTNAME_FILE`.
+///
+/// # Arguments
+///
+/// * `configured` - The hostname configured with `hostname=`.
+/// * `file` - The static hostname (see `read`).
+pub fn initial(configured: Option<&Hostname>, file: Option<String>) -> Option<String> {
+    match configured {
+        Some(Hostname::Static(name)) => Some(name.clone()),
+        // until a DHCP server hands out one, the static hostname is used
+        _ => file,
+    }
+}
+
+/// Returns the persisted hostname restored once the filesystems are
+/// mounted, it takes precedence over all but a configured hostname.
+///
+/// # Arguments
+///
+/// * `configured` - The hostname configured with `hostname=`.
+/// * `persisted` - The persisted hostname (see `read`).
+pub fn restored(configured: Option<&Hostname>, persisted: Option<String>) -> Option<String> {
+    match configured {
+        Some(Hostname::Static(_)) => None,
+        _ => persisted,
+    }
+}
+
+/// Returns the first valid hostname handed out by a DHCP server.
+///
+/// # Arguments
+///
+/// * `leases` - The leases in the order the interfaces were configured.
+pub fn from_leases(leases: &[DhcpLease]) -> Option<String> {
+    leases
+        .iter()
+        .filter_map(|lease| lease.hostname.as_deref())
+        .find(|hostname| cmdline::is_valid_domain(hostname))
+        .map(|hostname| hostname.to_string())
+}
+
+/// Sets the hostname and writes it to `HOSTNAME_FILE`, so
+/// programs reading the file instead of `uname` agree.
+pub fn apply(hostname: &str) -> io::Result<()> {
+    set(hostname)?;
+    write_config_file(HOSTNAME_FILE, &format!("{}\n", hostname))
+}
+
+/// Parses the value of `hostname.persist=`.
+///
+/// # Returns
+///
+/// * `Option<Option<String>>` - The absolute path the hostname is persisted
+///   to, `Some(None)` for `none`, or `None` if the value is invalid.
+pub fn parse_persist(value: &str) -> Option<Option<String>> {
+    match value {
+        "none" => Some(None),
+        path if Path::new(path).is_absolute() => Some(Some(path.to_string())),
+        _ => None,
+    }
+}
+
+/// Applies the hostname (see `apply`) and persists it
+/// to `persist`, so the next boot restores it.
+///
+/// # Arguments
+///
+/// * `hostname` - The new hostname.
+/// * `persist` - The file the hostname is persisted to, if any.
+pub fn change(hostname: &str, persist: Option<&str>) -> io::Result<()> {
+    apply(hostname)?;
+    match persist {
+        Some(path) => atomic_write(Path::new(path), &format!("{}\n", hostname)),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lease(iface: &str, hostname: Option<&str>) -> DhcpLease {
+        DhcpLease {
+            hostname: hostname.map(|hostname| hostname.to_string()),
+            ..DhcpLease::for_test(iface)
+        }
+    }
+
+    #[test]
+    fn configured_hostname_takes_precedence() {
+        let configured = Hostname::parse("node1");
+        let file = || Some("image".to_string());
+        let persisted = || Some("renamed".to_string());
+
+        assert_eq!(
+            initial(configured.as_ref(), file()).as_deref(),
+            Some("node1")
+        );
+        assert_eq!(restored(configured.as_ref(), persisted()), None);
+
+        // until DHCP hands out one, the persisted and the static hostname are used
+        let dhcp = Hostname::parse("dhcp");
+        assert_eq!(dhcp, Some(Hostname::Dhcp));
+        assert_eq!(initial(dhcp.as_ref(), file()).as_deref(), Some("image"));
+        assert_eq!(
+            restored(dhcp.as_ref(), persisted()).as_deref(),
+            Some("renamed")
+        );
+        assert_eq!(initial(None, file()).as_deref(), Some("image"));
+        assert_eq!(restored(None, persisted()).as_deref(), Some("renamed"));
+        assert_eq!(initial(None, None), None);
+    }
+
+    #[test]
+    fn first_valid_leased_hostname_is_used() {
+        let leases = [
+            lease("eth0", None),
+            lease("eth1", Some("-invalid")),
+            lease("eth2", Some("node2")),
+            lease("eth3", Some("node3")),
+        ];
+
+        assert_eq!(from_leases(&leases).as_deref(), Some("node2"));
+        assert_eq!(from_leases(&leases[..2]), None);
+        assert_eq!(Hostname::parse("-invalid"), None);
+    }
+}
@@ -0,0 +1,131 @@
+//! In-memory buffer of the latest log lines.
+//!
+//! Every log record written to the console is also kept in a bounded
+//! ring buffer, once it is full the oldest line is dropped. The buffer
+//! is dumped by the `log` query of the control socket, so the log can be
+//! fetched on systems without a console or writable storage.
+use std::{collections::VecDeque, sync::Mutex};
+
+use log::{Log, Metadata, Record, SetLoggerError};
+use nix::time::{clock_gettime, ClockId};
+
+/// The number of lines kept by default (see `log.lines=`).
+pub const DEFAULT_LOG_LINES: usize = 1000;
+
+/// A buffer of at most `capacity` lines, dropping the oldest.
+#[derive(Debug)]
+pub struct RingBuffer {
+    lines: VecDeque<String>,
+    capacity: usize,
+}
+
+impl RingBuffer {
+    pub const fn new(capacity: usize) -> RingBuffer {
+        RingBuffer {
+            lines: VecDeque::new(),
+            capacity,
+        }
+    }
+
+    /// Appends a line, dropping the oldest if the buffer is full.
+    pub fn push(&mut self, line: String) {
+        if self.capacity == 0 {
+            return;
+        }
+        while self.lines.len() >= self.capacity {
+            self.lines.pop_front();
+        }
+        self.lines.push_back(line);
+    }
+
+    /// Changes the capacity, a capacity of `0` disables the buffer.
+    ///
+    /// Shrinking the buffer drops the oldest lines that no longer fit.
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        while self.lines.len() > capacity {
+            self.lines.pop_front();
+        }
+    }
+
+    /// Returns the buffered lines, oldest first, each terminated by a newline.
+    pub fn dump(&self) -> String {
+        self.lines
+            .iter()
+            .map(|line| format!("{}\n", line))
+            .collect()
+    }
+}
+
+static LOG_BUFFER: Mutex<RingBuffer> = Mutex::new(RingBuffer::new(DEFAULT_LOG_LINES));
+
+/// Writes the records to the console and the log buffer.
+struct Logger {
+    console: env_logger::Logger,
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.console.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        // the console filter (`LOG=`) applies to the buffer as well
+        if !self.console.matches(record) {
+            return;
+        }
+        self.console.log(record);
+
+        // the time since boot, the same as the kernel log
+        let (secs, nanos) = clock_gettime(ClockId::CLOCK_MONOTONIC)
+            .map(|uptime| (uptime.tv_sec(), uptime.tv_nsec()))
+            .unwrap_or((0, 0));
+        let line = format!(
+            "[{:>5}.{:06}] {:<5} {}: {}",
+            secs,
+            nanos / 1000,
+            record.level(),
+            record.target(),
+            record.args()
+        );
+        LOG_BUFFER
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .push(line);
+    }
+
+    fn flush(&self) {
+        self.console.flush();
+    }
+}
+
+/// Installs the logger writing to the console and the log buffer.
+///
+/// # Arguments
+///
+/// * `console` - The console logger.
+/// * `capacity` - The number of lines kept in the buffer, `0` disables it.
+pub fn init(console: env_logger::Logger, capacity: usize) -> Result<(), SetLoggerError> {
+    set_capacity(capacity);
+    let max_level = console.filter();
+    log::set_boxed_logger(Box::new(Logger { console }))?;
+    log::set_max_level(max_level);
+
+    Ok(())
+}
+
+/// Changes the number of lines kept in the log buffer.
+pub fn set_capacity(capacity: usize) {
+    LOG_BUFFER
+        .lock()
+        .unwrap_or_else(|err| err.into_inner())
+        .set_capacity(capacity);
+}
+
+/// Returns the buffered log lines, oldest first.
+pub fn dump() -> String {
+    LOG_BUFFER
+        .lock()
+        .unwrap_or_else(|err| err.into_inner())
+        .dump()
+}
@@ -0,0 +1,113 @@
+//! Loading of mandatory access control (MAC) policies.
+//!
+//! With `selinux=1` the SELinux policy is loaded into the kernel, with
+//! `apparmor=1` the AppArmor profiles are. Both are loaded before the
+//! services are started and the system is handed off, so all of them
+//! run confined.
+use core::fmt;
+use std::{fs, io, path::Path};
+
+use log::{debug, info};
+use nix::{
+    errno::Errno,
+    mount::{mount, MsFlags},
+};
+
+use crate::{exec, power, reaper};
+
+/// Mountpoint of the SELinux filesystem.
+pub const SELINUXFS: &str = "/sys/fs/selinux";
+
+/// Mountpoint of the security filesystem AppArmor is controlled through.
+pub const SECURITYFS: &str = "/sys/kernel/security";
+
+/// SELinux policy loaded if `selinux.policy=` is not given.
+pub const DEFAULT_SELINUX_POLICY: &str = "/etc/selinux/policy";
+
+/// AppArmor profiles loaded if `apparmor.profiles=` is not given.
+pub const DEFAULT_APPARMOR_PROFILES: &str = "/etc/apparmor.d";
+
+/// The AppArmor profile compiler and loader.
+pub const APPARMOR_PARSER: &str = "/sbin/apparmor_parser";
+
+/// A MAC policy to load.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Policy {
+    /// The path of a compiled SELinux policy.
+    SELinux(String),
+    /// The path of an AppArmor profile or a directory of profiles.
+    AppArmor(String),
+}
+
+impl fmt::Display for Policy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Policy::SELinux(path) => write!(f, "SELinux policy '{}'", path),
+            Policy::AppArmor(path) => write!(f, "AppArmor profiles '{}'", path),
+        }
+    }
+}
+
+/// Mounts a kernel filesystem, unless it is mounted already.
+fn mount_once(fstype: &str, mountpoint: &str) -> io::Result<()> {
+    if !Path::new(mountpoint).is_dir() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("'{}' is missing, not supported by the kernel?", mountpoint),
+        ));
+    }
+
+    match mount(
+        Some(fstype),
+        Path::new(mountpoint),
+        Some(fstype),
+        MsFlags::MS_NOSUID | MsFlags::MS_NOEXEC,
+        None::<&str>,
+    ) {
+        Ok(()) | Err(Errno::EBUSY) => Ok(()),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Loads a compiled SELinux policy by writing it to `selinuxfs`.
+fn load_selinux(path: &str) -> io::Result<()> {
+    mount_once("selinuxfs", SELINUXFS)?;
+    let policy = fs::read(path)?;
+    fs::write(Path::new(SELINUXFS).join("load"), policy)
+}
+
+/// Loads AppArmor profiles using `apparmor_parser`.
+fn load_apparmor(path: &str) -> io::Result<()> {
+    mount_once("securityfs", SECURITYFS)?;
+    let mut command = exec::command(APPARMOR_PARSER, &["--replace", path], exec::Output::Inherit)?;
+    let child = reaper::spawn_command(&mut command, "apparmor_parser")?;
+    let status = reaper::wait(&child)?;
+    if !power::is_clean_exit(&status) {
+        return Err(io::Error::other(format!(
+            "{} {}",
+            APPARMOR_PARSER,
+            reaper::describe(&status)
+        )));
+    }
+
+    Ok(())
+}
+
+/// Loads a MAC policy into the kernel.
+///
+/// The filesystem the policy is loaded through
+/// is mounted first, if it is not mounted yet.
+///
+/// # Arguments
+///
+/// * `policy` - The policy to load.
+pub fn load(policy: &Policy) -> io::Result<()> {
+    debug!("Loading {}", policy);
+    match policy {
+        Policy::SELinux(path) => load_selinux(path)?,
+        Policy::AppArmor(path) => load_apparmor(path)?,
+    }
+    info!("Loaded {}", policy);
+
+    Ok(())
+}
@@ -6,74 +6,549 @@
 //! The main goal is to avoid the complexity of maintaining
 //! and patching a full blown linux distribution.
 
+pub mod attempts;
+pub mod caps;
+pub mod cmdline;
+pub mod config;
+pub mod console;
+pub mod control;
+pub mod crash;
+pub mod debugshell;
+pub mod exec;
 pub mod fs;
+pub mod hostname;
+pub mod logbuf;
+pub mod lsm;
+pub mod marker;
+pub mod metrics;
+pub mod modules;
 pub mod net;
+pub mod phase;
+pub mod power;
+pub mod priority;
+pub mod reaper;
+pub mod selftest;
+pub mod state;
+pub mod supervisor;
+pub mod sysinfo;
 
-use std::env;
+use std::{env, path::Path, time::Instant};
 
-use env_logger;
-use log::{debug, info};
+use log::{debug, info, warn};
+use phase::Phase;
+use state::BootState;
 
-/// Represents arguments parsed from
-/// the kernel command line.
-#[derive(Debug)]
-struct Cmdline {
-    quiet: bool,
-    root: String,
+/// Drops to a rescue shell after a fatal boot error, or reboots
+/// to try again if boot attempts are left (see `attempts`).
+///
+/// Logging might not be set up yet, so the reason
+/// is written to stderr directly.
+fn rescue(reason: &str) -> ! {
+    eprintln!("[rescue] {}", reason);
+    state::transition(BootState::Rescue);
+    metrics::write();
+    if let Some((attempt, budget)) = attempts::retry() {
+        eprintln!(
+            "[rescue] rebooting to try again (attempt {}/{})",
+            attempt, budget
+        );
+        nix::unistd::sync();
+        if let Err(err) = power::power(power::ExitAction::Reboot) {
+            eprintln!("[rescue] failed rebooting: {}", err);
+        }
+    }
+    eprintln!("[rescue] dropping to a rescue shell");
+    if let Err(err) = exec::exec("/busybox", &["sh"], true, exec::Output::Inherit) {
+        eprintln!("[rescue] failed spawning rescue shell: {}", err);
+    }
+
+    panic!("[panic] {}", reason);
 }
 
-/// Parses the kernel command line by reading `/proc/cmdline`.
+/// Counts the boot attempt if a budget is given (`boot.attempts=`).
 ///
-/// The proc filesystem must be mounted before running
-/// this function.
-fn parse_cmdline() -> Cmdline {
-    let args = match std::fs::read_to_string("/proc/cmdline") {
-        Ok(contents) => contents,
-        Err(err) => panic!("Could not read /proc/cmdline: {:?}", err),
-    };
-    let args = args.trim().split_whitespace().collect::<Vec<&str>>();
+/// The root filesystem has to be in place, as the counter is kept on it.
+fn begin_attempt(cmdline: &cmdline::Cmdline) {
+    if let Some(budget) = cmdline.boot_attempts {
+        match attempts::begin(Path::new(attempts::BOOT_ATTEMPTS), budget) {
+            Ok(attempt) => info!("Boot attempt {}/{}", attempt, budget),
+            Err(err) => warn!("Failed counting boot attempts: {}", err),
+        }
+    }
+}
 
-    let quiet = match args.iter().find(|arg| arg.starts_with("quiet")) {
-        Some(_) => true,
-        None => false,
-    };
+/// Assembles the settings of the network configuration.
+fn network_options(cmdline: &cmdline::Cmdline) -> net::NetworkOptions {
+    net::NetworkOptions {
+        dhcp: net::dhcp::DhcpConfig {
+            rapid_commit: cmdline.dhcp_rapid,
+            zero_udp_checksum: cmdline.dhcp_csum_none,
+            vendor_class: cmdline.dhcp_vendor.clone(),
+            max_message_size: cmdline
+                .dhcp_max_size
+                .unwrap_or(net::dhcp::DEFAULT_MAX_MESSAGE_SIZE),
+            server: cmdline.dhcp_server,
+            transport: cmdline.dhcp_transport,
+            requested_lease_time: cmdline.dhcp_lease_time,
+        },
+        dhcp_iface: cmdline.dhcp_iface.clone(),
+        boot_if: cmdline.boot_if,
+        dhcp_parallel: cmdline
+            .dhcp_parallel
+            .unwrap_or(net::networkd::DEFAULT_DHCP_PARALLEL),
+        dns_search: cmdline.dns_search.clone(),
+        force: cmdline.net_force,
+        monitor: cmdline.net_monitor,
+        nsswitch: cmdline.nsswitch,
+        required: cmdline.net_required,
+    }
+}
 
-    let root = match args.iter().find(|arg| arg.starts_with("root=")) {
-        Some(arg) => arg,
-        None => panic!("No root device specified"),
-    };
-    let root_device = root.trim_start_matches("root=");
+/// Loads the kernel modules listed in `/etc/modules-load.d` and `modules=`.
+fn load_modules(cmdline: &cmdline::Cmdline) {
+    let started = Instant::now();
+    let mut module_specs = modules::read_specs(Path::new(modules::MODULES_LOAD_DIR));
+    module_specs.extend(modules::cmdline_specs(&cmdline.args));
+    let loaded = modules::load_all(&module_specs);
+    metrics::record(Phase::Modules, started, loaded);
+}
 
-    Cmdline {
-        quiet,
-        root: root_device.to_string(),
+/// Checks that the network works (`net.check=`), reconfiguring it once
+/// if it does not. Drops to a rescue shell if the check still fails and
+/// the network is required, otherwise the boot continues.
+///
+/// # Returns
+///
+/// * `Vec<net::DhcpLease>` - The leases of the working configuration.
+fn check_network(
+    endpoint: &std::net::SocketAddr,
+    options: &net::NetworkOptions,
+    leases: Vec<net::DhcpLease>,
+    required: bool,
+) -> Vec<net::DhcpLease> {
+    if net::check::check(endpoint).is_ok() {
+        return leases;
     }
+
+    warn!("Network does not work, reconfiguring it");
+    // the carrier monitor started by the first configuration keeps running
+    let options = net::NetworkOptions {
+        force: true,
+        monitor: false,
+        ..options.clone()
+    };
+    let leases = match net::configure_network(&options) {
+        Ok(leases) => leases,
+        Err(err) => {
+            warn!("Failed reconfiguring network: {}", err);
+            leases
+        }
+    };
+    if net::check::check(endpoint).is_err() {
+        let reason = format!("network check failed, {} is unreachable", endpoint);
+        if required {
+            rescue(&reason);
+        }
+        warn!(
+            "{}, continuing (net.required=1 drops to a rescue shell)",
+            reason
+        );
+    }
+
+    leases
 }
 
 fn main() {
+    // -- subcommands, when not running as init
+    if env::args().nth(1).as_deref() == Some("selftest") {
+        std::process::exit(if selftest::run() { 0 } else { 1 });
+    }
+
     env::set_var("RUST_BACKTRACE", "1");
+    // as early as possible, PID 1 must not exit on a crash
+    crash::install();
     // -- parse kernel command line arguments
     if let Err(err) = fs::mount::proc() {
         panic!("[panic] failed mounting filesystem: {}", err)
     }
-    let cmdline = parse_cmdline();
+    state::transition(BootState::ProcMounted);
+    let mut cmdline = match cmdline::read_cmdline() {
+        Ok(cmdline) => cmdline,
+        Err(err) => rescue(&format!("failed parsing kernel command line: {}", err)),
+    };
 
     // -- set up logging
     let env = env_logger::Env::new()
         .filter_or("LOG", if cmdline.quiet { "warn" } else { "trace" })
         .write_style("LOG_STYLE");
-    env_logger::init_from_env(env);
+    let console = env_logger::Builder::from_env(env).build();
+    if let Err(err) = logbuf::init(console, cmdline.log_lines) {
+        panic!("[panic] failed setting up logging: {}", err)
+    }
+    state::transition(BootState::CmdlineParsed);
 
     // -- system startup
     info!(" => starting linµos");
-    debug!("{:?}", cmdline);
+    if cmdline.oom_protect {
+        match priority::protect_from_oom() {
+            Ok(()) => debug!(
+                "Set {} to {}",
+                priority::OOM_SCORE_ADJ,
+                priority::OOM_SCORE_ADJ_MIN
+            ),
+            Err(err) => warn!("Failed protecting init from the OOM killer: {}", err),
+        }
+    }
+    let sysinfo = sysinfo::SysInfo::gather();
+    info!("Running on {}", sysinfo);
+    reaper::start();
 
-    if let Err(err) = fs::mountfs(&cmdline.root) {
+    // -- mount filesystems
+    phase::pause_before(Phase::Mount, &cmdline.pauses, &cmdline.breaks);
+    let started = Instant::now();
+    if let Err(err) = fs::mountfs(&cmdline.root, cmdline.cgroup) {
         panic!("[panic] failed mounting filesystem: {}", err)
     }
+    // as early as possible, so failures of the following phases reboot
+    // to try again, a root mounted by linµos is counted on once switched to
+    if !fs::root::is_mounted_by_init(&cmdline.root) && cmdline.nfs_root.is_none() {
+        begin_attempt(&cmdline);
+    }
+    if let Some(tty) = &cmdline.debug_shell {
+        // as early as possible, to inspect the following phases if they hang
+        if let Err(err) = debugshell::spawn(tty) {
+            warn!("Failed starting debug shell on '{}': {}", tty, err);
+        }
+    }
+    if fs::root::is_mounted_by_init(&cmdline.root) {
+        let mounted = fs::root::mount_root(&cmdline.root, fs::mount::SYSROOT)
+            .map_err(|err| err.to_string())
+            .and_then(|_| fs::switch_root(fs::mount::SYSROOT).map_err(|err| err.to_string()));
+        if let Err(err) = mounted {
+            metrics::record(Phase::Mount, started, false);
+            rescue(&format!("failed mounting root: {}", err));
+        }
+        begin_attempt(&cmdline);
+    }
+    metrics::record(Phase::Mount, started, true);
+    // an NFS root is only reachable over the network, so the kernel
+    // modules are loaded and the network is configured first (see `fs::nfs`)
+    let mut early_leases = None;
+    if let Some(nfs_root) = cmdline.nfs_root.clone() {
+        load_modules(&cmdline);
+        let started = Instant::now();
+        let leases = match net::configure_network(&network_options(&cmdline)) {
+            Ok(leases) => leases,
+            Err(err) => {
+                metrics::record(Phase::Network, started, false);
+                rescue(&format!(
+                    "failed configuring network for the NFS root: {}",
+                    err
+                ))
+            }
+        };
+        metrics::record(Phase::Network, started, true);
+        info!("Mounting NFS root {}", nfs_root);
+        let mounted = nfs_root
+            .mount(fs::mount::SYSROOT)
+            .and_then(|_| fs::switch_root(fs::mount::SYSROOT));
+        if let Err(err) = mounted {
+            rescue(&format!("failed mounting NFS root: {}", err));
+        }
+        begin_attempt(&cmdline);
+        early_leases = Some(leases);
+    }
+    let init_config = match config::load(Path::new(config::INIT_CONFIG)) {
+        Ok(config) => config,
+        Err(err) => {
+            warn!("Ignoring {}: {}", config::INIT_CONFIG, err);
+            config::InitConfig::default()
+        }
+    };
+    init_config.merge_into(&mut cmdline);
+    if cmdline.etc_overlay {
+        // once the final root is in place and before anything writes to its
+        // /etc, the NFS root network files went to the initramfs and are
+        // written again with the network phase
+        debug!("Mounting a writable overlay over '/etc'");
+        if let Err(err) = fs::mount::etc_overlay() {
+            panic!("[panic] failed mounting filesystem: {}", err)
+        }
+    }
+    logbuf::set_capacity(cmdline.log_lines);
+    if cmdline.quiet && env::var_os("LOG").is_none() {
+        log::set_max_level(log::LevelFilter::Warn);
+    }
+    debug!("{:?}", cmdline);
+    if let Some(nice) = cmdline.nice {
+        match priority::set_nice(nice) {
+            Ok(()) => debug!("Set nice value to {}", nice),
+            Err(err) => warn!("Failed setting nice value {}: {}", nice, err),
+        }
+    }
+    let initial_hostname = hostname::initial(
+        cmdline.hostname.as_ref(),
+        hostname::read(Path::new(hostname::HOSTNAME_FILE)),
+    );
+    if let Some(name) = initial_hostname {
+        if let Err(err) = hostname::set(&name) {
+            warn!("Failed setting hostname '{}': {}", name, err);
+        }
+    }
+    if let Some(size) = &cmdline.log_tmpfs {
+        // before any program logs to it
+        match fs::mount::log_tmpfs(size) {
+            Ok(()) => debug!("Mounted a {} tmpfs at '{}'", size, exec::LOG_DIR),
+            Err(err) => warn!("Failed capping the logs: {}", err),
+        }
+    }
+    if phase::start(
+        Phase::MountExtra,
+        &cmdline.pauses,
+        &cmdline.breaks,
+        &cmdline.skip,
+    ) {
+        let started = Instant::now();
+        if let Err(err) = fs::mount::mount_extra(&cmdline.mount_extra) {
+            panic!("[panic] failed mounting filesystem: {}", err)
+        }
+        let mut fstab = fs::fstab::read(Path::new(fs::fstab::FSTAB));
+        fstab.extend(init_config.mounts.iter().cloned());
+        match fs::fsck::check_all(&mut fstab, cmdline.fsck_corrected) {
+            fs::fsck::FsckAction::Mount | fs::fsck::FsckAction::Rerun => {}
+            fs::fsck::FsckAction::Reboot => {
+                info!("Rebooting after filesystem errors were corrected");
+                state::transition(BootState::ShuttingDown);
+                nix::unistd::sync();
+                if let Err(err) = power::power(power::ExitAction::Reboot) {
+                    exec::halt(&format!("failed to reboot: {}", err));
+                }
+            }
+            fs::fsck::FsckAction::Rescue => {
+                metrics::record(Phase::MountExtra, started, false);
+                rescue("filesystem errors left uncorrected")
+            }
+        }
+        if let Err(err) = fs::fstab::mount_all(&fstab) {
+            panic!("[panic] failed mounting filesystem: {}", err)
+        }
+        metrics::record(Phase::MountExtra, started, true);
+    }
+    if cmdline.container {
+        // container runtimes (e.g. k3s) expect `/` to be rshared,
+        // so bidirectional volume mounts propagate to the host
+        debug!("Making '/' rshared for container runtimes");
+        if let Err(err) = fs::set_propagation("/", fs::Propagation::RShared) {
+            panic!("[panic] failed setting mount propagation: {}", err)
+        }
+    }
+    state::transition(BootState::FsMounted);
+    // the persisted hostname might be on a filesystem mounted from fstab
+    let persisted = cmdline
+        .hostname_persist
+        .as_deref()
+        .and_then(|path| hostname::read(Path::new(path)));
+    if let Some(name) = hostname::restored(cmdline.hostname.as_ref(), persisted) {
+        match hostname::apply(&name) {
+            Ok(()) => info!("Restored hostname '{}'", name),
+            Err(err) => warn!("Failed restoring hostname '{}': {}", name, err),
+        }
+    }
+
+    // -- listen for control queries
+    let network_options = network_options(&cmdline);
+    let spawned = control::spawn(
+        control::CONTROL_SOCKET,
+        network_options.clone(),
+        cmdline.hostname_persist.clone(),
+    );
+    if let Err(err) = spawned {
+        warn!("Failed listening on '{}': {}", control::CONTROL_SOCKET, err);
+    }
+
+    // -- load kernel modules
+    if early_leases.is_none()
+        && phase::start(
+            Phase::Modules,
+            &cmdline.pauses,
+            &cmdline.breaks,
+            &cmdline.skip,
+        )
+    {
+        load_modules(&cmdline);
+    }
+
+    // -- configure network
+    let network_runs = early_leases.is_some()
+        || phase::start(
+            Phase::Network,
+            &cmdline.pauses,
+            &cmdline.breaks,
+            &cmdline.skip,
+        );
+    let leases = match early_leases {
+        Some(leases) => {
+            // the interfaces are kept, the files are written to the new root
+            if let Err(err) = net::networkd::write_system_files(&network_options) {
+                warn!("{}", err);
+            }
+            leases
+        }
+        None if network_runs => {
+            let started = Instant::now();
+            match net::configure_network(&network_options) {
+                Ok(leases) => {
+                    metrics::record(Phase::Network, started, true);
+                    leases
+                }
+                Err(err) => {
+                    metrics::record(Phase::Network, started, false);
+                    rescue(&format!("failed configuring network: {}", err))
+                }
+            }
+        }
+        None => Vec::new(),
+    };
+    // the checks are skipped along with the network phase
+    let leases = match cmdline.net_check.filter(|_| network_runs) {
+        Some(endpoint) => check_network(&endpoint, &network_options, leases, cmdline.net_required),
+        None => leases,
+    };
+    if let Some(host) = cmdline.dns_check.as_ref().filter(|_| network_runs) {
+        if net::dns::check(host).is_err() {
+            let reason = format!("DNS check failed, {} does not resolve", host);
+            if cmdline.net_required {
+                rescue(&reason);
+            }
+            warn!(
+                "{}, continuing (net.required=1 drops to a rescue shell)",
+                reason
+            );
+        }
+    }
+
+    if cmdline.hostname == Some(hostname::Hostname::Dhcp) {
+        match hostname::from_leases(&leases) {
+            Some(name) => match hostname::change(&name, cmdline.hostname_persist.as_deref()) {
+                Ok(()) => info!("Set hostname '{}' handed out by DHCP", name),
+                Err(err) => warn!("Failed setting hostname '{}': {}", name, err),
+            },
+            None => warn!("No hostname handed out by DHCP"),
+        }
+    }
+
+    state::transition(BootState::NetworkUp);
 
-    if let Err(err) = net::configure_network() {
-        panic!("[panic] failed configuring network: {}", err)
+    // -- set the clock
+    let ntp_servers = net::sntp::servers(&leases, &cmdline.ntp);
+    if !ntp_servers.is_empty()
+        && phase::start(
+            Phase::Clock,
+            &cmdline.pauses,
+            &cmdline.breaks,
+            &cmdline.skip,
+        )
+    {
+        let started = Instant::now();
+        let synced = net::sntp::sync(&ntp_servers);
+        if let Err(err) = &synced {
+            warn!("Failed setting the clock: {}", err);
+        }
+        metrics::record(Phase::Clock, started, synced.is_ok());
+    }
+
+    // -- mark the boot as completed
+    let mut marker = marker::BootMarker::new();
+    marker.add_section("system", sysinfo.to_marker_section());
+    marker.add_section("states", state::to_marker_section());
+    leases.iter().for_each(|lease| {
+        marker.add_section(&format!("lease {}", lease.iface), lease.to_lease_file());
+    });
+    if let Err(err) = marker.write() {
+        warn!("Failed writing boot marker: {}", err);
+    }
+    if let Err(err) = attempts::complete(Path::new(attempts::BOOT_ATTEMPTS)) {
+        warn!("Failed resetting boot attempts: {}", err);
+    }
+
+    // -- load security policies
+    for policy in &cmdline.mac_policies {
+        if let Err(err) = lsm::load(policy) {
+            let reason = format!("failed loading {}: {}", policy, err);
+            if cmdline.mac_rescue {
+                rescue(&reason);
+            }
+            exec::halt(&reason);
+        }
+    }
+
+    // -- start services
+    let mut services = if phase::start(
+        Phase::Services,
+        &cmdline.pauses,
+        &cmdline.breaks,
+        &cmdline.skip,
+    ) {
+        let started = Instant::now();
+        let mut services =
+            init_config.services(supervisor::load(Path::new(supervisor::SERVICES_DIR)));
+        if cmdline.maintenance {
+            services = supervisor::maintenance(services);
+        }
+        let loaded = services.len();
+        let services = supervisor::Supervisor::start(services);
+        metrics::record(Phase::Services, started, services.running() == loaded);
+        services
+    } else {
+        supervisor::Supervisor::start(Vec::new())
+    };
+
+    // -- hand off to the shell
+    phase::pause_before(Phase::Handoff, &cmdline.pauses, &cmdline.breaks);
+    if let Some(console) = &cmdline.console {
+        if let Err(err) = console::setup(console) {
+            warn!("Failed setting up console '{}': {}", console, err);
+        }
+    }
+    // the maintenance shell is a full root shell
+    let caps = cmdline.caps.as_ref().filter(|_| !cmdline.maintenance);
+    if let Some(caps) = caps {
+        debug!("Restricting handoff to capabilities: {:?}", caps);
+    }
+    if cmdline.maintenance {
+        info!("Maintenance mode, handing off to a root shell");
+    }
+    state::transition(BootState::HandedOff);
+    metrics::write();
+    let handoff = exec::handoff(&exec::SHELLS, |shell| {
+        if let Some(caps) = caps {
+            caps::restrict(shell, caps);
+        }
+    });
+    let status = match handoff {
+        Ok(status) => status,
+        Err(err) => exec::halt(&format!(
+            "failed handing off to any of {}: {}",
+            exec::SHELLS.join(", "),
+            err
+        )),
+    };
+    let action = power::action(&status, cmdline.on_exit, cmdline.on_crash);
+    info!("Handoff {}, {}", reaper::describe(&status), action);
+    if action == power::ExitAction::Rescue {
+        rescue(&format!("handoff {}", reaper::describe(&status)));
+    }
+
+    // -- shut down
+    state::transition(BootState::ShuttingDown);
+    services.stop_all(supervisor::STOP_GRACE);
+    nix::unistd::sync();
+    fs::mount::unmount_all();
+    if action == power::ExitAction::Halt {
+        exec::halt(&format!("handoff {}", reaper::describe(&status)));
+    }
+    if let Err(err) = power::power(action) {
+        exec::halt(&format!("failed to {}: {}", action, err));
     }
 
     panic!("[panic] init tried to return!");
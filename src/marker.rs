@@ -0,0 +1,63 @@
+//! Boot-complete marker.
+//!
+//! Once the system is booted, linµos writes a marker file
+//! describing the booted system, so other services can
+//! tell that (and how) the boot completed.
+use std::{
+    fs, io,
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Path of the boot-complete marker.
+pub const BOOT_MARKER: &str = "/run/linos/booted";
+
+/// The contents of the boot-complete marker.
+///
+/// The marker consists of named sections, each containing
+/// `KEY=VALUE` lines.
+#[derive(Debug, Default)]
+pub struct BootMarker {
+    sections: Vec<(String, String)>,
+}
+
+impl BootMarker {
+    /// Creates a new marker containing the `[boot]` section.
+    pub fn new() -> BootMarker {
+        let completed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|time| time.as_secs())
+            .unwrap_or_default();
+
+        let mut marker = BootMarker::default();
+        marker.add_section("boot", format!("COMPLETED={}\n", completed));
+        marker
+    }
+
+    /// Adds a section to the marker.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The name of the section (e.g. `lease eth0`).
+    /// * `contents` - The `KEY=VALUE` lines of the section.
+    pub fn add_section(&mut self, name: &str, contents: String) {
+        self.sections.push((name.to_string(), contents));
+    }
+
+    /// Writes the marker to `BOOT_MARKER`.
+    pub fn write(&self) -> io::Result<()> {
+        if let Some(parent) = Path::new(BOOT_MARKER).parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(BOOT_MARKER, self.to_string())
+    }
+}
+
+impl std::fmt::Display for BootMarker {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        for (name, contents) in &self.sections {
+            write!(f, "[{}]\n{}\n", name, contents)?;
+        }
+        Ok(())
+    }
+}
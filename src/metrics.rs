@@ -0,0 +1,229 @@
+//! Boot metrics for the node_exporter textfile collector.
+//!
+//! The duration and outcome of each phase and the time each boot state
+//! was reached are written in the Prometheus text exposition format to
+//! `/run/linos/metrics.prom`:
+//!
+//! ```text
+//! # HELP linos_boot_phase_seconds Duration of the boot phase.
+//! # TYPE linos_boot_phase_seconds gauge
+//! linos_boot_phase_seconds{phase="network"} 1.234567
+//! # HELP linos_boot_phase_success Whether the boot phase succeeded.
+//! # TYPE linos_boot_phase_success gauge
+//! linos_boot_phase_success{phase="network"} 1
+//! ```
+//!
+//! The file is replaced atomically, so the collector never reads a
+//! partial file. Writing it is best effort, failures are only logged.
+use std::{
+    path::Path,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use log::debug;
+
+use crate::{
+    fs::atomic_write,
+    phase::Phase,
+    state::{self, BootState},
+};
+
+/// Path of the metrics file, node_exporter reads `*.prom` files.
+pub const METRICS_FILE: &str = "/run/linos/metrics.prom";
+
+/// The outcome of a finished phase.
+#[derive(Debug, Clone, Copy)]
+pub struct PhaseMetrics {
+    pub phase: Phase,
+    pub duration: Duration,
+    pub success: bool,
+}
+
+/// The phases finished so far, in the order they finished.
+static PHASES: Mutex<Vec<PhaseMetrics>> = Mutex::new(Vec::new());
+
+/// Escapes a label value, backslashes, double quotes
+/// and newlines are escaped with a backslash.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Writes a metric family: the `HELP` and `TYPE` lines followed by a
+/// sample per labelled value.
+fn family(out: &mut String, name: &str, help: &str, label: &str, samples: &[(&str, String)]) {
+    if samples.is_empty() {
+        return;
+    }
+    out.push_str(&format!(
+        "# HELP {} {}\n# TYPE {} gauge\n",
+        name, help, name
+    ));
+    for (value, sample) in samples {
+        out.push_str(&format!(
+            "{}{{{}=\"{}\"}} {}\n",
+            name,
+            label,
+            escape(value),
+            sample
+        ));
+    }
+}
+
+/// Renders the metrics in the Prometheus text exposition format.
+///
+/// # Arguments
+///
+/// * `phases` - The finished phases.
+/// * `states` - The boot states reached and the time since boot they
+///   were reached at (see `state::history`).
+pub fn render(phases: &[PhaseMetrics], states: &[(BootState, Duration)]) -> String {
+    let mut out = String::new();
+    family(
+        &mut out,
+        "linos_boot_phase_seconds",
+        "Duration of the boot phase.",
+        "phase",
+        &phases
+            .iter()
+            .map(|m| (m.phase.name(), format!("{:.6}", m.duration.as_secs_f64())))
+            .collect::<Vec<_>>(),
+    );
+    family(
+        &mut out,
+        "linos_boot_phase_success",
+        "Whether the boot phase succeeded.",
+        "phase",
+        &phases
+            .iter()
+            .map(|m| (m.phase.name(), (m.success as u8).to_string()))
+            .collect::<Vec<_>>(),
+    );
+    family(
+        &mut out,
+        "linos_boot_state_reached_seconds",
+        "Time since boot the boot state was reached at.",
+        "state",
+        &states
+            .iter()
+            .map(|(state, uptime)| (state.name(), format!("{:.6}", uptime.as_secs_f64())))
+            .collect::<Vec<_>>(),
+    );
+    family(
+        &mut out,
+        "linos_boot_state",
+        "The current boot state.",
+        "state",
+        &states
+            .last()
+            .map(|(state, _)| vec![(state.name(), "1".to_string())])
+            .unwrap_or_default(),
+    );
+
+    out
+}
+
+/// Records the outcome of a phase and writes the metrics (see `write`).
+///
+/// A phase recorded again replaces the earlier outcome.
+///
+/// # Arguments
+///
+/// * `phase` - The finished phase.
+/// * `started` - When the phase started.
+/// * `success` - Whether the phase succeeded.
+pub fn record(phase: Phase, started: Instant, success: bool) {
+    let metrics = PhaseMetrics {
+        phase,
+        duration: started.elapsed(),
+        success,
+    };
+    {
+        let mut phases = PHASES.lock().unwrap_or_else(|err| err.into_inner());
+        phases.retain(|m| m.phase != phase);
+        phases.push(metrics);
+    }
+    write();
+}
+
+/// Writes the metrics to `METRICS_FILE`, once `/run` is mounted.
+///
+/// Until then, the phases are only recorded and written with the next call.
+pub fn write() {
+    let states = state::history();
+    // earlier, the file would end up below the `/run` mount
+    if !states
+        .iter()
+        .any(|(state, _)| *state == BootState::FsMounted)
+    {
+        return;
+    }
+
+    let phases = PHASES.lock().unwrap_or_else(|err| err.into_inner()).clone();
+    if let Err(err) = atomic_write(Path::new(METRICS_FILE), &render(&phases, &states)) {
+        debug!("Failed writing '{}': {}", METRICS_FILE, err);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_exposition_format() {
+        let phases = [PhaseMetrics {
+            phase: Phase::Network,
+            duration: Duration::from_millis(1500),
+            success: false,
+        }];
+        let states = [
+            (BootState::FsMounted, Duration::from_millis(250)),
+            (BootState::NetworkUp, Duration::from_secs(2)),
+        ];
+        let out = render(&phases, &states);
+
+        let mut families = 0;
+        for line in out.lines() {
+            if let Some(help) = line.strip_prefix("# HELP ") {
+                let (name, text) = help.split_once(' ').unwrap();
+                assert!(name.starts_with("linos_") && !text.is_empty());
+                families += 1;
+            } else if let Some(kind) = line.strip_prefix("# TYPE ") {
+                assert!(kind.ends_with(" gauge"), "{}", line);
+            } else {
+                // name{label="value"} sample
+                let (series, sample) = line.rsplit_once(' ').unwrap();
+                let (name, labels) = series.split_once('{').unwrap();
+                assert!(name.starts_with("linos_"));
+                assert!(labels.ends_with("\"}"), "{}", line);
+                assert!(sample.parse::<f64>().is_ok(), "{}", line);
+            }
+        }
+        assert_eq!(families, 4);
+        assert!(out.contains("linos_boot_phase_seconds{phase=\"network\"} 1.500000\n"));
+        assert!(out.contains("linos_boot_phase_success{phase=\"network\"} 0\n"));
+        assert!(out.contains("linos_boot_state{state=\"network-up\"} 1\n"));
+    }
+
+    #[test]
+    fn escapes_label_values() {
+        let mut out = String::new();
+        family(
+            &mut out,
+            "linos_test",
+            "Test.",
+            "name",
+            &[("a\\b\"c\nd", "1".to_string())],
+        );
+
+        assert!(out.ends_with("linos_test{name=\"a\\\\b\\\"c\\nd\"} 1\n"));
+    }
+
+    #[test]
+    fn nothing_recorded_renders_nothing() {
+        assert_eq!(render(&[], &[]), "");
+    }
+}
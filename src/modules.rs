@@ -0,0 +1,251 @@
+//! Loading of kernel modules.
+//!
+//! Modules are listed in `/etc/modules-load.d/*.conf`, one module per line
+//! with optional parameters (e.g. `e1000e InterruptThrottleRate=3000`),
+//! or on the kernel command line (`modules=e1000e` and `e1000e.param=value`).
+use std::{
+    ffi::CString,
+    fs::{self, File},
+    io,
+    os::unix::io::AsRawFd,
+    path::{Path, PathBuf},
+};
+
+use log::{debug, info, warn};
+
+use crate::cmdline::CmdlineArgs;
+
+/// Directory containing the module lists.
+pub const MODULES_LOAD_DIR: &str = "/etc/modules-load.d";
+
+/// Directory containing the modules of all kernel releases.
+const MODULES_DIR: &str = "/lib/modules";
+
+/// A module to load and the parameters passed to it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ModuleSpec {
+    pub name: String,
+    /// Space separated `param=value` pairs.
+    pub params: String,
+}
+
+/// Normalizes a module name, `-` and `_` are equivalent in module names.
+pub fn normalize(name: &str) -> String {
+    name.replace('-', "_")
+}
+
+/// Parses a `modname param1=val1 param2=val2` entry.
+///
+/// # Returns
+///
+/// * `Option<ModuleSpec>` - The module, or `None` for empty lines and comments.
+pub fn parse_spec(line: &str) -> Option<ModuleSpec> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+        return None;
+    }
+
+    let mut words = line.split_whitespace();
+    let name = normalize(words.next()?);
+    Some(ModuleSpec {
+        name,
+        params: words.collect::<Vec<&str>>().join(" "),
+    })
+}
+
+/// Reads the modules listed in the `*.conf` files in `dir`, in lexical order.
+pub fn read_specs(dir: &Path) -> Vec<ModuleSpec> {
+    let mut files = match fs::read_dir(dir) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "conf"))
+            .collect::<Vec<_>>(),
+        Err(_) => Vec::new(),
+    };
+    files.sort();
+
+    files
+        .iter()
+        .flat_map(|file| match fs::read_to_string(file) {
+            Ok(contents) => contents.lines().filter_map(parse_spec).collect(),
+            Err(err) => {
+                warn!("Skipping '{}': {}", file.display(), err);
+                Vec::new()
+            }
+        })
+        .collect()
+}
+
+/// Reads the modules listed on the kernel command line (`modules=`).
+///
+/// The parameters of a listed module are given as `<module>.<param>=<value>`.
+pub fn cmdline_specs(args: &CmdlineArgs) -> Vec<ModuleSpec> {
+    args.list("modules")
+        .into_iter()
+        .map(|name| {
+            let name = normalize(name);
+            let params = args
+                .iter()
+                .filter_map(|arg| arg.split_once('.'))
+                .filter(|(module, param)| normalize(module) == name && param.contains('='))
+                .map(|(_, param)| param)
+                .collect::<Vec<&str>>()
+                .join(" ");
+            ModuleSpec { name, params }
+        })
+        .collect()
+}
+
+/// Searches the module file `<name>.ko` below `dir`.
+fn find_module(dir: &Path, name: &str) -> Option<PathBuf> {
+    for entry in fs::read_dir(dir).ok()?.filter_map(|entry| entry.ok()) {
+        let path = entry.path();
+        if path.is_dir() {
+            if let Some(found) = find_module(&path, name) {
+                return Some(found);
+            }
+        } else if path.extension().is_some_and(|ext| ext == "ko")
+            && path
+                .file_stem()
+                .is_some_and(|stem| normalize(&stem.to_string_lossy()) == name)
+        {
+            return Some(path);
+        }
+    }
+
+    None
+}
+
+/// Loads a single module.
+///
+/// # Returns
+///
+/// * `io::Result<bool>` - `true` if the module was loaded,
+///   `false` if it was already loaded.
+fn load(spec: &ModuleSpec) -> io::Result<bool> {
+    let release = nix::sys::utsname::uname()?
+        .release()
+        .to_string_lossy()
+        .to_string();
+    let path = match find_module(&Path::new(MODULES_DIR).join(release), &spec.name) {
+        Some(path) => path,
+        None => {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("module '{}' not found", spec.name),
+            ))
+        }
+    };
+
+    let file = File::open(&path)?;
+    let params = CString::new(spec.params.as_str())?;
+    let res =
+        unsafe { libc::syscall(libc::SYS_finit_module, file.as_raw_fd(), params.as_ptr(), 0) };
+    if res != 0 {
+        let err = io::Error::last_os_error();
+        if err.raw_os_error() == Some(libc::EEXIST) {
+            return Ok(false);
+        }
+        return Err(err);
+    }
+
+    Ok(true)
+}
+
+/// Loads the given modules in order.
+///
+/// Modules listed more than once are only loaded the first time,
+/// failures are logged, but not fatal.
+///
+/// # Returns
+///
+/// * `bool` - Whether all modules were loaded.
+pub fn load_all(specs: &[ModuleSpec]) -> bool {
+    let mut loaded = true;
+    let mut seen: Vec<&str> = Vec::new();
+    for spec in specs {
+        if seen.contains(&spec.name.as_str()) {
+            warn!("Module '{}' is listed more than once, skipping", spec.name);
+            continue;
+        }
+        seen.push(&spec.name);
+
+        match load(spec) {
+            Ok(true) => info!("Loaded module '{}' ({})", spec.name, spec.params),
+            Ok(false) => debug!("Module '{}' is already loaded", spec.name),
+            Err(err) => {
+                warn!("Failed loading module '{}': {}", spec.name, err);
+                loaded = false;
+            }
+        }
+    }
+
+    loaded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_modules_from_cmdline() {
+        let args = CmdlineArgs::new(
+            "root=/dev/vda modules=e1000e,vfio-pci e1000e.debug=1 vfio_pci.ids=8086:1533 quiet",
+        );
+
+        assert_eq!(
+            cmdline_specs(&args),
+            vec![
+                ModuleSpec {
+                    name: "e1000e".to_string(),
+                    params: "debug=1".to_string(),
+                },
+                ModuleSpec {
+                    name: "vfio_pci".to_string(),
+                    params: "ids=8086:1533".to_string(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn reads_modules_from_conf_files() {
+        let dir = std::env::temp_dir().join(format!("linos-test-modules-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("20-vfio.conf"),
+            "; passthrough\nvfio-pci ids=8086:1533  disable_vga=1\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("10-net.conf"),
+            "# network drivers\n\n  e1000e InterruptThrottleRate=3000\n\t\nvirtio_net\n",
+        )
+        .unwrap();
+        fs::write(dir.join("30-ignored.conf.bak"), "bonding\n").unwrap();
+
+        let specs = read_specs(&dir);
+        fs::remove_dir_all(&dir).unwrap();
+
+        // the files are read in lexical order
+        assert_eq!(
+            specs,
+            vec![
+                ModuleSpec {
+                    name: "e1000e".to_string(),
+                    params: "InterruptThrottleRate=3000".to_string(),
+                },
+                ModuleSpec {
+                    name: "virtio_net".to_string(),
+                    params: String::new(),
+                },
+                ModuleSpec {
+                    name: "vfio_pci".to_string(),
+                    params: "ids=8086:1533 disable_vga=1".to_string(),
+                },
+            ]
+        );
+        assert!(read_specs(&dir).is_empty());
+    }
+}
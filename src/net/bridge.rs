@@ -0,0 +1,68 @@
+//! Software bridges.
+//!
+//! Bridges are created before the interfaces are configured,
+//! so a regular interface config can address the bridge.
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use super::{err::ErrorKind, iface_config::ConfigSocket, NetworkConfigurationError};
+
+/// A bridge and the interfaces enslaved to it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BridgeConfig {
+    pub name: String,
+    pub members: Vec<String>,
+}
+
+/// Returns the bridge (or bond) an interface is enslaved to.
+fn master(iface: &str) -> Option<String> {
+    let link = Path::new("/sys/class/net").join(iface).join("master");
+    let target = link.read_link().ok()?;
+    Some(target.file_name()?.to_string_lossy().to_string())
+}
+
+impl BridgeConfig {
+    /// Creates the bridge, adds the members and brings them up.
+    ///
+    /// The members have to exist and must not be
+    /// enslaved to another bridge already.
+    pub fn create(&self) -> Result<(), NetworkConfigurationError> {
+        let interfaces = pnet::datalink::interfaces();
+        let mut members = Vec::new();
+        for member in &self.members {
+            let iface = match interfaces.iter().find(|iface| &iface.name == member) {
+                Some(iface) => iface,
+                None => {
+                    return Err(NetworkConfigurationError::with_kind(
+                        format!("Bridge '{}': member '{}' does not exist", self.name, member),
+                        ErrorKind::MissingInterface,
+                    ))
+                }
+            };
+            match master(member) {
+                Some(master) if master == self.name => {}
+                Some(master) => {
+                    return Err(NetworkConfigurationError::with_kind(
+                        format!(
+                            "Bridge '{}': member '{}' is already enslaved to '{}'",
+                            self.name, member, master
+                        ),
+                        ErrorKind::Permanent,
+                    ))
+                }
+                None => members.push(iface),
+            }
+        }
+
+        let bridge = ConfigSocket::new(self.name.clone())?;
+        bridge.add_bridge()?;
+        for member in members {
+            bridge.add_bridge_member(member.index)?;
+            ConfigSocket::new(member.name.clone())?.enable(true)?;
+        }
+        bridge.enable(true)?;
+
+        Ok(())
+    }
+}
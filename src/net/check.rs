@@ -0,0 +1,101 @@
+//! Connectivity check after the network configuration.
+//!
+//! Assigned addresses do not mean the network works, a wrong gateway or
+//! a dead uplink only shows when connecting somewhere. With
+//! `net.check=<ip>:<port>` a TCP connection is opened to the given
+//! endpoint once the network is configured.
+use std::{
+    io,
+    net::{SocketAddr, TcpStream},
+    thread,
+    time::{Duration, Instant},
+};
+
+use log::{debug, info, warn};
+
+/// Time a single connection attempt may take.
+pub const CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Number of connection attempts, as links might take a moment to carry traffic.
+pub const CHECK_ATTEMPTS: u32 = 3;
+
+/// Time waited between the connection attempts.
+const CHECK_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// Connects to `endpoint` once.
+///
+/// # Returns
+///
+/// * `io::Result<Duration>` - The time it took to connect.
+pub fn probe(endpoint: &SocketAddr, timeout: Duration) -> io::Result<Duration> {
+    let start = Instant::now();
+    TcpStream::connect_timeout(endpoint, timeout)?;
+
+    Ok(start.elapsed())
+}
+
+/// Checks if `endpoint` can be connected to, trying `CHECK_ATTEMPTS` times.
+///
+/// # Arguments
+///
+/// * `endpoint` - The endpoint to connect to (`net.check=`).
+pub fn check(endpoint: &SocketAddr) -> io::Result<()> {
+    check_with(endpoint, CHECK_ATTEMPTS, CHECK_RETRY_DELAY)
+}
+
+/// Checks if `endpoint` can be connected to, trying `attempts` times
+/// and waiting `delay` between the attempts (see `check`).
+fn check_with(endpoint: &SocketAddr, attempts: u32, delay: Duration) -> io::Result<()> {
+    let mut attempt = 1;
+    loop {
+        match probe(endpoint, CHECK_TIMEOUT) {
+            Ok(elapsed) => {
+                info!("Network check: connected to {} in {:?}", endpoint, elapsed);
+                return Ok(());
+            }
+            Err(err) if attempt < attempts => {
+                debug!(
+                    "Network check: connecting to {} failed ({}/{}): {}",
+                    endpoint, attempt, attempts, err
+                );
+            }
+            Err(err) => {
+                warn!("Network check: connecting to {} failed: {}", endpoint, err);
+                return Err(err);
+            }
+        }
+        attempt += 1;
+        thread::sleep(delay);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+
+    use super::*;
+
+    #[test]
+    fn connects_to_listening_endpoint() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let endpoint = listener.local_addr().unwrap();
+
+        assert!(probe(&endpoint, CHECK_TIMEOUT).is_ok());
+        assert!(check_with(&endpoint, 1, Duration::ZERO).is_ok());
+    }
+
+    #[test]
+    fn fails_after_all_attempts() {
+        // a port nothing listens on anymore refuses the connection
+        let endpoint = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let delay = Duration::from_millis(50);
+
+        let start = Instant::now();
+        let err = check_with(&endpoint, 3, delay).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionRefused);
+        assert!(start.elapsed() >= delay * 2);
+    }
+}
@@ -0,0 +1,299 @@
+//! Loading of the network configuration files.
+//!
+//! The interfaces are read from `/etc/linos/network.toml`:
+//!
+//! ```toml
+//! [[interface]]
+//! type = "static"
+//! name = "eth0"
+//! ip = "10.0.0.2"
+//! netmask = "255.255.255.0"
+//! gateway = "10.0.0.1"
+//! dns = ["10.0.0.1", "10.0.0.2"]
+//! search = ["example.com"]
+//! aliases = [{ ip = "10.0.1.2", netmask = "255.255.255.0" }]
+//! neighbors = [{ ip = "10.0.0.5", mac = "02:00:00:00:00:05" }]
+//! routing = { table = 100, priority = 1000 }
+//!
+//! [[interface]]
+//! type = "dhcp"
+//! name = "eth1"
+//! priority = 10
+//! requested_lease_time = 3600
+//! lease_lifetimes = true
+//!
+//! [[bridge]]
+//! name = "br0"
+//! members = ["eth2", "eth3"]
+//! ```
+//!
+//! and from drop-in files in `/etc/linos/network.d/*.toml`,
+//! each defining a single interface:
+//!
+//! ```toml
+//! type = "dhcp"
+//! name = "eth1"
+//! ```
+//!
+//! The drop-ins are applied in lexical order after `network.toml`,
+//! a later definition of an interface replaces an earlier one.
+//!
+//! A drop-in can define a bridge instead, the bridge itself is
+//! configured like any other interface (e.g. `name = "br0"`):
+//!
+//! ```toml
+//! type = "bridge"
+//! name = "br0"
+//! members = ["eth2", "eth3"]
+//! ```
+//!
+//! With `routing`, traffic from the subnet of the interface uses the
+//! routing table `table` holding its own default route (policy routing).
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use log::{debug, warn};
+use serde::Deserialize;
+
+use super::{bridge::BridgeConfig, iface::NetworkInterfaceConfig};
+
+/// Path of the main network configuration file.
+pub const NETWORK_CONFIG: &str = "/etc/linos/network.toml";
+
+/// Directory containing the network configuration drop-ins.
+pub const NETWORK_CONFIG_DIR: &str = "/etc/linos/network.d";
+
+#[derive(Debug, Deserialize)]
+struct NetworkConfigFile {
+    #[serde(default)]
+    interface: Vec<NetworkInterfaceConfig>,
+    #[serde(default)]
+    bridge: Vec<BridgeConfig>,
+}
+
+/// A drop-in file, defining a single interface or bridge.
+enum DropIn {
+    Interface(NetworkInterfaceConfig),
+    Bridge(BridgeConfig),
+}
+
+/// Parses a drop-in file, a bridge if its `type` is `bridge`.
+fn parse_dropin(contents: &str) -> Result<DropIn, toml::de::Error> {
+    let value = toml::from_str::<toml::Table>(contents)?;
+    if value.get("type").and_then(|value| value.as_str()) == Some("bridge") {
+        toml::from_str::<BridgeConfig>(contents).map(DropIn::Bridge)
+    } else {
+        toml::from_str::<NetworkInterfaceConfig>(contents).map(DropIn::Interface)
+    }
+}
+
+/// Returns the drop-in files in `dir`, in lexical order.
+fn dropins(dir: &Path) -> Vec<PathBuf> {
+    let mut dropins = match fs::read_dir(dir) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "toml"))
+            .collect::<Vec<_>>(),
+        Err(_) => Vec::new(),
+    };
+    dropins.sort();
+    dropins
+}
+
+/// Loads the bridges, merged like the interfaces (see `load`).
+///
+/// # Arguments
+///
+/// * `path` - The main configuration file (see `NETWORK_CONFIG`).
+/// * `dir` - The drop-in directory (see `NETWORK_CONFIG_DIR`).
+pub fn load_bridges(path: &Path, dir: &Path) -> Vec<BridgeConfig> {
+    let mut bridges = Vec::new();
+
+    // errors are reported when loading the interfaces
+    if path.exists() {
+        if let Ok(Ok(file)) =
+            fs::read_to_string(path).map(|contents| toml::from_str::<NetworkConfigFile>(&contents))
+        {
+            file.bridge
+                .into_iter()
+                .for_each(|bridge| merge_bridge(&mut bridges, bridge));
+        }
+    }
+    for dropin in dropins(dir) {
+        if let Ok(Ok(DropIn::Bridge(bridge))) =
+            fs::read_to_string(&dropin).map(|contents| parse_dropin(&contents))
+        {
+            merge_bridge(&mut bridges, bridge);
+        }
+    }
+
+    bridges
+}
+
+/// Adds `config` to `configs`, replacing the config
+/// of an interface with the same name.
+pub(crate) fn merge(configs: &mut Vec<NetworkInterfaceConfig>, config: NetworkInterfaceConfig) {
+    match configs.iter().position(|c| c.name() == config.name()) {
+        Some(i) => configs[i] = config,
+        None => configs.push(config),
+    }
+}
+
+/// Adds `bridge` to `bridges`, replacing the bridge with the same name.
+fn merge_bridge(bridges: &mut Vec<BridgeConfig>, bridge: BridgeConfig) {
+    match bridges.iter().position(|b| b.name == bridge.name) {
+        Some(i) => bridges[i] = bridge,
+        None => bridges.push(bridge),
+    }
+}
+
+/// Loads the network configuration.
+///
+/// Files which can not be read or parsed are skipped with a warning.
+///
+/// # Arguments
+///
+/// * `path` - The main configuration file (see `NETWORK_CONFIG`).
+/// * `dir` - The drop-in directory (see `NETWORK_CONFIG_DIR`).
+///
+/// # Returns
+///
+/// * `Option<Vec<NetworkInterfaceConfig>>` - The configured interfaces,
+///   or `None` if there is no configuration file at all.
+pub fn load(path: &Path, dir: &Path) -> Option<Vec<NetworkInterfaceConfig>> {
+    let mut found = false;
+    let mut configs = Vec::new();
+
+    if path.exists() {
+        found = true;
+        match fs::read_to_string(path)
+            .map(|contents| toml::from_str::<NetworkConfigFile>(&contents))
+        {
+            Ok(Ok(file)) => file
+                .interface
+                .into_iter()
+                .for_each(|config| merge(&mut configs, config)),
+            Ok(Err(err)) => warn!("Skipping malformed '{}': {}", path.display(), err),
+            Err(err) => warn!("Skipping '{}': {}", path.display(), err),
+        }
+    }
+
+    for dropin in dropins(dir) {
+        found = true;
+        debug!("Loading network config drop-in '{}'", dropin.display());
+        match fs::read_to_string(&dropin).map(|contents| parse_dropin(&contents)) {
+            Ok(Ok(DropIn::Interface(config))) => merge(&mut configs, config),
+            // loaded by load_bridges
+            Ok(Ok(DropIn::Bridge(_))) => {}
+            Ok(Err(err)) => warn!("Skipping malformed '{}': {}", dropin.display(), err),
+            Err(err) => warn!("Skipping '{}': {}", dropin.display(), err),
+        }
+    }
+
+    if found {
+        Some(configs)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bridges_are_merged_with_dropins() {
+        let dir = std::env::temp_dir().join(format!("linos-test-bridges-{}", std::process::id()));
+        let dropin_dir = dir.join("network.d");
+        fs::create_dir_all(&dropin_dir).unwrap();
+        let path = dir.join("network.toml");
+        fs::write(
+            &path,
+            r#"
+[[interface]]
+type = "dhcp"
+name = "br0"
+
+[[bridge]]
+name = "br0"
+members = ["eth3", "eth2"]
+
+[[bridge]]
+name = "br1"
+members = ["eth4"]
+"#,
+        )
+        .unwrap();
+        fs::write(
+            dropin_dir.join("10-br1.toml"),
+            "type = \"bridge\"\nname = \"br1\"\nmembers = [\"eth5\", \"eth4\"]\n",
+        )
+        .unwrap();
+        fs::write(
+            dropin_dir.join("20-br2.toml"),
+            "type = \"bridge\"\nname = \"br2\"\nmembers = [\"eth6\"]\n",
+        )
+        .unwrap();
+        fs::write(
+            dropin_dir.join("30-br2.toml"),
+            "type = \"dhcp\"\nname = \"br2\"\n",
+        )
+        .unwrap();
+
+        let bridges = load_bridges(&path, &dropin_dir);
+        let configs = load(&path, &dropin_dir).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        // the members keep their configured order, a drop-in replaces a bridge
+        let bridges = bridges
+            .iter()
+            .map(|bridge| (bridge.name.as_str(), bridge.members.clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            bridges,
+            [
+                ("br0", vec!["eth3".to_string(), "eth2".to_string()]),
+                ("br1", vec!["eth5".to_string(), "eth4".to_string()]),
+                ("br2", vec!["eth6".to_string()]),
+            ]
+        );
+        // the bridge drop-ins do not define interfaces
+        let names = configs
+            .iter()
+            .map(|config| config.name())
+            .collect::<Vec<&str>>();
+        assert_eq!(names, ["br0", "br2"]);
+    }
+
+    #[test]
+    fn malformed_bridges_are_skipped() {
+        assert!(matches!(
+            parse_dropin("type = \"bridge\"\nname = \"br0\"\nmembers = [\"eth0\"]\n"),
+            Ok(DropIn::Bridge(_))
+        ));
+        assert!(matches!(
+            parse_dropin("type = \"dhcp\"\nname = \"eth0\"\n"),
+            Ok(DropIn::Interface(_))
+        ));
+        // a bridge without members
+        assert!(parse_dropin("type = \"bridge\"\nname = \"br0\"\n").is_err());
+        assert!(parse_dropin("type = \"tunnel\"\nname = \"tun0\"\n").is_err());
+
+        let dir = std::env::temp_dir().join(format!(
+            "linos-test-bridges-malformed-{}",
+            std::process::id()
+        ));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("10-br0.toml"),
+            "type = \"bridge\"\nname = \"br0\"\n",
+        )
+        .unwrap();
+        let bridges = load_bridges(&dir.join("network.toml"), &dir);
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(bridges.is_empty());
+    }
+}
@@ -1,15 +1,19 @@
-use log::{debug, trace};
+use log::{debug, trace, warn};
 use rand::{self, Rng};
 use std::{
+    ffi::OsString,
     io::{self, Error},
-    net::{IpAddr, Ipv4Addr},
+    net::{Ipv4Addr, UdpSocket},
+    os::unix::io::AsRawFd,
     time::{Duration, Instant},
 };
 
-use dhcproto::{v4, Decodable, Decoder, Encodable, Encoder};
+use dhcproto::{v4, Decodable, Decoder, Encodable};
+use nix::sys::socket::{setsockopt, sockopt};
 use pnet::{
     datalink::{self, Channel, Config, NetworkInterface},
     packet::{
+        arp::{ArpHardwareTypes, ArpOperations, ArpPacket, MutableArpPacket},
         ethernet::{EtherTypes, EthernetPacket, MutableEthernetPacket},
         ip::IpNextHeaderProtocols,
         ipv4::{self, Ipv4Packet, MutableIpv4Packet},
@@ -19,10 +23,142 @@ use pnet::{
     util::MacAddr,
 };
 
-use super::iface::StaticNetworkInterfaceConfig;
+use super::lease::DhcpLease;
 
 pub const IPV4_HEADER_LENGTH: u8 = 20;
 
+/// Maximum length of a DHCP option payload.
+pub const MAX_OPTION_LENGTH: usize = 255;
+
+/// Default maximum DHCP message size (option 57) announced to the server.
+pub const DEFAULT_MAX_MESSAGE_SIZE: u16 = 1500;
+
+/// Smallest maximum DHCP message size allowed (see: https://www.rfc-editor.org/rfc/rfc2132#section-9.10).
+pub const MIN_MAX_MESSAGE_SIZE: u16 = 576;
+
+/// Time to wait for the configured DHCP server to answer the ARP probe.
+const ARP_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Time to wait for the reply to a DHCP message.
+const REPLY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Interval in which the timeout is checked while waiting for a reply.
+const REPLY_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// The UDP port DHCP clients receive on.
+const CLIENT_PORT: u16 = 68;
+
+/// The UDP port DHCP servers receive on.
+const SERVER_PORT: u16 = 67;
+
+/// How the DHCP messages are sent and received (`dhcp.transport=`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DhcpTransport {
+    /// Ethernet frames on a packet socket, works on
+    /// interfaces without an address (the default).
+    Raw,
+    /// A UDP socket bound to the interface, the kernel routes the messages,
+    /// so unicasting to `DhcpConfig::server` needs an address on the interface.
+    Udp,
+}
+
+impl DhcpTransport {
+    pub const ALL: [DhcpTransport; 2] = [DhcpTransport::Raw, DhcpTransport::Udp];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            DhcpTransport::Raw => "raw",
+            DhcpTransport::Udp => "udp",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<DhcpTransport> {
+        DhcpTransport::ALL
+            .into_iter()
+            .find(|transport| transport.name() == name)
+    }
+}
+
+/// Settings of the DHCP client.
+#[derive(Debug, Clone)]
+pub struct DhcpConfig {
+    /// Asks the server for an immediate ACK to the DISCOVER
+    /// (rapid commit, see: https://www.rfc-editor.org/rfc/rfc4039).
+    pub rapid_commit: bool,
+    /// Sends the UDP packets with a zero checksum (allowed for IPv4),
+    /// the kernel computes the checksum with `DhcpTransport::Udp`.
+    ///
+    /// Some virtual NICs (e.g. virtio with checksum offloading) drop the
+    /// raw DHCP packets with a precomputed checksum. Only enable this if
+    /// the DISCOVER never gets an answer on such an interface.
+    pub zero_udp_checksum: bool,
+    /// The vendor class identifier (option 60) sent to the server,
+    /// at most `MAX_OPTION_LENGTH` bytes long.
+    pub vendor_class: Option<String>,
+    /// The maximum DHCP message size (option 57) announced to the server,
+    /// at least `MIN_MAX_MESSAGE_SIZE`. Some relays drop or fragment replies
+    /// to clients not announcing it.
+    pub max_message_size: u16,
+    /// The DHCP server the DISCOVER and REQUEST are addressed to instead
+    /// of broadcasting them, the server answers with unicast replies.
+    ///
+    /// The mac address of the server is resolved with an ARP probe first,
+    /// so the frames are only sent to the server. If the server does not
+    /// answer (e.g. because it is behind a relay agent, which only forwards
+    /// broadcasts), the DISCOVER or REQUEST is broadcast.
+    pub server: Option<Ipv4Addr>,
+    /// How the messages are sent and received.
+    pub transport: DhcpTransport,
+    /// The lease time in seconds hinted to the server (option 51).
+    ///
+    /// The server may hand out a different lease time, the lease
+    /// is always renewed according to the lease time of the ACK.
+    pub requested_lease_time: Option<u32>,
+}
+
+impl Default for DhcpConfig {
+    fn default() -> Self {
+        DhcpConfig {
+            rapid_commit: false,
+            zero_udp_checksum: false,
+            vendor_class: None,
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+            server: None,
+            transport: DhcpTransport::Raw,
+            requested_lease_time: None,
+        }
+    }
+}
+
+/// Validates a vendor class identifier (option 60).
+///
+/// # Arguments
+///
+/// * `vendor_class` - The vendor class identifier.
+pub fn validate_vendor_class(vendor_class: &str) -> io::Result<()> {
+    if vendor_class.is_empty() || vendor_class.len() > MAX_OPTION_LENGTH {
+        return Err(Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "Vendor class must be 1 to {} bytes long, got {}",
+                MAX_OPTION_LENGTH,
+                vendor_class.len()
+            ),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Parses a requested lease time in seconds (`dhcp.leasetime=`).
+///
+/// # Returns
+///
+/// * `Option<u32>` - The lease time, or `None` if it is not a positive number.
+pub fn parse_lease_time(value: &str) -> Option<u32> {
+    value.parse::<u32>().ok().filter(|secs| *secs > 0)
+}
+
 /// Creates a default dhcpv4 message.
 ///
 /// The message asks for the following options:
@@ -30,34 +166,179 @@ pub const IPV4_HEADER_LENGTH: u8 = 20;
 /// - Router
 /// - DomainNameServer
 /// - DomainName
+/// - Hostname
+/// - NTPServers
+/// - VendorExtensions (if a vendor class is configured)
+///
+/// DISCOVER and REQUEST messages announce the maximum message size.
 ///
 /// # Arguments
 ///
 /// * `mac` - The mac address of the interface.
 /// * `dhcp_message_type` - The type of the dhcp message.
-fn create_dhcpv4_message(mac: MacAddr, dhcp_message_type: v4::MessageType) -> v4::Message {
+/// * `config` - The DHCP client settings.
+fn create_dhcpv4_message(
+    mac: MacAddr,
+    dhcp_message_type: v4::MessageType,
+    config: &DhcpConfig,
+) -> v4::Message {
     // construct a new Message
     let chaddr = mac.octets();
 
+    // a configured server answers unicast, others are asked to broadcast
+    let flags = match config.server {
+        Some(_) => v4::Flags::default(),
+        None => v4::Flags::default().set_broadcast(),
+    };
+
     let mut msg = v4::Message::default();
-    msg.set_flags(v4::Flags::default().set_broadcast()) // set broadcast to true
+    msg.set_flags(flags)
         .set_chaddr(&chaddr) // set chaddr
         .opts_mut()
         .insert(v4::DhcpOption::MessageType(dhcp_message_type)); // set msg type
 
     // set some more options
+    let mut parameters = vec![
+        v4::OptionCode::SubnetMask,
+        v4::OptionCode::Router,
+        v4::OptionCode::DomainNameServer,
+        v4::OptionCode::DomainName,
+        v4::OptionCode::Hostname,
+        v4::OptionCode::NTPServers,
+    ];
+    if let Some(vendor_class) = &config.vendor_class {
+        parameters.push(v4::OptionCode::VendorExtensions);
+        msg.opts_mut().insert(v4::DhcpOption::ClassIdentifier(
+            vendor_class.as_bytes().to_vec(),
+        ));
+    }
     msg.opts_mut()
-        .insert(v4::DhcpOption::ParameterRequestList(vec![
-            v4::OptionCode::SubnetMask,
-            v4::OptionCode::Router,
-            v4::OptionCode::DomainNameServer,
-            v4::OptionCode::DomainName,
-        ]));
+        .insert(v4::DhcpOption::ParameterRequestList(parameters));
     msg.opts_mut()
         .insert(v4::DhcpOption::ClientIdentifier(chaddr.to_vec()));
+
+    if matches!(
+        dhcp_message_type,
+        v4::MessageType::Discover | v4::MessageType::Request
+    ) {
+        msg.opts_mut().insert(v4::DhcpOption::MaxMessageSize(
+            config.max_message_size.max(MIN_MAX_MESSAGE_SIZE),
+        ));
+        if let Some(secs) = config.requested_lease_time {
+            msg.opts_mut()
+                .insert(v4::DhcpOption::AddressLeaseTime(secs));
+        }
+    }
+
+    if config.rapid_commit && dhcp_message_type == v4::MessageType::Discover {
+        msg.opts_mut().insert(v4::DhcpOption::RapidCommit);
+    }
     msg
 }
 
+/// Checks if a DISCOVER response is a rapid commit ACK.
+///
+/// Servers honoring rapid commit answer the DISCOVER with an ACK
+/// carrying the rapid commit option instead of an OFFER.
+///
+/// # Arguments
+///
+/// * `msg` - The DHCP discover response message.
+fn is_rapid_commit_ack(msg: &v4::Message) -> bool {
+    matches!(
+        msg.opts().get(v4::OptionCode::MessageType),
+        Some(v4::DhcpOption::MessageType(v4::MessageType::Ack))
+    ) && msg.opts().get(v4::OptionCode::RapidCommit).is_some()
+}
+
+/// Creates an ARP probe for `ip` (see: https://www.rfc-editor.org/rfc/rfc5227).
+///
+/// The sender address is `0.0.0.0`, as the interface has no address yet.
+///
+/// # Arguments
+///
+/// * `mac` - The mac address of the interface.
+/// * `ip` - The address to resolve the mac address of.
+fn create_arp_probe(mac: MacAddr, ip: Ipv4Addr) -> EthernetPacket<'static> {
+    let mut arp_packet =
+        MutableArpPacket::owned(vec![0; ArpPacket::minimum_packet_size()]).unwrap();
+    arp_packet.set_hardware_type(ArpHardwareTypes::Ethernet);
+    arp_packet.set_protocol_type(EtherTypes::Ipv4);
+    arp_packet.set_hw_addr_len(6);
+    arp_packet.set_proto_addr_len(4);
+    arp_packet.set_operation(ArpOperations::Request);
+    arp_packet.set_sender_hw_addr(mac);
+    arp_packet.set_sender_proto_addr(Ipv4Addr::UNSPECIFIED);
+    arp_packet.set_target_hw_addr(MacAddr::zero());
+    arp_packet.set_target_proto_addr(ip);
+
+    let payload = arp_packet.packet();
+    let buf = vec![0u8; EthernetPacket::minimum_packet_size() + payload.len()];
+    let mut ethernet_packet = MutableEthernetPacket::owned(buf).unwrap();
+    ethernet_packet.set_destination(MacAddr::broadcast());
+    ethernet_packet.set_source(mac);
+    ethernet_packet.set_ethertype(EtherTypes::Arp);
+    ethernet_packet.set_payload(payload);
+
+    ethernet_packet.consume_to_immutable()
+}
+
+/// Reads the mac address of `ip` from an ARP reply.
+///
+/// # Returns
+///
+/// * `Option<MacAddr>` - `None` if the frame is no ARP reply from `ip`.
+fn parse_arp_reply(frame: &[u8], ip: Ipv4Addr) -> Option<MacAddr> {
+    let ether_packet = EthernetPacket::new(frame)?;
+    if ether_packet.get_ethertype() != EtherTypes::Arp {
+        return None;
+    }
+    let arp_packet = ArpPacket::new(ether_packet.payload())?;
+    (arp_packet.get_operation() == ArpOperations::Reply && arp_packet.get_sender_proto_addr() == ip)
+        .then(|| arp_packet.get_sender_hw_addr())
+}
+
+/// Resolves the mac address of the DHCP server `ip` with an ARP probe.
+///
+/// # Arguments
+///
+/// * `interface` - The interface the server is reachable on.
+/// * `ip` - The address of the DHCP server.
+fn resolve_server_mac(interface: &NetworkInterface, ip: Ipv4Addr) -> io::Result<MacAddr> {
+    let mac = match interface.mac {
+        Some(mac) => mac,
+        None => return Err(Error::new(io::ErrorKind::NotFound, "No MAC address found")),
+    };
+    let config = Config {
+        read_timeout: Some(ARP_TIMEOUT),
+        ..Config::default()
+    };
+    let (mut sender, mut receiver) = match datalink::channel(interface, config) {
+        Ok(Channel::Ethernet(tx, rx)) => (tx, rx),
+        Ok(_) => return Err(Error::other("Unknown channel type")),
+        Err(err) => return Err(err),
+    };
+
+    sender.send_to(create_arp_probe(mac, ip).packet(), None);
+    let start_time = Instant::now();
+    while start_time.elapsed() < ARP_TIMEOUT {
+        match receiver.next() {
+            Ok(frame) => {
+                if let Some(server_mac) = parse_arp_reply(frame, ip) {
+                    return Ok(server_mac);
+                }
+            }
+            Err(err) if err.kind() == io::ErrorKind::TimedOut => break,
+            Err(err) => return Err(err),
+        }
+    }
+
+    Err(Error::new(
+        io::ErrorKind::TimedOut,
+        format!("No ARP reply from {}", ip),
+    ))
+}
+
 /// Creates a dhcp udp packet from a dhcp message.
 ///
 /// The packet is wrapped in an udp packet, ipv4 packet and then in an ethernet packet.
@@ -65,7 +346,13 @@ fn create_dhcpv4_message(mac: MacAddr, dhcp_message_type: v4::MessageType) -> v4
 /// # Arguments
 ///
 /// * `dhcp_message` - The dhcp message to put into an ethernet frame.
-fn create_dhcp_packet(dhcp_message: v4::Message) -> io::Result<EthernetPacket<'static>> {
+/// * `config` - The DHCP client settings.
+/// * `dst_mac` - The mac address of the configured server, broadcast otherwise.
+fn create_dhcp_packet(
+    dhcp_message: v4::Message,
+    config: &DhcpConfig,
+    dst_mac: MacAddr,
+) -> io::Result<EthernetPacket<'static>> {
     // the mac address is required to do a dhcp request
     let mac = dhcp_message.chaddr();
 
@@ -75,19 +362,24 @@ fn create_dhcp_packet(dhcp_message: v4::Message) -> io::Result<EthernetPacket<'s
     let buf = vec![0; 8 + payload.len()];
     let mut udp_packet = MutableUdpPacket::owned(buf).unwrap();
 
-    udp_packet.set_source(68);
-    udp_packet.set_destination(67);
+    udp_packet.set_source(CLIENT_PORT);
+    udp_packet.set_destination(SERVER_PORT);
     udp_packet.set_length((8 + payload.len()) as u16);
     udp_packet.set_payload(&payload);
 
-    let dst_ip = Ipv4Addr::new(255, 255, 255, 255);
+    let dst_ip = config.server.unwrap_or(Ipv4Addr::BROADCAST);
     let src_ip = Ipv4Addr::new(0, 0, 0, 0);
 
-    udp_packet.set_checksum(udp::ipv4_checksum(
-        &udp_packet.to_immutable(),
-        &src_ip,
-        &dst_ip,
-    ));
+    if config.zero_udp_checksum {
+        // a zero checksum means no checksum was computed
+        udp_packet.set_checksum(0);
+    } else {
+        udp_packet.set_checksum(udp::ipv4_checksum(
+            &udp_packet.to_immutable(),
+            &src_ip,
+            &dst_ip,
+        ));
+    }
 
     let payload = udp_packet.packet();
 
@@ -117,15 +409,9 @@ fn create_dhcp_packet(dhcp_message: v4::Message) -> io::Result<EthernetPacket<'s
     let buf = vec![0u8; EthernetPacket::minimum_packet_size() + payload.len()];
     let mut ethernet_packet = MutableEthernetPacket::owned(buf).unwrap();
 
-    let dst_mac = MacAddr::broadcast();
     let src_mac = match *mac {
         [a, b, c, d, e, f] => MacAddr::new(a, b, c, d, e, f),
-        _ => {
-            return Err(Error::new(
-                io::ErrorKind::Other,
-                format!("Invalid MAC address: {:?}", mac),
-            ))
-        }
+        _ => return Err(Error::other(format!("Invalid MAC address: {:?}", mac))),
     };
 
     ethernet_packet.set_destination(dst_mac);
@@ -136,81 +422,187 @@ fn create_dhcp_packet(dhcp_message: v4::Message) -> io::Result<EthernetPacket<'s
     Ok(ethernet_packet.consume_to_immutable())
 }
 
-/// Receives a dhcp message from the given interface.
+/// Decodes a DHCP message and checks that it is the reply to `sent`.
 ///
-/// The message is received and then unwrapped from an ethernet frame,
-/// ipv4 frame and udp frame.
+/// Replies to other clients or transactions are ignored.
 ///
-/// # Arguments
+/// # Returns
 ///
-/// * `interface` - The interface to receive the message on.
-fn receive_message(interface: NetworkInterface) -> io::Result<v4::Message> {
-    let (_, mut receiver) = match datalink::channel(&interface, Config::default()) {
-        Ok(Channel::Ethernet(tx, rx)) => (tx, rx),
-        Ok(_) => return Err(Error::new(io::ErrorKind::Other, "Unknown channel type")),
-        Err(err) => return Err(err),
+/// * `Option<v4::Message>` - `None` if the payload is no reply to `sent`.
+fn decode_reply(payload: &[u8], sent: &v4::Message) -> Option<v4::Message> {
+    let msg = match v4::Message::decode(&mut Decoder::new(payload)) {
+        Ok(msg) => msg,
+        Err(err) => {
+            debug!("Skipping malformed DHCP message: {}", err);
+            return None;
+        }
     };
+    if msg.xid() != sent.xid() || msg.chaddr() != sent.chaddr() {
+        trace!("Skipping DHCP message of transaction {:#x}", msg.xid());
+        return None;
+    }
 
-    let timeout = Duration::from_secs(10);
-    let start_time = Instant::now();
+    Some(msg)
+}
 
-    let msg = loop {
-        if Instant::now().duration_since(start_time) > timeout {
-            return Err(io::Error::new(
-                io::ErrorKind::TimedOut,
-                "Timeout waiting for OFFER",
-            ));
-        }
+/// Reads the reply to `sent` from a frame.
+///
+/// The message is unwrapped from an ethernet frame, ipv4 frame and udp frame.
+///
+/// # Returns
+///
+/// * `Option<v4::Message>` - `None` if the frame is no reply to `sent`.
+fn parse_dhcp_reply(frame: &[u8], sent: &v4::Message) -> Option<v4::Message> {
+    // -- Ethernet frame
+    let ether_packet = EthernetPacket::new(frame)?;
+    if ether_packet.get_ethertype() != EtherTypes::Ipv4 {
+        return None;
+    }
 
-        let buf = receiver
-            .next()
-            .map_err(|e| format!("Error receiving packets: {}", e))
-            .unwrap();
+    // -- IPv4 packet
+    let ip_packet = Ipv4Packet::new(ether_packet.payload())?;
+    if ip_packet.get_next_level_protocol() != IpNextHeaderProtocols::Udp {
+        return None;
+    }
 
-        // -- Ethernet frame
-        let ether_packet = match EthernetPacket::new(&buf[..]) {
-            Some(ether_packet) => ether_packet,
-            None => continue,
-        };
+    // -- UDP packet
+    let udp_packet = UdpPacket::new(ip_packet.payload())?;
+    if udp_packet.get_destination() != CLIENT_PORT {
+        debug!("Received packet on port {}", udp_packet.get_destination());
+        return None;
+    }
 
-        if ether_packet.get_ethertype() != EtherTypes::Ipv4 {
-            continue;
-        }
+    decode_reply(udp_packet.payload(), sent)
+}
 
-        // -- IPv4 packet
-        let ip_packet = match Ipv4Packet::new(ether_packet.payload()) {
-            Some(ip_packet) => ip_packet,
-            None => continue,
-        };
+/// Sends a DHCP message and receives the reply to it,
+/// using the transport selected in the settings.
+///
+/// # Arguments
+///
+/// * `interface` - The interface to send the message from.
+/// * `msg` - The message to send.
+/// * `config` - The DHCP client settings.
+/// * `dst_mac` - The mac address of the configured server, broadcast
+///   otherwise (only used by `DhcpTransport::Raw`).
+fn exchange(
+    interface: &NetworkInterface,
+    msg: &v4::Message,
+    config: &DhcpConfig,
+    dst_mac: MacAddr,
+) -> io::Result<v4::Message> {
+    match config.transport {
+        DhcpTransport::Raw => raw_exchange(interface, msg, config, dst_mac),
+        DhcpTransport::Udp => udp_exchange(interface, msg, config),
+    }
+}
 
-        if ip_packet.get_next_level_protocol() != IpNextHeaderProtocols::Udp {
-            continue;
-        }
+/// Sends a DHCP message in an ethernet frame and receives the reply to it.
+///
+/// # Arguments
+///
+/// * `interface` - The interface to send the message from.
+/// * `msg` - The message to send.
+/// * `config` - The DHCP client settings.
+/// * `dst_mac` - The mac address of the configured server, broadcast otherwise.
+fn raw_exchange(
+    interface: &NetworkInterface,
+    msg: &v4::Message,
+    config: &DhcpConfig,
+    dst_mac: MacAddr,
+) -> io::Result<v4::Message> {
+    let channel_config = Config {
+        read_timeout: Some(REPLY_POLL_INTERVAL),
+        ..Config::default()
+    };
+    // the reply is received on the channel the message is sent
+    // on, so it can not arrive before anything listens for it
+    let (mut sender, mut receiver) = match datalink::channel(interface, channel_config) {
+        Ok(Channel::Ethernet(tx, rx)) => (tx, rx),
+        Ok(_) => return Err(Error::other("Unknown channel type")),
+        Err(err) => return Err(err),
+    };
 
-        // -- UDP packet
-        let udp_packet = match UdpPacket::new(ip_packet.payload()) {
-            Some(udp_packet) => udp_packet,
-            None => continue,
-        };
+    let frame = create_dhcp_packet(msg.clone(), config, dst_mac)?;
+    if let Some(Err(err)) = sender.send_to(frame.packet(), None) {
+        return Err(err);
+    }
 
-        if udp_packet.get_destination() != 68 {
-            debug!("Received packet on port {}", udp_packet.get_destination());
-            continue;
+    let start_time = Instant::now();
+    while start_time.elapsed() < REPLY_TIMEOUT {
+        match receiver.next() {
+            Ok(frame) => {
+                if let Some(reply) = parse_dhcp_reply(frame, msg) {
+                    return Ok(reply);
+                }
+            }
+            Err(err) if err.kind() == io::ErrorKind::TimedOut => {}
+            Err(err) => {
+                return Err(Error::new(
+                    err.kind(),
+                    format!("Error receiving packets: {}", err),
+                ))
+            }
         }
+    }
 
-        let input = udp_packet.payload();
+    Err(Error::new(
+        io::ErrorKind::TimedOut,
+        "Timeout waiting for a DHCP reply",
+    ))
+}
 
-        let msg = v4::Message::decode(&mut Decoder::new(&input)).unwrap();
+/// Sends a DHCP message from a UDP socket and receives the reply to it.
+///
+/// The socket is bound to the interface, the kernel routes the message
+/// and computes its checksum. Without an address on the interface, only
+/// broadcasts can be sent, unicasting to the configured server fails.
+///
+/// # Arguments
+///
+/// * `interface` - The interface to send the message from.
+/// * `msg` - The message to send.
+/// * `config` - The DHCP client settings.
+fn udp_exchange(
+    interface: &NetworkInterface,
+    msg: &v4::Message,
+    config: &DhcpConfig,
+) -> io::Result<v4::Message> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, CLIENT_PORT))?;
+    setsockopt(
+        socket.as_raw_fd(),
+        sockopt::BindToDevice,
+        &OsString::from(&interface.name),
+    )?;
+    socket.set_broadcast(true)?;
+    socket.set_read_timeout(Some(REPLY_POLL_INTERVAL))?;
 
-        // now encode
-        let mut buf = Vec::new();
-        let mut e = Encoder::new(&mut buf);
-        msg.encode(&mut e).unwrap();
+    let payload = msg.to_vec().map_err(Error::other)?;
+    let dst = config.server.unwrap_or(Ipv4Addr::BROADCAST);
+    socket.send_to(&payload, (dst, SERVER_PORT))?;
 
-        break msg;
-    };
+    let mut buf = vec![0u8; u16::MAX as usize];
+    let start_time = Instant::now();
+    while start_time.elapsed() < REPLY_TIMEOUT {
+        match socket.recv(&mut buf) {
+            Ok(len) => {
+                if let Some(reply) = decode_reply(&buf[..len], msg) {
+                    return Ok(reply);
+                }
+            }
+            Err(err)
+                if matches!(
+                    err.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) => {}
+            Err(err) => return Err(err),
+        }
+    }
 
-    Ok(msg)
+    Err(Error::new(
+        io::ErrorKind::TimedOut,
+        "Timeout waiting for a DHCP reply",
+    ))
 }
 
 /// Sends a DHCP discover message from the given interface.
@@ -220,31 +612,28 @@ fn receive_message(interface: NetworkInterface) -> io::Result<v4::Message> {
 /// # Arguments
 ///
 /// * `interface` - The interface to send the message from.
+/// * `config` - The DHCP client settings.
+/// * `dst_mac` - The mac address of the configured server, broadcast otherwise.
 ///
 /// # Returns
 ///
-/// * `io::Result<v4::Message>` - The DHCP offer message.
-fn dhcp_discover(interface: NetworkInterface) -> io::Result<v4::Message> {
+/// * `io::Result<v4::Message>` - The DHCP offer message
+///   (or ack in case of a rapid commit).
+fn dhcp_discover(
+    interface: &NetworkInterface,
+    config: &DhcpConfig,
+    dst_mac: MacAddr,
+) -> io::Result<v4::Message> {
     let mac = match interface.mac {
         Some(mac) => mac,
         None => return Err(Error::new(io::ErrorKind::NotFound, "No MAC address found")),
     };
 
-    let (mut sender, _) = match datalink::channel(&interface, Config::default()) {
-        Ok(Channel::Ethernet(tx, rx)) => (tx, rx),
-        Ok(_) => panic!("Error creating channel: Unknown channel type"),
-        Err(err) => return Err(err),
-    };
-
     // -- DHCP discover message
-    let msg = create_dhcpv4_message(mac, v4::MessageType::Discover);
-    let dhcp_discover_packet = create_dhcp_packet(msg)?;
-    let dhcp_discover_packet = dhcp_discover_packet.packet();
-
-    sender.send_to(dhcp_discover_packet, Some(interface.clone()));
+    let msg = create_dhcpv4_message(mac, v4::MessageType::Discover, config);
     debug!("DISCOVER from {}", mac);
 
-    let msg = receive_message(interface)?;
+    let msg = exchange(interface, &msg, config, dst_mac)?;
     trace!("DISCOVER response: {}", msg);
 
     Ok(msg)
@@ -259,67 +648,82 @@ fn dhcp_discover(interface: NetworkInterface) -> io::Result<v4::Message> {
 /// * `interface` - The interface to send the message from.
 /// * `discover_response` - The DHCP discover response message.
 ///   Obtained from `dhcp_discover`.
+/// * `config` - The DHCP client settings.
+/// * `dst_mac` - The mac address of the configured server, broadcast otherwise.
 ///
 /// # Returns
 ///
 /// * `io::Result<v4::Message>` - The DHCP ack message.
 fn dhcp_request(
-    interface: NetworkInterface,
-    discover_response: v4::Message,
+    interface: &NetworkInterface,
+    discover_response: &v4::Message,
+    config: &DhcpConfig,
+    dst_mac: MacAddr,
 ) -> io::Result<v4::Message> {
     let mac = match interface.mac {
         Some(mac) => mac,
         None => return Err(Error::new(io::ErrorKind::NotFound, "No MAC address found")),
     };
 
-    let (mut sender, _) = match datalink::channel(&interface, Config::default()) {
-        Ok(Channel::Ethernet(tx, rx)) => (tx, rx),
-        Ok(_) => panic!("Error creating channel: Unknown channel type"),
-        Err(err) => return Err(err),
-    };
-
     // -- DHCP request message
-    let mut msg = create_dhcpv4_message(mac, v4::MessageType::Request);
+    let mut msg = create_dhcpv4_message(mac, v4::MessageType::Request, config);
     msg.opts_mut().insert(v4::DhcpOption::RequestedIpAddress(
         discover_response.yiaddr(),
     ));
     msg.opts_mut()
         .insert(v4::DhcpOption::ServerIdentifier(discover_response.siaddr()));
-
-    let dhcp_discover_packet = create_dhcp_packet(msg)?;
-    let dhcp_discover_packet = dhcp_discover_packet.packet();
-
-    sender.send_to(dhcp_discover_packet, Some(interface.clone()));
     debug!("REQUEST ip {} from {}", discover_response.yiaddr(), mac);
 
-    let msg = receive_message(interface)?;
+    let msg = exchange(interface, &msg, config, dst_mac)?;
     trace!("REQUEST response: {}", msg);
 
     Ok(msg)
 }
 
+/// Checks if a failed exchange with the configured server is retried
+/// as broadcast, e.g. as the server is behind a relay agent, which only
+/// forwards broadcasts, or the interface has no route to it yet.
+///
+/// # Arguments
+///
+/// * `config` - The DHCP client settings the exchange used.
+/// * `err` - The error of the exchange.
+fn falls_back_to_broadcast(config: &DhcpConfig, err: &io::Error) -> bool {
+    config.server.is_some()
+        && matches!(
+            err.kind(),
+            io::ErrorKind::TimedOut
+                | io::ErrorKind::NetworkUnreachable
+                | io::ErrorKind::HostUnreachable
+        )
+}
+
 /// Request an IP address from a DHCP server.
 ///
 /// # Arguments
 ///
 /// * `iface_name` - The name of the interface to request an IP address for.
+/// * `config` - The DHCP client settings.
+///
+/// # Returns
+///
+/// * `io::Result<DhcpLease>` - The lease handed out by the DHCP server.
 ///
 /// # Example
 ///
 /// ```rust
-/// use dhcp::request;
+/// use dhcp::{request, DhcpConfig};
 ///
 /// let iface_name = "eth0".to_string();
-/// let iface = request(&iface_name).unwrap();
+/// let lease = request(&iface_name, &DhcpConfig::default()).unwrap();
 /// ```
-pub fn request(iface_name: &String) -> io::Result<StaticNetworkInterfaceConfig> {
+pub fn request(iface_name: &String, config: &DhcpConfig) -> io::Result<DhcpLease> {
     // TODO: add some retry logic in case of faillures and timeouts
 
     // check if the interface exists and is up
     let interface = match datalink::interfaces()
         .into_iter()
-        .filter(|i| &i.name == iface_name)
-        .next()
+        .find(|i| &i.name == iface_name)
     {
         Some(interface) => interface,
         None => {
@@ -337,65 +741,382 @@ pub fn request(iface_name: &String) -> io::Result<StaticNetworkInterfaceConfig>
     }
 
     // -- do the dhcp request
-    let discover_response = dhcp_discover(interface.clone())?;
-    let request_response = dhcp_request(interface.clone(), discover_response)?;
-
-    // assemble a static network interface config
-    // from the dhcp response
-    let netmask = match request_response.opts().get(v4::OptionCode::SubnetMask) {
-        Some(v4::DhcpOption::SubnetMask(netmask)) => IpAddr::V4(*netmask),
-        _ => {
-            return Err(Error::new(
-                io::ErrorKind::NotFound,
-                format!("{}: no netmask returned by dhcp.", iface_name),
-            ))
+    let broadcast = DhcpConfig {
+        server: None,
+        ..config.clone()
+    };
+    // a server behind a relay agent does not answer the ARP probe,
+    // sockets leave resolving the server to the kernel
+    let (config, dst_mac) = match config.server {
+        Some(server) if config.transport == DhcpTransport::Raw => {
+            match resolve_server_mac(&interface, server) {
+                Ok(server_mac) => {
+                    debug!(
+                        "{}: DHCP server {} is at {}",
+                        iface_name, server, server_mac
+                    );
+                    (config, server_mac)
+                }
+                Err(err) => {
+                    warn!(
+                        "{}: could not resolve DHCP server {} ({}), broadcasting",
+                        iface_name, server, err
+                    );
+                    (&broadcast, MacAddr::broadcast())
+                }
+            }
         }
+        _ => (config, MacAddr::broadcast()),
     };
-
-    let gateway = match request_response.opts().get(v4::OptionCode::Router) {
-        Some(v4::DhcpOption::Router(router)) => match router.first() {
-            Some(r) => IpAddr::V4(*r),
-            None => {
-                return Err(Error::new(
-                    io::ErrorKind::NotFound,
-                    format!("{}: no gateway found in dhcp response.", iface_name),
-                ))
+    let (discover_response, config, dst_mac) = match dhcp_discover(&interface, config, dst_mac) {
+        Ok(response) => (response, config, dst_mac),
+        Err(err) if falls_back_to_broadcast(config, &err) => {
+            warn!(
+                "{}: DISCOVER to DHCP server {} failed ({}), broadcasting",
+                iface_name,
+                config.server.unwrap(),
+                err
+            );
+            let response = dhcp_discover(&interface, &broadcast, MacAddr::broadcast())?;
+            (response, &broadcast, MacAddr::broadcast())
+        }
+        Err(err) => return Err(err),
+    };
+    let request_response = if config.rapid_commit && is_rapid_commit_ack(&discover_response) {
+        debug!("{}: rapid commit ACK received", iface_name);
+        discover_response
+    } else {
+        match dhcp_request(&interface, &discover_response, config, dst_mac) {
+            Ok(response) => response,
+            Err(err) if falls_back_to_broadcast(config, &err) => {
+                warn!(
+                    "{}: REQUEST to DHCP server {} failed ({}), broadcasting",
+                    iface_name,
+                    config.server.unwrap(),
+                    err
+                );
+                dhcp_request(
+                    &interface,
+                    &discover_response,
+                    &broadcast,
+                    MacAddr::broadcast(),
+                )?
             }
-        },
-        _ => {
-            return Err(Error::new(
-                io::ErrorKind::NotFound,
-                format!("{}: no gateway returned by dhcp.", iface_name),
-            ))
+            Err(err) => return Err(err),
         }
     };
 
-    let dns = match request_response
-        .opts()
-        .get(v4::OptionCode::DomainNameServer)
-    {
-        Some(v4::DhcpOption::DomainNameServer(dns)) => match dns.first() {
-            Some(r) => IpAddr::V4(*r),
-            None => {
-                return Err(Error::new(
-                    io::ErrorKind::NotFound,
-                    format!("{}: no gateway found in dhcp response.", iface_name),
-                ))
+    DhcpLease::from_message(&interface.name, &request_response)
+}
+
+#[cfg(test)]
+mod tests {
+    use dhcproto::Encoder;
+    use pnet::packet::MutablePacket;
+
+    use super::*;
+
+    const MAC: MacAddr = MacAddr(0x52, 0x54, 0x00, 0x12, 0x34, 0x56);
+    const SERVER_MAC: MacAddr = MacAddr(0x52, 0x54, 0x00, 0xab, 0xcd, 0xef);
+
+    #[test]
+    fn rapid_commit_ack_is_accepted() {
+        let config = DhcpConfig {
+            rapid_commit: true,
+            ..DhcpConfig::default()
+        };
+        let discover = create_dhcpv4_message(MAC, v4::MessageType::Discover, &config);
+        assert!(discover.opts().get(v4::OptionCode::RapidCommit).is_some());
+        let request = create_dhcpv4_message(MAC, v4::MessageType::Request, &config);
+        assert!(request.opts().get(v4::OptionCode::RapidCommit).is_none());
+
+        let mut ack = v4::Message::default();
+        ack.opts_mut()
+            .insert(v4::DhcpOption::MessageType(v4::MessageType::Ack));
+        // an ACK without option 80 does not answer the rapid commit
+        assert!(!is_rapid_commit_ack(&ack));
+        ack.opts_mut().insert(v4::DhcpOption::RapidCommit);
+        assert!(is_rapid_commit_ack(&ack));
+
+        let mut offer = v4::Message::default();
+        offer
+            .opts_mut()
+            .insert(v4::DhcpOption::MessageType(v4::MessageType::Offer));
+        offer.opts_mut().insert(v4::DhcpOption::RapidCommit);
+        assert!(!is_rapid_commit_ack(&offer));
+    }
+
+    #[test]
+    fn vendor_class_asks_for_vendor_specific_information() {
+        let msg = create_dhcpv4_message(MAC, v4::MessageType::Discover, &DhcpConfig::default());
+        assert!(msg.opts().get(v4::OptionCode::ClassIdentifier).is_none());
+
+        let config = DhcpConfig {
+            vendor_class: Some("linos".to_string()),
+            ..DhcpConfig::default()
+        };
+        let msg = create_dhcpv4_message(MAC, v4::MessageType::Discover, &config);
+        assert_eq!(
+            msg.opts().get(v4::OptionCode::ClassIdentifier),
+            Some(&v4::DhcpOption::ClassIdentifier(b"linos".to_vec()))
+        );
+        assert!(matches!(
+            msg.opts().get(v4::OptionCode::ParameterRequestList),
+            Some(v4::DhcpOption::ParameterRequestList(codes))
+                if codes.contains(&v4::OptionCode::VendorExtensions)
+        ));
+
+        assert!(validate_vendor_class("linos").is_ok());
+        assert!(validate_vendor_class("").is_err());
+        assert!(validate_vendor_class(&"x".repeat(MAX_OPTION_LENGTH + 1)).is_err());
+    }
+
+    #[test]
+    fn vendor_options_survive_encoding() {
+        let config = DhcpConfig {
+            vendor_class: Some("linos".to_string()),
+            ..DhcpConfig::default()
+        };
+        let discover = create_dhcpv4_message(MAC, v4::MessageType::Discover, &config);
+        let mut buf = Vec::new();
+        discover.encode(&mut Encoder::new(&mut buf)).unwrap();
+        // option 60 on the wire: code, length, value
+        assert!(buf.windows(7).any(|option| option == b"\x3c\x05linos"));
+
+        let decoded = v4::Message::decode(&mut Decoder::new(&buf)).unwrap();
+        assert_eq!(
+            decoded.opts().get(v4::OptionCode::ClassIdentifier),
+            Some(&v4::DhcpOption::ClassIdentifier(b"linos".to_vec()))
+        );
+        assert!(matches!(
+            decoded.opts().get(v4::OptionCode::ParameterRequestList),
+            Some(v4::DhcpOption::ParameterRequestList(codes))
+                if codes.contains(&v4::OptionCode::VendorExtensions)
+        ));
+
+        // the server answers with option 43, encoded as sub-options
+        let mut offer = v4::Message::default();
+        offer
+            .set_xid(decoded.xid())
+            .set_yiaddr(Ipv4Addr::new(10, 0, 0, 2))
+            .set_chaddr(decoded.chaddr());
+        offer
+            .opts_mut()
+            .insert(v4::DhcpOption::MessageType(v4::MessageType::Offer));
+        offer
+            .opts_mut()
+            .insert(v4::DhcpOption::SubnetMask(Ipv4Addr::new(255, 255, 255, 0)));
+        offer
+            .opts_mut()
+            .insert(v4::DhcpOption::Router(vec![Ipv4Addr::new(10, 0, 0, 1)]));
+        offer
+            .opts_mut()
+            .insert(v4::DhcpOption::VendorExtensions(vec![
+                1, 3, b'a', b'b', b'c',
+            ]));
+        let mut buf = Vec::new();
+        offer.encode(&mut Encoder::new(&mut buf)).unwrap();
+        assert!(buf
+            .windows(7)
+            .any(|option| option == [43, 5, 1, 3, b'a', b'b', b'c']));
+
+        let decoded = v4::Message::decode(&mut Decoder::new(&buf)).unwrap();
+        let lease = DhcpLease::from_message("eth0", &decoded).unwrap();
+        assert_eq!(lease.vendor_specific, Some(vec![1, 3, b'a', b'b', b'c']));
+        assert_eq!(lease.vendor_suboptions(), Some(vec![(1, b"abc".to_vec())]));
+    }
+
+    #[test]
+    fn max_message_size_is_sent() {
+        let sent = |message_type, max_message_size| {
+            let config = DhcpConfig {
+                max_message_size,
+                ..DhcpConfig::default()
+            };
+            let msg = create_dhcpv4_message(MAC, message_type, &config);
+            let frame = create_dhcp_packet(msg, &config, MacAddr::broadcast()).unwrap();
+            let ip_packet = Ipv4Packet::new(frame.payload()).unwrap();
+            let udp_packet = UdpPacket::new(ip_packet.payload()).unwrap();
+            let msg = v4::Message::decode(&mut Decoder::new(udp_packet.payload())).unwrap();
+            match msg.opts().get(v4::OptionCode::MaxMessageSize) {
+                Some(v4::DhcpOption::MaxMessageSize(size)) => Some(*size),
+                _ => None,
             }
-        },
-        _ => {
-            return Err(Error::new(
-                io::ErrorKind::NotFound,
-                format!("{}: no gateway returned by dhcp.", iface_name),
-            ))
+        };
+
+        assert_eq!(
+            sent(v4::MessageType::Discover, DEFAULT_MAX_MESSAGE_SIZE),
+            Some(1500)
+        );
+        assert_eq!(sent(v4::MessageType::Request, 9000), Some(9000));
+        // raised to the minimum every client has to accept
+        assert_eq!(
+            sent(v4::MessageType::Discover, 100),
+            Some(MIN_MAX_MESSAGE_SIZE)
+        );
+        assert_eq!(sent(v4::MessageType::Release, 9000), None);
+    }
+
+    #[test]
+    fn requested_lease_time_is_sent() {
+        let config = DhcpConfig {
+            requested_lease_time: Some(3600),
+            ..DhcpConfig::default()
+        };
+        for message_type in [v4::MessageType::Discover, v4::MessageType::Request] {
+            let msg = create_dhcpv4_message(MAC, message_type, &config);
+            assert_eq!(
+                msg.opts().get(v4::OptionCode::AddressLeaseTime),
+                Some(&v4::DhcpOption::AddressLeaseTime(3600))
+            );
         }
-    };
+        let msg = create_dhcpv4_message(MAC, v4::MessageType::Discover, &DhcpConfig::default());
+        assert!(msg.opts().get(v4::OptionCode::AddressLeaseTime).is_none());
 
-    return Ok(StaticNetworkInterfaceConfig {
-        name: interface.name,
-        ip: IpAddr::V4(request_response.yiaddr()),
-        netmask: netmask,
-        gateway: gateway,
-        dns: Some(dns),
-    });
+        assert_eq!(parse_lease_time("3600"), Some(3600));
+        assert_eq!(parse_lease_time("0"), None);
+        assert_eq!(parse_lease_time("1h"), None);
+    }
+
+    #[test]
+    fn configured_server_is_unicast() {
+        let config = DhcpConfig {
+            server: Some(Ipv4Addr::new(10, 0, 0, 1)),
+            ..DhcpConfig::default()
+        };
+        let msg = create_dhcpv4_message(MAC, v4::MessageType::Discover, &config);
+        assert!(!msg.flags().broadcast());
+
+        let frame = create_dhcp_packet(msg, &config, SERVER_MAC).unwrap();
+        assert_eq!(frame.get_destination(), SERVER_MAC);
+        let ip_packet = Ipv4Packet::new(frame.payload()).unwrap();
+        assert_eq!(ip_packet.get_destination(), Ipv4Addr::new(10, 0, 0, 1));
+    }
+
+    #[test]
+    fn without_server_is_broadcast() {
+        let config = DhcpConfig::default();
+        let msg = create_dhcpv4_message(MAC, v4::MessageType::Discover, &config);
+        assert!(msg.flags().broadcast());
+
+        let frame = create_dhcp_packet(msg, &config, MacAddr::broadcast()).unwrap();
+        assert_eq!(frame.get_destination(), MacAddr::broadcast());
+        let ip_packet = Ipv4Packet::new(frame.payload()).unwrap();
+        assert_eq!(ip_packet.get_destination(), Ipv4Addr::BROADCAST);
+    }
+
+    #[test]
+    fn falls_back_to_broadcast_only_with_a_server() {
+        let unicast = DhcpConfig {
+            server: Some(Ipv4Addr::new(10, 0, 0, 1)),
+            ..DhcpConfig::default()
+        };
+        for kind in [
+            io::ErrorKind::TimedOut,
+            io::ErrorKind::NetworkUnreachable,
+            io::ErrorKind::HostUnreachable,
+        ] {
+            let err = Error::new(kind, "no reply");
+            assert!(falls_back_to_broadcast(&unicast, &err));
+            // a broadcast is not retried
+            assert!(!falls_back_to_broadcast(&DhcpConfig::default(), &err));
+        }
+
+        // errors a broadcast would run into as well are returned
+        for kind in [io::ErrorKind::PermissionDenied, io::ErrorKind::InvalidData] {
+            assert!(!falls_back_to_broadcast(
+                &unicast,
+                &Error::new(kind, "failed")
+            ));
+        }
+    }
+
+    #[test]
+    fn transport_names_round_trip() {
+        for transport in DhcpTransport::ALL {
+            assert_eq!(DhcpTransport::parse(transport.name()), Some(transport));
+        }
+        assert_eq!(DhcpTransport::parse("tcp"), None);
+    }
+
+    #[test]
+    fn arp_reply_resolves_server() {
+        let server = Ipv4Addr::new(10, 0, 0, 1);
+        let probe = create_arp_probe(MAC, server);
+        let arp_packet = ArpPacket::new(probe.payload()).unwrap();
+        assert_eq!(arp_packet.get_sender_proto_addr(), Ipv4Addr::UNSPECIFIED);
+        assert_eq!(arp_packet.get_target_proto_addr(), server);
+        // a probe is no reply
+        assert_eq!(parse_arp_reply(probe.packet(), server), None);
+
+        let mut reply = probe.packet().to_vec();
+        {
+            let mut ether_packet = MutableEthernetPacket::new(&mut reply).unwrap();
+            ether_packet.set_destination(MAC);
+            let mut arp_packet = MutableArpPacket::new(ether_packet.payload_mut()).unwrap();
+            arp_packet.set_operation(ArpOperations::Reply);
+            arp_packet.set_sender_hw_addr(SERVER_MAC);
+            arp_packet.set_sender_proto_addr(server);
+            arp_packet.set_target_hw_addr(MAC);
+        }
+        assert_eq!(parse_arp_reply(&reply, server), Some(SERVER_MAC));
+        assert_eq!(parse_arp_reply(&reply, Ipv4Addr::new(10, 0, 0, 2)), None);
+    }
+
+    #[test]
+    fn only_replies_to_the_transaction_are_received() {
+        let config = DhcpConfig::default();
+        let sent = create_dhcpv4_message(MAC, v4::MessageType::Discover, &config);
+        // the request itself goes to the server port
+        let frame = create_dhcp_packet(sent.clone(), &config, MacAddr::broadcast()).unwrap();
+        assert!(parse_dhcp_reply(frame.packet(), &sent).is_none());
+
+        let reply = |msg: &v4::Message| {
+            let frame = create_dhcp_packet(msg.clone(), &config, MacAddr::broadcast()).unwrap();
+            let mut frame = frame.packet().to_vec();
+            {
+                let mut ether_packet = MutableEthernetPacket::new(&mut frame).unwrap();
+                let mut ip_packet = MutableIpv4Packet::new(ether_packet.payload_mut()).unwrap();
+                let mut udp_packet = MutableUdpPacket::new(ip_packet.payload_mut()).unwrap();
+                udp_packet.set_source(67);
+                udp_packet.set_destination(68);
+            }
+            frame
+        };
+
+        let mut offer = v4::Message::default();
+        offer
+            .set_opcode(v4::Opcode::BootReply)
+            .set_xid(sent.xid())
+            .set_chaddr(sent.chaddr())
+            .set_yiaddr(Ipv4Addr::new(10, 0, 0, 2));
+        offer
+            .opts_mut()
+            .insert(v4::DhcpOption::MessageType(v4::MessageType::Offer));
+        let received = parse_dhcp_reply(&reply(&offer), &sent).unwrap();
+        assert_eq!(received.yiaddr(), Ipv4Addr::new(10, 0, 0, 2));
+
+        // another transaction
+        let mut other = offer.clone();
+        other.set_xid(sent.xid().wrapping_add(1));
+        assert!(parse_dhcp_reply(&reply(&other), &sent).is_none());
+
+        // another client
+        let mut other = offer.clone();
+        other.set_chaddr(&SERVER_MAC.octets());
+        assert!(parse_dhcp_reply(&reply(&other), &sent).is_none());
+
+        // a message which does not decode is skipped instead of panicking
+        let mut malformed = reply(&offer);
+        malformed
+            .truncate(EthernetPacket::minimum_packet_size() + IPV4_HEADER_LENGTH as usize + 8 + 20);
+        {
+            let mut ether_packet = MutableEthernetPacket::new(&mut malformed).unwrap();
+            let mut ip_packet = MutableIpv4Packet::new(ether_packet.payload_mut()).unwrap();
+            ip_packet.set_total_length(IPV4_HEADER_LENGTH as u16 + 8 + 20);
+            let mut udp_packet = MutableUdpPacket::new(ip_packet.payload_mut()).unwrap();
+            udp_packet.set_length(8 + 20);
+        }
+        assert!(parse_dhcp_reply(&malformed, &sent).is_none());
+    }
 }
@@ -0,0 +1,435 @@
+//! Minimal DNS client checking name resolution (see RFC 1035).
+//!
+//! Working addresses do not mean names resolve, a broken `resolv.conf`
+//! or a firewall dropping DNS only shows when resolving a name. With
+//! `dnscheck=<host>` an A record of the host is queried from the
+//! nameservers in `/etc/resolv.conf` once the network is configured.
+use std::{
+    fs, io,
+    net::{Ipv4Addr, SocketAddrV4, UdpSocket},
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use log::{debug, info, warn};
+
+use super::iface_config::RESOLV_CONF;
+
+/// The port DNS servers listen on.
+const DNS_PORT: u16 = 53;
+
+/// Time to wait for the answer of a single nameserver.
+const DNS_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// The size of the header of a DNS message.
+const HEADER_LENGTH: usize = 12;
+
+/// The A record type and the internet class.
+const TYPE_A: u16 = 1;
+const CLASS_IN: u16 = 1;
+
+/// The maximum size of a DNS message over UDP.
+const MAX_MESSAGE_SIZE: usize = 512;
+
+/// Reads the IPv4 nameservers from the contents of `resolv.conf`.
+pub fn nameservers(resolv_conf: &str) -> Vec<Ipv4Addr> {
+    resolv_conf
+        .lines()
+        .filter_map(|line| line.trim().strip_prefix("nameserver"))
+        .filter_map(|server| server.trim().parse::<Ipv4Addr>().ok())
+        .collect()
+}
+
+/// Encodes a recursive query for the A records of `host`.
+///
+/// # Returns
+///
+/// * `Option<Vec<u8>>` - The query, or `None` if `host` is no valid name.
+pub fn encode_query(id: u16, host: &str) -> Option<Vec<u8>> {
+    let host = host.strip_suffix('.').unwrap_or(host);
+    if host.is_empty() || host.len() > 253 {
+        return None;
+    }
+
+    let mut msg = Vec::with_capacity(HEADER_LENGTH + host.len() + 6);
+    msg.extend_from_slice(&id.to_be_bytes());
+    // recursion desired, one question
+    msg.extend_from_slice(&[0x01, 0x00, 0x00, 0x01, 0, 0, 0, 0, 0, 0]);
+    for label in host.split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return None;
+        }
+        msg.push(label.len() as u8);
+        msg.extend_from_slice(label.as_bytes());
+    }
+    msg.push(0);
+    msg.extend_from_slice(&TYPE_A.to_be_bytes());
+    msg.extend_from_slice(&CLASS_IN.to_be_bytes());
+
+    Some(msg)
+}
+
+/// Returns the offset following the (possibly compressed) name at `offset`.
+fn skip_name(msg: &[u8], mut offset: usize) -> Option<usize> {
+    loop {
+        let len = *msg.get(offset)? as usize;
+        match len {
+            0 => return Some(offset + 1),
+            // a pointer ends the name
+            len if len & 0xc0 == 0xc0 => return Some(offset + 2),
+            len => offset += 1 + len,
+        }
+    }
+}
+
+/// Reads a big endian `u16` at `offset`.
+fn read_u16(msg: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_be_bytes([
+        *msg.get(offset)?,
+        *msg.get(offset + 1)?,
+    ]))
+}
+
+/// Decodes the A records of the answer to the query `id`.
+///
+/// # Returns
+///
+/// * `io::Result<Vec<Ipv4Addr>>` - The addresses, an answer without
+///   any A record (e.g. `NXDOMAIN`) is an error.
+pub fn decode_answer(id: u16, msg: &[u8]) -> io::Result<Vec<Ipv4Addr>> {
+    let invalid = |reason: &str| io::Error::new(io::ErrorKind::InvalidData, reason.to_string());
+    if msg.len() < HEADER_LENGTH || read_u16(msg, 0) != Some(id) || msg[2] & 0x80 == 0 {
+        return Err(invalid("no answer to the query"));
+    }
+    match msg[3] & 0x0f {
+        0 => {}
+        3 => return Err(io::Error::new(io::ErrorKind::NotFound, "no such name")),
+        rcode => return Err(invalid(&format!("query failed with rcode {}", rcode))),
+    }
+
+    let questions = read_u16(msg, 4).unwrap_or(0);
+    let answers = read_u16(msg, 6).unwrap_or(0);
+    let mut offset = HEADER_LENGTH;
+    for _ in 0..questions {
+        offset = skip_name(msg, offset).ok_or_else(|| invalid("truncated question"))? + 4;
+    }
+
+    let mut addrs = Vec::new();
+    for _ in 0..answers {
+        offset = skip_name(msg, offset).ok_or_else(|| invalid("truncated answer"))?;
+        let (kind, class, len) = match (
+            read_u16(msg, offset),
+            read_u16(msg, offset + 2),
+            read_u16(msg, offset + 8),
+        ) {
+            (Some(kind), Some(class), Some(len)) => (kind, class, len as usize),
+            _ => return Err(invalid("truncated answer")),
+        };
+        let data = msg
+            .get(offset + 10..offset + 10 + len)
+            .ok_or_else(|| invalid("truncated answer"))?;
+        // CNAME records preceding the address are skipped
+        if kind == TYPE_A && class == CLASS_IN && len == 4 {
+            addrs.push(Ipv4Addr::new(data[0], data[1], data[2], data[3]));
+        }
+        offset += 10 + len;
+    }
+
+    if addrs.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::NotFound, "no A record"));
+    }
+    Ok(addrs)
+}
+
+/// Queries the A records of `host` from a single nameserver.
+///
+/// # Arguments
+///
+/// * `host` - The name to resolve.
+/// * `server` - The address and port of the nameserver.
+pub fn resolve(host: &str, server: SocketAddrV4) -> io::Result<Vec<Ipv4Addr>> {
+    let id = rand::random::<u16>();
+    let query = encode_query(id, host).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("invalid name '{}'", host),
+        )
+    })?;
+
+    let socket = UdpSocket::bind("0.0.0.0:0")?;
+    socket.set_read_timeout(Some(DNS_TIMEOUT))?;
+    socket.connect(server)?;
+    socket.send(&query)?;
+
+    let mut buf = [0u8; MAX_MESSAGE_SIZE];
+    let deadline = Instant::now() + DNS_TIMEOUT;
+    loop {
+        let len = socket.recv(&mut buf)?;
+        match decode_answer(id, &buf[..len]) {
+            // a stray answer to an earlier query is ignored
+            Err(err) if err.kind() == io::ErrorKind::InvalidData && Instant::now() < deadline => {
+                debug!("Ignoring answer of {}: {}", server, err)
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Checks if `host` resolves using the nameservers in `resolv.conf`,
+/// which are tried in order.
+///
+/// # Arguments
+///
+/// * `host` - The name to resolve (`dnscheck=`).
+pub fn check(host: &str) -> io::Result<Vec<Ipv4Addr>> {
+    check_with(host, Path::new(RESOLV_CONF), DNS_PORT)
+}
+
+/// Checks if `host` resolves (see `check`).
+///
+/// # Arguments
+///
+/// * `host` - The name to resolve (`dnscheck=`).
+/// * `resolv_conf` - Lists the nameservers (see `RESOLV_CONF`).
+/// * `port` - The port the nameservers listen on (see `DNS_PORT`).
+fn check_with(host: &str, resolv_conf: &Path, port: u16) -> io::Result<Vec<Ipv4Addr>> {
+    let servers = nameservers(&fs::read_to_string(resolv_conf)?);
+    let mut last_err = io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("no IPv4 nameserver in {}", resolv_conf.display()),
+    );
+    for server in servers {
+        match resolve(host, SocketAddrV4::new(server, port)) {
+            Ok(addrs) => {
+                info!("DNS check: {} resolved to {:?} by {}", host, addrs, server);
+                return Ok(addrs);
+            }
+            Err(err) => {
+                debug!(
+                    "DNS check: resolving {} by {} failed: {}",
+                    host, server, err
+                );
+                last_err = err;
+            }
+        }
+    }
+
+    warn!("DNS check: resolving {} failed: {}", host, last_err);
+    Err(last_err)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread::{self, JoinHandle};
+
+    use super::*;
+
+    /// An answer to `query` with the given answer records.
+    fn answer(query: &[u8], rcode: u8, records: &[(u16, &[u8])]) -> Vec<u8> {
+        let mut msg = query.to_vec();
+        // response, recursion desired and available
+        msg[2] = 0x81;
+        msg[3] = 0x80 | rcode;
+        msg[6..8].copy_from_slice(&(records.len() as u16).to_be_bytes());
+        for (kind, data) in records {
+            // a pointer to the name of the question
+            msg.extend_from_slice(&[0xc0, HEADER_LENGTH as u8]);
+            msg.extend_from_slice(&kind.to_be_bytes());
+            msg.extend_from_slice(&CLASS_IN.to_be_bytes());
+            msg.extend_from_slice(&300u32.to_be_bytes());
+            msg.extend_from_slice(&(data.len() as u16).to_be_bytes());
+            msg.extend_from_slice(data);
+        }
+        msg
+    }
+
+    #[test]
+    fn encodes_query() {
+        let query = encode_query(0x1234, "example.com.").unwrap();
+
+        let mut expected = vec![0x12, 0x34, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0];
+        expected.extend_from_slice(b"\x07example\x03com\x00");
+        expected.extend_from_slice(&[0, 1, 0, 1]);
+        assert_eq!(query, expected);
+
+        assert_eq!(encode_query(1, ""), None);
+        assert_eq!(encode_query(1, "a..com"), None);
+        assert_eq!(encode_query(1, &"a".repeat(64)), None);
+    }
+
+    #[test]
+    fn decodes_answer() {
+        let query = encode_query(7, "www.example.com").unwrap();
+        // the CNAME preceding the address is skipped
+        let msg = answer(
+            &query,
+            0,
+            &[(5, b"\x03web\xc0\x10"), (TYPE_A, &[93, 184, 216, 34])],
+        );
+
+        assert_eq!(
+            decode_answer(7, &msg).unwrap(),
+            [Ipv4Addr::new(93, 184, 216, 34)]
+        );
+        // an answer to another query
+        assert_eq!(
+            decode_answer(8, &msg).unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+        assert_eq!(
+            decode_answer(7, &msg[..msg.len() - 2]).unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+    }
+
+    #[test]
+    fn missing_records_are_not_found() {
+        let query = encode_query(7, "example.com").unwrap();
+
+        let nxdomain = answer(&query, 3, &[]);
+        assert_eq!(
+            decode_answer(7, &nxdomain).unwrap_err().kind(),
+            io::ErrorKind::NotFound
+        );
+        let empty = answer(&query, 0, &[]);
+        assert_eq!(
+            decode_answer(7, &empty).unwrap_err().kind(),
+            io::ErrorKind::NotFound
+        );
+        // the query itself is no answer
+        assert_eq!(
+            decode_answer(7, &query).unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+    }
+
+    #[test]
+    fn reads_nameservers() {
+        assert_eq!(
+            nameservers(
+                "search example.com\nnameserver 10.0.0.1\n  nameserver\t10.0.0.2\nnameserver ::1\n"
+            ),
+            [Ipv4Addr::new(10, 0, 0, 1), Ipv4Addr::new(10, 0, 0, 2)]
+        );
+    }
+
+    /// How the local nameserver answers a query.
+    #[derive(Clone, Copy)]
+    enum Responder {
+        Address([u8; 4]),
+        NxDomain,
+        Silent,
+    }
+
+    /// Starts a nameserver answering a single query on `addr` (port `0`
+    /// picks a free one).
+    ///
+    /// # Returns
+    ///
+    /// * `(SocketAddrV4, JoinHandle<()>)` - The address the nameserver
+    ///   listens on and its thread, which ends after the query.
+    fn nameserver(addr: SocketAddrV4, responder: Responder) -> (SocketAddrV4, JoinHandle<()>) {
+        let socket = UdpSocket::bind(addr).unwrap();
+        let addr = match socket.local_addr().unwrap() {
+            std::net::SocketAddr::V4(addr) => addr,
+            addr => panic!("unexpected address {}", addr),
+        };
+        let thread = thread::spawn(move || {
+            let mut buf = [0u8; MAX_MESSAGE_SIZE];
+            let (len, client) = socket.recv_from(&mut buf).unwrap();
+            let query = &buf[..len];
+            let msg = match responder {
+                Responder::Address(addr) => answer(query, 0, &[(TYPE_A, &addr)]),
+                Responder::NxDomain => answer(query, 3, &[]),
+                Responder::Silent => return,
+            };
+            // a stray answer to another query goes first
+            let mut stray = msg.clone();
+            stray[0] ^= 0xff;
+            socket.send_to(&stray, client).unwrap();
+            socket.send_to(&msg, client).unwrap();
+        });
+
+        (addr, thread)
+    }
+
+    fn localhost(last: u8) -> SocketAddrV4 {
+        SocketAddrV4::new(Ipv4Addr::new(127, 0, 0, last), 0)
+    }
+
+    #[test]
+    fn resolves_from_a_nameserver() {
+        let (server, thread) = nameserver(localhost(1), Responder::Address([10, 0, 0, 7]));
+        assert_eq!(
+            resolve("example.com", server).unwrap(),
+            [Ipv4Addr::new(10, 0, 0, 7)]
+        );
+        thread.join().unwrap();
+
+        let (server, thread) = nameserver(localhost(1), Responder::NxDomain);
+        assert_eq!(
+            resolve("example.com", server).unwrap_err().kind(),
+            io::ErrorKind::NotFound
+        );
+        thread.join().unwrap();
+    }
+
+    #[test]
+    fn silent_nameserver_times_out() {
+        let (server, thread) = nameserver(localhost(1), Responder::Silent);
+        let started = Instant::now();
+        let err = resolve("example.com", server).unwrap_err();
+        assert!(
+            matches!(
+                err.kind(),
+                io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+            ),
+            "{}",
+            err
+        );
+        assert!(started.elapsed() >= DNS_TIMEOUT);
+        thread.join().unwrap();
+    }
+
+    #[test]
+    fn checks_the_nameservers_in_order() {
+        let dir = std::env::temp_dir().join(format!("linos-test-dns-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let resolv_conf = dir.join("resolv.conf");
+
+        // the nameservers share the port, on different loopback addresses
+        let (server, answering) = nameserver(localhost(1), Responder::Address([10, 0, 0, 7]));
+        let (_, silent) = nameserver(
+            SocketAddrV4::new(Ipv4Addr::new(127, 0, 0, 2), server.port()),
+            Responder::Silent,
+        );
+        fs::write(&resolv_conf, "nameserver 127.0.0.2\nnameserver 127.0.0.1\n").unwrap();
+        assert_eq!(
+            check_with("example.com", &resolv_conf, server.port()).unwrap(),
+            [Ipv4Addr::new(10, 0, 0, 7)]
+        );
+        answering.join().unwrap();
+        silent.join().unwrap();
+
+        // the error of the last nameserver is returned
+        let (server, thread) = nameserver(localhost(1), Responder::NxDomain);
+        fs::write(&resolv_conf, "nameserver 127.0.0.1\n").unwrap();
+        assert_eq!(
+            check_with("example.com", &resolv_conf, server.port())
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::NotFound
+        );
+        thread.join().unwrap();
+
+        fs::write(&resolv_conf, "nameserver ::1\n").unwrap();
+        assert_eq!(
+            check_with("example.com", &resolv_conf, DNS_PORT)
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::NotFound
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
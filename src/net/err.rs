@@ -1,20 +1,108 @@
 use core::fmt;
 use std::error::Error;
 
+use nix::errno::Errno;
+
+/// The kind of a network configuration error, deciding
+/// whether configuring the interface again can help.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// Not classified any further.
+    Generic,
+    /// A temporary failure (e.g. a DHCP timeout), worth retrying.
+    Transient,
+    /// A failure retrying does not fix (e.g. an invalid address).
+    Permanent,
+    /// The interface does not exist (anymore).
+    MissingInterface,
+    /// linµos lacks the privileges to configure the interface.
+    Permission,
+}
+
+impl ErrorKind {
+    /// Classifies the error number of a failed system call.
+    pub fn from_errno(errno: Errno) -> ErrorKind {
+        match errno {
+            Errno::ENODEV | Errno::ENXIO => ErrorKind::MissingInterface,
+            Errno::EPERM | Errno::EACCES => ErrorKind::Permission,
+            Errno::EAGAIN
+            | Errno::EBUSY
+            | Errno::EINTR
+            | Errno::ETIMEDOUT
+            | Errno::ENETDOWN
+            | Errno::ENETUNREACH => ErrorKind::Transient,
+            _ => ErrorKind::Permanent,
+        }
+    }
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            ErrorKind::Generic => "generic",
+            ErrorKind::Transient => "transient",
+            ErrorKind::Permanent => "permanent",
+            ErrorKind::MissingInterface => "missing interface",
+            ErrorKind::Permission => "permission denied",
+        };
+        write!(f, "{}", name)
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct NetworkConfigurationError {
     details: String,
+    kind: ErrorKind,
+    errno: Option<Errno>,
 }
 
 impl NetworkConfigurationError {
     pub fn new(msg: String) -> NetworkConfigurationError {
-        NetworkConfigurationError{details: msg}
+        NetworkConfigurationError::with_kind(msg, ErrorKind::Generic)
+    }
+
+    /// Creates an error of the given kind.
+    pub fn with_kind(msg: String, kind: ErrorKind) -> NetworkConfigurationError {
+        NetworkConfigurationError {
+            details: msg,
+            kind,
+            errno: None,
+        }
+    }
+
+    /// Creates an error caused by a failed system call,
+    /// its kind is derived from the error number.
+    pub fn from_errno(msg: String, errno: Errno) -> NetworkConfigurationError {
+        NetworkConfigurationError {
+            details: msg,
+            kind: ErrorKind::from_errno(errno),
+            errno: Some(errno),
+        }
+    }
+
+    /// The kind of the error.
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
+
+    /// The error number of the failed system call, if any.
+    pub fn errno(&self) -> Option<Errno> {
+        self.errno
+    }
+
+    /// Whether the interface vanished while it was configured
+    /// (e.g. an unplugged USB network adapter).
+    pub fn interface_disappeared(&self) -> bool {
+        self.kind == ErrorKind::MissingInterface
     }
 }
 
 impl fmt::Display for NetworkConfigurationError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f,"{}",self.details)
+        match self.kind {
+            ErrorKind::Generic => write!(f, "{}", self.details),
+            kind => write!(f, "{} ({})", self.details, kind),
+        }
     }
 }
 
@@ -1,23 +1,145 @@
-use std::net::IpAddr;
+use std::{
+    io,
+    net::{IpAddr, Ipv4Addr},
+};
+
+use log::{debug, warn};
+use nix::net::if_::if_nametoindex;
+use pnet::util::MacAddr;
+use serde::{Deserialize, Serialize};
 
 use super::{
-    iface_config::{set_dns, ConfigSocket},
+    err::ErrorKind,
+    iface_config::{self, set_dns, ConfigSocket},
+    lease::DhcpLease,
+    netlink::{self, Netlink, Route, Rule},
     NetworkConfigurationError,
 };
-use crate::net::dhcp;
+use crate::net::dhcp::{self, DhcpConfig};
+
+/// The name of the loopback interface.
+pub const LOOPBACK: &str = "lo";
+
+/// An additional address of an interface.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InterfaceAddress {
+    pub ip: IpAddr,
+    pub netmask: IpAddr,
+}
+
+/// A static neighbor (ARP) entry of an interface.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Neighbor {
+    pub ip: IpAddr,
+    #[serde(with = "mac_address")]
+    pub mac: MacAddr,
+}
+
+/// (De)serializes mac addresses as `aa:bb:cc:dd:ee:ff`.
+mod mac_address {
+    use pnet::util::MacAddr;
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(mac: &MacAddr, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(mac)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<MacAddr, D::Error> {
+        let mac = String::deserialize(deserializer)?;
+        mac.parse::<MacAddr>().map_err(D::Error::custom)
+    }
+}
+
+/// Deserializes the nameservers from a single address or a list
+/// (`dns = "10.0.0.1"` or `dns = ["10.0.0.1", "10.0.0.2"]`).
+mod nameservers {
+    use std::net::IpAddr;
+
+    use serde::{Deserialize, Deserializer};
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(IpAddr),
+        Many(Vec<IpAddr>),
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<IpAddr>, D::Error> {
+        Ok(match OneOrMany::deserialize(deserializer)? {
+            OneOrMany::One(addr) => vec![addr],
+            OneOrMany::Many(addrs) => addrs,
+        })
+    }
+}
 
-#[derive(Debug)]
+/// Priority of the policy routing rule if none is configured.
+pub const DEFAULT_RULE_PRIORITY: u32 = 1000;
+
+fn default_rule_priority() -> u32 {
+    DEFAULT_RULE_PRIORITY
+}
+
+/// Source based policy routing of an interface: traffic from the subnet
+/// of the interface looks up its own routing table, which holds the
+/// subnet and a default route via the gateway of the interface.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PolicyRouting {
+    /// The routing table, not one of the `netlink::RESERVED_TABLES`.
+    pub table: u32,
+    /// The priority of the rule, not one of the `netlink::RESERVED_PRIORITIES`.
+    #[serde(default = "default_rule_priority")]
+    pub priority: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct StaticNetworkInterfaceConfig {
     pub name: String,
     pub ip: IpAddr,
     pub netmask: IpAddr,
     pub gateway: IpAddr,
-    pub dns: Option<IpAddr>,
+    /// The DNS servers, in the order they are queried.
+    #[serde(default, deserialize_with = "nameservers::deserialize")]
+    pub dns: Vec<IpAddr>,
+    /// DNS search domains, written to `/etc/resolv.conf` along with `dns`.
+    #[serde(default)]
+    pub search: Vec<String>,
+    #[serde(default)]
+    pub priority: u32,
+    /// Additional addresses, configured on the
+    /// alias interfaces `<name>:0`, `<name>:1`, ...
+    #[serde(default)]
+    pub aliases: Vec<InterfaceAddress>,
+    /// Static neighbor entries, added once the interface is addressed.
+    #[serde(default)]
+    pub neighbors: Vec<Neighbor>,
+    /// Policy routing, the gateway is added to the table of the
+    /// interface in addition to the main routing table.
+    #[serde(default)]
+    pub routing: Option<PolicyRouting>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DynamicNetworkInterfaceConfig {
     pub name: String,
+    #[serde(skip)]
+    pub dhcp: DhcpConfig,
+    /// DNS search domains, added to the domain handed out by DHCP.
+    #[serde(default)]
+    pub search: Vec<String>,
+    #[serde(default)]
+    pub priority: u32,
+    /// Policy routing of the leased subnet and gateway.
+    #[serde(default)]
+    pub routing: Option<PolicyRouting>,
+    /// The lease time in seconds requested from the DHCP server (option 51).
+    #[serde(default)]
+    pub requested_lease_time: Option<u32>,
+    /// Hands the lifetimes of the lease to the kernel, which removes
+    /// the address once the lease expired without being renewed.
+    #[serde(default)]
+    pub lease_lifetimes: bool,
 }
 
 /// A network iface config, either static or dhcp.
@@ -34,9 +156,21 @@ pub struct DynamicNetworkInterfaceConfig {
 ///         ip: IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
 ///         netmask: IpAddr::V4(Ipv4Addr::new(255, 0, 0, 0)),
 ///         gateway: IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
+///         dns: vec![],
+///         search: vec![],
+///         priority: 0,
+///         aliases: vec![],
+///         neighbors: vec![],
+///         routing: None,
 ///     }),
 ///     NetworkInterfaceConfig::Dynamic(DynamicNetworkInterfaceConfig {
 ///         name: "eth0".to_string(),
+///         dhcp: DhcpConfig::default(),
+///         search: vec![],
+///         priority: 0,
+///         routing: None,
+///         requested_lease_time: None,
+///         lease_lifetimes: false,
 ///     }),
 /// ];
 ///
@@ -44,12 +178,59 @@ pub struct DynamicNetworkInterfaceConfig {
 ///   config.apply();
 /// });
 /// ```
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
 pub enum NetworkInterfaceConfig {
     Static(StaticNetworkInterfaceConfig),
+    #[serde(rename = "dhcp")]
     Dynamic(DynamicNetworkInterfaceConfig),
 }
 
+impl NetworkInterfaceConfig {
+    /// The name of the configured interface.
+    pub fn name(&self) -> &str {
+        match self {
+            NetworkInterfaceConfig::Static(config) => &config.name,
+            NetworkInterfaceConfig::Dynamic(config) => &config.name,
+        }
+    }
+
+    /// The same config applied to the interface `name`,
+    /// e.g. after the interface was renamed.
+    pub fn renamed(&self, name: &str) -> NetworkInterfaceConfig {
+        let mut config = self.clone();
+        match &mut config {
+            NetworkInterfaceConfig::Static(config) => config.name = name.to_string(),
+            NetworkInterfaceConfig::Dynamic(config) => config.name = name.to_string(),
+        }
+        config
+    }
+
+    /// The priority of the configured interface.
+    pub fn priority(&self) -> u32 {
+        match self {
+            NetworkInterfaceConfig::Static(config) => config.priority,
+            NetworkInterfaceConfig::Dynamic(config) => config.priority,
+        }
+    }
+}
+
+/// Orders the configs in the order they have to be applied.
+///
+/// The loopback interface is always applied first, whatever its
+/// `priority`, as software started during the boot may bind to
+/// `127.0.0.1`. The other configs are applied in ascending `priority`,
+/// configs with the same priority keep their order (the order of the
+/// configuration files).
+///
+/// Each applied gateway installs a default route in front of the
+/// already existing ones, so when several interfaces provide a
+/// default gateway the one applied last wins. The interface that
+/// should carry the default route therefore needs the highest priority.
+pub fn sort_by_priority(configs: &mut [NetworkInterfaceConfig]) {
+    configs.sort_by_key(|config| (config.name() != LOOPBACK, config.priority()));
+}
+
 pub trait NetworkInterfaceConfigApply {
     fn apply(&self) -> Result<(), NetworkConfigurationError>;
 }
@@ -63,46 +244,833 @@ impl NetworkInterfaceConfigApply for NetworkInterfaceConfig {
     }
 }
 
+/// Validates an address and netmask pair.
+///
+/// Both have to be IPv4, the netmask has to be contiguous
+/// and the address must not be unspecified.
+fn validate_address(ip: IpAddr, netmask: IpAddr) -> Result<(), NetworkConfigurationError> {
+    let (ip, netmask) = match (ip, netmask) {
+        (IpAddr::V4(ip), IpAddr::V4(netmask)) => (ip, netmask),
+        _ => {
+            return Err(NetworkConfigurationError::with_kind(
+                format!("Invalid address {}/{}: only IPv4 is supported", ip, netmask),
+                ErrorKind::Permanent,
+            ))
+        }
+    };
+
+    let mask = u32::from(netmask);
+    if mask.leading_ones() + mask.trailing_zeros() != 32 {
+        return Err(NetworkConfigurationError::with_kind(
+            format!(
+                "Invalid netmask {} for address {}: not contiguous",
+                netmask, ip
+            ),
+            ErrorKind::Permanent,
+        ));
+    }
+    if ip.is_unspecified() {
+        return Err(NetworkConfigurationError::with_kind(
+            format!("Invalid address {}: unspecified", ip),
+            ErrorKind::Permanent,
+        ));
+    }
+
+    Ok(())
+}
+
+impl StaticNetworkInterfaceConfig {
+    /// All addresses of the interface, the primary address first.
+    pub fn addresses(&self) -> Vec<InterfaceAddress> {
+        let mut addresses = vec![InterfaceAddress {
+            ip: self.ip,
+            netmask: self.netmask,
+        }];
+        addresses.extend(self.aliases.iter().cloned());
+        addresses
+    }
+
+    /// Checks if `ip` is within the subnet of one of the interface addresses.
+    pub fn is_on_link(&self, ip: IpAddr) -> bool {
+        self.addresses()
+            .iter()
+            .any(|address| match (address.ip, address.netmask, ip) {
+                (IpAddr::V4(addr), IpAddr::V4(netmask), IpAddr::V4(ip)) => {
+                    u32::from(addr) & u32::from(netmask) == u32::from(ip) & u32::from(netmask)
+                }
+                _ => false,
+            })
+    }
+}
+
 impl NetworkInterfaceConfigApply for StaticNetworkInterfaceConfig {
     fn apply(&self) -> Result<(), NetworkConfigurationError> {
+        for address in self.addresses() {
+            validate_address(address.ip, address.netmask)?;
+        }
+        for neighbor in &self.neighbors {
+            if !self.is_on_link(neighbor.ip) {
+                return Err(NetworkConfigurationError::with_kind(
+                    format!(
+                        "Invalid neighbor {}: not on-link for '{}'",
+                        neighbor.ip, self.name
+                    ),
+                    ErrorKind::Permanent,
+                ));
+            }
+        }
+        if let Some(routing) = &self.routing {
+            netlink::validate_table(routing.table)
+                .and_then(|_| netlink::validate_priority(routing.priority))
+                .map_err(|err| {
+                    NetworkConfigurationError::with_kind(
+                        format!("Invalid routing of '{}': {}", self.name, err),
+                        ErrorKind::Permanent,
+                    )
+                })?;
+        }
+
         let iface = pnet::datalink::interfaces()
             .into_iter()
             .find(|iface| iface.name == self.name)
             .ok_or_else(|| {
-                NetworkConfigurationError::new(format!("Interface '{}' not found", self.name))
+                NetworkConfigurationError::with_kind(
+                    format!("Interface '{}' not found", self.name),
+                    ErrorKind::MissingInterface,
+                )
             })?;
 
-        let config = ConfigSocket::new(self.name.clone())?;
-        config.enable(true)?;
-        config.set_ip(self.ip)?;
-        config.set_netmask(self.netmask)?;
-        if !iface.is_loopback() {
-            config.set_gateway(self.gateway)?;
+        let mut applied = Vec::new();
+        let steps = self.apply_steps(
+            &SystemBackend,
+            iface.is_loopback(),
+            iface.index,
+            &mut applied,
+        );
+        if let Err(err) = steps {
+            if !applied.is_empty() {
+                warn!(
+                    "'{}' is left partially configured ({} applied, then failed: {})",
+                    self.name,
+                    applied.join(", "),
+                    err
+                );
+            }
+            return match err.errno() {
+                Some(errno) if err.interface_disappeared() => {
+                    Err(NetworkConfigurationError::from_errno(
+                        format!(
+                            "Interface '{}' disappeared during configuration: {}",
+                            self.name, err
+                        ),
+                        errno,
+                    ))
+                }
+                _ => Err(err),
+            };
         }
-        if self.dns.is_some() {
-            set_dns(self.dns.unwrap())?;
+        if applied.is_empty() {
+            debug!("'{}' is configured already", self.name);
+        } else {
+            debug!("Applied {} to '{}'", applied.join(", "), self.name);
         }
 
         Ok(())
     }
 }
 
-impl NetworkInterfaceConfigApply for DynamicNetworkInterfaceConfig {
-    fn apply(&self) -> Result<(), NetworkConfigurationError> {
+/// The system calls configuring an interface (see `SystemBackend`),
+/// so `apply_steps` can compare against the state of a fake system.
+pub(crate) trait InterfaceBackend {
+    fn is_up(&self, iface: &str) -> Result<bool, NetworkConfigurationError>;
+    fn enable(&self, iface: &str) -> Result<(), NetworkConfigurationError>;
+    fn get_ip(&self, iface: &str) -> Result<Option<Ipv4Addr>, NetworkConfigurationError>;
+    fn set_ip(&self, iface: &str, ip: IpAddr) -> Result<(), NetworkConfigurationError>;
+    fn get_netmask(&self, iface: &str) -> Result<Option<Ipv4Addr>, NetworkConfigurationError>;
+    fn set_netmask(&self, iface: &str, netmask: IpAddr) -> Result<(), NetworkConfigurationError>;
+    fn has_default_route(&self, iface: &str, gateway: Ipv4Addr) -> bool;
+    fn set_gateway(&self, iface: &str, gateway: IpAddr) -> Result<(), NetworkConfigurationError>;
+    fn routes(&self) -> Result<Vec<Route>, NetworkConfigurationError>;
+    fn add_route(&self, route: Route) -> Result<(), NetworkConfigurationError>;
+    fn rules(&self) -> Result<Vec<Rule>, NetworkConfigurationError>;
+    fn add_rule(&self, rule: Rule) -> Result<(), NetworkConfigurationError>;
+    fn has_neighbor(&self, iface: &str, neighbor: &Neighbor) -> bool;
+    fn add_neighbor(
+        &self,
+        iface: &str,
+        neighbor: &Neighbor,
+    ) -> Result<(), NetworkConfigurationError>;
+    fn is_dns_configured(&self, iface: &str, dns: &[IpAddr], search: &[String]) -> bool;
+    fn set_dns(
+        &self,
+        iface: &str,
+        dns: &[IpAddr],
+        search: &[String],
+    ) -> Result<(), NetworkConfigurationError>;
+}
+
+/// Configures the interfaces of the running system,
+/// using ioctls, rtnetlink and the resolver files.
+pub(crate) struct SystemBackend;
+
+impl InterfaceBackend for SystemBackend {
+    fn is_up(&self, iface: &str) -> Result<bool, NetworkConfigurationError> {
+        ConfigSocket::new(iface.to_string())?.is_up()
+    }
+
+    fn enable(&self, iface: &str) -> Result<(), NetworkConfigurationError> {
+        ConfigSocket::new(iface.to_string())?.enable(true)
+    }
+
+    fn get_ip(&self, iface: &str) -> Result<Option<Ipv4Addr>, NetworkConfigurationError> {
+        ConfigSocket::new(iface.to_string())?.get_ip()
+    }
+
+    fn set_ip(&self, iface: &str, ip: IpAddr) -> Result<(), NetworkConfigurationError> {
+        ConfigSocket::new(iface.to_string())?.set_ip(ip)
+    }
+
+    fn get_netmask(&self, iface: &str) -> Result<Option<Ipv4Addr>, NetworkConfigurationError> {
+        ConfigSocket::new(iface.to_string())?.get_netmask()
+    }
+
+    fn set_netmask(&self, iface: &str, netmask: IpAddr) -> Result<(), NetworkConfigurationError> {
+        ConfigSocket::new(iface.to_string())?.set_netmask(netmask)
+    }
+
+    fn has_default_route(&self, iface: &str, gateway: Ipv4Addr) -> bool {
+        ConfigSocket::new(iface.to_string())
+            .map(|config| config.has_default_route(gateway))
+            .unwrap_or(false)
+    }
+
+    fn set_gateway(&self, iface: &str, gateway: IpAddr) -> Result<(), NetworkConfigurationError> {
+        ConfigSocket::new(iface.to_string())?.set_gateway(gateway)
+    }
+
+    fn routes(&self) -> Result<Vec<Route>, NetworkConfigurationError> {
+        Netlink::new()?.routes()
+    }
+
+    fn add_route(&self, route: Route) -> Result<(), NetworkConfigurationError> {
+        Netlink::new()?.add_route_to_table(
+            route.table,
+            route.dst,
+            route.gateway,
+            route.index.unwrap_or(0),
+        )
+    }
+
+    fn rules(&self) -> Result<Vec<Rule>, NetworkConfigurationError> {
+        Netlink::new()?.rules()
+    }
+
+    fn add_rule(&self, rule: Rule) -> Result<(), NetworkConfigurationError> {
+        Netlink::new()?.add_rule(rule.src, rule.table, rule.priority)
+    }
+
+    fn has_neighbor(&self, iface: &str, neighbor: &Neighbor) -> bool {
+        iface_config::has_neighbor(iface, neighbor.ip, neighbor.mac)
+    }
+
+    fn add_neighbor(
+        &self,
+        iface: &str,
+        neighbor: &Neighbor,
+    ) -> Result<(), NetworkConfigurationError> {
+        ConfigSocket::new(iface.to_string())?.add_neighbor(neighbor.ip, neighbor.mac)
+    }
+
+    fn is_dns_configured(&self, iface: &str, dns: &[IpAddr], search: &[String]) -> bool {
+        iface_config::is_dns_configured(iface, dns, search)
+    }
+
+    fn set_dns(
+        &self,
+        iface: &str,
+        dns: &[IpAddr],
+        search: &[String],
+    ) -> Result<(), NetworkConfigurationError> {
+        set_dns(iface, dns, search)
+    }
+}
+
+impl StaticNetworkInterfaceConfig {
+    /// Applies the configuration step by step.
+    ///
+    /// Each step is compared against the current state first and only
+    /// applied if it differs, so reapplying an unchanged configuration
+    /// changes nothing (and does not flap the link).
+    ///
+    /// # Arguments
+    ///
+    /// * `backend` - The system calls configuring the interface.
+    /// * `loopback` - Whether the interface is a loopback interface.
+    /// * `index` - The index of the interface.
+    /// * `applied` - Collects the names of the successfully applied steps.
+    fn apply_steps(
+        &self,
+        backend: &impl InterfaceBackend,
+        loopback: bool,
+        index: u32,
+        applied: &mut Vec<&'static str>,
+    ) -> Result<(), NetworkConfigurationError> {
+        if !backend.is_up(&self.name)? {
+            backend.enable(&self.name)?;
+            applied.push("link");
+        }
+        let address_changed = backend.get_ip(&self.name)?.map(IpAddr::V4) != Some(self.ip);
+        if address_changed {
+            backend.set_ip(&self.name, self.ip)?;
+            applied.push("address");
+        }
+        // setting the address resets the netmask
+        if address_changed || backend.get_netmask(&self.name)?.map(IpAddr::V4) != Some(self.netmask)
+        {
+            backend.set_netmask(&self.name, self.netmask)?;
+            applied.push("netmask");
+        }
+        let has_gateway = match self.gateway {
+            IpAddr::V4(gateway) => backend.has_default_route(&self.name, gateway),
+            IpAddr::V6(_) => false,
+        };
+        if !loopback && (address_changed || !has_gateway) {
+            backend.set_gateway(&self.name, self.gateway)?;
+            applied.push("gateway");
+        }
+        if let Some(routing) = &self.routing {
+            if self.apply_routing(backend, routing, index)? {
+                applied.push("routing");
+            }
+        }
+        for (i, alias) in self.aliases.iter().enumerate() {
+            let alias_name = format!("{}:{}", self.name, i);
+            // a missing alias interface has no address yet
+            let current = (
+                backend.get_ip(&alias_name).ok().flatten().map(IpAddr::V4),
+                backend
+                    .get_netmask(&alias_name)
+                    .ok()
+                    .flatten()
+                    .map(IpAddr::V4),
+            );
+            if current != (Some(alias.ip), Some(alias.netmask)) {
+                backend.set_ip(&alias_name, alias.ip)?;
+                backend.set_netmask(&alias_name, alias.netmask)?;
+                applied.push("alias");
+            }
+        }
+        for neighbor in &self.neighbors {
+            if !backend.has_neighbor(&self.name, neighbor) {
+                backend.add_neighbor(&self.name, neighbor)?;
+                applied.push("neighbor");
+            }
+        }
+        // search domains are written without nameservers as well
+        let has_dns = !self.dns.is_empty() || !self.search.is_empty();
+        if has_dns && !backend.is_dns_configured(&self.name, &self.dns, &self.search) {
+            backend.set_dns(&self.name, &self.dns, &self.search)?;
+            applied.push("dns");
+        }
+
+        Ok(())
+    }
+
+    /// Adds the subnet and the default route via the gateway to the
+    /// routing table and the rule selecting the table, unless they exist.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<bool, NetworkConfigurationError>` - Whether anything was added.
+    fn apply_routing(
+        &self,
+        backend: &impl InterfaceBackend,
+        routing: &PolicyRouting,
+        index: u32,
+    ) -> Result<bool, NetworkConfigurationError> {
+        let (ip, netmask, gateway) = match (self.ip, self.netmask, self.gateway) {
+            (IpAddr::V4(ip), IpAddr::V4(netmask), IpAddr::V4(gateway)) => (ip, netmask, gateway),
+            _ => {
+                return Err(NetworkConfigurationError::with_kind(
+                    "IPv6 is not supported".to_string(),
+                    ErrorKind::Permanent,
+                ))
+            }
+        };
+        let prefix = u32::from(netmask).leading_ones() as u8;
+        let subnet = (Ipv4Addr::from(u32::from(ip) & u32::from(netmask)), prefix);
+
+        let mut changed = false;
+        let routes = backend.routes()?;
+        let wanted = [
+            Route {
+                table: routing.table,
+                dst: subnet,
+                gateway: None,
+                index: Some(index),
+            },
+            Route {
+                table: routing.table,
+                dst: (Ipv4Addr::UNSPECIFIED, 0),
+                gateway: Some(gateway),
+                index: Some(index),
+            },
+        ];
+        for route in wanted {
+            if !routes.contains(&route) {
+                backend.add_route(route)?;
+                changed = true;
+            }
+        }
+
+        let rule = Rule {
+            src: subnet,
+            table: routing.table,
+            priority: routing.priority,
+        };
+        if !backend.rules()?.contains(&rule) {
+            backend.add_rule(rule)?;
+            changed = true;
+        }
+
+        Ok(changed)
+    }
+}
+
+impl DynamicNetworkInterfaceConfig {
+    /// Requests a lease from the DHCP server and applies it.
+    ///
+    /// The lease is written to the lease file of the interface
+    /// and returned, so it can be handed to other consumers.
+    pub fn apply_lease(&self) -> Result<DhcpLease, NetworkConfigurationError> {
+        let lease = self.request_lease()?;
+        self.apply_requested(lease)
+    }
+
+    /// Enables the interface and requests a lease from the DHCP server,
+    /// without applying it.
+    pub fn request_lease(&self) -> Result<DhcpLease, NetworkConfigurationError> {
         let config = ConfigSocket::new(self.name.clone())?;
         config.enable(true)?;
 
-        let static_interface_config = match dhcp::request(&self.name) {
-            Ok(config) => config,
+        match dhcp::request(&self.name, &self.dhcp) {
+            Ok(lease) => Ok(lease),
             Err(err) => {
-                return Err(NetworkConfigurationError::new(format!(
-                    "DHCP config failed: {}",
-                    err
-                )))
+                // no answer from a DHCP server might just be a matter of time
+                let kind = match err.kind() {
+                    io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock => ErrorKind::Transient,
+                    _ => ErrorKind::Generic,
+                };
+                Err(NetworkConfigurationError::with_kind(
+                    format!("DHCP config failed: {}", err),
+                    kind,
+                ))
+            }
+        }
+    }
+
+    /// Applies a lease obtained by `request_lease`
+    /// and writes the lease file of the interface.
+    pub fn apply_requested(
+        &self,
+        lease: DhcpLease,
+    ) -> Result<DhcpLease, NetworkConfigurationError> {
+        let mut config = lease.to_static_config();
+        config.routing = self.routing.clone();
+        self.search.iter().for_each(|domain| {
+            if !config.search.contains(domain) {
+                config.search.push(domain.clone());
             }
+        });
+        config.apply()?;
+        if self.lease_lifetimes {
+            if let Err(err) = self.set_lifetimes(&lease) {
+                warn!("{}", err);
+            }
+        }
+
+        if let Err(err) = lease.write() {
+            warn!("Failed writing lease file for '{}': {}", self.name, err);
+        }
+
+        Ok(lease)
+    }
+
+    /// Sets the lifetimes of the leased address (see `DhcpLease::lifetimes`).
+    fn set_lifetimes(&self, lease: &DhcpLease) -> Result<(), NetworkConfigurationError> {
+        let lifetimes = match lease.lifetimes() {
+            Some(lifetimes) => lifetimes,
+            None => return Ok(()),
         };
-        static_interface_config.apply()?;
+        let index = if_nametoindex(self.name.as_str()).map_err(|err| {
+            NetworkConfigurationError::from_errno(
+                format!("Interface '{}' not found: {}", self.name, err),
+                err,
+            )
+        })?;
+        let prefix = u32::from(lease.netmask).leading_ones() as u8;
+
+        Netlink::new()?.set_address_lifetimes(index, (lease.ip, prefix), lifetimes)
+    }
+}
+
+impl NetworkInterfaceConfigApply for DynamicNetworkInterfaceConfig {
+    fn apply(&self) -> Result<(), NetworkConfigurationError> {
+        self.apply_lease()?;
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::cell::{Cell, RefCell};
+
+    use super::*;
+
+    /// The address and netmask of an interface.
+    type Address = (String, Option<IpAddr>, Option<IpAddr>);
+
+    /// A fake system, counting the calls changing its state.
+    #[derive(Default)]
+    struct FakeBackend {
+        up: Cell<bool>,
+        addresses: RefCell<Vec<Address>>,
+        gateway: Cell<Option<Ipv4Addr>>,
+        routes: RefCell<Vec<Route>>,
+        rules: RefCell<Vec<Rule>>,
+        neighbors: RefCell<Vec<(IpAddr, MacAddr)>>,
+        dns: RefCell<Option<(Vec<IpAddr>, Vec<String>)>>,
+        changes: Cell<usize>,
+    }
+
+    impl FakeBackend {
+        fn change(&self) {
+            self.changes.set(self.changes.get() + 1);
+        }
+
+        fn address(&self, iface: &str) -> (Option<IpAddr>, Option<IpAddr>) {
+            self.addresses
+                .borrow()
+                .iter()
+                .find(|(name, _, _)| name == iface)
+                .map(|(_, ip, netmask)| (*ip, *netmask))
+                .unwrap_or((None, None))
+        }
+
+        fn set_address(&self, iface: &str, ip: Option<IpAddr>, netmask: Option<IpAddr>) {
+            let mut addresses = self.addresses.borrow_mut();
+            addresses.retain(|(name, _, _)| name != iface);
+            addresses.push((iface.to_string(), ip, netmask));
+        }
+    }
+
+    fn v4(ip: Option<IpAddr>) -> Option<Ipv4Addr> {
+        match ip {
+            Some(IpAddr::V4(ip)) => Some(ip),
+            _ => None,
+        }
+    }
+
+    impl InterfaceBackend for FakeBackend {
+        fn is_up(&self, _iface: &str) -> Result<bool, NetworkConfigurationError> {
+            Ok(self.up.get())
+        }
+
+        fn enable(&self, _iface: &str) -> Result<(), NetworkConfigurationError> {
+            self.change();
+            self.up.set(true);
+            Ok(())
+        }
+
+        fn get_ip(&self, iface: &str) -> Result<Option<Ipv4Addr>, NetworkConfigurationError> {
+            Ok(v4(self.address(iface).0))
+        }
+
+        fn set_ip(&self, iface: &str, ip: IpAddr) -> Result<(), NetworkConfigurationError> {
+            self.change();
+            // as the kernel, setting the address resets the netmask
+            self.set_address(iface, Some(ip), None);
+            Ok(())
+        }
+
+        fn get_netmask(&self, iface: &str) -> Result<Option<Ipv4Addr>, NetworkConfigurationError> {
+            Ok(v4(self.address(iface).1))
+        }
+
+        fn set_netmask(
+            &self,
+            iface: &str,
+            netmask: IpAddr,
+        ) -> Result<(), NetworkConfigurationError> {
+            self.change();
+            let ip = self.address(iface).0;
+            self.set_address(iface, ip, Some(netmask));
+            Ok(())
+        }
+
+        fn has_default_route(&self, _iface: &str, gateway: Ipv4Addr) -> bool {
+            self.gateway.get() == Some(gateway)
+        }
+
+        fn set_gateway(
+            &self,
+            _iface: &str,
+            gateway: IpAddr,
+        ) -> Result<(), NetworkConfigurationError> {
+            self.change();
+            self.gateway.set(v4(Some(gateway)));
+            Ok(())
+        }
+
+        fn routes(&self) -> Result<Vec<Route>, NetworkConfigurationError> {
+            Ok(self.routes.borrow().clone())
+        }
+
+        fn add_route(&self, route: Route) -> Result<(), NetworkConfigurationError> {
+            self.change();
+            self.routes.borrow_mut().push(route);
+            Ok(())
+        }
+
+        fn rules(&self) -> Result<Vec<Rule>, NetworkConfigurationError> {
+            Ok(self.rules.borrow().clone())
+        }
+
+        fn add_rule(&self, rule: Rule) -> Result<(), NetworkConfigurationError> {
+            self.change();
+            self.rules.borrow_mut().push(rule);
+            Ok(())
+        }
+
+        fn has_neighbor(&self, _iface: &str, neighbor: &Neighbor) -> bool {
+            self.neighbors
+                .borrow()
+                .contains(&(neighbor.ip, neighbor.mac))
+        }
+
+        fn add_neighbor(
+            &self,
+            _iface: &str,
+            neighbor: &Neighbor,
+        ) -> Result<(), NetworkConfigurationError> {
+            self.change();
+            self.neighbors
+                .borrow_mut()
+                .push((neighbor.ip, neighbor.mac));
+            Ok(())
+        }
+
+        fn is_dns_configured(&self, _iface: &str, dns: &[IpAddr], search: &[String]) -> bool {
+            *self.dns.borrow() == Some((dns.to_vec(), search.to_vec()))
+        }
+
+        fn set_dns(
+            &self,
+            _iface: &str,
+            dns: &[IpAddr],
+            search: &[String],
+        ) -> Result<(), NetworkConfigurationError> {
+            self.change();
+            *self.dns.borrow_mut() = Some((dns.to_vec(), search.to_vec()));
+            Ok(())
+        }
+    }
+
+    fn config() -> StaticNetworkInterfaceConfig {
+        StaticNetworkInterfaceConfig {
+            name: "eth0".to_string(),
+            ip: "10.0.0.2".parse().unwrap(),
+            netmask: "255.255.255.0".parse().unwrap(),
+            gateway: "10.0.0.1".parse().unwrap(),
+            dns: vec!["10.0.0.1".parse().unwrap()],
+            search: vec!["example.com".to_string()],
+            priority: 0,
+            aliases: vec![InterfaceAddress {
+                ip: "10.0.1.2".parse().unwrap(),
+                netmask: "255.255.255.0".parse().unwrap(),
+            }],
+            neighbors: vec![Neighbor {
+                ip: "10.0.0.5".parse().unwrap(),
+                mac: MacAddr::new(2, 0, 0, 0, 0, 5),
+            }],
+            routing: Some(PolicyRouting {
+                table: 100,
+                priority: 1000,
+            }),
+        }
+    }
+
+    #[test]
+    fn second_apply_changes_nothing() {
+        let backend = FakeBackend::default();
+        let config = config();
+
+        let mut applied = Vec::new();
+        config
+            .apply_steps(&backend, false, 2, &mut applied)
+            .unwrap();
+        assert_eq!(
+            applied,
+            ["link", "address", "netmask", "gateway", "routing", "alias", "neighbor", "dns"]
+        );
+        let changes = backend.changes.get();
+        assert_eq!(changes, 11);
+
+        let mut applied = Vec::new();
+        config
+            .apply_steps(&backend, false, 2, &mut applied)
+            .unwrap();
+        assert!(applied.is_empty(), "applied again: {:?}", applied);
+        assert_eq!(backend.changes.get(), changes);
+    }
+
+    #[test]
+    fn ipv6_routing_is_a_permanent_failure() {
+        let backend = FakeBackend::default();
+        let config = StaticNetworkInterfaceConfig {
+            ip: "fd00::2".parse().unwrap(),
+            ..config()
+        };
+        let routing = config.routing.clone().unwrap();
+
+        let err = config.apply_routing(&backend, &routing, 2).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Permanent);
+        assert!(backend.routes.borrow().is_empty());
+    }
+
+    #[test]
+    fn changed_dns_is_applied_alone() {
+        let backend = FakeBackend::default();
+        let mut config = config();
+        config
+            .apply_steps(&backend, false, 2, &mut Vec::new())
+            .unwrap();
+        let changes = backend.changes.get();
+
+        config.dns.push("10.0.0.53".parse().unwrap());
+        let mut applied = Vec::new();
+        config
+            .apply_steps(&backend, false, 2, &mut applied)
+            .unwrap();
+        assert_eq!(applied, ["dns"]);
+        assert_eq!(backend.changes.get(), changes + 1);
+    }
+
+    #[test]
+    fn search_is_applied_without_nameservers() {
+        let backend = FakeBackend::default();
+        let mut config = config();
+        config.dns.clear();
+
+        let mut applied = Vec::new();
+        config
+            .apply_steps(&backend, false, 2, &mut applied)
+            .unwrap();
+        assert!(applied.contains(&"dns"));
+        assert_eq!(
+            *backend.dns.borrow(),
+            Some((Vec::new(), vec!["example.com".to_string()]))
+        );
+    }
+
+    #[test]
+    fn dns_is_one_or_many_nameservers() {
+        let config = |dns: &str| {
+            toml::from_str::<StaticNetworkInterfaceConfig>(&format!(
+                "name = \"eth0\"\nip = \"10.0.0.2\"\nnetmask = \"255.255.255.0\"\n\
+                 gateway = \"10.0.0.1\"\n{}",
+                dns
+            ))
+            .unwrap()
+            .dns
+        };
+        let ns = |addr: &str| addr.parse::<IpAddr>().unwrap();
+
+        assert_eq!(config("dns = \"10.0.0.1\""), vec![ns("10.0.0.1")]);
+        assert_eq!(
+            config("dns = [\"10.0.0.1\", \"10.0.0.2\"]"),
+            vec![ns("10.0.0.1"), ns("10.0.0.2")]
+        );
+        assert!(config("").is_empty());
+    }
+
+    #[test]
+    fn aliases_are_additional_addresses() {
+        let config = toml::from_str::<StaticNetworkInterfaceConfig>(
+            "name = \"eth0\"\nip = \"10.0.0.2\"\nnetmask = \"255.255.255.0\"\n\
+             gateway = \"10.0.0.1\"\n\
+             aliases = [{ ip = \"10.0.1.2\", netmask = \"255.255.255.0\" }]",
+        )
+        .unwrap();
+        let addresses = config
+            .addresses()
+            .iter()
+            .map(|address| address.ip.to_string())
+            .collect::<Vec<String>>();
+
+        assert_eq!(addresses, ["10.0.0.2", "10.0.1.2"]);
+        assert!(config.is_on_link("10.0.1.9".parse().unwrap()));
+        assert!(!config.is_on_link("10.0.2.9".parse().unwrap()));
+    }
+
+    #[test]
+    fn invalid_addresses_are_rejected() {
+        let ip = |ip: &str| ip.parse::<IpAddr>().unwrap();
+
+        assert!(validate_address(ip("10.0.1.2"), ip("255.255.255.0")).is_ok());
+        assert!(validate_address(ip("10.0.1.2"), ip("255.0.255.0")).is_err());
+        assert!(validate_address(ip("0.0.0.0"), ip("255.255.255.0")).is_err());
+        assert!(validate_address(ip("::1"), ip("255.255.255.0")).is_err());
+    }
+
+    #[test]
+    fn highest_priority_is_applied_last() {
+        let dynamic = |name: &str, priority| {
+            NetworkInterfaceConfig::Dynamic(DynamicNetworkInterfaceConfig {
+                name: name.to_string(),
+                dhcp: DhcpConfig::default(),
+                search: Vec::new(),
+                priority,
+                routing: None,
+                requested_lease_time: None,
+                lease_lifetimes: false,
+            })
+        };
+        let mut wired = config();
+        wired.priority = 10;
+        let mut configs = vec![NetworkInterfaceConfig::Static(wired), dynamic("wlan0", 5)];
+        sort_by_priority(&mut configs);
+
+        // the wired gateway is applied last, so its default route wins
+        let names = configs
+            .iter()
+            .map(|config| config.name())
+            .collect::<Vec<&str>>();
+        assert_eq!(names, ["wlan0", "eth0"]);
+    }
+
+    #[test]
+    fn loopback_is_sorted_first() {
+        let named = |name: &str, priority| {
+            let mut config = config();
+            config.name = name.to_string();
+            config.priority = priority;
+            NetworkInterfaceConfig::Static(config)
+        };
+        let mut configs = vec![
+            named("eth1", 10),
+            named("eth0", 0),
+            named("eth2", 10),
+            named(LOOPBACK, 20),
+        ];
+        sort_by_priority(&mut configs);
+
+        let names = configs
+            .iter()
+            .map(|config| config.name())
+            .collect::<Vec<&str>>();
+        // the same priority keeps the configured order
+        assert_eq!(names, [LOOPBACK, "eth0", "eth1", "eth2"]);
+    }
+}
@@ -1,14 +1,38 @@
-use std::{ffi::CString, fs, mem, net::IpAddr, ptr};
+use std::{
+    ffi::CString,
+    fs, mem,
+    net::{IpAddr, Ipv4Addr},
+    path::Path,
+    ptr,
+    sync::Mutex,
+};
 
 use libc;
-use nix::{ioctl_write_ptr_bad, sys::socket, unistd::close};
+use nix::{ioctl_read_bad, ioctl_write_ptr_bad, sys::socket, unistd::close};
+use pnet::util::MacAddr;
 
 use super::NetworkConfigurationError;
+use crate::fs::{atomic_write, write_config_file};
 
+ioctl_read_bad!(siocgifflags, libc::SIOCGIFFLAGS, libc::ifreq);
+ioctl_read_bad!(siocgifaddr, libc::SIOCGIFADDR, libc::ifreq);
+ioctl_read_bad!(siocgifnetmask, libc::SIOCGIFNETMASK, libc::ifreq);
 ioctl_write_ptr_bad!(siocsifflags, libc::SIOCSIFFLAGS, libc::ifreq);
 ioctl_write_ptr_bad!(siocsifaddr, libc::SIOCSIFADDR, libc::ifreq);
 ioctl_write_ptr_bad!(siocsifnetmask, libc::SIOCSIFNETMASK, libc::ifreq);
 ioctl_write_ptr_bad!(siocaddrt, libc::SIOCADDRT, libc::rtentry);
+ioctl_write_ptr_bad!(siocsarp, libc::SIOCSARP, libc::arpreq);
+ioctl_write_ptr_bad!(siocbraddbr, SIOCBRADDBR, libc::c_char);
+ioctl_write_ptr_bad!(siocbraddif, SIOCBRADDIF, libc::ifreq);
+
+/// Bridge ioctls, see `<linux/sockios.h>`.
+const SIOCBRADDBR: libc::c_ulong = 0x89a0;
+const SIOCBRADDIF: libc::c_ulong = 0x89a2;
+
+/// Flags of a completed (`ATF_COM`) and permanent (`ATF_PERM`)
+/// ARP entry, see `<net/if_arp.h>`.
+const ATF_COM: libc::c_int = 0x02;
+const ATF_PERM: libc::c_int = 0x04;
 
 pub struct ConfigSocket {
     pub fd: i32,
@@ -64,24 +88,24 @@ impl ConfigSocket {
         unsafe {
             let mut req = self.request();
 
-            if let Err(err) = siocsifflags(self.fd, &mut req) {
-                return Err(NetworkConfigurationError::new(format!(
-                    "Failed to get interface flags: {}",
-                    err
-                )));
+            if let Err(err) = siocgifflags(self.fd, &mut req) {
+                return Err(NetworkConfigurationError::from_errno(
+                    format!("Failed to get interface flags: {}", err),
+                    err,
+                ));
             }
 
             if value {
-                req.ifr_ifru.ifru_flags |= libc::IFF_UP as i16 | libc::IFF_UP as i16;
+                req.ifr_ifru.ifru_flags |= libc::IFF_UP as i16;
             } else {
                 req.ifr_ifru.ifru_flags &= !libc::IFF_UP as i16;
             }
 
             if let Err(err) = siocsifflags(self.fd, &req) {
-                return Err(NetworkConfigurationError::new(format!(
-                    "Failed to set interface flags: {}",
-                    err
-                )));
+                return Err(NetworkConfigurationError::from_errno(
+                    format!("Failed to set interface flags: {}", err),
+                    err,
+                ));
             }
         }
 
@@ -108,16 +132,113 @@ impl ConfigSocket {
             });
 
             if let Err(err) = siocsifaddr(self.fd, &req) {
-                return Err(NetworkConfigurationError::new(format!(
-                    "Failed to set interface address: {}",
-                    err
-                )));
+                return Err(NetworkConfigurationError::from_errno(
+                    format!("Failed to set interface address: {}", err),
+                    err,
+                ));
             }
         }
 
         Ok(())
     }
 
+    /// Reads the address of the interface.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Option<Ipv4Addr>, NetworkConfigurationError>` - The address,
+    ///   or `None` if the interface has no address.
+    pub(crate) fn get_ip(&self) -> Result<Option<Ipv4Addr>, NetworkConfigurationError> {
+        unsafe {
+            let mut req = self.request();
+
+            match siocgifaddr(self.fd, &mut req) {
+                Ok(_) => {}
+                Err(nix::errno::Errno::EADDRNOTAVAIL) => return Ok(None),
+                Err(err) => {
+                    return Err(NetworkConfigurationError::from_errno(
+                        format!("Failed to get interface address: {}", err),
+                        err,
+                    ));
+                }
+            }
+
+            // offset by `libc::AF_*` size
+            let data = &req.ifr_ifru.ifru_addr.sa_data;
+            let offset = mem::size_of::<u16>();
+            Ok(Some(Ipv4Addr::new(
+                data[offset] as u8,
+                data[offset + 1] as u8,
+                data[offset + 2] as u8,
+                data[offset + 3] as u8,
+            )))
+        }
+    }
+
+    /// Checks if the interface is up.
+    pub(crate) fn is_up(&self) -> Result<bool, NetworkConfigurationError> {
+        unsafe {
+            let mut req = self.request();
+
+            if let Err(err) = siocgifflags(self.fd, &mut req) {
+                return Err(NetworkConfigurationError::from_errno(
+                    format!("Failed to get interface flags: {}", err),
+                    err,
+                ));
+            }
+
+            Ok(req.ifr_ifru.ifru_flags & libc::IFF_UP as i16 != 0)
+        }
+    }
+
+    /// Reads the netmask of the interface.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Option<Ipv4Addr>, NetworkConfigurationError>` - The netmask,
+    ///   or `None` if the interface has no address.
+    pub(crate) fn get_netmask(&self) -> Result<Option<Ipv4Addr>, NetworkConfigurationError> {
+        unsafe {
+            let mut req = self.request();
+
+            match siocgifnetmask(self.fd, &mut req) {
+                Ok(_) => {}
+                Err(nix::errno::Errno::EADDRNOTAVAIL) => return Ok(None),
+                Err(err) => {
+                    return Err(NetworkConfigurationError::from_errno(
+                        format!("Failed to get interface netmask: {}", err),
+                        err,
+                    ));
+                }
+            }
+
+            // offset by `libc::AF_*` size
+            let data = &req.ifr_ifru.ifru_netmask.sa_data;
+            let offset = mem::size_of::<u16>();
+            Ok(Some(Ipv4Addr::new(
+                data[offset] as u8,
+                data[offset + 1] as u8,
+                data[offset + 2] as u8,
+                data[offset + 3] as u8,
+            )))
+        }
+    }
+
+    /// Checks if the main routing table has a default route
+    /// via `gateway` on the interface (see `/proc/net/route`).
+    pub(crate) fn has_default_route(&self, gateway: Ipv4Addr) -> bool {
+        let routes = fs::read_to_string(ROUTES).unwrap_or_default();
+        // the addresses are hex encoded in host byte order
+        let hex = |addr: Ipv4Addr| format!("{:08X}", u32::from_ne_bytes(addr.octets()));
+        routes.lines().skip(1).any(|line| {
+            let fields = line.split_whitespace().collect::<Vec<&str>>();
+            fields.len() > 2
+                && fields[0] == self.iface
+                && fields[1] == "00000000"
+                && fields[2].eq_ignore_ascii_case(&hex(gateway))
+        })
+    }
+
     pub(crate) fn set_netmask(&self, netmask: IpAddr) -> Result<(), NetworkConfigurationError> {
         let ip = match netmask {
             IpAddr::V4(ip) => ip,
@@ -138,10 +259,10 @@ impl ConfigSocket {
             });
 
             if let Err(err) = siocsifnetmask(self.fd, &req) {
-                return Err(NetworkConfigurationError::new(format!(
-                    "Failed to set interface netmask: {}",
-                    err
-                )));
+                return Err(NetworkConfigurationError::from_errno(
+                    format!("Failed to set interface netmask: {}", err),
+                    err,
+                ));
             }
         }
 
@@ -181,27 +302,269 @@ impl ConfigSocket {
 
         rt.rt_dev = c_world;
         unsafe {
-            if let Err(err) = siocaddrt(self.fd, &rt) {
-                return Err(NetworkConfigurationError::new(format!(
-                    "Failed to set interface gateway: {}",
-                    err
-                )));
+            match siocaddrt(self.fd, &rt) {
+                // the route exists already, e.g. on reconfiguration
+                Ok(_) | Err(nix::errno::Errno::EEXIST) => {}
+                Err(err) => {
+                    return Err(NetworkConfigurationError::from_errno(
+                        format!("Failed to set interface gateway: {}", err),
+                        err,
+                    ));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn add_neighbor(
+        &self,
+        addr: IpAddr,
+        mac: MacAddr,
+    ) -> Result<(), NetworkConfigurationError> {
+        let ip = match addr {
+            IpAddr::V4(ip) => ip,
+            IpAddr::V6(_) => {
+                return Err(NetworkConfigurationError::new(
+                    "IPv6 is not supported".to_string(),
+                ));
+            }
+        };
+
+        let mut req: libc::arpreq = unsafe { mem::zeroed() };
+
+        req.arp_pa.sa_family = libc::AF_INET as u16;
+        ip.octets().iter().enumerate().for_each(|(i, octet)| {
+            // offset by `libc::AF_*` size
+            req.arp_pa.sa_data[i + mem::size_of::<u16>()] = *octet as i8;
+        });
+        req.arp_ha.sa_family = libc::ARPHRD_ETHER;
+        mac.octets().iter().enumerate().for_each(|(i, octet)| {
+            req.arp_ha.sa_data[i] = *octet as i8;
+        });
+        req.arp_flags = ATF_COM | ATF_PERM;
+        unsafe {
+            ptr::copy_nonoverlapping(
+                self.iface.as_ptr() as *const libc::c_char,
+                req.arp_dev.as_mut_ptr(),
+                self.iface.len(),
+            );
+        }
+
+        unsafe {
+            if let Err(err) = siocsarp(self.fd, &req) {
+                return Err(NetworkConfigurationError::from_errno(
+                    format!("Failed to add neighbor {} ({}): {}", ip, mac, err),
+                    err,
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Creates the bridge named after the interface of this socket.
+    ///
+    /// An already existing bridge is kept.
+    pub(crate) fn add_bridge(&self) -> Result<(), NetworkConfigurationError> {
+        let name = CString::new(self.iface.clone()).unwrap();
+        unsafe {
+            match siocbraddbr(self.fd, name.as_ptr()) {
+                Ok(_) | Err(nix::errno::Errno::EEXIST) => {}
+                Err(err) => {
+                    return Err(NetworkConfigurationError::from_errno(
+                        format!("Failed to create bridge: {}", err),
+                        err,
+                    ));
+                }
             }
         }
 
         Ok(())
     }
+
+    /// Adds an interface to the bridge of this socket.
+    ///
+    /// # Arguments
+    ///
+    /// * `index` - The index of the interface to add.
+    pub(crate) fn add_bridge_member(&self, index: u32) -> Result<(), NetworkConfigurationError> {
+        unsafe {
+            let mut req = self.request();
+            req.ifr_ifru.ifru_ifindex = index as libc::c_int;
+
+            if let Err(err) = siocbraddif(self.fd, &req) {
+                return Err(NetworkConfigurationError::from_errno(
+                    format!("Failed to add bridge member: {}", err),
+                    err,
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// The IPv4 routes of the main routing table.
+const ROUTES: &str = "/proc/net/route";
+
+/// The ARP table of the kernel.
+const ARP: &str = "/proc/net/arp";
+
+/// Checks if the ARP table holds a permanent entry of `ip` with `mac` on `iface`.
+///
+/// # Arguments
+///
+/// * `arp` - The contents of `/proc/net/arp`, a header line followed
+///   by `<ip> <hw type> <flags> <mac> <mask> <device>` lines.
+pub(crate) fn has_arp_entry(arp: &str, iface: &str, ip: Ipv4Addr, mac: MacAddr) -> bool {
+    arp.lines().skip(1).any(|line| {
+        let fields = line.split_whitespace().collect::<Vec<&str>>();
+        let flags = fields
+            .get(2)
+            .and_then(|flags| i32::from_str_radix(flags.trim_start_matches("0x"), 16).ok())
+            .unwrap_or(0);
+        fields.len() > 5
+            && fields[0].parse::<Ipv4Addr>().ok() == Some(ip)
+            && fields[3].parse::<MacAddr>().ok() == Some(mac)
+            && fields[5] == iface
+            && flags & ATF_PERM != 0
+    })
+}
+
+/// Checks if the neighbor entry of `ip` with `mac` on `iface` exists already.
+pub(crate) fn has_neighbor(iface: &str, ip: IpAddr, mac: MacAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            has_arp_entry(&fs::read_to_string(ARP).unwrap_or_default(), iface, ip, mac)
+        }
+        IpAddr::V6(_) => false,
+    }
+}
+
+/// Path of the resolver configuration.
+pub const RESOLV_CONF: &str = "/etc/resolv.conf";
+
+/// Path of the static hostname lookup table.
+pub const HOSTS: &str = "/etc/hosts";
+
+/// Path of the name service switch configuration.
+pub const NSSWITCH_CONF: &str = "/etc/nsswitch.conf";
+
+/// Directory of the per-interface resolver configurations
+/// (`<iface>.conf`), e.g. for split-DNS resolvers.
+pub const RESOLV_DIR: &str = "/run/linos/resolv";
+
+/// The DNS configuration of an interface.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct InterfaceDns {
+    iface: String,
+    nameservers: Vec<IpAddr>,
+    search: Vec<String>,
+}
+
+/// The DNS configuration of every interface, in the order they were applied.
+static INTERFACE_DNS: Mutex<Vec<InterfaceDns>> = Mutex::new(Vec::new());
+
+/// Formats a resolver configuration with the search domains and
+/// nameservers of all `entries` in order, without duplicates.
+fn resolv_conf<'a>(entries: impl IntoIterator<Item = &'a InterfaceDns>) -> String {
+    let mut search: Vec<&str> = Vec::new();
+    let mut nameservers: Vec<IpAddr> = Vec::new();
+    for entry in entries {
+        for domain in &entry.search {
+            if !search.contains(&domain.as_str()) {
+                search.push(domain);
+            }
+        }
+        for nameserver in &entry.nameservers {
+            if !nameservers.contains(nameserver) {
+                nameservers.push(*nameserver);
+            }
+        }
+    }
+
+    let mut contents = String::new();
+    if !search.is_empty() {
+        contents.push_str(&format!("search {}\n", search.join(" ")));
+    }
+    for nameserver in nameservers {
+        contents.push_str(&format!("nameserver {}\n", nameserver));
+    }
+    contents
 }
 
-/// Configures the DNS server
+/// Replaces the DNS configuration of the interface in `interfaces`,
+/// an interface configured for the first time is appended.
+fn update_interface_dns(interfaces: &mut Vec<InterfaceDns>, dns: InterfaceDns) {
+    match interfaces.iter_mut().find(|entry| entry.iface == dns.iface) {
+        Some(entry) => *entry = dns,
+        None => interfaces.push(dns),
+    }
+}
+
+/// Configures the DNS servers of an interface
 ///
-/// This is done by writeing to the `/etc/resolv.conf` file.
+/// The configuration is written to `/run/linos/resolv/<iface>.conf`, and
+/// `/etc/resolv.conf` is rewritten with the DNS servers and search domains
+/// of all configured interfaces, in the order they were configured.
 ///
 /// # Arguments
 ///
-/// * `addr`: The dns ip addres to use
-pub(crate) fn set_dns(addr: IpAddr) -> Result<(), NetworkConfigurationError> {
-    if let Err(err) = fs::write("/etc/resolv.conf", format!("nameserver {}", addr)) {
+/// * `iface`: The interface the DNS servers were configured for
+/// * `addrs`: The dns ip addresses to use, may be empty
+/// * `search`: The DNS search domains, may be empty
+pub(crate) fn set_dns(
+    iface: &str,
+    addrs: &[IpAddr],
+    search: &[String],
+) -> Result<(), NetworkConfigurationError> {
+    let dns = InterfaceDns {
+        iface: iface.to_string(),
+        nameservers: addrs.to_vec(),
+        search: search.to_vec(),
+    };
+
+    let mut interfaces = INTERFACE_DNS.lock().unwrap_or_else(|err| err.into_inner());
+    update_interface_dns(&mut interfaces, dns.clone());
+
+    let path = Path::new(RESOLV_DIR).join(format!("{}.conf", iface));
+    if let Err(err) = atomic_write(&path, &resolv_conf([&dns])) {
+        return Err(NetworkConfigurationError::new(format!(
+            "Failed writing {}: {}",
+            path.display(),
+            err
+        )));
+    }
+
+    write_interface_dns(&interfaces)
+}
+
+/// Checks if the DNS configuration of an interface is applied already:
+/// it is unchanged and the resolver files have the expected contents.
+///
+/// # Arguments
+///
+/// * `iface`: The interface the DNS servers are configured for
+/// * `addrs`: The dns ip addresses to use, may be empty
+/// * `search`: The DNS search domains, may be empty
+pub(crate) fn is_dns_configured(iface: &str, addrs: &[IpAddr], search: &[String]) -> bool {
+    let dns = InterfaceDns {
+        iface: iface.to_string(),
+        nameservers: addrs.to_vec(),
+        search: search.to_vec(),
+    };
+
+    let interfaces = INTERFACE_DNS.lock().unwrap_or_else(|err| err.into_inner());
+    let path = Path::new(RESOLV_DIR).join(format!("{}.conf", iface));
+    interfaces.contains(&dns)
+        && fs::read_to_string(path).ok() == Some(resolv_conf([&dns]))
+        && fs::read_to_string(RESOLV_CONF).ok() == Some(resolv_conf(interfaces.iter()))
+}
+
+/// Writes the DNS configuration of the `interfaces` to `/etc/resolv.conf`.
+fn write_interface_dns(interfaces: &[InterfaceDns]) -> Result<(), NetworkConfigurationError> {
+    if let Err(err) = write_config_file(RESOLV_CONF, &resolv_conf(interfaces)) {
         return Err(NetworkConfigurationError::new(format!(
             "Failed configuring DNS: {}",
             err
@@ -210,3 +573,109 @@ pub(crate) fn set_dns(addr: IpAddr) -> Result<(), NetworkConfigurationError> {
 
     Ok(())
 }
+
+/// Writes the DNS configuration of all configured interfaces to
+/// `/etc/resolv.conf` again, nothing is written if there is none.
+pub(crate) fn write_resolv_conf() -> Result<(), NetworkConfigurationError> {
+    let interfaces = INTERFACE_DNS.lock().unwrap_or_else(|err| err.into_inner());
+    if interfaces.is_empty() {
+        return Ok(());
+    }
+
+    write_interface_dns(&interfaces)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn arp_entry_has_to_be_permanent() {
+        let arp =
+            "IP address       HW type     Flags       HW address            Mask     Device\n\
+                   10.0.0.5         0x1         0x6         02:00:00:00:00:05     *        eth0\n\
+                   10.0.0.6         0x1         0x2         02:00:00:00:00:06     *        eth0\n";
+        let mac = |last| MacAddr::new(2, 0, 0, 0, 0, last);
+
+        assert!(has_arp_entry(
+            arp,
+            "eth0",
+            Ipv4Addr::new(10, 0, 0, 5),
+            mac(5)
+        ));
+        assert!(!has_arp_entry(
+            arp,
+            "eth1",
+            Ipv4Addr::new(10, 0, 0, 5),
+            mac(5)
+        ));
+        assert!(!has_arp_entry(
+            arp,
+            "eth0",
+            Ipv4Addr::new(10, 0, 0, 5),
+            mac(6)
+        ));
+        // learned, but not permanent
+        assert!(!has_arp_entry(
+            arp,
+            "eth0",
+            Ipv4Addr::new(10, 0, 0, 6),
+            mac(6)
+        ));
+    }
+
+    #[test]
+    fn resolv_conf_has_all_nameservers_and_search_only_entries() {
+        let entries = [
+            InterfaceDns {
+                iface: "eth0".to_string(),
+                nameservers: vec!["10.0.0.1".parse().unwrap(), "10.0.0.2".parse().unwrap()],
+                search: vec!["example.com".to_string()],
+            },
+            InterfaceDns {
+                iface: "eth1".to_string(),
+                nameservers: Vec::new(),
+                search: vec!["corp.local".to_string(), "example.com".to_string()],
+            },
+        ];
+
+        assert_eq!(
+            resolv_conf(&entries),
+            "search example.com corp.local\nnameserver 10.0.0.1\nnameserver 10.0.0.2\n"
+        );
+        assert_eq!(
+            resolv_conf(&entries[1..]),
+            "search corp.local example.com\n"
+        );
+    }
+
+    #[test]
+    fn resolver_merges_interfaces_in_applied_order() {
+        let dns = |iface: &str, nameservers: &[&str], search: &[&str]| InterfaceDns {
+            iface: iface.to_string(),
+            nameservers: nameservers
+                .iter()
+                .map(|addr| addr.parse().unwrap())
+                .collect(),
+            search: search.iter().map(|domain| domain.to_string()).collect(),
+        };
+        let mut interfaces = Vec::new();
+        update_interface_dns(&mut interfaces, dns("eth0", &["10.0.0.1"], &["a.com"]));
+        update_interface_dns(
+            &mut interfaces,
+            dns("wlan0", &["192.168.1.1", "10.0.0.1"], &["b.com"]),
+        );
+        assert_eq!(
+            resolv_conf(&interfaces),
+            "search a.com b.com\nnameserver 10.0.0.1\nnameserver 192.168.1.1\n"
+        );
+
+        // a renewed lease replaces the servers, but keeps the position
+        update_interface_dns(&mut interfaces, dns("eth0", &["10.0.0.2"], &[]));
+        assert_eq!(interfaces.len(), 2);
+        assert_eq!(
+            resolv_conf(&interfaces),
+            "search b.com\nnameserver 10.0.0.2\nnameserver 192.168.1.1\nnameserver 10.0.0.1\n"
+        );
+    }
+}
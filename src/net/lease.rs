@@ -0,0 +1,405 @@
+use std::{
+    io,
+    io::Error,
+    net::{IpAddr, Ipv4Addr},
+    path::Path,
+};
+
+use dhcproto::v4;
+
+use super::{iface::StaticNetworkInterfaceConfig, netlink::INFINITE_LIFETIME};
+use crate::fs::atomic_write;
+
+/// Directory the lease files are written to.
+pub const LEASE_DIR: &str = "/run/linos/leases";
+
+/// A DHCP lease as handed out by the DHCP server.
+///
+/// Contains every option linµos parses from the DHCP ack,
+/// not only the ones required to configure the interface.
+#[derive(Debug, Clone)]
+pub struct DhcpLease {
+    pub iface: String,
+    pub ip: Ipv4Addr,
+    pub netmask: Ipv4Addr,
+    pub gateway: Ipv4Addr,
+    pub dns: Vec<Ipv4Addr>,
+    pub domain: Option<String>,
+    /// The hostname assigned by the server (option 12).
+    pub hostname: Option<String>,
+    pub lease_time: Option<u32>,
+    /// The time after which any server may extend the lease (option 59, T2).
+    pub rebinding_time: Option<u32>,
+    pub server_id: Option<Ipv4Addr>,
+    pub ntp: Vec<Ipv4Addr>,
+    pub mtu: Option<u16>,
+    pub broadcast: Option<Ipv4Addr>,
+    /// The raw vendor specific information (option 43).
+    pub vendor_specific: Option<Vec<u8>>,
+}
+
+impl DhcpLease {
+    /// Assembles a lease from a DHCP ack message.
+    ///
+    /// # Arguments
+    ///
+    /// * `iface` - The name of the interface the lease was obtained on.
+    /// * `msg` - The DHCP ack message.
+    pub fn from_message(iface: &str, msg: &v4::Message) -> io::Result<DhcpLease> {
+        let opts = msg.opts();
+
+        let netmask = match opts.get(v4::OptionCode::SubnetMask) {
+            Some(v4::DhcpOption::SubnetMask(netmask)) => *netmask,
+            _ => {
+                return Err(Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("{}: no netmask returned by dhcp.", iface),
+                ))
+            }
+        };
+
+        let gateway = match opts.get(v4::OptionCode::Router) {
+            Some(v4::DhcpOption::Router(router)) => match router.first() {
+                Some(r) => *r,
+                None => {
+                    return Err(Error::new(
+                        io::ErrorKind::NotFound,
+                        format!("{}: no gateway found in dhcp response.", iface),
+                    ))
+                }
+            },
+            _ => {
+                return Err(Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("{}: no gateway returned by dhcp.", iface),
+                ))
+            }
+        };
+
+        let dns = match opts.get(v4::OptionCode::DomainNameServer) {
+            Some(v4::DhcpOption::DomainNameServer(dns)) => dns.clone(),
+            _ => Vec::new(),
+        };
+
+        let domain = match opts.get(v4::OptionCode::DomainName) {
+            Some(v4::DhcpOption::DomainName(domain)) => Some(domain.clone()),
+            _ => None,
+        };
+
+        let hostname = match opts.get(v4::OptionCode::Hostname) {
+            Some(v4::DhcpOption::Hostname(hostname)) => Some(hostname.clone()),
+            _ => None,
+        };
+
+        let lease_time = match opts.get(v4::OptionCode::AddressLeaseTime) {
+            Some(v4::DhcpOption::AddressLeaseTime(secs)) => Some(*secs),
+            _ => None,
+        };
+
+        let rebinding_time = match opts.get(v4::OptionCode::Rebinding) {
+            Some(v4::DhcpOption::Rebinding(secs)) => Some(*secs),
+            _ => None,
+        };
+
+        let server_id = match opts.get(v4::OptionCode::ServerIdentifier) {
+            Some(v4::DhcpOption::ServerIdentifier(id)) => Some(*id),
+            _ => None,
+        };
+
+        let ntp = match opts.get(v4::OptionCode::NTPServers) {
+            Some(v4::DhcpOption::NTPServers(ntp)) => ntp.clone(),
+            _ => Vec::new(),
+        };
+
+        let mtu = match opts.get(v4::OptionCode::InterfaceMtu) {
+            Some(v4::DhcpOption::InterfaceMtu(mtu)) => Some(*mtu),
+            _ => None,
+        };
+
+        let broadcast = match opts.get(v4::OptionCode::BroadcastAddr) {
+            Some(v4::DhcpOption::BroadcastAddr(addr)) => Some(*addr),
+            _ => None,
+        };
+
+        let vendor_specific = match opts.get(v4::OptionCode::VendorExtensions) {
+            Some(v4::DhcpOption::VendorExtensions(data)) => Some(data.clone()),
+            _ => None,
+        };
+
+        Ok(DhcpLease {
+            iface: iface.to_string(),
+            ip: msg.yiaddr(),
+            netmask,
+            gateway,
+            dns,
+            domain,
+            hostname,
+            lease_time,
+            rebinding_time,
+            server_id,
+            ntp,
+            mtu,
+            broadcast,
+            vendor_specific,
+        })
+    }
+
+    /// Decodes the vendor specific information into sub-options.
+    ///
+    /// The vendor specific information is commonly encoded as
+    /// `code, length, data` sub-options (see RFC 2132, section 8.4).
+    ///
+    /// # Returns
+    ///
+    /// * `Option<Vec<(u8, Vec<u8>)>>` - The sub-options, or `None` if there
+    ///   is no vendor specific information or it is not encoded as sub-options.
+    pub fn vendor_suboptions(&self) -> Option<Vec<(u8, Vec<u8>)>> {
+        let data = self.vendor_specific.as_ref()?;
+
+        let mut suboptions = Vec::new();
+        let mut i = 0;
+        while i < data.len() {
+            match data[i] {
+                // pad
+                0 => i += 1,
+                // end
+                255 => break,
+                code => {
+                    let len = *data.get(i + 1)? as usize;
+                    let value = data.get(i + 2..i + 2 + len)?;
+                    suboptions.push((code, value.to_vec()));
+                    i += 2 + len;
+                }
+            }
+        }
+
+        Some(suboptions)
+    }
+
+    /// The preferred and valid lifetime of the leased address in seconds.
+    ///
+    /// The address is valid for the lease time and preferred until the
+    /// rebinding time (T2), which defaults to 7/8 of the lease time
+    /// (see: https://www.rfc-editor.org/rfc/rfc2131#section-4.4.5).
+    /// A lease without a lease time has no lifetimes, an infinite lease
+    /// time (`0xffffffff`) is an infinite lifetime for the kernel as well.
+    pub fn lifetimes(&self) -> Option<(u32, u32)> {
+        let valid = self.lease_time?;
+        if valid == INFINITE_LIFETIME {
+            return Some((INFINITE_LIFETIME, INFINITE_LIFETIME));
+        }
+        let preferred = self
+            .rebinding_time
+            .unwrap_or((valid as u64 * 7 / 8) as u32)
+            .min(valid);
+        Some((preferred, valid))
+    }
+
+    /// Creates a lease of `10.0.0.2/24` via `10.0.0.1` without any
+    /// further options, for tests to fill in the options they need.
+    ///
+    /// # Arguments
+    ///
+    /// * `iface` - The name of the interface the lease was obtained on.
+    #[cfg(test)]
+    pub fn for_test(iface: &str) -> DhcpLease {
+        DhcpLease {
+            iface: iface.to_string(),
+            ip: Ipv4Addr::new(10, 0, 0, 2),
+            netmask: Ipv4Addr::new(255, 255, 255, 0),
+            gateway: Ipv4Addr::new(10, 0, 0, 1),
+            dns: Vec::new(),
+            domain: None,
+            hostname: None,
+            lease_time: None,
+            rebinding_time: None,
+            server_id: None,
+            ntp: Vec::new(),
+            mtu: None,
+            broadcast: None,
+            vendor_specific: None,
+        }
+    }
+
+    /// Returns the static interface configuration described by this lease.
+    pub fn to_static_config(&self) -> StaticNetworkInterfaceConfig {
+        StaticNetworkInterfaceConfig {
+            name: self.iface.clone(),
+            ip: IpAddr::V4(self.ip),
+            netmask: IpAddr::V4(self.netmask),
+            gateway: IpAddr::V4(self.gateway),
+            dns: self.dns.iter().map(|dns| IpAddr::V4(*dns)).collect(),
+            search: self.domain.iter().cloned().collect(),
+            priority: 0,
+            aliases: Vec::new(),
+            neighbors: Vec::new(),
+            routing: None,
+        }
+    }
+
+    /// Serializes the lease as `KEY=VALUE` lines.
+    ///
+    /// Options not present in the lease are omitted,
+    /// lists are separated by spaces.
+    pub fn to_lease_file(&self) -> String {
+        let join = |addrs: &Vec<Ipv4Addr>| {
+            addrs
+                .iter()
+                .map(|addr| addr.to_string())
+                .collect::<Vec<String>>()
+                .join(" ")
+        };
+
+        let mut lines = vec![
+            format!("ADDRESS={}", self.ip),
+            format!("NETMASK={}", self.netmask),
+            format!("ROUTER={}", self.gateway),
+        ];
+        if !self.dns.is_empty() {
+            lines.push(format!("DNS={}", join(&self.dns)));
+        }
+        if let Some(domain) = &self.domain {
+            lines.push(format!("DOMAINNAME={}", domain));
+        }
+        if let Some(hostname) = &self.hostname {
+            lines.push(format!("HOSTNAME={}", hostname));
+        }
+        if let Some(lease_time) = self.lease_time {
+            lines.push(format!("LIFETIME={}", lease_time));
+        }
+        if let Some(rebinding_time) = self.rebinding_time {
+            lines.push(format!("REBINDING_TIME={}", rebinding_time));
+        }
+        if let Some(server_id) = self.server_id {
+            lines.push(format!("SERVER_ADDRESS={}", server_id));
+        }
+        if !self.ntp.is_empty() {
+            lines.push(format!("NTP={}", join(&self.ntp)));
+        }
+        if let Some(mtu) = self.mtu {
+            lines.push(format!("MTU={}", mtu));
+        }
+        if let Some(broadcast) = self.broadcast {
+            lines.push(format!("BROADCAST={}", broadcast));
+        }
+        if let Some(vendor_specific) = &self.vendor_specific {
+            let hex = vendor_specific
+                .iter()
+                .map(|byte| format!("{:02x}", byte))
+                .collect::<String>();
+            lines.push(format!("VENDOR_SPECIFIC={}", hex));
+        }
+
+        lines.join("\n") + "\n"
+    }
+
+    /// Writes the lease to `LEASE_DIR/<iface>`.
+    pub fn write(&self) -> io::Result<()> {
+        atomic_write(
+            &Path::new(LEASE_DIR).join(&self.iface),
+            &self.to_lease_file(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An ACK with the options required for a lease.
+    fn ack() -> v4::Message {
+        let mut msg = v4::Message::default();
+        msg.set_yiaddr(Ipv4Addr::new(10, 0, 0, 2));
+        msg.opts_mut()
+            .insert(v4::DhcpOption::MessageType(v4::MessageType::Ack));
+        msg.opts_mut()
+            .insert(v4::DhcpOption::SubnetMask(Ipv4Addr::new(255, 255, 255, 0)));
+        msg.opts_mut()
+            .insert(v4::DhcpOption::Router(vec![Ipv4Addr::new(10, 0, 0, 1)]));
+        msg
+    }
+
+    #[test]
+    fn vendor_specific_information_is_decoded() {
+        let mut msg = ack();
+        msg.opts_mut().insert(v4::DhcpOption::VendorExtensions(vec![
+            1, 2, 0xab, 0xcd, 0, 2, 1, 0x7f, 255, 9,
+        ]));
+        let lease = DhcpLease::from_message("eth0", &msg).unwrap();
+
+        assert_eq!(
+            lease.vendor_suboptions(),
+            Some(vec![(1, vec![0xab, 0xcd]), (2, vec![0x7f])])
+        );
+        assert!(lease
+            .to_lease_file()
+            .contains("VENDOR_SPECIFIC=0102abcd0002017fff09\n"));
+    }
+
+    #[test]
+    fn hostname_is_parsed() {
+        let lease = DhcpLease::from_message("eth0", &ack()).unwrap();
+        assert_eq!(lease.hostname, None);
+        assert!(!lease.to_lease_file().contains("HOSTNAME"));
+
+        let mut msg = ack();
+        msg.opts_mut()
+            .insert(v4::DhcpOption::Hostname("node1".to_string()));
+        let lease = DhcpLease::from_message("eth0", &msg).unwrap();
+        assert_eq!(lease.hostname.as_deref(), Some("node1"));
+        assert!(lease.to_lease_file().contains("HOSTNAME=node1\n"));
+    }
+
+    #[test]
+    fn ntp_servers_are_parsed() {
+        let lease = DhcpLease::from_message("eth0", &ack()).unwrap();
+        assert!(lease.ntp.is_empty());
+        assert!(!lease.to_lease_file().contains("NTP"));
+
+        let mut msg = ack();
+        msg.opts_mut().insert(v4::DhcpOption::NTPServers(vec![
+            Ipv4Addr::new(10, 0, 0, 1),
+            Ipv4Addr::new(10, 0, 0, 5),
+        ]));
+        let lease = DhcpLease::from_message("eth0", &msg).unwrap();
+        assert_eq!(
+            lease.ntp,
+            [Ipv4Addr::new(10, 0, 0, 1), Ipv4Addr::new(10, 0, 0, 5)]
+        );
+        assert!(lease.to_lease_file().contains("NTP=10.0.0.1 10.0.0.5\n"));
+    }
+
+    #[test]
+    fn lifetimes_follow_lease_and_rebinding_time() {
+        let mut lease = DhcpLease::from_message("eth0", &ack()).unwrap();
+        assert_eq!(lease.lifetimes(), None);
+
+        // preferred until T2, 7/8 of the lease time by default
+        lease.lease_time = Some(3600);
+        assert_eq!(lease.lifetimes(), Some((3150, 3600)));
+        lease.rebinding_time = Some(3000);
+        assert_eq!(lease.lifetimes(), Some((3000, 3600)));
+        // a rebinding time beyond the lease time is capped
+        lease.rebinding_time = Some(7200);
+        assert_eq!(lease.lifetimes(), Some((3600, 3600)));
+        lease.lease_time = Some(INFINITE_LIFETIME);
+        assert_eq!(
+            lease.lifetimes(),
+            Some((INFINITE_LIFETIME, INFINITE_LIFETIME))
+        );
+    }
+
+    #[test]
+    fn truncated_vendor_suboptions_are_rejected() {
+        let mut msg = ack();
+        msg.opts_mut()
+            .insert(v4::DhcpOption::VendorExtensions(vec![1, 4, 0xab]));
+        let lease = DhcpLease::from_message("eth0", &msg).unwrap();
+        assert_eq!(lease.vendor_suboptions(), None);
+
+        let lease = DhcpLease::from_message("eth0", &ack()).unwrap();
+        assert_eq!(lease.vendor_specific, None);
+        assert_eq!(lease.vendor_suboptions(), None);
+        assert!(!lease.to_lease_file().contains("VENDOR_SPECIFIC"));
+    }
+}
@@ -1,10 +1,19 @@
-pub mod err;
+pub mod bridge;
+pub mod check;
+pub mod config;
 pub mod dhcp;
+pub mod dns;
+pub mod err;
 pub mod iface;
+pub mod lease;
+pub mod monitor;
+pub mod netlink;
 pub mod networkd;
+pub mod sntp;
 
 mod iface_config;
 
-pub use networkd::configure_network;
+pub use err::{ErrorKind, NetworkConfigurationError};
 pub use iface::NetworkInterfaceConfig;
-pub use err::NetworkConfigurationError;
+pub use lease::DhcpLease;
+pub use networkd::{configure_network, NetworkOptions};
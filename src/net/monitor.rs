@@ -0,0 +1,76 @@
+//! Link carrier monitoring.
+//!
+//! After boot, the carrier of the configured interfaces is watched, and an
+//! interface is configured again when its link comes back up (e.g. when a
+//! cable is reconnected), renewing its DHCP lease.
+use std::{
+    fs,
+    path::Path,
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use log::{debug, error, info, warn};
+
+use super::{iface::NetworkInterfaceConfig, networkd};
+
+/// Interval in which the carrier of the interfaces is checked.
+pub const CARRIER_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Reads the carrier state of an interface from
+/// `/sys/class/net/<iface>/carrier`.
+///
+/// # Returns
+///
+/// * `Option<bool>` - Whether the link is up, or `None` if the
+///   state is unknown (e.g. the interface is down or gone).
+pub fn read_carrier(iface: &str) -> Option<bool> {
+    let path = Path::new("/sys/class/net").join(iface).join("carrier");
+    match fs::read_to_string(path).ok()?.trim() {
+        "1" => Some(true),
+        "0" => Some(false),
+        _ => None,
+    }
+}
+
+/// Starts monitoring the carrier of the given interfaces.
+///
+/// The monitor runs in its own thread, sleeping `CARRIER_POLL_INTERVAL`
+/// between checks. Whenever the carrier of an interface changes from
+/// down to up, its configuration is applied again, serialized with
+/// reconfigurations of the network (see `networkd::apply_interface`).
+///
+/// # Arguments
+///
+/// * `configs` - The configs of the interfaces to monitor.
+pub fn spawn(configs: Vec<NetworkInterfaceConfig>) -> JoinHandle<()> {
+    thread::spawn(move || {
+        let mut carriers = configs
+            .iter()
+            .map(|config| read_carrier(config.name()).unwrap_or(false))
+            .collect::<Vec<bool>>();
+
+        loop {
+            thread::sleep(CARRIER_POLL_INTERVAL);
+
+            for (config, carrier) in configs.iter().zip(carriers.iter_mut()) {
+                let up = read_carrier(config.name()).unwrap_or(false);
+                if up == *carrier {
+                    continue;
+                }
+                *carrier = up;
+
+                if !up {
+                    warn!("'{}' lost carrier", config.name());
+                    continue;
+                }
+
+                info!("'{}' regained carrier, reconfiguring", config.name());
+                match networkd::apply_interface(config) {
+                    Ok(()) => debug!("Reconfigured '{}'", config.name()),
+                    Err(err) => error!("Failed reconfiguring '{}': {}", config.name(), err),
+                }
+            }
+        }
+    })
+}
@@ -0,0 +1,685 @@
+//! Minimal rtnetlink client.
+//!
+//! The ioctl based configuration in `iface_config` can only install routes
+//! into the main routing table, routes in other tables and policy routing
+//! rules (`ip rule`) are configured using rtnetlink messages, as well as
+//! the lifetimes of addresses, which the ioctls can not set.
+use std::{mem, net::Ipv4Addr};
+
+use nix::errno::Errno;
+
+use super::NetworkConfigurationError;
+
+/// Routing tables reserved by the kernel: unspecified, default, main and local.
+pub const RESERVED_TABLES: [u32; 4] = [0, 253, 254, 255];
+
+/// Rule priorities reserved by the kernel's default rules (local, main and default).
+pub const RESERVED_PRIORITIES: [u32; 3] = [0, 32766, 32767];
+
+/// Routing rule attributes, see `<linux/fib_rules.h>`.
+const FRA_SRC: u16 = 2;
+const FRA_PRIORITY: u16 = 6;
+const FRA_TABLE: u16 = 15;
+const FRA_PROTOCOL: u16 = 21;
+const FR_ACT_TO_TBL: u8 = 1;
+
+/// Lifetime meaning the address never expires.
+pub const INFINITE_LIFETIME: u32 = u32::MAX;
+
+/// Flags of a request creating a new entry.
+const CREATE_FLAGS: i32 =
+    libc::NLM_F_REQUEST | libc::NLM_F_ACK | libc::NLM_F_CREATE | libc::NLM_F_EXCL;
+
+/// Flags of a request creating or replacing an entry.
+const REPLACE_FLAGS: i32 =
+    libc::NLM_F_REQUEST | libc::NLM_F_ACK | libc::NLM_F_CREATE | libc::NLM_F_REPLACE;
+
+/// Flags of a request dumping all entries.
+const DUMP_FLAGS: i32 = libc::NLM_F_REQUEST | libc::NLM_F_DUMP;
+
+/// Size of the `nlmsghdr` header.
+const HEADER_LENGTH: usize = 16;
+
+/// Size of the `rtmsg` (and `fib_rule_hdr`) header.
+const RTMSG_LENGTH: usize = 12;
+
+/// An IPv4 route, as dumped by the kernel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Route {
+    pub table: u32,
+    /// The destination network and its prefix length.
+    pub dst: (Ipv4Addr, u8),
+    pub gateway: Option<Ipv4Addr>,
+    /// The index of the outgoing interface.
+    pub index: Option<u32>,
+}
+
+/// An IPv4 routing rule looking up a table, as dumped by the kernel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rule {
+    /// The source network and its prefix length.
+    pub src: (Ipv4Addr, u8),
+    pub table: u32,
+    pub priority: u32,
+}
+
+/// Validates a routing table id (see `RESERVED_TABLES`).
+pub fn validate_table(table: u32) -> Result<(), String> {
+    if RESERVED_TABLES.contains(&table) {
+        return Err(format!(
+            "Routing table {} is reserved (reserved are {:?})",
+            table, RESERVED_TABLES
+        ));
+    }
+
+    Ok(())
+}
+
+/// Validates a routing rule priority (see `RESERVED_PRIORITIES`).
+pub fn validate_priority(priority: u32) -> Result<(), String> {
+    if RESERVED_PRIORITIES.contains(&priority) {
+        return Err(format!(
+            "Rule priority {} is reserved (reserved are {:?})",
+            priority, RESERVED_PRIORITIES
+        ));
+    }
+
+    Ok(())
+}
+
+/// Encodes an rtnetlink message.
+///
+/// # Arguments
+///
+/// * `kind` - The message type (e.g. `RTM_NEWROUTE`).
+/// * `flags` - The request flags (e.g. `CREATE_FLAGS`).
+/// * `seq` - The sequence number of the message.
+/// * `header` - The family specific header (e.g. `rtmsg`).
+/// * `attrs` - The attributes, as type and payload.
+pub fn encode(kind: u16, flags: i32, seq: u32, header: &[u8], attrs: &[(u16, Vec<u8>)]) -> Vec<u8> {
+    let align = |len: usize| (len + 3) & !3;
+
+    let mut msg = vec![0; HEADER_LENGTH];
+    msg.extend_from_slice(header);
+    msg.resize(align(msg.len()), 0);
+    for (kind, payload) in attrs {
+        msg.extend_from_slice(&((4 + payload.len()) as u16).to_ne_bytes());
+        msg.extend_from_slice(&kind.to_ne_bytes());
+        msg.extend_from_slice(payload);
+        msg.resize(align(msg.len()), 0);
+    }
+
+    let len = msg.len() as u32;
+    msg[0..4].copy_from_slice(&len.to_ne_bytes());
+    msg[4..6].copy_from_slice(&kind.to_ne_bytes());
+    msg[6..8].copy_from_slice(&(flags as u16).to_ne_bytes());
+    msg[8..12].copy_from_slice(&seq.to_ne_bytes());
+    // the port id stays 0, the message is addressed to the kernel
+    msg
+}
+
+/// Splits the attributes following the family specific header.
+///
+/// # Returns
+///
+/// * `Vec<(u16, &[u8])>` - The type and payload of each attribute,
+///   a truncated attribute ends the list.
+pub fn attributes(data: &[u8]) -> Vec<(u16, &[u8])> {
+    let mut attrs = Vec::new();
+    let mut offset = 0;
+    while offset + 4 <= data.len() {
+        let len = u16::from_ne_bytes([data[offset], data[offset + 1]]) as usize;
+        let kind = u16::from_ne_bytes([data[offset + 2], data[offset + 3]]);
+        if len < 4 || offset + len > data.len() {
+            break;
+        }
+        attrs.push((kind, &data[offset + 4..offset + len]));
+        offset += (len + 3) & !3;
+    }
+
+    attrs
+}
+
+/// Reads an IPv4 address attribute.
+fn ipv4(payload: &[u8]) -> Option<Ipv4Addr> {
+    let octets: [u8; 4] = payload.try_into().ok()?;
+    Some(Ipv4Addr::from(octets))
+}
+
+/// Reads a `u32` attribute.
+fn u32_attr(payload: &[u8]) -> Option<u32> {
+    Some(u32::from_ne_bytes(payload.try_into().ok()?))
+}
+
+/// Decodes a `RTM_NEWROUTE` message (without the `nlmsghdr`) of a dump.
+///
+/// # Returns
+///
+/// * `Option<Route>` - The route, or `None` if it is no IPv4 route.
+pub fn parse_route(msg: &[u8]) -> Option<Route> {
+    if msg.len() < RTMSG_LENGTH || msg[0] != libc::AF_INET as u8 {
+        return None;
+    }
+
+    let mut route = Route {
+        table: msg[4] as u32,
+        dst: (Ipv4Addr::UNSPECIFIED, msg[1]),
+        gateway: None,
+        index: None,
+    };
+    for (kind, payload) in attributes(&msg[RTMSG_LENGTH..]) {
+        match kind {
+            libc::RTA_DST => route.dst.0 = ipv4(payload)?,
+            libc::RTA_GATEWAY => route.gateway = Some(ipv4(payload)?),
+            libc::RTA_OIF => route.index = Some(u32_attr(payload)?),
+            libc::RTA_TABLE => route.table = u32_attr(payload)?,
+            _ => {}
+        }
+    }
+
+    Some(route)
+}
+
+/// Decodes a `RTM_NEWRULE` message (without the `nlmsghdr`) of a dump.
+///
+/// # Returns
+///
+/// * `Option<Rule>` - The rule, or `None` if it is no IPv4 rule
+///   looking up a table.
+pub fn parse_rule(msg: &[u8]) -> Option<Rule> {
+    if msg.len() < RTMSG_LENGTH || msg[0] != libc::AF_INET as u8 || msg[7] != FR_ACT_TO_TBL {
+        return None;
+    }
+
+    let mut rule = Rule {
+        src: (Ipv4Addr::UNSPECIFIED, msg[2]),
+        table: msg[4] as u32,
+        priority: 0,
+    };
+    for (kind, payload) in attributes(&msg[RTMSG_LENGTH..]) {
+        match kind {
+            FRA_SRC => rule.src.0 = ipv4(payload)?,
+            FRA_PRIORITY => rule.priority = u32_attr(payload)?,
+            FRA_TABLE => rule.table = u32_attr(payload)?,
+            _ => {}
+        }
+    }
+
+    Some(rule)
+}
+
+/// Encodes the message dumping all IPv4 entries of a kind
+/// (e.g. `RTM_GETROUTE` or `RTM_GETRULE`).
+pub fn dump_message(seq: u32, kind: u16) -> Vec<u8> {
+    encode(kind, DUMP_FLAGS, seq, &rtmsg(0, 0, 0, 0, 0, 0), &[])
+}
+
+/// Encodes the `rtmsg` (or the layout compatible `fib_rule_hdr`) header.
+fn rtmsg(dst_len: u8, src_len: u8, table: u32, protocol: u8, scope: u8, kind: u8) -> Vec<u8> {
+    // tables beyond 255 are only given in the table attribute
+    let table = if table < 256 { table as u8 } else { 0 };
+    let mut header = vec![
+        libc::AF_INET as u8,
+        dst_len,
+        src_len,
+        0,
+        table,
+        protocol,
+        scope,
+        kind,
+    ];
+    header.extend_from_slice(&0u32.to_ne_bytes());
+    header
+}
+
+/// Encodes the message adding an IPv4 route to a routing table.
+///
+/// # Arguments
+///
+/// * `seq` - The sequence number of the message.
+/// * `table` - The routing table.
+/// * `dst` - The destination network and its prefix length.
+/// * `gateway` - The gateway, or `None` for an on-link route.
+/// * `index` - The index of the outgoing interface.
+pub fn route_message(
+    seq: u32,
+    table: u32,
+    dst: (Ipv4Addr, u8),
+    gateway: Option<Ipv4Addr>,
+    index: u32,
+) -> Vec<u8> {
+    let scope = match gateway {
+        Some(_) => libc::RT_SCOPE_UNIVERSE,
+        None => libc::RT_SCOPE_LINK,
+    };
+    let header = rtmsg(dst.1, 0, table, libc::RTPROT_BOOT, scope, libc::RTN_UNICAST);
+
+    let mut attrs = vec![
+        (libc::RTA_TABLE, table.to_ne_bytes().to_vec()),
+        (libc::RTA_OIF, index.to_ne_bytes().to_vec()),
+    ];
+    if dst.1 > 0 {
+        attrs.push((libc::RTA_DST, dst.0.octets().to_vec()));
+    }
+    if let Some(gateway) = gateway {
+        attrs.push((libc::RTA_GATEWAY, gateway.octets().to_vec()));
+    }
+
+    encode(libc::RTM_NEWROUTE, CREATE_FLAGS, seq, &header, &attrs)
+}
+
+/// Encodes the message adding a rule looking up `table`
+/// for traffic from the source network `src`.
+///
+/// # Arguments
+///
+/// * `seq` - The sequence number of the message.
+/// * `src` - The source network and its prefix length.
+/// * `table` - The routing table to look up.
+/// * `priority` - The priority of the rule, lower is evaluated first.
+pub fn rule_message(seq: u32, src: (Ipv4Addr, u8), table: u32, priority: u32) -> Vec<u8> {
+    // the protocol is an attribute of rules, the header has no field for it
+    let header = rtmsg(0, src.1, table, 0, 0, FR_ACT_TO_TBL);
+
+    let mut attrs = vec![
+        (FRA_TABLE, table.to_ne_bytes().to_vec()),
+        (FRA_PRIORITY, priority.to_ne_bytes().to_vec()),
+        (FRA_PROTOCOL, vec![libc::RTPROT_BOOT]),
+    ];
+    if src.1 > 0 {
+        attrs.push((FRA_SRC, src.0.octets().to_vec()));
+    }
+
+    encode(libc::RTM_NEWRULE, CREATE_FLAGS, seq, &header, &attrs)
+}
+
+/// Encodes the message adding an IPv4 address with lifetimes, or
+/// updating the lifetimes of the address if it is configured already.
+///
+/// The kernel deprecates the address once the preferred lifetime
+/// passed and removes it once the valid lifetime passed.
+///
+/// # Arguments
+///
+/// * `seq` - The sequence number of the message.
+/// * `index` - The index of the interface.
+/// * `addr` - The address and its prefix length.
+/// * `lifetimes` - The preferred and valid lifetime in seconds (see `INFINITE_LIFETIME`).
+pub fn address_message(
+    seq: u32,
+    index: u32,
+    addr: (Ipv4Addr, u8),
+    lifetimes: (u32, u32),
+) -> Vec<u8> {
+    // struct ifaddrmsg
+    let mut header = vec![libc::AF_INET as u8, addr.1, 0, libc::RT_SCOPE_UNIVERSE];
+    header.extend_from_slice(&index.to_ne_bytes());
+
+    // struct ifa_cacheinfo, the timestamps are set by the kernel
+    let mut cacheinfo = Vec::new();
+    cacheinfo.extend_from_slice(&lifetimes.0.to_ne_bytes());
+    cacheinfo.extend_from_slice(&lifetimes.1.to_ne_bytes());
+    cacheinfo.extend_from_slice(&[0; 8]);
+
+    let attrs = vec![
+        (libc::IFA_LOCAL, addr.0.octets().to_vec()),
+        (libc::IFA_ADDRESS, addr.0.octets().to_vec()),
+        (libc::IFA_CACHEINFO, cacheinfo),
+    ];
+
+    encode(libc::RTM_NEWADDR, REPLACE_FLAGS, seq, &header, &attrs)
+}
+
+/// A `NETLINK_ROUTE` socket.
+pub struct Netlink {
+    fd: i32,
+    seq: u32,
+}
+
+impl Drop for Netlink {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.fd) };
+    }
+}
+
+impl Netlink {
+    pub fn new() -> Result<Netlink, NetworkConfigurationError> {
+        let fd = unsafe {
+            libc::socket(
+                libc::AF_NETLINK,
+                libc::SOCK_RAW | libc::SOCK_CLOEXEC,
+                libc::NETLINK_ROUTE,
+            )
+        };
+        if fd < 0 {
+            let err = Errno::last();
+            return Err(NetworkConfigurationError::from_errno(
+                format!("Failed to create netlink socket: {}", err),
+                err,
+            ));
+        }
+
+        Ok(Netlink { fd, seq: 0 })
+    }
+
+    /// Sends a request to the kernel and waits for the acknowledgement.
+    fn request(&mut self, msg: &[u8]) -> Result<(), Errno> {
+        let mut addr: libc::sockaddr_nl = unsafe { mem::zeroed() };
+        addr.nl_family = libc::AF_NETLINK as u16;
+
+        let sent = unsafe {
+            libc::sendto(
+                self.fd,
+                msg.as_ptr() as *const libc::c_void,
+                msg.len(),
+                0,
+                &addr as *const libc::sockaddr_nl as *const libc::sockaddr,
+                mem::size_of::<libc::sockaddr_nl>() as u32,
+            )
+        };
+        if sent < 0 {
+            return Err(Errno::last());
+        }
+
+        let mut buf = [0u8; 4096];
+        loop {
+            let len =
+                unsafe { libc::recv(self.fd, buf.as_mut_ptr() as *mut libc::c_void, buf.len(), 0) };
+            if len < 0 {
+                match Errno::last() {
+                    Errno::EINTR => continue,
+                    err => return Err(err),
+                }
+            }
+
+            let len = len as usize;
+            let mut offset = 0;
+            while offset + HEADER_LENGTH <= len {
+                let msg_len =
+                    u32::from_ne_bytes(buf[offset..offset + 4].try_into().unwrap()) as usize;
+                let kind = u16::from_ne_bytes(buf[offset + 4..offset + 6].try_into().unwrap());
+                let seq = u32::from_ne_bytes(buf[offset + 8..offset + 12].try_into().unwrap());
+                if kind as libc::c_int == libc::NLMSG_ERROR
+                    && seq == self.seq
+                    && offset + HEADER_LENGTH + 4 <= len
+                {
+                    let error = i32::from_ne_bytes(
+                        buf[offset + HEADER_LENGTH..offset + HEADER_LENGTH + 4]
+                            .try_into()
+                            .unwrap(),
+                    );
+                    return match error {
+                        0 => Ok(()),
+                        error => Err(Errno::from_i32(-error)),
+                    };
+                }
+                if msg_len < HEADER_LENGTH {
+                    break;
+                }
+                offset += (msg_len + 3) & !3;
+            }
+        }
+    }
+
+    /// Sends a dump request and collects the answers.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Vec<Vec<u8>>, Errno>` - The messages of the dump,
+    ///   without their `nlmsghdr`.
+    fn dump(&mut self, kind: u16) -> Result<Vec<Vec<u8>>, Errno> {
+        self.seq += 1;
+        let msg = dump_message(self.seq, kind);
+        let mut addr: libc::sockaddr_nl = unsafe { mem::zeroed() };
+        addr.nl_family = libc::AF_NETLINK as u16;
+
+        let sent = unsafe {
+            libc::sendto(
+                self.fd,
+                msg.as_ptr() as *const libc::c_void,
+                msg.len(),
+                0,
+                &addr as *const libc::sockaddr_nl as *const libc::sockaddr,
+                mem::size_of::<libc::sockaddr_nl>() as u32,
+            )
+        };
+        if sent < 0 {
+            return Err(Errno::last());
+        }
+
+        let mut messages = Vec::new();
+        let mut buf = vec![0u8; 32768];
+        loop {
+            let len =
+                unsafe { libc::recv(self.fd, buf.as_mut_ptr() as *mut libc::c_void, buf.len(), 0) };
+            if len < 0 {
+                match Errno::last() {
+                    Errno::EINTR => continue,
+                    err => return Err(err),
+                }
+            }
+
+            let len = len as usize;
+            let mut offset = 0;
+            while offset + HEADER_LENGTH <= len {
+                let msg_len =
+                    u32::from_ne_bytes(buf[offset..offset + 4].try_into().unwrap()) as usize;
+                let kind = u16::from_ne_bytes(buf[offset + 4..offset + 6].try_into().unwrap());
+                let seq = u32::from_ne_bytes(buf[offset + 8..offset + 12].try_into().unwrap());
+                if msg_len < HEADER_LENGTH || offset + msg_len > len {
+                    break;
+                }
+                if seq == self.seq {
+                    match kind as libc::c_int {
+                        libc::NLMSG_DONE => return Ok(messages),
+                        libc::NLMSG_ERROR if msg_len >= HEADER_LENGTH + 4 => {
+                            let error = i32::from_ne_bytes(
+                                buf[offset + HEADER_LENGTH..offset + HEADER_LENGTH + 4]
+                                    .try_into()
+                                    .unwrap(),
+                            );
+                            if error != 0 {
+                                return Err(Errno::from_i32(-error));
+                            }
+                        }
+                        _ => messages.push(buf[offset + HEADER_LENGTH..offset + msg_len].to_vec()),
+                    }
+                }
+                offset += (msg_len + 3) & !3;
+            }
+        }
+    }
+
+    /// Dumps the IPv4 routes of all tables.
+    pub fn routes(&mut self) -> Result<Vec<Route>, NetworkConfigurationError> {
+        match self.dump(libc::RTM_GETROUTE) {
+            Ok(messages) => Ok(messages.iter().filter_map(|msg| parse_route(msg)).collect()),
+            Err(err) => Err(NetworkConfigurationError::from_errno(
+                format!("Failed to dump routes: {}", err),
+                err,
+            )),
+        }
+    }
+
+    /// Dumps the IPv4 routing rules.
+    pub fn rules(&mut self) -> Result<Vec<Rule>, NetworkConfigurationError> {
+        match self.dump(libc::RTM_GETRULE) {
+            Ok(messages) => Ok(messages.iter().filter_map(|msg| parse_rule(msg)).collect()),
+            Err(err) => Err(NetworkConfigurationError::from_errno(
+                format!("Failed to dump rules: {}", err),
+                err,
+            )),
+        }
+    }
+
+    /// Sends a request, treating an already existing entry as success.
+    fn add(
+        &mut self,
+        what: &str,
+        msg: impl Fn(u32) -> Vec<u8>,
+    ) -> Result<(), NetworkConfigurationError> {
+        self.seq += 1;
+        match self.request(&msg(self.seq)) {
+            Ok(()) | Err(Errno::EEXIST) => Ok(()),
+            Err(err) => Err(NetworkConfigurationError::from_errno(
+                format!("Failed to add {}: {}", what, err),
+                err,
+            )),
+        }
+    }
+
+    /// Adds an IPv4 route to a routing table (see `route_message`).
+    pub fn add_route_to_table(
+        &mut self,
+        table: u32,
+        dst: (Ipv4Addr, u8),
+        gateway: Option<Ipv4Addr>,
+        index: u32,
+    ) -> Result<(), NetworkConfigurationError> {
+        let what = match gateway {
+            Some(gateway) => format!(
+                "route {}/{} via {} to table {}",
+                dst.0, dst.1, gateway, table
+            ),
+            None => format!("route {}/{} to table {}", dst.0, dst.1, table),
+        };
+        self.add(&what, |seq| route_message(seq, table, dst, gateway, index))
+    }
+
+    /// Adds a rule looking up `table` for traffic from `src` (see `rule_message`).
+    pub fn add_rule(
+        &mut self,
+        src: (Ipv4Addr, u8),
+        table: u32,
+        priority: u32,
+    ) -> Result<(), NetworkConfigurationError> {
+        let what = format!("rule from {}/{} lookup {}", src.0, src.1, table);
+        self.add(&what, |seq| rule_message(seq, src, table, priority))
+    }
+
+    /// Sets the lifetimes of an IPv4 address (see `address_message`).
+    pub fn set_address_lifetimes(
+        &mut self,
+        index: u32,
+        addr: (Ipv4Addr, u8),
+        lifetimes: (u32, u32),
+    ) -> Result<(), NetworkConfigurationError> {
+        let what = format!(
+            "lifetimes {}s/{}s of address {}/{}",
+            lifetimes.0, lifetimes.1, addr.0, addr.1
+        );
+        self.add(&what, |seq| address_message(seq, index, addr, lifetimes))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Encodes the `nlmsghdr` of a message addressed to the kernel.
+    fn nlmsghdr(len: u32, kind: u16, flags: i32, seq: u32) -> Vec<u8> {
+        let mut header = Vec::new();
+        header.extend_from_slice(&len.to_ne_bytes());
+        header.extend_from_slice(&kind.to_ne_bytes());
+        header.extend_from_slice(&(flags as u16).to_ne_bytes());
+        header.extend_from_slice(&seq.to_ne_bytes());
+        header.extend_from_slice(&0u32.to_ne_bytes());
+        header
+    }
+
+    /// Encodes a 4 byte attribute.
+    fn attr(kind: u16, payload: [u8; 4]) -> Vec<u8> {
+        let mut attr = Vec::new();
+        attr.extend_from_slice(&8u16.to_ne_bytes());
+        attr.extend_from_slice(&kind.to_ne_bytes());
+        attr.extend_from_slice(&payload);
+        attr
+    }
+
+    #[test]
+    fn encodes_rule_message() {
+        let msg = rule_message(7, (Ipv4Addr::new(10, 0, 0, 0), 24), 100, 1000);
+
+        let mut expected = nlmsghdr(60, libc::RTM_NEWRULE, CREATE_FLAGS, 7);
+        // fib_rule_hdr: family, dst_len, src_len, tos, table, res1, res2, action, flags
+        expected.extend_from_slice(&[libc::AF_INET as u8, 0, 24, 0, 100, 0, 0]);
+        expected.extend_from_slice(&[FR_ACT_TO_TBL, 0, 0, 0, 0]);
+        expected.extend(attr(FRA_TABLE, 100u32.to_ne_bytes()));
+        expected.extend(attr(FRA_PRIORITY, 1000u32.to_ne_bytes()));
+        // a single byte, padded to 4 bytes
+        expected.extend_from_slice(&5u16.to_ne_bytes());
+        expected.extend_from_slice(&FRA_PROTOCOL.to_ne_bytes());
+        expected.extend_from_slice(&[libc::RTPROT_BOOT, 0, 0, 0]);
+        expected.extend(attr(FRA_SRC, [10, 0, 0, 0]));
+        assert_eq!(msg, expected);
+
+        assert_eq!(
+            parse_rule(&msg[HEADER_LENGTH..]),
+            Some(Rule {
+                src: (Ipv4Addr::new(10, 0, 0, 0), 24),
+                table: 100,
+                priority: 1000,
+            })
+        );
+    }
+
+    #[test]
+    fn encodes_route_message() {
+        let gateway = Ipv4Addr::new(10, 0, 0, 1);
+        let msg = route_message(1, 1000, (Ipv4Addr::new(10, 0, 1, 0), 24), Some(gateway), 3);
+
+        let mut expected = nlmsghdr(60, libc::RTM_NEWROUTE, CREATE_FLAGS, 1);
+        // rtmsg: family, dst_len, src_len, tos, table, protocol, scope, type, flags
+        expected.extend_from_slice(&[libc::AF_INET as u8, 24, 0, 0]);
+        // tables beyond 255 are only in the attribute
+        expected.extend_from_slice(&[0, libc::RTPROT_BOOT, libc::RT_SCOPE_UNIVERSE]);
+        expected.extend_from_slice(&[libc::RTN_UNICAST, 0, 0, 0, 0]);
+        expected.extend(attr(libc::RTA_TABLE, 1000u32.to_ne_bytes()));
+        expected.extend(attr(libc::RTA_OIF, 3u32.to_ne_bytes()));
+        expected.extend(attr(libc::RTA_DST, [10, 0, 1, 0]));
+        expected.extend(attr(libc::RTA_GATEWAY, gateway.octets()));
+        assert_eq!(msg, expected);
+
+        assert_eq!(
+            parse_route(&msg[HEADER_LENGTH..]),
+            Some(Route {
+                table: 1000,
+                dst: (Ipv4Addr::new(10, 0, 1, 0), 24),
+                gateway: Some(gateway),
+                index: Some(3),
+            })
+        );
+    }
+
+    #[test]
+    fn encodes_address_lifetimes() {
+        let msg = address_message(3, 2, (Ipv4Addr::new(10, 0, 0, 2), 24), (3150, 3600));
+
+        let mut expected = nlmsghdr(60, libc::RTM_NEWADDR, REPLACE_FLAGS, 3);
+        // ifaddrmsg: family, prefixlen, flags, scope, index
+        expected.extend_from_slice(&[libc::AF_INET as u8, 24, 0, libc::RT_SCOPE_UNIVERSE]);
+        expected.extend_from_slice(&2u32.to_ne_bytes());
+        expected.extend(attr(libc::IFA_LOCAL, [10, 0, 0, 2]));
+        expected.extend(attr(libc::IFA_ADDRESS, [10, 0, 0, 2]));
+        // ifa_cacheinfo: preferred, valid, created and updated timestamps
+        expected.extend_from_slice(&20u16.to_ne_bytes());
+        expected.extend_from_slice(&libc::IFA_CACHEINFO.to_ne_bytes());
+        expected.extend_from_slice(&3150u32.to_ne_bytes());
+        expected.extend_from_slice(&3600u32.to_ne_bytes());
+        expected.extend_from_slice(&[0; 8]);
+        assert_eq!(msg, expected);
+    }
+
+    #[test]
+    fn default_on_link_route_has_no_destination() {
+        let msg = route_message(1, 100, (Ipv4Addr::UNSPECIFIED, 0), None, 3);
+
+        assert_eq!(msg.len(), HEADER_LENGTH + RTMSG_LENGTH + 16);
+        assert_eq!(msg[HEADER_LENGTH + 6], libc::RT_SCOPE_LINK);
+        let kinds = attributes(&msg[HEADER_LENGTH + RTMSG_LENGTH..])
+            .into_iter()
+            .map(|(kind, _)| kind)
+            .collect::<Vec<u16>>();
+        assert_eq!(kinds, [libc::RTA_TABLE, libc::RTA_OIF]);
+    }
+}
@@ -1,57 +1,1132 @@
 /// Network configuration daemon.
 use std::{
-    fs,
     net::{IpAddr, Ipv4Addr},
+    path::Path,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
 };
 
-use log::{debug, error, trace};
-use pnet::datalink;
+use log::{debug, error, info, trace, warn};
+use pnet::{
+    datalink::{self, NetworkInterface},
+    util::MacAddr,
+};
+use serde::Serialize;
 
-use crate::net::iface::NetworkInterfaceConfigApply;
+use crate::{
+    fs::{atomic_write, write_config_file},
+    net::{dhcp::DhcpConfig, iface::NetworkInterfaceConfigApply},
+};
 
 use super::{
-    iface::{DynamicNetworkInterfaceConfig, NetworkInterfaceConfig, StaticNetworkInterfaceConfig},
-    NetworkConfigurationError,
+    config::{self, merge, NETWORK_CONFIG, NETWORK_CONFIG_DIR},
+    err::ErrorKind,
+    iface::{
+        sort_by_priority, DynamicNetworkInterfaceConfig, NetworkInterfaceConfig,
+        StaticNetworkInterfaceConfig, LOOPBACK,
+    },
+    iface_config::{self, ConfigSocket, HOSTS, NSSWITCH_CONF},
+    lease::DhcpLease,
+    monitor, NetworkConfigurationError,
 };
 
-pub fn configure_network() -> Result<(), NetworkConfigurationError> {
-    // TODO: read from config file
-    let network_config = vec![
-        NetworkInterfaceConfig::Static(StaticNetworkInterfaceConfig {
-            name: "lo".to_string(),
+/// File the resolved network configuration is written to.
+pub const NETWORK_STATE: &str = "/run/linos/network-state.json";
+
+/// The outcome of configuring a single interface, as written to `NETWORK_STATE`.
+#[derive(Debug, Serialize)]
+struct InterfaceState<'a> {
+    #[serde(flatten)]
+    config: &'a NetworkInterfaceConfig,
+    configured: bool,
+    /// The effective static configuration, for dynamic
+    /// interfaces as obtained from the DHCP lease.
+    effective: Option<StaticNetworkInterfaceConfig>,
+}
+
+/// Serializes the network configurations, as the network can
+/// be reconfigured while it is being configured during boot.
+static CONFIGURE: Mutex<()> = Mutex::new(());
+
+/// The addresses linµos configured itself, by interface name.
+static CONFIGURED: Mutex<Vec<(String, Ipv4Addr)>> = Mutex::new(Vec::new());
+
+/// Applies the configuration of a single interface, serialized with
+/// the configuration of the whole network (e.g. by the carrier monitor).
+pub fn apply_interface(config: &NetworkInterfaceConfig) -> Result<(), NetworkConfigurationError> {
+    let _lock = CONFIGURE.lock().unwrap_or_else(|err| err.into_inner());
+    config.apply()
+}
+
+/// Number of attempts to configure an interface failing transiently.
+const CONFIGURE_ATTEMPTS: u32 = 3;
+
+/// Delay before retrying to configure an interface, growing with each attempt.
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// Time the link of a freshly enabled interface gets to come up.
+const CARRIER_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Interval in which the carrier is checked while waiting for it.
+const CARRIER_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Interface configured using DHCP if there is no configuration file.
+pub const DEFAULT_DHCP_IFACE: &str = "eth0";
+
+/// Number of interfaces requesting a DHCP lease in parallel by default.
+pub const DEFAULT_DHCP_PARALLEL: usize = 4;
+
+/// Settings of the network configuration.
+#[derive(Debug, Clone)]
+pub struct NetworkOptions {
+    /// Settings used for the dynamically configured interfaces.
+    pub dhcp: DhcpConfig,
+    /// Selects the interface configured using DHCP if there is no
+    /// configuration file (see `select_dhcp_iface`), `eth0` by default.
+    pub dhcp_iface: Option<String>,
+    /// The mac address of the interface the system was PXE booted
+    /// from (`BOOTIF=`), preferred over `dhcp_iface` if it exists.
+    pub boot_if: Option<MacAddr>,
+    /// The maximum number of interfaces requesting a DHCP lease in parallel.
+    pub dhcp_parallel: usize,
+    /// DNS search domains added to every interface (`dnssearch=`).
+    pub dns_search: Vec<String>,
+    /// Reconfigures interfaces which already have an address, e.g.
+    /// assigned by the kernel (`ip=`), instead of keeping it.
+    pub force: bool,
+    /// Reconfigures interfaces whose link comes back up after boot.
+    pub monitor: bool,
+    /// Writes a minimal `/etc/nsswitch.conf` if there is none
+    /// (only needed for glibc based images).
+    pub nsswitch: bool,
+    /// Fails the network configuration if no
+    /// non-loopback interface could be configured.
+    pub required: bool,
+}
+
+impl Default for NetworkOptions {
+    fn default() -> NetworkOptions {
+        NetworkOptions {
+            dhcp: DhcpConfig::default(),
+            dhcp_iface: None,
+            boot_if: None,
+            dhcp_parallel: DEFAULT_DHCP_PARALLEL,
+            dns_search: Vec::new(),
+            force: false,
+            monitor: false,
+            nsswitch: false,
+            required: false,
+        }
+    }
+}
+
+/// Requests the leases of the dynamically configured interfaces in parallel.
+///
+/// At most `parallel` interfaces request a lease at the same time,
+/// the remaining ones are queued.
+///
+/// # Arguments
+///
+/// * `configs` - The configs of the dynamically configured interfaces.
+/// * `parallel` - The maximum number of parallel requests.
+/// * `request` - Requests the lease of a single interface
+///   (`DynamicNetworkInterfaceConfig::request_lease`).
+///
+/// # Returns
+///
+/// * `Vec<Result<DhcpLease, NetworkConfigurationError>>` - The results,
+///   in the order of `configs`.
+fn request_leases(
+    configs: &[&DynamicNetworkInterfaceConfig],
+    parallel: usize,
+    request: impl Fn(&DynamicNetworkInterfaceConfig) -> Result<DhcpLease, NetworkConfigurationError>
+        + Sync,
+) -> Vec<Result<DhcpLease, NetworkConfigurationError>> {
+    let workers = parallel.clamp(1, configs.len().max(1));
+    if configs.len() > workers {
+        info!(
+            "Requesting {} DHCP leases, {} queued (at most {} in parallel)",
+            configs.len(),
+            configs.len() - workers,
+            workers
+        );
+    }
+
+    // each worker takes the next config not yet requested
+    let next = AtomicUsize::new(0);
+    let results = Mutex::new(configs.iter().map(|_| None).collect::<Vec<_>>());
+    thread::scope(|scope| {
+        for _ in 0..workers {
+            scope.spawn(|| loop {
+                let i = next.fetch_add(1, Ordering::SeqCst);
+                let Some(config) = configs.get(i) else {
+                    break;
+                };
+                trace!("Requesting DHCP lease for '{}'", config.name);
+                let result = request(config);
+                results.lock().unwrap_or_else(|err| err.into_inner())[i] = Some(result);
+            });
+        }
+    });
+
+    results
+        .into_inner()
+        .unwrap_or_else(|err| err.into_inner())
+        .into_iter()
+        .map(|result| {
+            result.unwrap_or_else(|| {
+                Err(NetworkConfigurationError::new(
+                    "DHCP request was not run".to_string(),
+                ))
+            })
+        })
+        .collect()
+}
+
+/// Enables the interfaces and waits for their link to come up.
+///
+/// Requesting a lease on an interface without a cable plugged in only
+/// waits for the DHCP timeout, such interfaces are skipped. They stay
+/// enabled, so the carrier monitor notices once their link comes up.
+///
+/// # Arguments
+///
+/// * `names` - The names of the interfaces.
+/// * `carrier` - Reads the carrier of an interface (see `monitor::read_carrier`).
+/// * `timeout` - The time the links get to come up.
+///
+/// # Returns
+///
+/// * `Vec<&str>` - The interfaces without carrier. Interfaces with an
+///   unknown carrier are not part of it, as DHCP might still work on them.
+fn without_carrier<'a>(
+    names: &[&'a str],
+    carrier: impl Fn(&str) -> Option<bool>,
+    timeout: Duration,
+) -> Vec<&'a str> {
+    for name in names {
+        let enabled = ConfigSocket::new(name.to_string()).and_then(|config| config.enable(true));
+        if let Err(err) = enabled {
+            // requesting the lease reports the error
+            debug!("Failed enabling '{}': {}", name, err);
+        }
+    }
+
+    let start = Instant::now();
+    loop {
+        let missing = names
+            .iter()
+            .copied()
+            .filter(|name| carrier(name) == Some(false))
+            .collect::<Vec<&str>>();
+        if missing.is_empty() || start.elapsed() >= timeout {
+            return missing;
+        }
+        thread::sleep(CARRIER_POLL_INTERVAL);
+    }
+}
+
+/// Returns the address an interface already has, e.g. as configured
+/// by the kernel (`ip=`). Link-local addresses are ignored.
+fn preset_address(name: &str) -> Option<Ipv4Addr> {
+    ConfigSocket::new(name.to_string())
+        .ok()?
+        .get_ip()
+        .ok()?
+        .filter(|ip| !ip.is_unspecified() && !ip.is_link_local())
+}
+
+/// Checks if the address an interface already has is kept.
+///
+/// Only addresses linµos did not configure itself (e.g. configured by the
+/// kernel) are kept, reconfiguring the network replaces its own addresses.
+///
+/// # Arguments
+///
+/// * `name` - The name of the interface.
+/// * `preset` - The address the interface has (see `preset_address`).
+/// * `force` - Whether every address is reconfigured (`net.force=1`).
+/// * `configured` - The addresses linµos configured (see `CONFIGURED`).
+fn keeps_address(
+    name: &str,
+    preset: Option<Ipv4Addr>,
+    force: bool,
+    configured: &[(String, Ipv4Addr)],
+) -> bool {
+    match preset {
+        Some(ip) => {
+            !force
+                && !configured
+                    .iter()
+                    .any(|(iface, addr)| iface == name && *addr == ip)
+        }
+        None => false,
+    }
+}
+
+/// Parses the `BOOTIF=` argument passed by PXE bootloaders.
+///
+/// The value is the hardware type followed by the mac address of the
+/// interface the system was booted from, separated by dashes
+/// (e.g. `01-52-54-00-12-34-56` for an ethernet interface).
+pub fn parse_bootif(value: &str) -> Option<MacAddr> {
+    let (_hardware_type, mac) = value.split_once('-')?;
+    mac.replace('-', ":").parse::<MacAddr>().ok()
+}
+
+/// Selects the interface to configure using DHCP.
+///
+/// The selection is one of:
+///
+/// * `auto` - the first non-loopback interface with carrier,
+///   or the first non-loopback interface if none has carrier.
+/// * a mac address (e.g. `52:54:00:12:34:56`) - the interface with this address.
+/// * a name pattern ending in `*` (e.g. `en*`) - the first interface matching it.
+/// * an interface name (e.g. `eth0`).
+///
+/// # Arguments
+///
+/// * `selection` - The selection, see above.
+/// * `interfaces` - The available interfaces.
+/// * `carrier` - Whether an interface has carrier.
+///
+/// # Returns
+///
+/// * `Option<(String, String)>` - The selected interface and why it was selected.
+pub fn select_dhcp_iface(
+    selection: &str,
+    interfaces: &[NetworkInterface],
+    carrier: impl Fn(&str) -> bool,
+) -> Option<(String, String)> {
+    let mut candidates = interfaces.iter().filter(|iface| !iface.is_loopback());
+
+    if selection == "auto" {
+        let candidates = candidates.collect::<Vec<&NetworkInterface>>();
+        return match candidates.iter().find(|iface| carrier(&iface.name)) {
+            Some(iface) => Some((
+                iface.name.clone(),
+                "first interface with carrier".to_string(),
+            )),
+            None => candidates.first().map(|iface| {
+                (
+                    iface.name.clone(),
+                    "first interface, none has carrier".to_string(),
+                )
+            }),
+        };
+    }
+
+    if let Ok(mac) = selection.parse::<MacAddr>() {
+        return candidates
+            .find(|iface| iface.mac == Some(mac))
+            .map(|iface| (iface.name.clone(), format!("has mac address {}", mac)));
+    }
+
+    if let Some(prefix) = selection.strip_suffix('*') {
+        return candidates
+            .find(|iface| iface.name.starts_with(prefix))
+            .map(|iface| (iface.name.clone(), format!("matches '{}'", selection)));
+    }
+
+    Some((selection.to_string(), "configured by name".to_string()))
+}
+
+/// Assembles the configs of all interfaces to configure.
+///
+/// # Arguments
+///
+/// * `options` - The settings of the network configuration.
+pub fn interface_configs(options: &NetworkOptions) -> Vec<NetworkInterfaceConfig> {
+    // loopback is always configured, the remaining interfaces come from
+    // the config files, falling back to dhcp on a single interface
+    let mut network_config = vec![NetworkInterfaceConfig::Static(
+        StaticNetworkInterfaceConfig {
+            name: LOOPBACK.to_string(),
             ip: IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
             netmask: IpAddr::V4(Ipv4Addr::new(255, 0, 0, 0)),
             gateway: IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
-            dns: None,
-        }),
-        NetworkInterfaceConfig::Dynamic(DynamicNetworkInterfaceConfig {
-            name: "eth0".to_string(),
-        }),
-    ];
+            dns: Vec::new(),
+            search: Vec::new(),
+            priority: 0,
+            aliases: Vec::new(),
+            neighbors: Vec::new(),
+            routing: None,
+        },
+    )];
+    match config::load(Path::new(NETWORK_CONFIG), Path::new(NETWORK_CONFIG_DIR)) {
+        Some(configs) => configs
+            .into_iter()
+            .for_each(|config| merge(&mut network_config, config)),
+        None => network_config.push(NetworkInterfaceConfig::Dynamic(
+            DynamicNetworkInterfaceConfig {
+                name: dhcp_iface(options.dhcp_iface.as_deref(), options.boot_if),
+                dhcp: DhcpConfig::default(),
+                search: Vec::new(),
+                priority: 0,
+                routing: None,
+                requested_lease_time: None,
+                lease_lifetimes: false,
+            },
+        )),
+    }
+    network_config.iter_mut().for_each(|config| {
+        let search = match config {
+            NetworkInterfaceConfig::Dynamic(cfg) => {
+                cfg.dhcp = dhcp_config(&options.dhcp, cfg);
+                &mut cfg.search
+            }
+            NetworkInterfaceConfig::Static(cfg) => &mut cfg.search,
+        };
+        options.dns_search.iter().for_each(|domain| {
+            if !search.contains(domain) {
+                search.push(domain.clone());
+            }
+        });
+    });
+    sort_by_priority(&mut network_config);
+
+    network_config
+}
+
+/// Returns the DHCP settings of an interface: the settings of all
+/// interfaces, with the lease time requested for the interface.
+///
+/// # Arguments
+///
+/// * `dhcp` - The settings of all interfaces (see `NetworkOptions`).
+/// * `config` - The config of the interface.
+fn dhcp_config(dhcp: &DhcpConfig, config: &DynamicNetworkInterfaceConfig) -> DhcpConfig {
+    DhcpConfig {
+        requested_lease_time: config.requested_lease_time.or(dhcp.requested_lease_time),
+        ..dhcp.clone()
+    }
+}
+
+/// Returns the name of the interface to configure using DHCP
+/// if there is no configuration file.
+///
+/// The interface the system was PXE booted from is preferred,
+/// if it exists, over the interface selected with `dhcp.iface=`.
+fn dhcp_iface(selection: Option<&str>, boot_if: Option<MacAddr>) -> String {
+    if let Some(mac) = boot_if {
+        let interfaces = datalink::interfaces();
+        match select_dhcp_iface(&mac.to_string(), &interfaces, |_| false) {
+            Some((name, _)) => {
+                info!("Using '{}' for DHCP: booted from {} (BOOTIF)", name, mac);
+                return name;
+            }
+            None => warn!("No interface has the BOOTIF mac address {}", mac),
+        }
+    }
+
+    let selection = match selection {
+        Some(selection) => selection,
+        None => return DEFAULT_DHCP_IFACE.to_string(),
+    };
 
+    let carrier = |name: &str| monitor::read_carrier(name).unwrap_or(false);
+    match select_dhcp_iface(selection, &datalink::interfaces(), carrier) {
+        Some((name, reason)) => {
+            info!("Using '{}' for DHCP: {}", name, reason);
+            name
+        }
+        None => {
+            warn!(
+                "No interface matches 'dhcp.iface={}', using '{}'",
+                selection, DEFAULT_DHCP_IFACE
+            );
+            DEFAULT_DHCP_IFACE.to_string()
+        }
+    }
+}
+
+/// Writes the system files of the network configuration, `/etc/hosts`,
+/// the DNS configuration of the configured interfaces to
+/// `/etc/resolv.conf` and `/etc/nsswitch.conf` if requested.
+///
+/// Called again after switching the root (e.g. to an NFS root), as the
+/// interfaces configured before are kept, but the files are not.
+pub fn write_system_files(options: &NetworkOptions) -> Result<(), NetworkConfigurationError> {
     let hosts = "127.0.0.1 localhost\n::1 localhost\n";
-    if let Err(err) = fs::write("/etc/hosts", hosts) {
+    if let Err(err) = write_config_file(HOSTS, hosts) {
         return Err(NetworkConfigurationError::new(format!(
-            "Failed configuring '/etc/hosts': {}",
-            err
+            "Failed configuring '{}': {}",
+            HOSTS, err
         )));
     }
+    iface_config::write_resolv_conf()?;
 
-    for config in network_config {
-        trace!("Applying config {:?}", config);
-        if let Err(err) = config.apply() {
-            let name = match config {
-                NetworkInterfaceConfig::Dynamic(cfg) => cfg.name,
-                NetworkInterfaceConfig::Static(cfg) => cfg.name,
-            };
-            error!("Failed configuring '{}': {}", name, err);
-            // TODO: retry config ...
+    if options.nsswitch {
+        write_nsswitch();
+    }
+
+    Ok(())
+}
+
+/// Configures all network interfaces.
+///
+/// # Arguments
+///
+/// * `options` - The settings of the network configuration.
+///
+/// # Returns
+///
+/// * `Result<Vec<DhcpLease>, NetworkConfigurationError>` - The leases
+///   obtained for the dynamically configured interfaces.
+pub fn configure_network(
+    options: &NetworkOptions,
+) -> Result<Vec<DhcpLease>, NetworkConfigurationError> {
+    // a panicked configuration leaves nothing to protect
+    let _lock = CONFIGURE.lock().unwrap_or_else(|err| err.into_inner());
+    let network_config = interface_configs(options);
+
+    write_system_files(options)?;
+
+    for bridge in config::load_bridges(Path::new(NETWORK_CONFIG), Path::new(NETWORK_CONFIG_DIR)) {
+        debug!("Creating bridge '{}' of {:?}", bridge.name, bridge.members);
+        if let Err(err) = bridge.create() {
+            error!("Failed creating bridge '{}': {}", bridge.name, err);
+        }
+    }
+
+    let interfaces = datalink::interfaces();
+    let is_loopback = |name: &str| {
+        interfaces
+            .iter()
+            .any(|iface| iface.name == name && iface.is_loopback())
+    };
+
+    let monitored = network_config
+        .iter()
+        .filter(|config| !is_loopback(config.name()))
+        .cloned()
+        .collect::<Vec<NetworkInterfaceConfig>>();
+
+    // interfaces which already have an address are kept, unless forced
+    let mut kept = Vec::new();
+    let mut pending = Vec::new();
+    {
+        let configured = CONFIGURED.lock().unwrap_or_else(|err| err.into_inner());
+        for config in network_config {
+            let preset = preset_address(config.name());
+            match preset {
+                Some(ip)
+                    if !is_loopback(config.name())
+                        && keeps_address(config.name(), preset, options.force, &configured) =>
+                {
+                    info!(
+                        "Keeping address {} of '{}' (net.force=1 reconfigures it)",
+                        ip,
+                        config.name()
+                    );
+                    kept.push(config);
+                }
+                _ => pending.push(config),
+            }
         }
     }
 
+    let mut states = kept
+        .iter()
+        .map(|config| InterfaceState {
+            config,
+            configured: true,
+            effective: None,
+        })
+        .collect::<Vec<InterfaceState>>();
+    let (leases, pending_states, configured) =
+        apply_configs(&pending, options, &interfaces, apply_config);
+    states.extend(pending_states);
+
     datalink::interfaces().iter().for_each(|iface| {
         debug!("Configured interfaces: {:?}", iface);
     });
 
+    write_state(&states);
+
+    check_required(options.required, kept.len() + configured)?;
+
+    if options.monitor {
+        debug!("Monitoring carrier of {} interfaces", monitored.len());
+        monitor::spawn(monitored);
+    }
+
+    Ok(leases)
+}
+
+/// Applies the configs of the interfaces not kept, in order.
+///
+/// Interfaces disappearing or failing transiently are retried.
+///
+/// # Arguments
+///
+/// * `pending` - The configs to apply.
+/// * `options` - The settings of the network configuration.
+/// * `interfaces` - The interfaces present before applying the configs.
+/// * `apply` - Applies a single config, with the lease requested
+///   for it if it is dynamic (see `apply_config`).
+///
+/// # Returns
+///
+/// * The obtained leases, the outcome of each config and the number
+///   of configured non-loopback interfaces.
+fn apply_configs<'a>(
+    pending: &'a [NetworkInterfaceConfig],
+    options: &NetworkOptions,
+    interfaces: &[NetworkInterface],
+    apply: impl Fn(
+        &NetworkInterfaceConfig,
+        Option<Result<DhcpLease, NetworkConfigurationError>>,
+    ) -> Result<
+        (StaticNetworkInterfaceConfig, Option<DhcpLease>),
+        NetworkConfigurationError,
+    >,
+) -> (Vec<DhcpLease>, Vec<InterfaceState<'a>>, usize) {
+    let is_loopback = |name: &str| {
+        interfaces
+            .iter()
+            .any(|iface| iface.name == name && iface.is_loopback())
+    };
+
+    // the leases are requested once the first dynamic config is reached,
+    // so loopback (sorted first) is up before waiting for carriers and
+    // leases, software started meanwhile may bind to 127.0.0.1
+    let mut dhcp: Option<(Vec<String>, std::vec::IntoIter<_>)> = None;
+
+    let mut leases = Vec::new();
+    let mut states = Vec::new();
+    let mut configured = 0;
+    for config in pending {
+        let requested = match config {
+            NetworkInterfaceConfig::Dynamic(cfg) => {
+                let (no_carrier, requested) =
+                    dhcp.get_or_insert_with(|| dhcp_leases(pending, options.dhcp_parallel));
+                if no_carrier.contains(&cfg.name) {
+                    info!("Skipping DHCP on '{}', it has no carrier", config.name());
+                    states.push(InterfaceState {
+                        config,
+                        configured: false,
+                        effective: None,
+                    });
+                    continue;
+                }
+                requested.next()
+            }
+            NetworkInterfaceConfig::Static(_) => None,
+        };
+        trace!("Applying config {:?}", config);
+        let mut result = apply(config, requested);
+        let mut target = config.clone();
+        let mut attempt = 1;
+        while let Err(err) = &result {
+            if attempt >= CONFIGURE_ATTEMPTS {
+                break;
+            }
+            if err.interface_disappeared() {
+                thread::sleep(RETRY_DELAY * attempt);
+                attempt += 1;
+                let mac = interfaces
+                    .iter()
+                    .find(|iface| iface.name == config.name())
+                    .and_then(|iface| iface.mac);
+                match reappeared(target.name(), mac, &datalink::interfaces()) {
+                    Some(name) if name != target.name() => {
+                        info!("'{}' reappeared as '{}', retrying", target.name(), name);
+                        target = target.renamed(&name);
+                    }
+                    Some(_) => info!("'{}' reappeared, retrying", target.name()),
+                    None => {
+                        debug!("'{}' did not reappear yet", target.name());
+                        continue;
+                    }
+                }
+            } else if err.kind() == ErrorKind::Transient {
+                warn!(
+                    "Retrying '{}' ({}/{}): {}",
+                    config.name(),
+                    attempt,
+                    CONFIGURE_ATTEMPTS,
+                    err
+                );
+                thread::sleep(RETRY_DELAY * attempt);
+                attempt += 1;
+            } else {
+                break;
+            }
+            result = apply(&target, None);
+        }
+        let result = result.map(|(effective, lease)| {
+            leases.extend(lease);
+            effective
+        });
+        if let Ok(StaticNetworkInterfaceConfig {
+            name,
+            ip: IpAddr::V4(ip),
+            ..
+        }) = &result
+        {
+            let mut configured = CONFIGURED.lock().unwrap_or_else(|err| err.into_inner());
+            configured.retain(|(iface, _)| iface != name);
+            configured.push((name.clone(), *ip));
+        }
+
+        states.push(InterfaceState {
+            config,
+            configured: result.is_ok(),
+            effective: result.as_ref().ok().cloned(),
+        });
+        match result {
+            Ok(_) if !is_loopback(config.name()) => configured += 1,
+            Ok(_) => {}
+            Err(err) if err.interface_disappeared() => {
+                warn!("Skipping '{}', it did not reappear: {}", config.name(), err)
+            }
+            Err(err) => error!("Failed configuring '{}': {}", config.name(), err),
+        }
+    }
+
+    (leases, states, configured)
+}
+
+/// Applies the config of a single interface.
+///
+/// # Arguments
+///
+/// * `config` - The config to apply.
+/// * `requested` - The lease requested up front for a dynamic config,
+///   it is requested now if there is none.
+///
+/// # Returns
+///
+/// * The effective static configuration and the lease of a dynamic config.
+fn apply_config(
+    config: &NetworkInterfaceConfig,
+    requested: Option<Result<DhcpLease, NetworkConfigurationError>>,
+) -> Result<(StaticNetworkInterfaceConfig, Option<DhcpLease>), NetworkConfigurationError> {
+    match config {
+        NetworkInterfaceConfig::Dynamic(cfg) => requested
+            .unwrap_or_else(|| cfg.request_lease())
+            .and_then(|lease| cfg.apply_requested(lease))
+            .map(|lease| (lease.to_static_config(), Some(lease))),
+        NetworkInterfaceConfig::Static(cfg) => cfg.apply().map(|_| (cfg.clone(), None)),
+    }
+}
+
+/// Waits for the carrier of the dynamically configured interfaces and
+/// requests their leases in parallel (see `request_leases`).
+///
+/// The leases are requested up front, but applied in order,
+/// as the order decides the default route.
+///
+/// # Arguments
+///
+/// * `configs` - The configs to apply.
+/// * `parallel` - The maximum number of parallel requests.
+///
+/// # Returns
+///
+/// * The interfaces without carrier, which are skipped, and the results
+///   of the lease requests of the other dynamic configs, in order.
+fn dhcp_leases(
+    configs: &[NetworkInterfaceConfig],
+    parallel: usize,
+) -> (
+    Vec<String>,
+    std::vec::IntoIter<Result<DhcpLease, NetworkConfigurationError>>,
+) {
+    let dynamic = configs
+        .iter()
+        .filter_map(|config| match config {
+            NetworkInterfaceConfig::Dynamic(cfg) => Some(cfg),
+            NetworkInterfaceConfig::Static(_) => None,
+        })
+        .collect::<Vec<&DynamicNetworkInterfaceConfig>>();
+    let names = dynamic
+        .iter()
+        .map(|cfg| cfg.name.as_str())
+        .collect::<Vec<&str>>();
+    let no_carrier = without_carrier(&names, monitor::read_carrier, CARRIER_TIMEOUT)
+        .into_iter()
+        .map(|name| name.to_string())
+        .collect::<Vec<String>>();
+
+    let dynamic = dynamic
+        .into_iter()
+        .filter(|cfg| !no_carrier.contains(&cfg.name))
+        .collect::<Vec<&DynamicNetworkInterfaceConfig>>();
+    let requested = request_leases(
+        &dynamic,
+        parallel,
+        DynamicNetworkInterfaceConfig::request_lease,
+    )
+    .into_iter();
+
+    (no_carrier, requested)
+}
+
+/// Finds the interface a config applies to after its interface disappeared.
+///
+/// # Arguments
+///
+/// * `name` - The name of the disappeared interface.
+/// * `mac` - The MAC address it had, to find it after a rename.
+/// * `interfaces` - The interfaces present now.
+///
+/// # Returns
+///
+/// * `Option<String>` - The name of the interface once it is back,
+///   either under the same name or renamed.
+fn reappeared(name: &str, mac: Option<MacAddr>, interfaces: &[NetworkInterface]) -> Option<String> {
+    interfaces
+        .iter()
+        .find(|iface| iface.name == name)
+        .or_else(|| {
+            let mac = mac.filter(|mac| *mac != MacAddr::zero())?;
+            interfaces.iter().find(|iface| iface.mac == Some(mac))
+        })
+        .map(|iface| iface.name.clone())
+}
+
+/// Writes the resolved network configuration to `NETWORK_STATE`,
+/// for other tools to pick up.
+///
+/// Failures are logged, but not fatal.
+fn write_state(states: &[InterfaceState]) {
+    let json = match serde_json::to_string_pretty(states) {
+        Ok(json) => json,
+        Err(err) => {
+            warn!("Failed serializing network state: {}", err);
+            return;
+        }
+    };
+
+    if let Err(err) = atomic_write(Path::new(NETWORK_STATE), &(json + "\n")) {
+        warn!("Failed writing '{}': {}", NETWORK_STATE, err);
+    }
+}
+
+/// Writes a minimal `/etc/nsswitch.conf` resolving hosts
+/// from `/etc/hosts` and DNS, unless one already exists.
+///
+/// Failures are logged, but not fatal.
+fn write_nsswitch() {
+    if Path::new(NSSWITCH_CONF).exists() {
+        debug!("Keeping existing '{}'", NSSWITCH_CONF);
+        return;
+    }
+
+    if let Err(err) = write_config_file(NSSWITCH_CONF, "hosts: files dns\n") {
+        warn!("Failed writing '{}': {}", NSSWITCH_CONF, err);
+    }
+}
+
+/// Checks the outcome of the network configuration against the policy.
+///
+/// # Arguments
+///
+/// * `required` - Whether the network is required (`net.required=1`).
+/// * `configured` - The number of configured non-loopback interfaces.
+fn check_required(required: bool, configured: usize) -> Result<(), NetworkConfigurationError> {
+    if configured > 0 {
+        return Ok(());
+    }
+    if required {
+        return Err(NetworkConfigurationError::new(
+            "No network interface could be configured".to_string(),
+        ));
+    }
+
+    warn!("No network interface could be configured, continuing without network");
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn interface(name: &str, mac: MacAddr) -> NetworkInterface {
+        NetworkInterface {
+            name: name.to_string(),
+            description: String::new(),
+            index: 2,
+            mac: Some(mac),
+            ips: Vec::new(),
+            flags: 0,
+        }
+    }
+
+    #[test]
+    fn finds_reappeared_interfaces() {
+        let mac = MacAddr::new(0x52, 0x54, 0x00, 0x12, 0x34, 0x56);
+        let other = MacAddr::new(0x52, 0x54, 0x00, 0xab, 0xcd, 0xef);
+
+        assert_eq!(
+            reappeared("eth0", Some(mac), &[interface("eth0", other)]),
+            Some("eth0".to_string())
+        );
+        assert_eq!(
+            reappeared("eth0", Some(mac), &[interface("enp1s0", mac)]),
+            Some("enp1s0".to_string())
+        );
+        assert_eq!(
+            reappeared("eth0", Some(mac), &[interface("eth1", other)]),
+            None
+        );
+        assert_eq!(
+            reappeared("eth0", None, &[interface("eth1", MacAddr::zero())]),
+            None
+        );
+    }
+
+    #[test]
+    fn unconfigured_network_fails_only_when_required() {
+        assert!(check_required(false, 1).is_ok());
+        assert!(check_required(true, 1).is_ok());
+        assert!(check_required(false, 0).is_ok());
+        assert!(check_required(true, 0).is_err());
+    }
+
+    #[test]
+    fn parallel_requests_are_capped() {
+        let configs = (0..5)
+            .map(|i| DynamicNetworkInterfaceConfig {
+                name: format!("eth{}", i),
+                dhcp: DhcpConfig::default(),
+                search: Vec::new(),
+                priority: 0,
+                routing: None,
+                requested_lease_time: None,
+                lease_lifetimes: false,
+            })
+            .collect::<Vec<_>>();
+        let configs = configs.iter().collect::<Vec<_>>();
+
+        let running = AtomicUsize::new(0);
+        let most = AtomicUsize::new(0);
+        let results = request_leases(&configs, 2, |config| {
+            let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+            most.fetch_max(now, Ordering::SeqCst);
+            thread::sleep(Duration::from_millis(20));
+            running.fetch_sub(1, Ordering::SeqCst);
+            Err(NetworkConfigurationError::new(config.name.clone()))
+        });
+
+        assert_eq!(most.load(Ordering::SeqCst), 2);
+        // the results keep the order of the configs
+        let names = results
+            .into_iter()
+            .map(|result| result.unwrap_err().to_string())
+            .collect::<Vec<String>>();
+        assert_eq!(names, ["eth0", "eth1", "eth2", "eth3", "eth4"]);
+    }
+
+    #[test]
+    fn only_foreign_addresses_are_kept() {
+        let kernel = Ipv4Addr::new(10, 0, 0, 2);
+        let own = Ipv4Addr::new(10, 0, 0, 3);
+        let configured = [("eth0".to_string(), own)];
+
+        // e.g. configured by the kernel (`ip=`)
+        assert!(keeps_address("eth0", Some(kernel), false, &configured));
+        assert!(!keeps_address("eth0", Some(kernel), true, &configured));
+        // configured by linµos during boot
+        assert!(!keeps_address("eth0", Some(own), false, &configured));
+        assert!(keeps_address("eth1", Some(own), false, &configured));
+        assert!(!keeps_address("eth0", None, false, &configured));
+    }
+
+    #[test]
+    fn selects_dhcp_interface() {
+        let mac = MacAddr::new(0x52, 0x54, 0x00, 0x12, 0x34, 0x56);
+        let mut lo = interface(LOOPBACK, MacAddr::zero());
+        lo.flags = libc::IFF_LOOPBACK as u32;
+        let interfaces = [
+            lo,
+            interface("eth0", MacAddr::new(0x52, 0x54, 0x00, 0xab, 0xcd, 0xef)),
+            interface("enp1s0", mac),
+        ];
+        let select = |selection: &str, carrier: &[&str]| {
+            select_dhcp_iface(selection, &interfaces, |name| carrier.contains(&name))
+                .map(|(name, _reason)| name)
+        };
+
+        assert_eq!(select("auto", &["lo", "enp1s0"]).as_deref(), Some("enp1s0"));
+        assert_eq!(select("auto", &[]).as_deref(), Some("eth0"));
+        assert_eq!(select("52:54:00:12:34:56", &[]).as_deref(), Some("enp1s0"));
+        assert_eq!(select("52:54:00:00:00:01", &[]), None);
+        assert_eq!(select("en*", &[]).as_deref(), Some("enp1s0"));
+        assert_eq!(select("wl*", &[]), None);
+        assert_eq!(select("eth1", &[]).as_deref(), Some("eth1"));
+    }
+
+    #[test]
+    fn parses_bootif() {
+        assert_eq!(
+            parse_bootif("01-52-54-00-12-34-56"),
+            Some(MacAddr::new(0x52, 0x54, 0x00, 0x12, 0x34, 0x56))
+        );
+        assert_eq!(parse_bootif("52:54:00:12:34:56"), None);
+        assert_eq!(parse_bootif("01-52-54-00-12-34"), None);
+        assert_eq!(parse_bootif("01-zz-54-00-12-34-56"), None);
+        assert_eq!(parse_bootif(""), None);
+    }
+
+    #[test]
+    fn interface_lease_time_overrides_cmdline() {
+        let config = |requested_lease_time| DynamicNetworkInterfaceConfig {
+            name: "eth0".to_string(),
+            dhcp: DhcpConfig::default(),
+            search: Vec::new(),
+            priority: 0,
+            routing: None,
+            requested_lease_time,
+            lease_lifetimes: false,
+        };
+        let cmdline = DhcpConfig {
+            rapid_commit: true,
+            requested_lease_time: Some(86400),
+            ..DhcpConfig::default()
+        };
+
+        let dhcp = dhcp_config(&cmdline, &config(Some(3600)));
+        assert_eq!(dhcp.requested_lease_time, Some(3600));
+        assert!(dhcp.rapid_commit);
+        assert_eq!(
+            dhcp_config(&cmdline, &config(None)).requested_lease_time,
+            Some(86400)
+        );
+        assert_eq!(
+            dhcp_config(&DhcpConfig::default(), &config(None)).requested_lease_time,
+            None
+        );
+    }
+
+    #[test]
+    fn waits_for_carrier_until_timeout() {
+        // interfaces which do not exist, so enabling them fails
+        let (up, down, unknown) = ("linos-up0", "linos-down0", "linos-unk0");
+        let polls = AtomicUsize::new(0);
+        let carrier = |name: &str| {
+            if name == up {
+                // the link comes up after a few polls
+                return Some(polls.fetch_add(1, Ordering::SeqCst) >= 2);
+            }
+            (name == down).then_some(false)
+        };
+
+        let start = Instant::now();
+        let timeout = Duration::from_millis(500);
+        assert_eq!(
+            without_carrier(&[up, down, unknown], carrier, timeout),
+            [down]
+        );
+        assert!(start.elapsed() >= timeout);
+        assert!(polls.load(Ordering::SeqCst) >= 3);
+
+        let start = Instant::now();
+        assert!(without_carrier(&[up, unknown], |_| Some(true), timeout).is_empty());
+        assert!(start.elapsed() < timeout);
+    }
+
+    fn static_config(name: &str, ip: Ipv4Addr) -> NetworkInterfaceConfig {
+        NetworkInterfaceConfig::Static(StaticNetworkInterfaceConfig {
+            name: name.to_string(),
+            ip: IpAddr::V4(ip),
+            netmask: IpAddr::V4(Ipv4Addr::new(255, 255, 255, 0)),
+            gateway: IpAddr::V4(ip),
+            dns: Vec::new(),
+            search: Vec::new(),
+            priority: 0,
+            aliases: Vec::new(),
+            neighbors: Vec::new(),
+            routing: None,
+        })
+    }
+
+    #[test]
+    fn failing_network_fails_only_when_required() {
+        let mut lo = interface(LOOPBACK, MacAddr::zero());
+        lo.flags = libc::IFF_LOOPBACK as u32;
+        let interfaces = [lo, interface("eth0", MacAddr::zero())];
+        let pending = [
+            static_config(LOOPBACK, Ipv4Addr::new(127, 0, 0, 1)),
+            static_config("eth0", Ipv4Addr::new(10, 0, 0, 2)),
+        ];
+
+        // loopback comes up, but the only other interface fails
+        let applied = std::cell::RefCell::new(Vec::new());
+        let apply = |config: &NetworkInterfaceConfig, _| {
+            applied.borrow_mut().push(config.name().to_string());
+            match config {
+                NetworkInterfaceConfig::Static(cfg) if cfg.name == LOOPBACK => {
+                    Ok((cfg.clone(), None))
+                }
+                _ => Err(NetworkConfigurationError::with_kind(
+                    "Failed adding address".to_string(),
+                    ErrorKind::Permanent,
+                )),
+            }
+        };
+
+        for required in [true, false] {
+            applied.borrow_mut().clear();
+            let options = NetworkOptions {
+                required,
+                ..NetworkOptions::default()
+            };
+            let (leases, states, configured) =
+                apply_configs(&pending, &options, &interfaces, apply);
+
+            // permanent failures are not retried
+            assert_eq!(*applied.borrow(), [LOOPBACK, "eth0"]);
+            assert!(leases.is_empty());
+            assert_eq!(
+                states
+                    .iter()
+                    .map(|state| state.configured)
+                    .collect::<Vec<bool>>(),
+                [true, false]
+            );
+            assert_eq!(configured, 0);
+            assert_eq!(check_required(required, configured).is_err(), required);
+        }
+    }
+
+    #[test]
+    fn configured_interface_satisfies_required_network() {
+        let interfaces = [
+            interface("eth0", MacAddr::zero()),
+            interface("eth1", MacAddr::zero()),
+        ];
+        let pending = [
+            static_config("eth0", Ipv4Addr::new(10, 0, 0, 2)),
+            static_config("eth1", Ipv4Addr::new(10, 0, 1, 2)),
+        ];
+        let options = NetworkOptions {
+            required: true,
+            ..NetworkOptions::default()
+        };
+
+        // the optional first interface fails, the second one comes up
+        let (_, states, configured) =
+            apply_configs(&pending, &options, &interfaces, |config, _| match config {
+                NetworkInterfaceConfig::Static(cfg) if cfg.name == "eth1" => {
+                    Ok((cfg.clone(), None))
+                }
+                _ => Err(NetworkConfigurationError::with_kind(
+                    "No such device".to_string(),
+                    ErrorKind::Permanent,
+                )),
+            });
+
+        assert!(!states[0].configured);
+        assert!(states[1].configured);
+        assert_eq!(states[1].effective.as_ref().unwrap().name, "eth1");
+        assert_eq!(configured, 1);
+        assert!(check_required(options.required, configured).is_ok());
+    }
+}
@@ -0,0 +1,214 @@
+//! Minimal SNTP client setting the system clock (see RFC 4330).
+use std::{
+    io,
+    net::{Ipv4Addr, SocketAddrV4, UdpSocket},
+    time::{Duration, Instant},
+};
+
+use log::{debug, warn};
+
+use super::lease::DhcpLease;
+
+/// The port NTP servers listen on.
+const NTP_PORT: u16 = 123;
+
+/// Seconds between the NTP epoch (1900) and the unix epoch (1970).
+const NTP_UNIX_OFFSET: u64 = 2_208_988_800;
+
+/// Time to wait for the answer of a single server.
+const NTP_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Orders the NTP servers to query.
+///
+/// The servers handed out by DHCP (option 42) come first,
+/// the statically configured ones (`ntp=`) are the fallback.
+///
+/// # Arguments
+///
+/// * `leases` - The DHCP leases obtained during boot.
+/// * `fallback` - The statically configured servers.
+pub fn servers(leases: &[DhcpLease], fallback: &[Ipv4Addr]) -> Vec<Ipv4Addr> {
+    let mut servers = Vec::new();
+    leases
+        .iter()
+        .flat_map(|lease| lease.ntp.iter())
+        .chain(fallback.iter())
+        .for_each(|server| {
+            if !servers.contains(server) {
+                servers.push(*server);
+            }
+        });
+
+    servers
+}
+
+/// Creates an NTP client request.
+fn encode_request() -> [u8; 48] {
+    // leap indicator 0, version 4, mode 3 (client)
+    let mut packet = [0u8; 48];
+    packet[0] = 0x23;
+    packet
+}
+
+/// Reads the transmit timestamp of an NTP server response.
+///
+/// # Arguments
+///
+/// * `server` - The server the response was received from.
+/// * `packet` - The response.
+///
+/// # Returns
+///
+/// * `io::Result<Duration>` - The time since the unix epoch.
+fn decode_response(server: Ipv4Addr, packet: &[u8]) -> io::Result<Duration> {
+    // mode 4 (server), stratum 0 is a kiss-o'-death message
+    if packet.len() < 48 || packet[0] & 0x7 != 4 || packet[1] == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{}: invalid NTP response", server),
+        ));
+    }
+
+    let secs = u32::from_be_bytes([packet[40], packet[41], packet[42], packet[43]]) as u64;
+    let fraction = u32::from_be_bytes([packet[44], packet[45], packet[46], packet[47]]) as u64;
+    if secs < NTP_UNIX_OFFSET {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{}: NTP time before the unix epoch", server),
+        ));
+    }
+
+    let nanos = (fraction * 1_000_000_000) >> 32;
+    Ok(Duration::new(secs - NTP_UNIX_OFFSET, nanos as u32))
+}
+
+/// Queries the current time from a single NTP server.
+///
+/// # Returns
+///
+/// * `io::Result<Duration>` - The time since the unix epoch.
+fn query(server: Ipv4Addr) -> io::Result<Duration> {
+    let socket = UdpSocket::bind("0.0.0.0:0")?;
+    socket.set_read_timeout(Some(NTP_TIMEOUT))?;
+    socket.connect(SocketAddrV4::new(server, NTP_PORT))?;
+
+    let mut packet = encode_request();
+    let sent = Instant::now();
+    socket.send(&packet)?;
+
+    let len = socket.recv(&mut packet)?;
+    let round_trip = sent.elapsed();
+    Ok(decode_response(server, &packet[..len])? + round_trip / 2)
+}
+
+/// Sets the system clock from the first of the `servers` answering.
+///
+/// # Arguments
+///
+/// * `servers` - The NTP servers to try in order (see `servers`).
+///
+/// # Returns
+///
+/// * `io::Result<Ipv4Addr>` - The server the clock was set from.
+pub fn sync(servers: &[Ipv4Addr]) -> io::Result<Ipv4Addr> {
+    for server in servers {
+        let now = match query(*server) {
+            Ok(now) => now,
+            Err(err) => {
+                warn!("Failed querying NTP server {}: {}", server, err);
+                continue;
+            }
+        };
+
+        let time = libc::timespec {
+            tv_sec: now.as_secs() as libc::time_t,
+            tv_nsec: now.subsec_nanos() as libc::c_long,
+        };
+        if unsafe { libc::clock_settime(libc::CLOCK_REALTIME, &time) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        debug!("Set the clock from {} to {:?}", server, now);
+        return Ok(*server);
+    }
+
+    Err(io::Error::new(
+        io::ErrorKind::NotFound,
+        "no NTP server answered",
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SERVER: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 1);
+
+    fn lease(ntp: Vec<Ipv4Addr>) -> DhcpLease {
+        DhcpLease {
+            ntp,
+            ..DhcpLease::for_test("eth0")
+        }
+    }
+
+    /// A server response with the transmit timestamp `secs.fraction`.
+    fn response(secs: u32, fraction: u32) -> [u8; 48] {
+        let mut packet = [0u8; 48];
+        // version 4, mode 4 (server), stratum 2
+        packet[0] = 0x24;
+        packet[1] = 2;
+        packet[40..44].copy_from_slice(&secs.to_be_bytes());
+        packet[44..48].copy_from_slice(&fraction.to_be_bytes());
+        packet
+    }
+
+    #[test]
+    fn request_is_a_version_4_client_packet() {
+        let packet = encode_request();
+
+        assert_eq!(packet[0] >> 6, 0);
+        assert_eq!((packet[0] >> 3) & 0x7, 4);
+        assert_eq!(packet[0] & 0x7, 3);
+        assert!(packet[1..].iter().all(|byte| *byte == 0));
+    }
+
+    #[test]
+    fn decodes_transmit_timestamp() {
+        let secs = (NTP_UNIX_OFFSET + 1_700_000_000) as u32;
+
+        assert_eq!(
+            decode_response(SERVER, &response(secs, 1 << 31)).unwrap(),
+            Duration::new(1_700_000_000, 500_000_000)
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_responses() {
+        let secs = (NTP_UNIX_OFFSET + 1_700_000_000) as u32;
+
+        // kiss-o'-death
+        let mut packet = response(secs, 0);
+        packet[1] = 0;
+        assert!(decode_response(SERVER, &packet).is_err());
+        // client mode
+        let mut packet = response(secs, 0);
+        packet[0] = 0x23;
+        assert!(decode_response(SERVER, &packet).is_err());
+        assert!(decode_response(SERVER, &response(secs, 0)[..47]).is_err());
+        assert!(decode_response(SERVER, &response(1, 0)).is_err());
+    }
+
+    #[test]
+    fn leased_servers_come_first() {
+        let leased = |last| Ipv4Addr::new(10, 0, 0, last);
+        let fallback = [Ipv4Addr::new(192, 0, 2, 1), leased(2)];
+
+        assert_eq!(
+            servers(
+                &[lease(vec![leased(1), leased(2)]), lease(vec![leased(1)])],
+                &fallback
+            ),
+            [leased(1), leased(2), Ipv4Addr::new(192, 0, 2, 1)]
+        );
+        assert_eq!(servers(&[], &fallback), fallback);
+    }
+}
@@ -0,0 +1,204 @@
+//! The phases of the boot.
+//!
+//! Phases are referred to by name on the kernel command line
+//! (e.g. `linos.pause=network:5`), so the names are part of the
+//! interface of linµos and must stay stable.
+use core::fmt;
+use std::{thread, time::Duration};
+
+use log::{info, warn};
+
+use crate::{debugshell::DEBUG_SHELL, exec};
+
+/// A phase of the boot, in the order they run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    /// Mounting the filesystems.
+    Mount,
+    /// Mounting the `mount.extra=` filesystems and the fstab entries.
+    MountExtra,
+    /// Loading the kernel modules.
+    Modules,
+    /// Configuring the network.
+    Network,
+    /// Setting the clock.
+    Clock,
+    /// Starting the services.
+    Services,
+    /// Handing off to the final process.
+    Handoff,
+}
+
+impl Phase {
+    /// All phases, in the order they run.
+    pub const ALL: [Phase; 7] = [
+        Phase::Mount,
+        Phase::MountExtra,
+        Phase::Modules,
+        Phase::Network,
+        Phase::Clock,
+        Phase::Services,
+        Phase::Handoff,
+    ];
+
+    /// The name of the phase, as used on the kernel command line.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Phase::Mount => "mount",
+            Phase::MountExtra => "mount-extra",
+            Phase::Modules => "modules",
+            Phase::Network => "network",
+            Phase::Clock => "clock",
+            Phase::Services => "services",
+            Phase::Handoff => "handoff",
+        }
+    }
+
+    /// Checks if the phase can be skipped (`skip=`).
+    ///
+    /// Mounting the root and the essential filesystems can not be skipped,
+    /// as all later phases depend on it, neither can the handoff.
+    pub fn is_skippable(&self) -> bool {
+        !matches!(self, Phase::Mount | Phase::Handoff)
+    }
+
+    /// Looks up a phase by its name.
+    pub fn from_name(name: &str) -> Option<Phase> {
+        Phase::ALL
+            .iter()
+            .find(|phase| phase.name() == name)
+            .copied()
+    }
+}
+
+impl fmt::Display for Phase {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// Parses a pause specification like `network:5`.
+///
+/// # Returns
+///
+/// * `Option<(Phase, Duration)>` - The phase to pause before and for how
+///   long, or `None` if the specification is invalid.
+pub fn parse_pause(spec: &str) -> Option<(Phase, Duration)> {
+    let (phase, secs) = spec.split_once(':')?;
+    let phase = Phase::from_name(phase)?;
+    let secs = secs.parse::<u64>().ok()?;
+
+    Some((phase, Duration::from_secs(secs)))
+}
+
+/// Resolves the break points given as `break=` and `rd.break=`.
+///
+/// # Returns
+///
+/// * `Result<Vec<Phase>, &str>` - The phases to break before, in order
+///   and without duplicates, or the first name which is no phase.
+pub fn parse_breaks<'a>(names: &[&'a str]) -> Result<Vec<Phase>, &'a str> {
+    let mut breaks = Vec::new();
+    for name in names {
+        let phase = Phase::from_name(name).ok_or(*name)?;
+        if !breaks.contains(&phase) {
+            breaks.push(phase);
+        }
+    }
+
+    Ok(breaks)
+}
+
+/// Sleeps for all pauses configured before `phase`, then drops
+/// to an interactive shell if `phase` is a break point.
+///
+/// The boot resumes with the phase once the shell exits.
+///
+/// # Arguments
+///
+/// * `phase` - The phase about to start.
+/// * `pauses` - The configured pauses (see `linos.pause=`).
+/// * `breaks` - The phases to break before (see `break=`).
+pub fn pause_before(phase: Phase, pauses: &[(Phase, Duration)], breaks: &[Phase]) {
+    pauses
+        .iter()
+        .filter(|(p, _)| *p == phase)
+        .for_each(|(_, duration)| {
+            info!("Pausing {}s before phase '{}'", duration.as_secs(), phase);
+            thread::sleep(*duration);
+        });
+
+    if breaks.contains(&phase) {
+        info!(
+            "Breaking before phase '{}', exit the shell to resume",
+            phase
+        );
+        if let Err(err) = exec::exec(DEBUG_SHELL, &["sh"], true, exec::Output::Inherit) {
+            warn!("Failed spawning break shell: {}", err);
+        }
+        info!("Resuming with phase '{}'", phase);
+    }
+}
+
+/// Starts a skippable phase: logs that it is skipped if it is
+/// listed in `skips` and otherwise pauses before it (see `pause_before`).
+///
+/// # Arguments
+///
+/// * `phase` - The phase about to start.
+/// * `pauses` - The configured pauses (see `linos.pause=`).
+/// * `breaks` - The phases to break before (see `break=`).
+/// * `skips` - The skipped phases (see `skip=`).
+///
+/// # Returns
+///
+/// * `bool` - Whether the phase runs.
+pub fn start(
+    phase: Phase,
+    pauses: &[(Phase, Duration)],
+    breaks: &[Phase],
+    skips: &[Phase],
+) -> bool {
+    if skips.contains(&phase) {
+        info!("Skipping phase '{}'", phase);
+        return false;
+    }
+    pause_before(phase, pauses, breaks);
+
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_breaks() {
+        assert_eq!(
+            parse_breaks(&["mount", "network", "mount"]),
+            Ok(vec![Phase::Mount, Phase::Network])
+        );
+        assert_eq!(parse_breaks(&[]), Ok(Vec::new()));
+        assert_eq!(parse_breaks(&["network", "pre-mount"]), Err("pre-mount"));
+    }
+
+    #[test]
+    fn skipped_phase_does_not_break() {
+        let breaks = [Phase::Network];
+
+        assert!(!start(Phase::Network, &[], &breaks, &[Phase::Network]));
+        // neither breaks nor pauses before any other phase
+        assert!(start(Phase::Clock, &[], &breaks, &[Phase::Network]));
+    }
+
+    #[test]
+    fn parses_pauses() {
+        assert_eq!(
+            parse_pause("network:5"),
+            Some((Phase::Network, Duration::from_secs(5)))
+        );
+        assert_eq!(parse_pause("network"), None);
+        assert_eq!(parse_pause("network:soon"), None);
+        assert_eq!(parse_pause("pre-mount:5"), None);
+    }
+}
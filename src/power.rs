@@ -0,0 +1,155 @@
+//! Actions taken once the handed-off process exited.
+//!
+//! A clean exit (e.g. `exit` typed in the shell) and a crash (nonzero
+//! status or killed by a signal) are handled separately, configured
+//! with `onexit=` and `oncrash=` on the kernel command line.
+use core::fmt;
+
+use nix::sys::{
+    reboot::{reboot, RebootMode},
+    wait::WaitStatus,
+};
+
+/// What to do once the handed-off process exited.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitAction {
+    /// Powers the system off.
+    Poweroff,
+    /// Reboots the system.
+    Reboot,
+    /// Drops to a rescue shell.
+    Rescue,
+    /// Reports the exit on the console and halts.
+    Halt,
+}
+
+impl ExitAction {
+    /// All actions, in the order they are documented.
+    pub const ALL: [ExitAction; 4] = [
+        ExitAction::Poweroff,
+        ExitAction::Reboot,
+        ExitAction::Rescue,
+        ExitAction::Halt,
+    ];
+
+    /// Action on a clean exit if `onexit=` is not given.
+    pub const DEFAULT_ON_EXIT: ExitAction = ExitAction::Poweroff;
+
+    /// Action on a crash if `oncrash=` is not given.
+    pub const DEFAULT_ON_CRASH: ExitAction = ExitAction::Rescue;
+
+    /// The name used on the kernel command line (e.g. `poweroff`).
+    pub fn name(&self) -> &'static str {
+        match self {
+            ExitAction::Poweroff => "poweroff",
+            ExitAction::Reboot => "reboot",
+            ExitAction::Rescue => "rescue",
+            ExitAction::Halt => "halt",
+        }
+    }
+
+    /// Parses an action by its name (see `name`).
+    pub fn parse(name: &str) -> Option<ExitAction> {
+        ExitAction::ALL
+            .into_iter()
+            .find(|action| action.name() == name)
+    }
+}
+
+impl fmt::Display for ExitAction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// Checks if the process exited cleanly, i.e. with status 0.
+pub fn is_clean_exit(status: &WaitStatus) -> bool {
+    matches!(status, WaitStatus::Exited(_, 0))
+}
+
+/// Decides what to do after the handed-off process exited.
+///
+/// # Arguments
+///
+/// * `status` - The exit status of the handed-off process.
+/// * `on_exit` - The action on a clean exit.
+/// * `on_crash` - The action on a nonzero exit status or a signal.
+pub fn action(status: &WaitStatus, on_exit: ExitAction, on_crash: ExitAction) -> ExitAction {
+    if is_clean_exit(status) {
+        on_exit
+    } else {
+        on_crash
+    }
+}
+
+/// Powers off or reboots the system.
+///
+/// The filesystems have to be synced before.
+///
+/// # Arguments
+///
+/// * `action` - Either `ExitAction::Poweroff` or `ExitAction::Reboot`,
+///   other actions halt the system.
+pub fn power(action: ExitAction) -> nix::Result<()> {
+    let mode = match action {
+        ExitAction::Poweroff => RebootMode::RB_POWER_OFF,
+        ExitAction::Reboot => RebootMode::RB_AUTOBOOT,
+        ExitAction::Rescue | ExitAction::Halt => RebootMode::RB_HALT_SYSTEM,
+    };
+    reboot(mode)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use nix::{sys::signal::Signal, unistd::Pid};
+
+    use super::*;
+
+    #[test]
+    fn exit_status_selects_action() {
+        let pid = Pid::from_raw(42);
+        let cases = [
+            (WaitStatus::Exited(pid, 0), ExitAction::Reboot),
+            (WaitStatus::Exited(pid, 1), ExitAction::Halt),
+            (
+                WaitStatus::Signaled(pid, Signal::SIGSEGV, true),
+                ExitAction::Halt,
+            ),
+        ];
+
+        let cmdline = crate::cmdline::parse_cmdline("root=/dev/vda onexit=reboot oncrash=halt");
+        let cmdline = cmdline.unwrap();
+        for (status, expected) in cases {
+            assert_eq!(
+                action(&status, cmdline.on_exit, cmdline.on_crash),
+                expected,
+                "{:?}",
+                status
+            );
+        }
+        // the defaults power off on a clean exit and rescue a crash
+        let defaults = |status| {
+            action(
+                &status,
+                ExitAction::DEFAULT_ON_EXIT,
+                ExitAction::DEFAULT_ON_CRASH,
+            )
+        };
+        assert_eq!(defaults(WaitStatus::Exited(pid, 0)), ExitAction::Poweroff);
+        assert_eq!(defaults(WaitStatus::Exited(pid, 1)), ExitAction::Rescue);
+        assert_eq!(
+            defaults(WaitStatus::Signaled(pid, Signal::SIGKILL, false)),
+            ExitAction::Rescue
+        );
+    }
+
+    #[test]
+    fn parses_action_names() {
+        for action in ExitAction::ALL {
+            assert_eq!(ExitAction::parse(action.name()), Some(action));
+        }
+        assert_eq!(ExitAction::parse("shutdown"), None);
+    }
+}
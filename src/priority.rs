@@ -0,0 +1,119 @@
+//! Protection and scheduling priority of the init process.
+//!
+//! The kernel panics if PID 1 dies, so linµos excludes itself from the
+//! OOM killer by default (opt out with `oom.protect=0`), its children are
+//! reset to the default adjustment. With `nice=<n>` it runs with the given
+//! nice value, children inherit it.
+use std::{fs, io};
+
+/// The OOM score adjustment of the init process itself.
+pub const OOM_SCORE_ADJ: &str = "/proc/self/oom_score_adj";
+
+/// The adjustment excluding a process from the OOM killer.
+pub const OOM_SCORE_ADJ_MIN: i32 = -1000;
+
+/// `OOM_SCORE_ADJ` as C string, to be opened without allocating.
+const OOM_SCORE_ADJ_C: &[u8] = b"/proc/self/oom_score_adj\0";
+
+/// The range of nice values, from the highest to the lowest priority.
+pub const NICE_RANGE: std::ops::RangeInclusive<i32> = -20..=19;
+
+/// Excludes the init process from the OOM killer.
+///
+/// `/proc` has to be mounted.
+pub fn protect_from_oom() -> io::Result<()> {
+    fs::write(OOM_SCORE_ADJ, OOM_SCORE_ADJ_MIN.to_string())
+}
+
+/// Resets the OOM score adjustment of the calling process to the default.
+///
+/// Children inherit the adjustment of init, so they are reset before
+/// the program is executed, otherwise the OOM killer never picks them.
+/// Runs in the forked child (see `Command::pre_exec`) and therefore
+/// neither allocates nor takes locks.
+pub fn reset_oom_score_adj() -> io::Result<()> {
+    let fd = unsafe {
+        libc::open(
+            OOM_SCORE_ADJ_C.as_ptr().cast(),
+            libc::O_WRONLY | libc::O_CLOEXEC,
+        )
+    };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let written = unsafe { libc::write(fd, b"0".as_ptr().cast(), 1) };
+    let result = if written == 1 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    };
+    unsafe { libc::close(fd) };
+
+    result
+}
+
+/// Sets the nice value of the init process.
+///
+/// # Arguments
+///
+/// * `nice` - The nice value, within `NICE_RANGE`.
+pub fn set_nice(nice: i32) -> io::Result<()> {
+    if unsafe { libc::setpriority(libc::PRIO_PROCESS, 0, nice) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        os::unix::process::CommandExt,
+        process::{Command, Stdio},
+    };
+
+    #[test]
+    fn c_path_matches_oom_score_adj() {
+        assert_eq!(OOM_SCORE_ADJ_C, format!("{}\0", OOM_SCORE_ADJ).as_bytes());
+    }
+
+    #[test]
+    fn resets_inherited_oom_score_adj() {
+        let mut command = Command::new("sh");
+        command
+            .args(["-c", "cat /proc/self/oom_score_adj"])
+            .stdout(Stdio::piped());
+        unsafe {
+            // stands in for an adjustment inherited from init,
+            // raising it needs no privileges, unlike lowering it
+            command.pre_exec(|| {
+                let fd = libc::open(OOM_SCORE_ADJ_C.as_ptr().cast(), libc::O_WRONLY);
+                let written = libc::write(fd, b"500".as_ptr().cast(), 3);
+                libc::close(fd);
+                match written {
+                    3 => Ok(()),
+                    _ => Err(io::Error::last_os_error()),
+                }
+            });
+            command.pre_exec(reset_oom_score_adj);
+        }
+        let output = command.output().unwrap();
+        assert!(output.status.success());
+        assert_eq!(String::from_utf8_lossy(&output.stdout).trim(), "0");
+    }
+
+    #[test]
+    fn sets_nice_of_the_calling_thread() {
+        // the nice value is per thread on Linux, raising it needs no privileges
+        std::thread::spawn(|| {
+            set_nice(*NICE_RANGE.end()).unwrap();
+            assert_eq!(
+                unsafe { libc::getpriority(libc::PRIO_PROCESS, 0) },
+                *NICE_RANGE.end()
+            );
+        })
+        .join()
+        .unwrap();
+    }
+}
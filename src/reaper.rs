@@ -0,0 +1,253 @@
+//! Reaping of child processes.
+//!
+//! As PID 1, linµos inherits every orphaned process and has to reap it.
+//! Once started, the reaper thread is the only one waiting for children:
+//! it logs how each child exited and hands the exit status to whoever
+//! waits for the child (see `wait`).
+use std::{
+    collections::BTreeMap,
+    io, mem,
+    os::unix::process::CommandExt,
+    process::{Child, Command},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Condvar, Mutex, MutexGuard,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use log::{info, warn};
+use nix::{
+    errno::Errno,
+    sys::wait::{waitpid, WaitPidFlag, WaitStatus},
+    unistd::Pid,
+};
+
+use crate::exec;
+
+/// Whether the reaper thread is running.
+static REAPING: AtomicBool = AtomicBool::new(false);
+
+/// Held while spawning a child, as the standard library reaps
+/// the child itself if it fails to exec.
+static SPAWN: Mutex<()> = Mutex::new(());
+
+/// The tracked children and the exit statuses not yet waited for.
+static CHILDREN: Mutex<Children> = Mutex::new(Children {
+    names: BTreeMap::new(),
+    exited: BTreeMap::new(),
+});
+
+/// Notified whenever a tracked child exited.
+static EXITED: Condvar = Condvar::new();
+
+struct Children {
+    /// The names of the tracked children by pid.
+    names: BTreeMap<i32, String>,
+    /// The exit statuses of the tracked children by pid.
+    exited: BTreeMap<i32, WaitStatus>,
+}
+
+fn children() -> MutexGuard<'static, Children> {
+    CHILDREN.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+/// Describes how a child exited (e.g. `was killed by SIGSEGV`).
+pub fn describe(status: &WaitStatus) -> String {
+    match status {
+        WaitStatus::Exited(_, 0) => "exited normally".to_string(),
+        WaitStatus::Exited(_, code) => format!("exited with status {}", code),
+        WaitStatus::Signaled(_, signal, true) => {
+            format!("was killed by {} (core dumped)", signal)
+        }
+        WaitStatus::Signaled(_, signal, false) => format!("was killed by {}", signal),
+        status => format!("changed state: {:?}", status),
+    }
+}
+
+/// Logs how a child exited and keeps its exit status if it is tracked.
+fn record(status: WaitStatus) {
+    let pid = match status.pid() {
+        Some(pid) => pid.as_raw(),
+        None => return,
+    };
+
+    let mut children = children();
+    let name = children.names.remove(&pid);
+    let child = match &name {
+        Some(name) => format!("'{}' (pid {})", name, pid),
+        None => format!("pid {}", pid),
+    };
+    match status {
+        WaitStatus::Exited(_, 0) => info!("{} {}", child, describe(&status)),
+        _ => warn!("{} {}", child, describe(&status)),
+    }
+
+    if name.is_some() {
+        children.exited.insert(pid, status);
+        EXITED.notify_all();
+    }
+}
+
+/// Spawns a command and tracks the child, so its exit is logged by name.
+///
+/// # Arguments
+///
+/// * `command` - The command to spawn.
+/// * `name` - The name of the child in the logs (e.g. the service name).
+pub fn spawn_command(command: &mut Command, name: &str) -> io::Result<Child> {
+    unsafe {
+        command.pre_exec(exec::reset_oom_score_adj);
+    }
+    let _spawning = SPAWN.lock().unwrap_or_else(|err| err.into_inner());
+    let child = command.spawn()?;
+    children().names.insert(child.id() as i32, name.to_string());
+
+    Ok(child)
+}
+
+/// Waits for a child spawned by `spawn_command` to exit.
+pub fn wait(child: &Child) -> io::Result<WaitStatus> {
+    let pid = child.id() as i32;
+    if !REAPING.load(Ordering::SeqCst) {
+        let status = waitpid(Pid::from_raw(pid), None)?;
+        record(status);
+        return Ok(children().exited.remove(&pid).unwrap_or(status));
+    }
+
+    let mut children = children();
+    loop {
+        if let Some(status) = children.exited.remove(&pid) {
+            return Ok(status);
+        }
+        children = EXITED.wait(children).unwrap_or_else(|err| err.into_inner());
+    }
+}
+
+/// Checks if a child spawned by `spawn_command` exited, without blocking.
+pub fn try_wait(child: &Child) -> io::Result<Option<WaitStatus>> {
+    let pid = child.id() as i32;
+    if !REAPING.load(Ordering::SeqCst) {
+        match waitpid(Pid::from_raw(pid), Some(WaitPidFlag::WNOHANG))? {
+            WaitStatus::StillAlive => return Ok(None),
+            status => record(status),
+        }
+    }
+
+    Ok(children().exited.remove(&pid))
+}
+
+/// Starts the reaper thread.
+///
+/// The reaper peeks at exited children without reaping them first, so
+/// children exiting while being spawned are left to the spawning thread.
+pub fn start() -> JoinHandle<()> {
+    REAPING.store(true, Ordering::SeqCst);
+
+    thread::spawn(|| loop {
+        let mut info: libc::siginfo_t = unsafe { mem::zeroed() };
+        let res = unsafe { libc::waitid(libc::P_ALL, 0, &mut info, libc::WEXITED | libc::WNOWAIT) };
+        if res != 0 {
+            if Errno::last() == Errno::ECHILD {
+                // nothing to reap until the next child is spawned
+                thread::sleep(Duration::from_secs(1));
+            }
+            continue;
+        }
+
+        let pid = unsafe { info.si_pid() };
+        let _spawning = SPAWN.lock().unwrap_or_else(|err| err.into_inner());
+        // the child might have been reaped while it was spawned
+        if let Ok(status) = waitpid(Pid::from_raw(pid), Some(WaitPidFlag::WNOHANG)) {
+            if status != WaitStatus::StillAlive {
+                record(status);
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{BufRead, BufReader},
+        process::Stdio,
+        time::Instant,
+    };
+
+    use nix::{
+        sys::signal::kill,
+        unistd::{fork, ForkResult},
+    };
+
+    use super::*;
+
+    /// Waits for `pid` to be reaped, its zombie exists until then.
+    fn reaped(pid: Pid, timeout: Duration) -> bool {
+        let start = Instant::now();
+        while start.elapsed() < timeout {
+            if kill(pid, None) == Err(Errno::ESRCH) {
+                return true;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        false
+    }
+
+    // the children are waited for with `wait`, not `Child::wait`
+    #[allow(clippy::zombie_processes)]
+    fn reap_orphans_and_tracked_children() {
+        // inherit the orphans, like PID 1 does
+        assert_eq!(
+            unsafe { libc::prctl(libc::PR_SET_CHILD_SUBREAPER, 1, 0, 0, 0) },
+            0
+        );
+        start();
+
+        let mut parent = spawn_command(
+            Command::new("sh")
+                .args(["-c", "sleep 0.2 >/dev/null & echo $!"])
+                .stdout(Stdio::piped()),
+            "parent",
+        )
+        .unwrap();
+        let mut line = String::new();
+        BufReader::new(parent.stdout.take().unwrap())
+            .read_line(&mut line)
+            .unwrap();
+        let orphan = Pid::from_raw(line.trim().parse().unwrap());
+        assert!(matches!(wait(&parent).unwrap(), WaitStatus::Exited(_, 0)));
+
+        // exits while the orphan is reaped
+        let tracked = spawn_command(
+            Command::new("sh").args(["-c", "sleep 0.2; exit 3"]),
+            "tracked",
+        )
+        .unwrap();
+        assert!(reaped(orphan, Duration::from_secs(5)));
+        assert_eq!(
+            wait(&tracked).unwrap(),
+            WaitStatus::Exited(Pid::from_raw(tracked.id() as i32), 3)
+        );
+        assert!(children().names.is_empty());
+        assert!(children().exited.is_empty());
+    }
+
+    #[test]
+    fn reaps_orphans_without_stealing_exit_statuses() {
+        // the reaper waits for any child, so it runs in a forked process
+        // to leave the children of the other tests alone
+        match unsafe { fork() }.unwrap() {
+            ForkResult::Child => {
+                let code = match std::panic::catch_unwind(reap_orphans_and_tracked_children) {
+                    Ok(()) => 0,
+                    Err(_) => 1,
+                };
+                unsafe { libc::_exit(code) };
+            }
+            ForkResult::Parent { child } => {
+                assert_eq!(waitpid(child, None).unwrap(), WaitStatus::Exited(child, 0));
+            }
+        }
+    }
+}
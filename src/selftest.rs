@@ -0,0 +1,104 @@
+//! Self test of a linµos image.
+//!
+//! Run as `init selftest` (not as PID 1) to check that the image and
+//! the running kernel provide what linµos needs to boot. The self test
+//! only reads the system state, it never mounts or configures anything.
+use std::{fs, path::Path};
+
+use pnet::datalink;
+
+use crate::{
+    fs::mount::REQUIRED_FILESYSTEMS,
+    net::{networkd::interface_configs, NetworkOptions},
+};
+
+/// The result of a single check.
+struct Check {
+    name: String,
+    result: Result<(), String>,
+}
+
+/// Checks that the binaries the boot hands off to exist.
+fn check_binaries() -> Vec<Check> {
+    ["/busybox"]
+        .iter()
+        .map(|path| Check {
+            name: format!("binary {}", path),
+            result: if Path::new(path).is_file() {
+                Ok(())
+            } else {
+                Err("not found".to_string())
+            },
+        })
+        .collect()
+}
+
+/// Checks that the kernel supports the filesystems mounted during boot.
+fn check_filesystems() -> Vec<Check> {
+    let supported = match fs::read_to_string("/proc/filesystems") {
+        Ok(contents) => contents,
+        Err(err) => {
+            return vec![Check {
+                name: "/proc/filesystems".to_string(),
+                result: Err(err.to_string()),
+            }]
+        }
+    };
+    // lines look like `nodev\tproc` or `\text4`
+    let supported = supported
+        .lines()
+        .filter_map(|line| line.split_whitespace().last())
+        .collect::<Vec<&str>>();
+
+    REQUIRED_FILESYSTEMS
+        .iter()
+        .map(|fs| Check {
+            name: format!("filesystem {}", fs),
+            result: if supported.contains(fs) {
+                Ok(())
+            } else {
+                Err("not supported by the kernel".to_string())
+            },
+        })
+        .collect()
+}
+
+/// Checks that the configured network interfaces exist.
+fn check_interfaces() -> Vec<Check> {
+    let interfaces = datalink::interfaces();
+    let configs = interface_configs(&NetworkOptions::default());
+
+    configs
+        .iter()
+        .map(|config| Check {
+            name: format!("interface {}", config.name()),
+            result: if interfaces.iter().any(|iface| iface.name == config.name()) {
+                Ok(())
+            } else {
+                Err("not found".to_string())
+            },
+        })
+        .collect()
+}
+
+/// Runs all checks and prints a pass/fail report.
+///
+/// # Returns
+///
+/// * `bool` - `true` if all checks passed.
+pub fn run() -> bool {
+    let checks = [check_binaries(), check_filesystems(), check_interfaces()]
+        .into_iter()
+        .flatten()
+        .collect::<Vec<Check>>();
+
+    checks.iter().for_each(|check| match &check.result {
+        Ok(()) => println!("[pass] {}", check.name),
+        Err(err) => println!("[fail] {}: {}", check.name, err),
+    });
+
+    let failed = checks.iter().filter(|check| check.result.is_err()).count();
+    println!("{} checks, {} failed", checks.len(), failed);
+
+    failed == 0
+}
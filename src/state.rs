@@ -0,0 +1,223 @@
+//! The state of the boot.
+//!
+//! The boot moves through the states in order:
+//!
+//! ```text
+//! uninitialized -> proc-mounted -> cmdline-parsed -> fs-mounted -> network-up -> handed-off
+//! ```
+//!
+//! branching off to `rescue` or `shutting-down` from any state. Every
+//! transition is logged and the current state is written to
+//! `/run/linos/state`. The names are read by other tools, so they are
+//! part of the interface of linµos and must stay stable.
+use core::fmt;
+use std::{path::Path, sync::Mutex, time::Duration};
+
+use log::{debug, info, warn};
+use nix::time::{clock_gettime, ClockId};
+
+use crate::fs::atomic_write;
+
+/// Path of the file holding the current state.
+pub const STATE_FILE: &str = "/run/linos/state";
+
+/// A state of the boot, in the order they are reached.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum BootState {
+    /// Nothing is set up yet.
+    Uninitialized,
+    /// `/proc` is mounted, so the kernel command line can be read.
+    ProcMounted,
+    /// The kernel command line is parsed and logging is set up.
+    CmdlineParsed,
+    /// The root and all other filesystems are mounted.
+    FsMounted,
+    /// The network is configured (or skipped).
+    NetworkUp,
+    /// The shell was handed off to.
+    HandedOff,
+    /// Dropping to a rescue shell.
+    Rescue,
+    /// Stopping the services and powering off, rebooting or halting.
+    ShuttingDown,
+}
+
+impl BootState {
+    /// The name of the state, as written to `STATE_FILE`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            BootState::Uninitialized => "uninitialized",
+            BootState::ProcMounted => "proc-mounted",
+            BootState::CmdlineParsed => "cmdline-parsed",
+            BootState::FsMounted => "fs-mounted",
+            BootState::NetworkUp => "network-up",
+            BootState::HandedOff => "handed-off",
+            BootState::Rescue => "rescue",
+            BootState::ShuttingDown => "shutting-down",
+        }
+    }
+
+    /// Checks if the boot can move from this state to `to`.
+    ///
+    /// The regular states are only reached in order, the rescue
+    /// and shutting down from any state but shutting down.
+    pub fn can_transition(&self, to: BootState) -> bool {
+        match (self, to) {
+            (BootState::ShuttingDown, _) => false,
+            (_, BootState::Rescue | BootState::ShuttingDown) => self != &to,
+            (BootState::Rescue, _) => false,
+            (from, to) => *from < to,
+        }
+    }
+}
+
+impl fmt::Display for BootState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// The states reached so far and the time since boot they were reached at.
+static HISTORY: Mutex<Vec<(BootState, Duration)>> = Mutex::new(Vec::new());
+
+/// Returns the current state.
+pub fn current() -> BootState {
+    HISTORY
+        .lock()
+        .unwrap_or_else(|err| err.into_inner())
+        .last()
+        .map(|(state, _)| *state)
+        .unwrap_or(BootState::Uninitialized)
+}
+
+/// Moves the boot to the state `to`.
+///
+/// The transition is logged and written to `STATE_FILE`, once `/run` is
+/// mounted. Invalid transitions (e.g. back to an earlier state) are
+/// ignored with a warning.
+pub fn transition(to: BootState) {
+    let mut history = HISTORY.lock().unwrap_or_else(|err| err.into_inner());
+    let from = history
+        .last()
+        .map(|(state, _)| *state)
+        .unwrap_or(BootState::Uninitialized);
+    if !from.can_transition(to) {
+        warn!("Ignoring invalid boot state transition {} -> {}", from, to);
+        return;
+    }
+
+    let uptime = clock_gettime(ClockId::CLOCK_MONOTONIC)
+        .map(Duration::from)
+        .unwrap_or_default();
+    history.push((to, uptime));
+    info!("Boot state {} -> {}", from, to);
+
+    // earlier, the file would end up below the `/run` mount
+    if history
+        .iter()
+        .any(|(state, _)| *state == BootState::FsMounted)
+    {
+        if let Err(err) = atomic_write(Path::new(STATE_FILE), &format!("{}\n", to)) {
+            debug!("Failed writing '{}': {}", STATE_FILE, err);
+        }
+    }
+}
+
+/// Returns the states reached so far and the time since boot
+/// they were reached at, in order.
+pub fn history() -> Vec<(BootState, Duration)> {
+    HISTORY
+        .lock()
+        .unwrap_or_else(|err| err.into_inner())
+        .clone()
+}
+
+/// Serializes the states reached so far as `<state>=<seconds since boot>`
+/// lines for the boot marker.
+pub fn to_marker_section() -> String {
+    history()
+        .iter()
+        .map(|(state, uptime)| format!("{}={:.3}\n", state, uptime.as_secs_f64()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use BootState::*;
+
+    #[test]
+    fn allowed_transitions() {
+        for (from, to) in [
+            (Uninitialized, ProcMounted),
+            (ProcMounted, CmdlineParsed),
+            (CmdlineParsed, FsMounted),
+            (FsMounted, NetworkUp),
+            (NetworkUp, HandedOff),
+            // skipped phases (e.g. `skip=network`)
+            (FsMounted, HandedOff),
+            (Uninitialized, CmdlineParsed),
+            // rescue and shutting down are reached from anywhere
+            (Uninitialized, Rescue),
+            (CmdlineParsed, Rescue),
+            (HandedOff, Rescue),
+            (Uninitialized, ShuttingDown),
+            (NetworkUp, ShuttingDown),
+            (HandedOff, ShuttingDown),
+            (Rescue, ShuttingDown),
+        ] {
+            assert!(from.can_transition(to), "{} -> {}", from, to);
+        }
+    }
+
+    #[test]
+    fn rejected_transitions() {
+        for (from, to) in [
+            // back to an earlier or the same state
+            (ProcMounted, Uninitialized),
+            (NetworkUp, FsMounted),
+            (HandedOff, NetworkUp),
+            (FsMounted, FsMounted),
+            (Rescue, Rescue),
+            // the rescue shell does not continue the boot
+            (Rescue, FsMounted),
+            (Rescue, HandedOff),
+            // nothing follows shutting down
+            (ShuttingDown, ShuttingDown),
+            (ShuttingDown, Rescue),
+            (ShuttingDown, HandedOff),
+            (ShuttingDown, Uninitialized),
+        ] {
+            assert!(!from.can_transition(to), "{} -> {}", from, to);
+        }
+    }
+
+    #[test]
+    fn names_are_stable() {
+        let names = [
+            Uninitialized,
+            ProcMounted,
+            CmdlineParsed,
+            FsMounted,
+            NetworkUp,
+            HandedOff,
+            Rescue,
+            ShuttingDown,
+        ]
+        .map(|state| state.name());
+        assert_eq!(
+            names,
+            [
+                "uninitialized",
+                "proc-mounted",
+                "cmdline-parsed",
+                "fs-mounted",
+                "network-up",
+                "handed-off",
+                "rescue",
+                "shutting-down"
+            ]
+        );
+    }
+}
@@ -0,0 +1,451 @@
+//! Supervision of services running next to the handed-off process.
+//!
+//! Each service is defined in a file in `/etc/linos/services.d/*.toml`:
+//!
+//! ```toml
+//! name = "sshd"
+//! exec = ["/usr/sbin/sshd", "-D"]
+//! after = ["syslogd"]
+//! wait_for = ["/run/syslogd.sock"]
+//! log = true
+//! log_tee = true
+//! maintenance = true
+//! ```
+//!
+//! Services are started before the handoff, after the services listed
+//! in `after` and once the paths in `wait_for` exist. A service waiting
+//! for its paths only holds back the services started after it, the
+//! others are started meanwhile. They are stopped in the reverse order
+//! on shutdown.
+//!
+//! With `log`, the output is piped through linµos and every line is
+//! timestamped and logged as soon as it is complete, with `log_tee` to
+//! the console as well. Services with a lot of output can redirect it
+//! to the log file directly with `log_mode = "file"`.
+//!
+//! When booting in maintenance mode (`maintenance`), only the services
+//! marked with `maintenance` are started.
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::Child,
+    thread,
+    time::{Duration, Instant},
+};
+
+use log::{debug, error, info, warn};
+use nix::{
+    sys::signal::{kill, Signal},
+    unistd::Pid,
+};
+use serde::Deserialize;
+
+use crate::{exec, reaper};
+
+/// Directory containing the service definitions.
+pub const SERVICES_DIR: &str = "/etc/linos/services.d";
+
+/// Time the services are given to exit after `SIGTERM` on shutdown.
+pub const STOP_GRACE: Duration = Duration::from_secs(10);
+
+/// Time a service waits for the paths in its `wait_for` to appear.
+pub const WAIT_FOR_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Interval in which the paths in `wait_for` are checked for existence.
+const WAIT_FOR_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Interval in which stopping services are checked for having exited.
+const STOP_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// A service definition.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Service {
+    pub name: String,
+    /// The program and its arguments.
+    pub exec: Vec<String>,
+    /// Services which have to be started before this one.
+    #[serde(default)]
+    pub after: Vec<String>,
+    /// Paths (e.g. sockets or devices) which have to exist
+    /// before this service is started.
+    #[serde(default)]
+    pub wait_for: Vec<PathBuf>,
+    /// Logs stdout and stderr to `/var/log/<program>.log`.
+    #[serde(default)]
+    pub log: bool,
+    /// How the output is logged (see `LogMode`).
+    #[serde(default)]
+    pub log_mode: LogMode,
+    /// Writes the logged output to the console as well
+    /// (only with the `capture` log mode).
+    #[serde(default)]
+    pub log_tee: bool,
+    /// Started in maintenance mode as well (e.g. `sshd`).
+    #[serde(default)]
+    pub maintenance: bool,
+}
+
+/// How the output of a service is logged.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogMode {
+    /// Every line is timestamped and written as soon as it is complete.
+    #[default]
+    Capture,
+    /// The output is redirected to the file directly, with less
+    /// overhead for services with a lot of output.
+    File,
+}
+
+impl Service {
+    /// Where the output of the service goes.
+    pub fn output(&self) -> exec::Output {
+        match (self.log, self.log_mode) {
+            (false, _) => exec::Output::Inherit,
+            (true, LogMode::File) => exec::Output::File,
+            (true, LogMode::Capture) => exec::Output::Capture { tee: self.log_tee },
+        }
+    }
+}
+
+/// Loads the service definitions from `dir` in lexical order.
+///
+/// Files which can not be read or parsed are skipped with a warning,
+/// a later definition of a service replaces an earlier one.
+///
+/// # Arguments
+///
+/// * `dir` - The directory containing the definitions (see `SERVICES_DIR`).
+pub fn load(dir: &Path) -> Vec<Service> {
+    let mut files = match fs::read_dir(dir) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "toml"))
+            .collect::<Vec<_>>(),
+        Err(_) => Vec::new(),
+    };
+    files.sort();
+
+    let mut services: Vec<Service> = Vec::new();
+    for file in files {
+        debug!("Loading service '{}'", file.display());
+        let service =
+            match fs::read_to_string(&file).map(|contents| toml::from_str::<Service>(&contents)) {
+                Ok(Ok(service)) => service,
+                Ok(Err(err)) => {
+                    warn!("Skipping malformed '{}': {}", file.display(), err);
+                    continue;
+                }
+                Err(err) => {
+                    warn!("Skipping '{}': {}", file.display(), err);
+                    continue;
+                }
+            };
+        if service.exec.is_empty() {
+            warn!("Skipping '{}': 'exec' is empty", file.display());
+            continue;
+        }
+
+        match services.iter().position(|s| s.name == service.name) {
+            Some(i) => services[i] = service,
+            None => services.push(service),
+        }
+    }
+
+    services
+}
+
+/// Filters the services started in maintenance mode, the
+/// others are skipped with a log message.
+pub fn maintenance(services: Vec<Service>) -> Vec<Service> {
+    services
+        .into_iter()
+        .filter(|service| {
+            if !service.maintenance {
+                info!("Maintenance mode, not starting service '{}'", service.name);
+            }
+            service.maintenance
+        })
+        .collect()
+}
+
+/// Orders the services such that every service comes after
+/// the services listed in its `after`.
+///
+/// Unknown dependencies are ignored, services with cyclic
+/// dependencies are appended in their original order.
+pub fn order(services: Vec<Service>) -> Vec<Service> {
+    let mut remaining = services;
+    let mut ordered: Vec<Service> = Vec::new();
+
+    while !remaining.is_empty() {
+        let ready = remaining.iter().position(|service| {
+            service.after.iter().all(|dependency| {
+                ordered.iter().any(|s| &s.name == dependency)
+                    || !remaining.iter().any(|s| &s.name == dependency)
+            })
+        });
+        match ready {
+            Some(i) => ordered.push(remaining.remove(i)),
+            None => {
+                warn!(
+                    "Cyclic service dependencies between: {}",
+                    remaining
+                        .iter()
+                        .map(|s| s.name.as_str())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                );
+                ordered.append(&mut remaining);
+            }
+        }
+    }
+
+    ordered
+}
+
+/// Calls `start` for the services in dependency order (see `order`),
+/// each once the paths in its `wait_for` exist.
+///
+/// A waiting service only holds back the services which (transitively)
+/// list it in their `after`, the others are started meanwhile. Services
+/// whose paths do not appear within `timeout` are skipped with an error.
+///
+/// # Arguments
+///
+/// * `services` - The services to start.
+/// * `timeout` - The time to wait for the paths at most.
+/// * `start` - Starts a service.
+fn start_when_ready(services: Vec<Service>, timeout: Duration, mut start: impl FnMut(Service)) {
+    let deadline = Instant::now() + timeout;
+    let mut pending = order(services);
+    for service in pending
+        .iter()
+        .filter(|service| !service.wait_for.is_empty())
+    {
+        debug!(
+            "Service '{}' waits for {:?}",
+            service.name, service.wait_for
+        );
+    }
+
+    while !pending.is_empty() {
+        let mut i = 0;
+        let mut progressed = false;
+        while i < pending.len() {
+            let service = &pending[i];
+            // the pending services are in dependency order
+            let blocked = service
+                .after
+                .iter()
+                .any(|dependency| pending[..i].iter().any(|s| &s.name == dependency));
+            match service.wait_for.iter().find(|path| !path.exists()) {
+                _ if blocked => i += 1,
+                Some(_) if Instant::now() < deadline => i += 1,
+                Some(path) => {
+                    error!(
+                        "Failed starting service '{}': '{}' did not appear within {:?}",
+                        service.name,
+                        path.display(),
+                        timeout
+                    );
+                    pending.remove(i);
+                    progressed = true;
+                }
+                None => {
+                    start(pending.remove(i));
+                    progressed = true;
+                }
+            }
+        }
+        if !progressed {
+            thread::sleep(WAIT_FOR_POLL_INTERVAL);
+        }
+    }
+}
+
+/// The running services.
+pub struct Supervisor {
+    /// The running services, in the order they were started.
+    running: Vec<(Service, Child)>,
+}
+
+impl Supervisor {
+    /// Starts the services in dependency order (see `start_when_ready`).
+    ///
+    /// Services failing to start are logged and skipped, this includes
+    /// services whose `wait_for` paths do not appear within `WAIT_FOR_TIMEOUT`.
+    pub fn start(services: Vec<Service>) -> Supervisor {
+        let mut running = Vec::new();
+        start_when_ready(services, WAIT_FOR_TIMEOUT, |service| {
+            let args = service.exec[1..]
+                .iter()
+                .map(|arg| arg.as_str())
+                .collect::<Vec<&str>>();
+            match exec::command(&service.exec[0], &args, service.output())
+                .and_then(|mut command| exec::spawn(&mut command, &service.name, service.output()))
+            {
+                Ok(child) => {
+                    info!("Started service '{}' ({})", service.name, child.id());
+                    running.push((service, child));
+                }
+                Err(err) => error!("Failed starting service '{}': {}", service.name, err),
+            }
+        });
+
+        Supervisor { running }
+    }
+
+    /// The number of running services.
+    pub fn running(&self) -> usize {
+        self.running.len()
+    }
+
+    /// Stops all running services.
+    ///
+    /// The services are sent `SIGTERM` in the reverse order they were
+    /// started. Services which did not exit within the `grace` period
+    /// are killed with `SIGKILL`.
+    ///
+    /// # Arguments
+    ///
+    /// * `grace` - The time the services are given to exit.
+    pub fn stop_all(&mut self, grace: Duration) {
+        // the pid of an exited (and reaped) service may have been reused
+        self.remove_stopped();
+        for (service, child) in self.running.iter().rev() {
+            debug!("Stopping service '{}'", service.name);
+            if let Err(err) = kill(Pid::from_raw(child.id() as i32), Signal::SIGTERM) {
+                warn!("Failed sending SIGTERM to '{}': {}", service.name, err);
+            }
+        }
+
+        let deadline = Instant::now() + grace;
+        loop {
+            self.remove_stopped();
+            if self.running.is_empty() || Instant::now() >= deadline {
+                break;
+            }
+            thread::sleep(STOP_POLL_INTERVAL);
+        }
+
+        for (service, mut child) in self.running.drain(..).rev() {
+            if let Ok(Some(status)) = reaper::try_wait(&child) {
+                info!(
+                    "Stopped service '{}': {}",
+                    service.name,
+                    reaper::describe(&status)
+                );
+                continue;
+            }
+            warn!(
+                "Service '{}' did not stop within {:?}, killing it",
+                service.name, grace
+            );
+            if let Err(err) = child.kill().and_then(|_| reaper::wait(&child)) {
+                error!("Failed killing service '{}': {}", service.name, err);
+            }
+        }
+    }
+
+    /// Removes the services which exited from the running services.
+    fn remove_stopped(&mut self) {
+        self.running
+            .retain(|(service, child)| match reaper::try_wait(child) {
+                Ok(Some(status)) => {
+                    info!(
+                        "Stopped service '{}': {}",
+                        service.name,
+                        reaper::describe(&status)
+                    );
+                    false
+                }
+                Ok(None) => true,
+                Err(err) => {
+                    warn!("Failed waiting for '{}': {}", service.name, err);
+                    false
+                }
+            });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn service(name: &str, after: &[&str], wait_for: &[&Path]) -> Service {
+        Service {
+            name: name.to_string(),
+            exec: vec!["/bin/true".to_string()],
+            after: after.iter().map(|name| name.to_string()).collect(),
+            wait_for: wait_for.iter().map(|path| path.to_path_buf()).collect(),
+            log: false,
+            log_mode: LogMode::default(),
+            log_tee: false,
+            maintenance: false,
+        }
+    }
+
+    #[test]
+    fn maintenance_starts_marked_services_only() {
+        let mut sshd = service("sshd", &[], &[]);
+        sshd.maintenance = true;
+        let services = vec![service("crond", &[], &[]), sshd, service("web", &[], &[])];
+
+        let names = maintenance(services)
+            .into_iter()
+            .map(|service| service.name)
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["sshd"]);
+    }
+
+    #[test]
+    fn maintenance_flag_is_parsed() {
+        let service = toml::from_str::<Service>(
+            "name = \"sshd\"\nexec = [\"/usr/sbin/sshd\"]\nmaintenance = true\n",
+        )
+        .unwrap();
+        assert!(service.maintenance);
+
+        let service = toml::from_str::<Service>("name = \"web\"\nexec = [\"/bin/web\"]\n");
+        assert!(!service.unwrap().maintenance);
+    }
+
+    #[test]
+    fn waiting_service_only_holds_back_dependents() {
+        let dir = std::env::temp_dir().join(format!("linos-test-wait-for-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let socket = dir.join("syslogd.sock");
+        let creator = {
+            let socket = socket.clone();
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(200));
+                fs::write(socket, "").unwrap();
+            })
+        };
+
+        let services = vec![
+            service("syslogd", &[], &[&socket]),
+            service("sshd", &["syslogd"], &[]),
+            service("crond", &[], &[]),
+            service("never", &[], &[&dir.join("missing")]),
+        ];
+        let started = Instant::now();
+        let mut order = Vec::new();
+        start_when_ready(services, Duration::from_millis(600), |service| {
+            order.push((service.name, started.elapsed()))
+        });
+        creator.join().unwrap();
+
+        let names = order
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["crond", "syslogd", "sshd"]);
+        assert!(order[0].1 < Duration::from_millis(200));
+        assert!(order[1].1 >= Duration::from_millis(200));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,112 @@
+//! Information about the booted system.
+//!
+//! Gathered at the start of the boot for diagnostics and written to
+//! the boot-complete marker. Every value is optional, as the proc
+//! entries might be missing or formatted unexpectedly.
+use core::fmt;
+use std::{fs, thread};
+
+use nix::sys::utsname::uname;
+
+/// The kernel and hardware linµos runs on.
+#[derive(Debug, Clone, Default)]
+pub struct SysInfo {
+    /// The kernel release (e.g. `6.1.0`).
+    pub kernel: Option<String>,
+    /// The hardware architecture (e.g. `x86_64`).
+    pub machine: Option<String>,
+    /// The number of CPUs available.
+    pub cpus: Option<usize>,
+    /// The total RAM in KiB.
+    pub mem_total_kib: Option<u64>,
+}
+
+/// Reads a value in KiB from the contents of `/proc/meminfo`.
+///
+/// # Arguments
+///
+/// * `meminfo` - The contents of `/proc/meminfo`.
+/// * `key` - The key of the value (e.g. `MemTotal`).
+pub fn meminfo_kib(meminfo: &str, key: &str) -> Option<u64> {
+    meminfo.lines().find_map(|line| {
+        let (name, value) = line.split_once(':')?;
+        if name.trim() != key {
+            return None;
+        }
+        value.split_whitespace().next()?.parse::<u64>().ok()
+    })
+}
+
+/// Reads the kernel release from the contents of `/proc/version`
+/// (e.g. `Linux version 6.1.0 (...)`).
+fn kernel_from_version(version: &str) -> Option<String> {
+    version
+        .strip_prefix("Linux version ")?
+        .split_whitespace()
+        .next()
+        .map(|release| release.to_string())
+}
+
+impl SysInfo {
+    /// Gathers the information, `/proc` has to be mounted.
+    pub fn gather() -> SysInfo {
+        let (kernel, machine) = match uname() {
+            Ok(uts) => (
+                Some(uts.release().to_string_lossy().to_string()),
+                Some(uts.machine().to_string_lossy().to_string()),
+            ),
+            Err(_) => (
+                fs::read_to_string("/proc/version")
+                    .ok()
+                    .and_then(|version| kernel_from_version(&version)),
+                None,
+            ),
+        };
+
+        SysInfo {
+            kernel,
+            machine,
+            cpus: thread::available_parallelism().ok().map(|cpus| cpus.get()),
+            mem_total_kib: fs::read_to_string("/proc/meminfo")
+                .ok()
+                .and_then(|meminfo| meminfo_kib(&meminfo, "MemTotal")),
+        }
+    }
+
+    /// Serializes the information as `KEY=VALUE` lines for
+    /// the boot marker, unknown values are omitted.
+    pub fn to_marker_section(&self) -> String {
+        let mut lines = String::new();
+        if let Some(kernel) = &self.kernel {
+            lines.push_str(&format!("KERNEL={}\n", kernel));
+        }
+        if let Some(machine) = &self.machine {
+            lines.push_str(&format!("MACHINE={}\n", machine));
+        }
+        if let Some(cpus) = self.cpus {
+            lines.push_str(&format!("CPUS={}\n", cpus));
+        }
+        if let Some(mem) = self.mem_total_kib {
+            lines.push_str(&format!("MEM_TOTAL_KIB={}\n", mem));
+        }
+        lines
+    }
+}
+
+impl fmt::Display for SysInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let unknown = "unknown".to_string();
+        write!(
+            f,
+            "kernel {} ({}), {} cpus, {} MiB RAM",
+            self.kernel.as_ref().unwrap_or(&unknown),
+            self.machine.as_ref().unwrap_or(&unknown),
+            self.cpus
+                .map(|cpus| cpus.to_string())
+                .unwrap_or_else(|| unknown.clone()),
+            self.mem_total_kib
+                .map(|mem| (mem / 1024).to_string())
+                .unwrap_or_else(|| unknown.clone()),
+        )
+    }
+}